@@ -1,6 +1,7 @@
 #!/usr/bin/python3
 #tags = ["core_approved", "example",]
 #developer = [ "example", "https://example.org" ]
+#description = "Prints the arguments it was called with"
 #trigger_port = "80"
 #call_format = "python3 {{script}} {{ip}} {{port}}"
 
@@ -0,0 +1,14 @@
+#!/usr/bin/python3
+"""
+RUSTSCAN-START
+tags = ["core_approved", "example", "block"]
+developer = [ "example", "https://example.org" ]
+call_format = "python3 {{script}} {{ip}} {{port}}"
+RUSTSCAN-END
+
+The headers are fenced in a docstring instead of # comments, everything between the two markers is parsed.
+"""
+
+import sys
+
+print('Block header script ran with arguments', str(sys.argv))
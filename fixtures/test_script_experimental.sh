@@ -0,0 +1,7 @@
+#!/bin/bash
+#tags = ["core_approved", "example", "experimental"]
+#developer = [ "example", "https://example.org" ]
+#call_format = "bash {{script}} {{ip}} {{port}}"
+
+# Tagged experimental, it only runs with --scripts-experimental.
+echo "$@"
@@ -0,0 +1,20 @@
+#!/bin/bash
+#tags = ["core_approved", "example", "findings"]
+#developer = [ "example", "https://example.org" ]
+#call_format = "bash {{script}} {{ip}}"
+#extract = 'Server: (\w+)'
+#[[findings]]
+#name = "version"
+#pattern = 'version: ([\d.]+)'
+#[[findings]]
+#name = "cve"
+#pattern = 'CVE-\d{4}-\d+'
+#[[findings]]
+#name = "missing"
+#pattern = 'Title: (.*)'
+
+# Prints a sample output of a tool, every version: line and every CVE id end up in the findings.
+echo "Server: nginx on $1"
+echo "version: 1.18"
+echo "CVE-2021-23017 resolver off-by-one"
+echo "openssl version: 3.0.2, CVE-2022-0778"
@@ -0,0 +1,11 @@
+#!/usr/bin/env lua
+--[[
++++
+tags = ["core_approved", "example", "front_matter"]
+developer = [ "example", "https://example.org" ]
+call_format = "lua {{script}} {{ip}} {{port}}"
++++
+The headers are a TOML front matter between two +++ lines, read as is.
+]]
+
+print("Front matter script ran with arguments " .. table.concat(arg, " "))
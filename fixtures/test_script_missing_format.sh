@@ -0,0 +1,6 @@
+# No shebang line, so there is no interpreter to fall back to.
+#tags = ["core_approved", "example"]
+#developer = [ "example", "https://example.org" ]
+
+# The headers are valid, but without a call_format or an interpreter this script can never run.
+echo "$@"
@@ -0,0 +1,6 @@
+#!/usr/bin/env bash
+#tags = ["core_approved", "example"]
+#developer = [ "example", "https://example.org" ]
+
+# No call_format or interpreter, the script runs with the bash of its shebang line.
+echo "shebang $1 $2"
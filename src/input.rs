@@ -1,6 +1,9 @@
 use serde_derive::Deserialize;
 use std::collections::HashMap;
 use std::fs;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::str::FromStr;
 use structopt::{clap::arg_enum, StructOpt};
 
 const LOWEST_PORT_NUMBER: u16 = 1;
@@ -30,6 +33,62 @@ arg_enum! {
     }
 }
 
+arg_enum! {
+    /// Represents the format of the scripts related output.
+    ///   - text is the human readable default.
+    ///   - json prints a single JSON document.
+    ///   - jsonl prints one JSON object per line, every script result as soon as it completes.
+    #[derive(Deserialize, Debug, StructOpt, Clone, PartialEq, Copy)]
+    pub enum ScriptsOutput {
+        Text,
+        Json,
+        Jsonl,
+    }
+}
+
+arg_enum! {
+    /// Represents the template engine filling the call formats of the scripts.
+    ///   - placeholder replaces the {{name}} placeholders, the default.
+    ///   - jinja renders the Jinja syntax of minijinja, RustScan has to be built with the jinja feature.
+    #[derive(Deserialize, Debug, StructOpt, Clone, PartialEq, Copy)]
+    pub enum ScriptsTemplate {
+        Placeholder,
+        Jinja,
+    }
+}
+
+/// The ip version of the hosts the scripts run against, 4, 6 or both.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IpVersion {
+    V4,
+    V6,
+    Both,
+}
+
+impl IpVersion {
+    /// Whether the ip is of this version.
+    pub fn matches(self, ip: &IpAddr) -> bool {
+        match self {
+            IpVersion::V4 => ip.is_ipv4(),
+            IpVersion::V6 => ip.is_ipv6(),
+            IpVersion::Both => true,
+        }
+    }
+}
+
+impl FromStr for IpVersion {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input.to_lowercase().as_str() {
+            "4" | "v4" | "ipv4" => Ok(IpVersion::V4),
+            "6" | "v6" | "ipv6" => Ok(IpVersion::V6),
+            "both" => Ok(IpVersion::Both),
+            _ => Err(String::from("the ip version must be 4, 6 or both.")),
+        }
+    }
+}
+
 /// Represents the range of ports to be scanned.
 #[derive(Deserialize, Debug, Clone, PartialEq)]
 pub struct PortRange {
@@ -124,6 +183,231 @@ pub struct Opts {
     #[structopt(long, possible_values = &ScriptsRequired::variants(), case_insensitive = true, default_value = "default")]
     pub scripts: ScriptsRequired,
 
+    /// A list of comma separated tags to filter the custom scripts on.
+    /// Overrides the tags found in the script config file, an empty string runs all parsed scripts.
+    /// Example: --scripts-tags fast,thorough
+    #[structopt(long, use_delimiter = true)]
+    pub scripts_tags: Option<Vec<String>>,
+
+    /// Filters the custom scripts on the tags of a named set of the [aliases] table in the script config.
+    /// Example: --scripts-alias web
+    #[structopt(long)]
+    pub scripts_alias: Option<String>,
+
+    /// Selects the tags, developer and ports of a [profiles.name] table of the script config,
+    /// the [profiles.default] one applies without it.
+    #[structopt(long)]
+    pub scripts_profile: Option<String>,
+
+    /// Treats the scripts as a best effort, a missing scripts folder or a broken config is a warning
+    /// and the scan results are still reported, instead of stopping RustScan.
+    #[structopt(long)]
+    pub scripts_optional: bool,
+
+    /// Fails when the custom scripts mode selects no script at all, like for a misspelled tag in CI.
+    #[structopt(long, conflicts_with = "scripts-optional")]
+    pub scripts_require_match: bool,
+
+    /// Also runs the scripts with experimental = true or an experimental tag, they are left out otherwise.
+    #[structopt(long)]
+    pub scripts_experimental: bool,
+
+    /// A list of comma separated script names to leave out, matched case-insensitively on the file name without extension.
+    /// Example: --scripts-exclude test_script,slow_scan
+    #[structopt(long, use_delimiter = true)]
+    pub scripts_exclude: Option<Vec<String>>,
+
+    /// Writes the output of every script into its own file under this directory,
+    /// named <ip>_<port>_<scriptname>.txt. The directory is created if it's missing.
+    #[structopt(long, parse(from_os_str))]
+    pub scripts_output_dir: Option<PathBuf>,
+
+    /// Overwrite existing files in the scripts output directory instead of adding a numeric suffix.
+    #[structopt(long)]
+    pub scripts_output_overwrite: bool,
+
+    /// Gzip the files written to the scripts output directory, named <ip>_<port>_<scriptname>.txt.gz.
+    #[structopt(long)]
+    pub scripts_output_compress: bool,
+
+    /// Lists the available custom scripts with their parsed headers and exits, nothing is executed.
+    #[structopt(long)]
+    pub scripts_list: bool,
+
+    /// Prints every discovered custom script as parsed from its headers, or why they failed to parse, and exits.
+    #[structopt(long)]
+    pub scripts_dump: bool,
+
+    /// The format of the scripts related output.
+    #[structopt(long, possible_values = &ScriptsOutput::variants(), case_insensitive = true, default_value = "text")]
+    pub scripts_output: ScriptsOutput,
+
+    /// The template engine filling the call formats, jinja needs RustScan built with the jinja feature.
+    #[structopt(long, possible_values = &ScriptsTemplate::variants(), case_insensitive = true, default_value = "placeholder")]
+    pub scripts_template: ScriptsTemplate,
+
+    /// How many times a failed script is retried, with an exponential backoff between the attempts.
+    /// A retries field in the script file takes precedence over this.
+    #[structopt(long, default_value = "0")]
+    pub script_retries: u8,
+
+    /// How many scripts run at the same time against a host.
+    #[structopt(long, alias = "scripts-per-host-concurrency", default_value = "1")]
+    pub scripts_concurrency: usize,
+
+    /// Starts scripts until the costs of the running ones add up to this, a script costs its cost header, 1 without one.
+    /// Replaces --scripts-concurrency when given.
+    #[structopt(long)]
+    pub scripts_budget: Option<u32>,
+
+    /// Seeds the random choices of the scripting, like the {{scan_id}}, the runs with the same seed are reproducible.
+    #[structopt(long)]
+    pub scripts_seed: Option<u64>,
+
+    /// Starts at most this many script commands per second over the whole run, unlimited when not given.
+    #[structopt(long)]
+    pub scripts_rate: Option<u32>,
+
+    /// Caps the whole scripting phase at this many seconds. Then the running scripts are stopped,
+    /// no new ones are started and the results completed so far are reported.
+    #[structopt(long)]
+    pub scripts_deadline: Option<u64>,
+
+    /// How many seconds a script command terminated at the --scripts-deadline gets to exit before it is killed, 2 by default.
+    #[structopt(long)]
+    pub scripts_kill_grace: Option<u64>,
+
+    /// How many hosts have their scripts running at the same time.
+    /// At most this times --scripts-concurrency script commands run at once.
+    #[structopt(long, default_value = "1")]
+    pub scripts_host_concurrency: usize,
+
+    /// Only the N lowest numbered open ports of a host are given to the scripts, 0 gives all of them.
+    /// This cap is applied before the max_ports of a script.
+    #[structopt(long, default_value = "0")]
+    pub scripts_top_ports: usize,
+
+    /// Prints the script status lines without colors. Setting the NO_COLOR environment variable does the same.
+    #[structopt(long)]
+    pub no_color: bool,
+
+    /// Script failures are only logged, they neither print a warning nor change the exit code.
+    #[structopt(long, conflicts_with = "scripts-strict")]
+    pub scripts_ignore_errors: bool,
+
+    /// Any failing script makes RustScan exit with a non-zero exit code.
+    #[structopt(long)]
+    pub scripts_strict: bool,
+
+    /// Skips the scripts with an os_match or service_match against the hosts nothing was detected on,
+    /// instead of running them.
+    #[structopt(long)]
+    pub scripts_skip_undetected: bool,
+
+    /// Reads what is known about the hosts for the os_match and service_match of the scripts from this TOML file,
+    /// a table per quoted ip with an optional os string, like os = "Windows", and an optional services list.
+    #[structopt(long, parse(from_os_str))]
+    pub scripts_detection: Option<PathBuf>,
+
+    /// Runs the scripts with requires_root = true when RustScan is not elevated, with a warning,
+    /// instead of skipping them.
+    #[structopt(long)]
+    pub scripts_run_unprivileged: bool,
+
+    /// Checks the script config and every script without running anything, and exits with 1 on any problem.
+    #[structopt(long)]
+    pub scripts_validate: bool,
+
+    /// Reports the selected scripts sharing an identical tag set, and exits.
+    #[structopt(long)]
+    pub scripts_lint: bool,
+
+    /// Lists the programs the selected scripts start, and exits non zero if any is missing from the PATH.
+    #[structopt(long)]
+    pub scripts_check_deps: bool,
+
+    /// Writes a starter script config and example scripts into the scripts folder, and exits.
+    #[structopt(long)]
+    pub scripts_init: bool,
+
+    /// Lets --scripts-init overwrite the existing config and example script files.
+    #[structopt(long)]
+    pub force: bool,
+
+    /// Runs the scripts without a shell field of their own through the system shell, sh -c or cmd /C.
+    /// This enables pipes and globs in call formats, only use it with scripts you trust.
+    #[structopt(long)]
+    pub scripts_shell: bool,
+
+    /// Gives the open ports to the scripts without a sort_ports field of their own in the order the scan found them,
+    /// instead of sorted and deduplicated.
+    #[structopt(long)]
+    pub scripts_scan_port_order: bool,
+
+    /// A command every script output is piped through, its stdout replacing the output.
+    /// An output_filter field in the script file takes precedence over this.
+    #[structopt(long)]
+    pub scripts_output_filter: Option<String>,
+
+    /// Also prints the stdout of the script commands line by line as it comes, not only once they finished.
+    #[structopt(long)]
+    pub scripts_stream: bool,
+
+    /// The most bytes of stdout captured from a script command, a command writing more is stopped
+    /// and its output cut off.
+    #[structopt(long, default_value = "4194304")]
+    pub script_max_output: usize,
+
+    /// How many -v flags the default nmap script gets, 3 when not given.
+    #[structopt(long)]
+    pub scripts_default_verbosity: Option<u8>,
+
+    /// Extra arguments of the default nmap script, put before its -p ports and ip.
+    #[structopt(long, allow_hyphen_values = true)]
+    pub scripts_default_args: Option<String>,
+
+    /// Only runs the scripts against the hosts of this ip version, 4, 6 or both.
+    #[structopt(long, default_value = "both")]
+    pub scripts_ip_version: IpVersion,
+
+    /// Only runs the scripts against the hosts inside these comma separated ips and CIDRs, or the ones of a file
+    /// with one of them per line. The other hosts are scanned but not scripted.
+    #[structopt(long)]
+    pub scripts_hosts: Option<String>,
+
+    /// The ports_separator of the scripts without their own, "," when not given.
+    #[structopt(long)]
+    pub scripts_ports_separator: Option<String>,
+
+    /// Appends the output of every script to this single file, each under a line naming the script, ip and port.
+    #[structopt(long, parse(from_os_str))]
+    pub scripts_combined_output: Option<PathBuf>,
+
+    /// Inserts every script result into the script_results table of this SQLite database, created if it's missing.
+    #[structopt(long, parse(from_os_str))]
+    pub scripts_sqlite: Option<PathBuf>,
+
+    /// Writes a JSON summary of the scripting phase to this file once all the scripts completed.
+    #[structopt(long, parse(from_os_str))]
+    pub scripts_manifest: Option<PathBuf>,
+
+    /// Writes a JUnit XML report of the script runs to this file, every script against a host is a testcase.
+    #[structopt(long, parse(from_os_str))]
+    pub scripts_junit: Option<PathBuf>,
+
+    /// Reads the script config from this file instead of looking it up, can be repeated.
+    /// The files are merged in order, the later ones extending the lists and overriding the values of the earlier ones.
+    #[structopt(long, parse(from_os_str), number_of_values = 1)]
+    pub scripts_config: Vec<PathBuf>,
+
+    /// Reads the custom scripts and their config from a zip archive instead of the home dir.
+    #[structopt(long, parse(from_os_str))]
+    pub scripts_bundle: Option<PathBuf>,
+
+    /// Prints how long every script took at the end of the run, slowest first.
+    #[structopt(long)]
+    pub scripts_timing: bool,
+
     /// Use the top 1000 ports.
     #[structopt(long)]
     pub top: bool,
@@ -200,6 +484,33 @@ impl Opts {
 
         merge_optional!(range, ulimit);
     }
+
+    /// A short hash of the options deciding what the port scan does: the ports, the timing and the order.
+    /// It's stable across runs and versions, the same scan profile always gives the same one.
+    pub fn scan_fingerprint(&self) -> String {
+        let mut ports = self.ports.clone();
+        if let Some(ports) = &mut ports {
+            ports.sort_unstable();
+            ports.dedup();
+        }
+        let profile = format!(
+            "ports={:?};range={:?};batch_size={};timeout={};tries={};scan_order={:?};top={}",
+            ports,
+            self.range.as_ref().map(|range| (range.start, range.end)),
+            self.batch_size,
+            self.timeout,
+            self.tries,
+            self.scan_order,
+            self.top
+        );
+        // FNV-1a, the hashers of std may change between Rust versions.
+        let hash = profile
+            .bytes()
+            .fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+                (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+            });
+        format!("{hash:016x}")
+    }
 }
 
 /// Struct used to deserialize the options specified within our config file.
@@ -263,7 +574,11 @@ impl Config {
 
 #[cfg(test)]
 mod tests {
-    use super::{Config, Opts, PortRange, ScanOrder, ScriptsRequired};
+    use super::{
+        Config, IpVersion, Opts, PortRange, ScanOrder, ScriptsOutput, ScriptsRequired,
+        ScriptsTemplate,
+    };
+    use std::net::IpAddr;
     impl Config {
         fn default() -> Self {
             Self {
@@ -300,6 +615,57 @@ mod tests {
                 no_config: true,
                 top: false,
                 scripts: ScriptsRequired::Default,
+                scripts_tags: None,
+                scripts_exclude: None,
+                scripts_output_dir: None,
+                scripts_output_overwrite: false,
+                scripts_output_compress: false,
+                scripts_list: false,
+                scripts_dump: false,
+                scripts_output: ScriptsOutput::Text,
+                scripts_template: ScriptsTemplate::Placeholder,
+                script_retries: 0,
+                scripts_timing: false,
+                scripts_bundle: None,
+                scripts_config: Vec::new(),
+                scripts_combined_output: None,
+                scripts_sqlite: None,
+                scripts_manifest: None,
+                scripts_junit: None,
+                scripts_default_verbosity: None,
+                scripts_default_args: None,
+                scripts_ip_version: IpVersion::Both,
+                scripts_hosts: None,
+                scripts_rate: None,
+                scripts_run_unprivileged: false,
+                scripts_skip_undetected: false,
+                scripts_detection: None,
+                scripts_stream: false,
+                scripts_output_filter: None,
+                scripts_ports_separator: None,
+                scripts_alias: None,
+                scripts_profile: None,
+                scripts_optional: false,
+                scripts_require_match: false,
+                scripts_experimental: false,
+                scripts_deadline: None,
+                scripts_kill_grace: None,
+                script_max_output: 4_194_304,
+                scripts_shell: false,
+                scripts_scan_port_order: false,
+                scripts_lint: false,
+                scripts_check_deps: false,
+                scripts_init: false,
+                force: false,
+                scripts_validate: false,
+                scripts_ignore_errors: false,
+                scripts_strict: false,
+                scripts_concurrency: 1,
+                scripts_budget: None,
+                scripts_seed: None,
+                scripts_host_concurrency: 1,
+                no_color: false,
+                scripts_top_ports: 0,
             }
         }
     }
@@ -335,6 +701,52 @@ mod tests {
         assert_eq!(opts.scripts, ScriptsRequired::Default)
     }
 
+    #[test]
+    fn ip_version_matches() {
+        use structopt::StructOpt;
+        let ips: Vec<IpAddr> = ["127.0.0.1", "::1", "10.0.0.1", "fe80::1"]
+            .iter()
+            .map(|ip| ip.parse().unwrap())
+            .collect();
+        let kept = |version: IpVersion| -> Vec<String> {
+            ips.iter()
+                .filter(|ip| version.matches(ip))
+                .map(ToString::to_string)
+                .collect()
+        };
+        assert_eq!(kept(IpVersion::V4), vec!["127.0.0.1", "10.0.0.1"]);
+        assert_eq!(kept(IpVersion::V6), vec!["::1", "fe80::1"]);
+        assert_eq!(kept(IpVersion::Both).len(), 4);
+
+        assert_eq!(
+            Opts::from_iter(vec!["rustscan"]).scripts_ip_version,
+            IpVersion::Both
+        );
+        let opts = Opts::from_iter(vec!["rustscan", "--scripts-ip-version", "6"]);
+        assert_eq!(opts.scripts_ip_version, IpVersion::V6);
+        assert!("5".parse::<IpVersion>().is_err());
+    }
+
+    #[test]
+    fn scan_fingerprint_follows_the_scan_options() {
+        use structopt::StructOpt;
+        let fingerprint =
+            |args: &[&str]| Opts::from_iter([&["rustscan"], args].concat()).scan_fingerprint();
+        let profile = fingerprint(&["-p", "443,80", "-t", "500", "-a", "10.0.0.1"]);
+        assert_eq!(profile.len(), 16);
+        // The targets are not part of the scan profile, nor is the order of the ports.
+        assert_eq!(
+            profile,
+            fingerprint(&["-p", "80,443", "-t", "500", "-a", "10.0.0.2"])
+        );
+        assert_ne!(profile, fingerprint(&["-p", "80,443", "-t", "1500"]));
+        assert_ne!(profile, fingerprint(&["-p", "80,443,8080", "-t", "500"]));
+        assert_ne!(
+            fingerprint(&["-r", "1-1000"]),
+            fingerprint(&["-r", "1-1000", "--scan-order", "random"])
+        );
+    }
+
     #[test]
     fn opts_merge_optional_arguments() {
         let mut opts = Opts::default();
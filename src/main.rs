@@ -7,10 +7,10 @@ extern crate shell_words;
 mod tui;
 
 mod input;
-use input::{Config, Opts, PortRange, ScanOrder, ScriptsRequired};
+use input::{Config, Opts, PortRange, ScanOrder, ScriptsOutput, ScriptsRequired};
 
 mod scanner;
-use scanner::Scanner;
+use scanner::{PortState, Scanner};
 
 mod port_strategy;
 use port_strategy::PortStrategy;
@@ -19,7 +19,16 @@ mod benchmark;
 use benchmark::{Benchmark, NamedTimer};
 
 mod scripts;
-use scripts::{init_scripts, Script, ScriptFile};
+use scripts::{
+    duplicate_tag_sets, format_script_dump, format_script_list, format_stats, format_status,
+    format_timings, init_example_scripts, init_hooks, init_scripts, init_vars,
+    install_interrupt_handler, interrupted, list_scripts, lowest_ports, missing_binaries,
+    script_binaries, scripts_exit_code, select_template_engine, validate_scripts, write_junit,
+    write_manifest, write_result_to_dir, CombinedOutput, CommandRunner, Detection, HostFilter,
+    HostJob, PortSet, RateLimitedRunner, ResultWriter, RunContext, RunTargets, ScanContext,
+    ScriptBundle, ScriptEngine, ScriptFile, ScriptHooks, ScriptObserver, ScriptReport,
+    ScriptResult, ScriptScope, ScriptStatus, SqliteOutput, SubprocessRunner, DEFAULT_KILL_GRACE,
+};
 
 use cidr_utils::cidr::IpCidr;
 use colorful::{Color, Colorful};
@@ -29,10 +38,12 @@ use std::collections::HashMap;
 use std::convert::TryInto;
 use std::fs::File;
 use std::io::{prelude::*, BufReader};
-use std::net::{IpAddr, ToSocketAddrs};
+use std::net::{IpAddr, Ipv4Addr, ToSocketAddrs};
 use std::path::Path;
 use std::string::ToString;
-use std::time::Duration;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
 use trust_dns_resolver::{
     config::{ResolverConfig, ResolverOpts},
     Resolver,
@@ -64,25 +75,225 @@ fn main() {
 
     debug!("Main() `opts` arguments are {:?}", opts);
 
-    let scripts_to_run: Vec<ScriptFile> = match init_scripts(opts.scripts) {
-        Ok(scripts_to_run) => scripts_to_run,
+    if opts.scripts_list || opts.scripts_dump {
+        let format = if opts.scripts_dump {
+            format_script_dump
+        } else {
+            format_script_list
+        };
+        match list_scripts(&opts.scripts_config)
+            .and_then(|listings| format(&listings, opts.scripts_output))
+        {
+            Ok(list) => {
+                print!("{list}");
+                std::process::exit(0);
+            }
+            Err(e) => {
+                warning!(
+                    format!("Listing scripts failed!\n{}", e),
+                    opts.greppable,
+                    opts.accessible
+                );
+                std::process::exit(1);
+            }
+        }
+    }
+
+    if opts.scripts_init {
+        match init_example_scripts(opts.force) {
+            Ok(written) => {
+                for path in &written {
+                    println!("Wrote {}", path.display());
+                }
+                std::process::exit(0);
+            }
+            Err(e) => {
+                warning!(
+                    format!("Initiating the example scripts failed!\n{e:#}"),
+                    opts.greppable,
+                    opts.accessible
+                );
+                std::process::exit(1);
+            }
+        }
+    }
+
+    // Kept until the end of the run, the scripts of a bundle are unpacked into a temporary dir.
+    let script_bundle: Option<ScriptBundle> =
+        match opts.scripts_bundle.as_deref().map(ScriptBundle::open) {
+            Some(Ok(bundle)) => Some(bundle),
+            Some(Err(e)) => {
+                warning!(
+                    format!("Opening the scripts bundle failed!\n{e:#}"),
+                    opts.greppable,
+                    opts.accessible
+                );
+                std::process::exit(1);
+            }
+            None => None,
+        };
+
+    if opts.scripts_validate {
+        match validate_scripts(script_bundle.as_ref(), &opts.scripts_config) {
+            Ok(validations) => {
+                let color = !opts.no_color && std::env::var_os("NO_COLOR").is_none();
+                let failed = validations
+                    .iter()
+                    .filter(|validation| !validation.problems.is_empty())
+                    .count();
+                for validation in &validations {
+                    let path = validation.path.display();
+                    if validation.problems.is_empty() {
+                        println!(
+                            "{}",
+                            format_status(ScriptStatus::Ok, &path.to_string(), color)
+                        );
+                    } else {
+                        for problem in &validation.problems {
+                            println!(
+                                "{}",
+                                format_status(
+                                    ScriptStatus::Fail,
+                                    &format!("{path}: {problem}"),
+                                    color
+                                )
+                            );
+                        }
+                    }
+                }
+                println!(
+                    "{} file(s) checked, {} with problems",
+                    validations.len(),
+                    failed
+                );
+                drop(script_bundle);
+                std::process::exit(i32::from(failed > 0));
+            }
+            Err(e) => {
+                warning!(
+                    format!("Validating scripts failed!\n{e:#}"),
+                    opts.greppable,
+                    opts.accessible
+                );
+                drop(script_bundle);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    let (scripts_to_run, mut script_stats, scripts_origin) =
+        match init_scripts(&opts, script_bundle.as_ref(), None) {
+            Ok(initialized) => initialized,
+            Err(e) => {
+                warning!(
+                    format!("Initiating scripts failed!\n{e:#}"),
+                    opts.greppable,
+                    opts.accessible
+                );
+                drop(script_bundle);
+                std::process::exit(1);
+            }
+        };
+
+    debug!("Scripts initialized {:?}", &scripts_to_run);
+
+    if opts.scripts_lint {
+        let duplicates = duplicate_tag_sets(&scripts_to_run);
+        for paths in &duplicates {
+            warning!(
+                format!(
+                    "Scripts with identical tags, they all run on the same selection: {:?}",
+                    paths
+                ),
+                opts.greppable,
+                opts.accessible
+            );
+        }
+        if duplicates.is_empty() {
+            detail!(
+                "No scripts with identical tags found",
+                opts.greppable,
+                opts.accessible
+            );
+        }
+        drop(script_bundle);
+        std::process::exit(0);
+    }
+
+    if opts.scripts_check_deps {
+        let color = !opts.no_color && std::env::var_os("NO_COLOR").is_none();
+        let binaries = script_binaries(&scripts_to_run);
+        let missing = missing_binaries(&binaries);
+        for binary in &binaries {
+            let status = if missing.contains(binary) {
+                ScriptStatus::Fail
+            } else {
+                ScriptStatus::Ok
+            };
+            println!("{}", format_status(status, binary, color));
+        }
+        println!(
+            "{} program(s) needed, {} missing from the PATH",
+            binaries.len(),
+            missing.len()
+        );
+        drop(script_bundle);
+        std::process::exit(i32::from(!missing.is_empty()));
+    }
+
+    let script_hooks: ScriptHooks = match init_hooks(&opts, script_bundle.as_ref()) {
+        Ok(script_hooks) => script_hooks,
         Err(e) => {
             warning!(
-                format!("Initiating scripts failed!\n{}", e.to_string()),
+                format!("Initiating script hooks failed!\n{e:#}"),
                 opts.greppable,
                 opts.accessible
             );
+            drop(script_bundle);
             std::process::exit(1);
         }
     };
 
-    debug!("Scripts initialized {:?}", &scripts_to_run);
+    let script_vars = match init_vars(&opts, script_bundle.as_ref()) {
+        Ok(script_vars) => script_vars,
+        Err(e) => {
+            warning!(
+                format!("Initiating script vars failed!\n{e:#}"),
+                opts.greppable,
+                opts.accessible
+            );
+            drop(script_bundle);
+            std::process::exit(1);
+        }
+    };
+    let script_detection = match opts.scripts_detection.as_deref().map(Detection::read_from) {
+        Some(Ok(detection)) => Some(detection),
+        Some(Err(e)) => {
+            warning!(
+                format!("Reading the detection file failed!\n{e:#}"),
+                opts.greppable,
+                opts.accessible
+            );
+            drop(script_bundle);
+            std::process::exit(1);
+        }
+        None => None,
+    };
+
+    let template_engine = match select_template_engine(opts.scripts_template) {
+        Ok(template_engine) => template_engine,
+        Err(e) => {
+            warning!(e.to_string(), opts.greppable, opts.accessible);
+            drop(script_bundle);
+            std::process::exit(1);
+        }
+    };
 
     if !opts.greppable && !opts.accessible {
         print_opening(&opts);
     }
 
-    let ips: Vec<IpAddr> = parse_addresses(&opts);
+    let (ips, original_targets) = parse_addresses(&opts);
 
     if ips.is_empty() {
         warning!(
@@ -90,6 +301,7 @@ fn main() {
             opts.greppable,
             opts.accessible
         );
+        drop(script_bundle);
         std::process::exit(1);
     }
 
@@ -102,27 +314,52 @@ fn main() {
         Duration::from_millis(opts.timeout.into()),
         opts.tries,
         opts.greppable,
-        PortStrategy::pick(&opts.range, opts.ports, opts.scan_order),
+        PortStrategy::pick(&opts.range, opts.ports.clone(), opts.scan_order),
         opts.accessible,
     );
     debug!("Scanner finished building: {:?}", scanner);
 
+    let scan_started = SystemTime::now();
     let mut portscan_bench = NamedTimer::start("Portscan");
-    let scan_result = block_on(scanner.run());
+    // The closed and filtered ports are only kept when a selected script asks for them.
+    let keep_states = !opts.greppable
+        && opts.scripts != ScriptsRequired::None
+        && scripts_to_run.iter().any(ScriptFile::wants_unopened_ports);
+    let scan_result = if keep_states {
+        block_on(scanner.run_with_states())
+    } else {
+        block_on(scanner.run())
+            .into_iter()
+            .map(|socket| (socket, PortState::Open))
+            .collect()
+    };
     portscan_bench.end();
     benchmarks.push(portscan_bench);
 
     let mut ports_per_ip = HashMap::new();
-
-    for socket in scan_result {
-        ports_per_ip
-            .entry(socket.ip())
-            .or_insert_with(Vec::new)
-            .push(socket.port());
+    let mut unopened_per_ip: HashMap<IpAddr, PortSet> = HashMap::new();
+
+    for (socket, state) in scan_result {
+        match state {
+            PortState::Open => ports_per_ip
+                .entry(socket.ip())
+                .or_insert_with(Vec::new)
+                .push(socket.port()),
+            PortState::Closed => unopened_per_ip
+                .entry(socket.ip())
+                .or_default()
+                .closed
+                .push(socket.port()),
+            PortState::Filtered => unopened_per_ip
+                .entry(socket.ip())
+                .or_default()
+                .filtered
+                .push(socket.port()),
+        }
     }
 
-    for ip in ips {
-        if ports_per_ip.contains_key(&ip) {
+    for ip in &ips {
+        if ports_per_ip.contains_key(ip) {
             continue;
         }
 
@@ -139,7 +376,101 @@ fn main() {
     }
 
     let mut script_bench = NamedTimer::start("Scripts");
+    let ports_open = ports_per_ip.values().map(Vec::len).sum();
+    let mut run_context = RunContext::new()
+        .scan_fingerprint(opts.scan_fingerprint())
+        .vars(script_vars)
+        .scan(ScanContext::new(
+            scan_started,
+            scanner.sockets_count(),
+            ports_open,
+        ))
+        .skip_undetected(opts.scripts_skip_undetected);
+    if let Some(detection) = script_detection {
+        run_context = run_context.detection(detection);
+    }
+    let scripts_deadline = opts
+        .scripts_deadline
+        .map(|seconds| Instant::now() + Duration::from_secs(seconds));
+    if let Some(deadline) = scripts_deadline {
+        run_context = run_context.deadline(deadline);
+    }
+    if let Some(seed) = opts.scripts_seed {
+        run_context = run_context.seed(seed);
+    }
+    // Status lines go to stderr, to keep stdout clean for the machine readable outputs.
+    let color = !opts.no_color && std::env::var_os("NO_COLOR").is_none();
+    let run_hooks = !opts.greppable && opts.scripts != ScriptsRequired::None;
+    // From here on Ctrl-C stops the script commands, instead of orphaning them.
+    if opts.scripts != ScriptsRequired::None {
+        if let Err(e) = install_interrupt_handler() {
+            warning!(e.to_string(), opts.greppable, opts.accessible);
+        }
+    }
+    if run_hooks
+        && report_hook(
+            &script_hooks,
+            script_hooks.run_pre(&ips),
+            opts.greppable,
+            opts.accessible,
+        )
+    {
+        drop(script_bundle);
+        std::process::exit(1);
+    }
+    let (run_scoped, host_scoped): (Vec<ScriptFile>, Vec<ScriptFile>) = scripts_to_run
+        .iter()
+        .cloned()
+        .partition(|script_f| script_f.scope() == ScriptScope::Run);
+    let combined_output = match &opts.scripts_combined_output {
+        Some(path) if run_hooks => match CombinedOutput::open(path) {
+            Ok(combined_output) => Some(combined_output),
+            Err(e) => {
+                warning!(
+                    format!("Failed to open {}: {}", path.display(), e),
+                    opts.greppable,
+                    opts.accessible
+                );
+                None
+            }
+        },
+        _ => None,
+    };
+    let sqlite_output = match &opts.scripts_sqlite {
+        Some(path) if run_hooks => match SqliteOutput::open(path, &run_context) {
+            Ok(sqlite_output) => Some(sqlite_output),
+            Err(e) => {
+                warning!(
+                    format!("Failed to open {}: {}", path.display(), e),
+                    opts.greppable,
+                    opts.accessible
+                );
+                None
+            }
+        },
+        _ => None,
+    };
+    let script_hosts = match opts.scripts_hosts.as_deref().map(HostFilter::read) {
+        Some(Ok(script_hosts)) => Some(script_hosts),
+        Some(Err(e)) => {
+            warning!(
+                format!("Reading the scripts hosts failed!\n{e:#}"),
+                opts.greppable,
+                opts.accessible
+            );
+            drop(script_bundle);
+            std::process::exit(1);
+        }
+        None => None,
+    };
+    let mut written_files: usize = 0;
+    let mut skipped_scripts: usize = 0;
+    let mut script_results: Vec<ScriptResult> = Vec::new();
+    let mut host_jobs: Vec<HostJob> = Vec::new();
     for (ip, ports) in &ports_per_ip {
+        if interrupted() {
+            break;
+        }
         let vec_str_ports: Vec<String> = ports.iter().map(ToString::to_string).collect();
 
         // nmap port style is 80,443. Comma separated with no spaces.
@@ -150,53 +481,271 @@ fn main() {
             println!("{} -> [{}]", &ip, ports_str);
             continue;
         }
+        if !opts.scripts_ip_version.matches(ip) {
+            debug!(
+                "Skipping the scripts on ip {}, it is not of ip version {:?}",
+                ip, opts.scripts_ip_version
+            );
+            continue;
+        }
+        if script_hosts
+            .as_ref()
+            .is_some_and(|script_hosts| !script_hosts.contains(*ip))
+        {
+            debug!(
+                "Skipping the scripts on ip {}, it is not in --scripts-hosts",
+                ip
+            );
+            continue;
+        }
         detail!("Starting Script(s)", opts.greppable, opts.accessible);
 
-        // Run all the scripts we found and parsed based on the script config file tags field.
-        for mut script_f in scripts_to_run.clone() {
-            // This part allows us to add commandline arguments to the Script call_format, appending them to the end of the command.
-            if !opts.command.is_empty() {
-                let user_extra_args = &opts.command.join(" ");
-                debug!("Extra args vec {:?}", user_extra_args);
-                if script_f.call_format.is_some() {
-                    let mut call_f = script_f.call_format.unwrap();
-                    call_f.push(' ');
-                    call_f.push_str(user_extra_args);
-                    output!(
-                        format!("Running script {:?} on ip {}\nDepending on the complexity of the script, results may take some time to appear.", call_f, &ip),
-                        opts.greppable,
-                        opts.accessible
-                    );
-                    debug!("Call format {}", call_f);
-                    script_f.call_format = Some(call_f);
-                }
+        // Prepare all the scripts we found and parsed based on the script config file tags field.
+        let mut host_scripts: Vec<ScriptFile> = Vec::with_capacity(scripts_to_run.len());
+        for script_f in host_scoped.iter().cloned() {
+            let missing_ports = script_f.missing_required_ports(ports);
+            if !missing_ports.is_empty() {
+                skipped_scripts += 1;
+                debug!(
+                    "Skipping script {:?} on ip {}, required ports {:?} are not open",
+                    script_f.path, ip, missing_ports
+                );
+                eprintln!(
+                    "{}",
+                    format_status(
+                        ScriptStatus::Skip,
+                        &format!(
+                            "{} on {}, ports {:?} are not open",
+                            script_label(&script_f),
+                            ip,
+                            missing_ports
+                        ),
+                        color
+                    )
+                );
+                continue;
             }
 
-            // Building the script with the arguments from the ScriptFile, and ip-ports.
-            let script = Script::build(
-                script_f.path,
-                *ip,
-                ports.to_vec(),
-                script_f.port,
-                script_f.ports_separator,
-                script_f.tags,
-                script_f.call_format,
-            );
-            match script.run() {
-                Ok(script_result) => {
-                    detail!(script_result.to_string(), opts.greppable, opts.accessible);
-                }
-                Err(e) => {
-                    warning!(
-                        &format!("Error {}", e.to_string()),
-                        opts.greppable,
-                        opts.accessible
-                    );
-                }
+            if script_f.excludes_all_ports(ports) {
+                skipped_scripts += 1;
+                debug!(
+                    "Skipping script {:?} on ip {}, its exclude_ports cover every open port",
+                    script_f.path, ip
+                );
+                continue;
+            }
+
+            if script_f.outside_open_ports_range(ports) {
+                skipped_scripts += 1;
+                debug!(
+                    "Skipping script {:?} on ip {}, its {} open ports are outside min_open_ports {:?} and max_open_ports {:?}",
+                    script_f.path, ip, ports.len(), script_f.min_open_ports, script_f.max_open_ports
+                );
+                continue;
+            }
+
+            if script_f.out_of_scope(ports) {
+                skipped_scripts += 1;
+                debug!(
+                    "Skipping script {:?} on ip {}, none of its ports are among the script config ports",
+                    script_f.path, ip
+                );
+                continue;
+            }
+
+            host_scripts.push(apply_opts(script_f, &opts, &ip.to_string()));
+        }
+
+        host_jobs.push(HostJob {
+            ip: *ip,
+            ports: PortSet {
+                open: lowest_ports(ports, opts.scripts_top_ports),
+                ..unopened_per_ip.remove(ip).unwrap_or_default()
+            },
+            scripts: host_scripts,
+            original_target: original_targets.get(ip).cloned(),
+        });
+    }
+
+    // The scripts are built with the ip-ports and run on the engine workers, results come back as they complete.
+    // The engines print them to stdout as they complete, the JSON report goes there too.
+    let result_writer = Arc::new(
+        ResultWriter::stdout(opts.scripts_output)
+            .accessible(opts.accessible)
+            .ignore_errors(opts.scripts_ignore_errors),
+    );
+    let status_reporter = Arc::new(StatusReporter {
+        color,
+        skipped: AtomicUsize::new(0),
+    });
+    // One runner for every engine, so the --scripts-rate limit holds over the whole run.
+    let mut script_runner: Arc<dyn CommandRunner> = Arc::new(SubprocessRunner {
+        max_output: opts.script_max_output,
+        stream: opts.scripts_stream,
+        deadline: scripts_deadline,
+        kill_grace: opts
+            .scripts_kill_grace
+            .map_or(DEFAULT_KILL_GRACE, Duration::from_secs),
+    });
+    if let Some(rate) = opts.scripts_rate {
+        script_runner = Arc::new(RateLimitedRunner::new(script_runner, rate));
+    }
+    let mut engine = ScriptEngine::new(Vec::new())
+        .workers(opts.scripts_concurrency)
+        .context(run_context.clone())
+        .observer(Arc::clone(&status_reporter) as Arc<dyn ScriptObserver>)
+        .output(Arc::clone(&result_writer))
+        .runner(Arc::clone(&script_runner))
+        .template_engine(Arc::clone(&template_engine));
+    if let Some(budget) = opts.scripts_budget {
+        engine = engine.budget(budget);
+    }
+    for result in engine.hosts_stream(host_jobs, opts.scripts_host_concurrency) {
+        if report_script_result(
+            &result,
+            &opts,
+            combined_output.as_ref(),
+            sqlite_output.as_ref(),
+        ) {
+            written_files += 1;
+        }
+        script_results.push(result.redacted());
+    }
+
+    // The run scoped scripts go once, after the host ones, with every scripted ip and its ports.
+    let targets = RunTargets::new(&ips, &ports_per_ip, script_hosts.as_ref());
+    if !run_scoped.is_empty()
+        && !targets.hosts.is_empty()
+        && !interrupted()
+        && !opts.greppable
+        && opts.scripts != ScriptsRequired::None
+    {
+        let all_ports = targets.all_ports();
+        let run_scripts: Vec<ScriptFile> = run_scoped
+            .into_iter()
+            .map(|script_f| apply_opts(script_f, &opts, "all ips"))
+            .collect();
+        let mut engine = ScriptEngine::new(run_scripts)
+            .workers(opts.scripts_concurrency)
+            .context(run_context.clone())
+            .observer(Arc::clone(&status_reporter) as Arc<dyn ScriptObserver>)
+            .output(Arc::clone(&result_writer))
+            .runner(Arc::clone(&script_runner))
+            .template_engine(Arc::clone(&template_engine))
+            .targets(targets);
+        if let Some(budget) = opts.scripts_budget {
+            engine = engine.budget(budget);
+        }
+        for result in engine.results_stream(IpAddr::V4(Ipv4Addr::UNSPECIFIED), &all_ports) {
+            if report_script_result(
+                &result,
+                &opts,
+                combined_output.as_ref(),
+                sqlite_output.as_ref(),
+            ) {
+                written_files += 1;
             }
+            script_results.push(result.redacted());
         }
     }
 
+    skipped_scripts += status_reporter.skipped.load(Ordering::SeqCst);
+
+    if interrupted() {
+        warning!(
+            "Interrupted, the running scripts were stopped",
+            opts.greppable,
+            opts.accessible
+        );
+    } else if run_context.past_deadline() {
+        warning!(
+            format!(
+                "The scripts deadline passed, {} script(s) completed, the running ones were stopped \
+                 and the rest not started",
+                script_results
+                    .iter()
+                    .filter(|result| result.error_kind.as_deref() != Some("deadline"))
+                    .count()
+            ),
+            opts.greppable,
+            opts.accessible
+        );
+    }
+    if !interrupted()
+        && run_hooks
+        && report_hook(
+            &script_hooks,
+            script_hooks.run_post(&ips),
+            opts.greppable,
+            opts.accessible,
+        )
+    {
+        drop(script_bundle);
+        std::process::exit(1);
+    }
+
+    if let Some(output_dir) = &opts.scripts_output_dir {
+        detail!(
+            format!(
+                "Wrote {} script output file(s) to {}",
+                written_files,
+                output_dir.display()
+            ),
+            opts.greppable,
+            opts.accessible
+        );
+    }
+
+    let script_report = ScriptReport::new(
+        &run_context,
+        (
+            scripts_origin.scripts_dir.clone(),
+            scripts_origin.tags.clone(),
+        ),
+        &scripts_to_run,
+        &script_results,
+        skipped_scripts,
+    );
+    if run_hooks {
+        script_stats.record_results(&script_results, skipped_scripts);
+        eprintln!("{}", format_stats(&script_stats));
+    }
+    if opts.scripts_output == ScriptsOutput::Json && run_hooks {
+        if let Err(e) = result_writer.write_report(&script_report) {
+            warning!(
+                format!("Failed to write the scripts report: {}", e),
+                opts.greppable,
+                opts.accessible
+            );
+        }
+    }
+    if let Some(manifest_path) = &opts.scripts_manifest {
+        if let Err(e) = write_manifest(&script_report, manifest_path) {
+            warning!(
+                format!("Failed to write the scripts manifest: {}", e),
+                opts.greppable,
+                opts.accessible
+            );
+        }
+    }
+    if let Some(junit_path) = &opts.scripts_junit {
+        if let Err(e) = write_junit(&script_results, &run_context.timestamp, junit_path) {
+            warning!(
+                format!("Failed to write the JUnit report: {}", e),
+                opts.greppable,
+                opts.accessible
+            );
+        }
+    }
+
+    if opts.scripts_timing {
+        detail!(
+            format_timings(&script_results),
+            opts.greppable,
+            opts.accessible
+        );
+    }
+
     // To use the runtime benchmark, run the process as: RUST_LOG=info ./rustscan
     script_bench.end();
     benchmarks.push(script_bench);
@@ -204,6 +753,168 @@ fn main() {
     benchmarks.push(rustscan_bench);
     debug!("Benchmarks raw {:?}", benchmarks);
     info!("{}", benchmarks.summary());
+
+    let exit_code = if interrupted() {
+        130
+    } else {
+        scripts_exit_code(&script_results, opts.scripts_strict)
+    };
+    // process::exit skips the destructors, remove the unpacked bundle first.
+    drop(script_bundle);
+    if exit_code != 0 {
+        std::process::exit(exit_code);
+    }
+}
+
+/// Fills in the commandline defaults of a script: the extra arguments and the retries, ports_separator and shell
+/// for the scripts without their own. target names what the script runs against in the output.
+fn apply_opts(mut script_f: ScriptFile, opts: &Opts, target: &str) -> ScriptFile {
+    // This part allows us to add commandline arguments to the Script call_format, appending them to the end of the command.
+    if !opts.command.is_empty() {
+        let user_extra_args = &opts.command.join(" ");
+        debug!("Extra args vec {:?}", user_extra_args);
+        if let Some(mut call_f) = script_f.call_format {
+            call_f.push_args(user_extra_args);
+            output!(
+                format!("Running script {:?} on {}\nDepending on the complexity of the script, results may take some time to appear.", call_f.to_string(), target),
+                opts.greppable,
+                opts.accessible
+            );
+            debug!("Call format {}", call_f);
+            script_f.call_format = Some(call_f);
+        }
+    }
+
+    if script_f.retries.is_none() {
+        script_f.retries = Some(opts.script_retries);
+    }
+    if script_f.ports_separator.is_none() {
+        script_f
+            .ports_separator
+            .clone_from(&opts.scripts_ports_separator);
+    }
+    if script_f.shell.is_none() {
+        script_f.shell = Some(opts.scripts_shell);
+    }
+    if script_f.sort_ports.is_none() {
+        script_f.sort_ports = Some(!opts.scripts_scan_port_order);
+    }
+    if script_f.output_filter.is_none() {
+        script_f
+            .output_filter
+            .clone_from(&opts.scripts_output_filter);
+    }
+    script_f
+}
+
+/// Writes a ScriptResult into the files configured, the ResultWriter of the engine printed it already.
+/// Returns whether its output was written into a file of the output dir.
+fn report_script_result(
+    result: &ScriptResult,
+    opts: &Opts,
+    combined_output: Option<&CombinedOutput>,
+    sqlite_output: Option<&SqliteOutput>,
+) -> bool {
+    // The output of a sensitive script only goes to its --scripts-output-dir file in full.
+    if let Some(combined_output) = combined_output {
+        if let Err(e) = combined_output.append(&result.redacted()) {
+            warning!(
+                format!("Failed to append script output: {}", e),
+                opts.greppable,
+                opts.accessible
+            );
+        }
+    }
+    if let Some(sqlite_output) = sqlite_output {
+        if let Err(e) = sqlite_output.insert(&result.redacted()) {
+            warning!(
+                format!("Failed to insert script result: {}", e),
+                opts.greppable,
+                opts.accessible
+            );
+        }
+    }
+    if let (None, Some(output_dir)) = (&result.error, &opts.scripts_output_dir) {
+        match write_result_to_dir(
+            result,
+            output_dir,
+            opts.scripts_output_overwrite,
+            opts.scripts_output_compress,
+        ) {
+            Ok(_) => return true,
+            Err(e) => {
+                warning!(
+                    format!("Failed to write script output: {}", e),
+                    opts.greppable,
+                    opts.accessible
+                );
+            }
+        }
+    }
+    false
+}
+
+/// Prints the status line of every script the engine ran or skipped, and counts the skipped ones.
+#[derive(Debug)]
+struct StatusReporter {
+    color: bool,
+    skipped: AtomicUsize,
+}
+
+impl ScriptObserver for StatusReporter {
+    fn on_script_done(&self, result: &ScriptResult) {
+        eprintln!(
+            "{}",
+            format_status(
+                ScriptStatus::of(result),
+                &format!(
+                    "{} on {} ({}ms)",
+                    result.script_name,
+                    result.ip,
+                    result.duration.as_millis()
+                ),
+                self.color
+            )
+        );
+    }
+
+    fn on_script_skipped(&self, script: &ScriptFile, ip: IpAddr, reason: &str) {
+        self.skipped.fetch_add(1, Ordering::SeqCst);
+        eprintln!(
+            "{}",
+            format_status(
+                ScriptStatus::Skip,
+                &format!("{} on {}, {}", script_label(script), ip, reason),
+                self.color
+            )
+        );
+    }
+}
+
+/// Name of the script for the status lines, the file name without extension or "default".
+fn script_label(script_f: &ScriptFile) -> String {
+    script_f.name()
+}
+
+/// Prints the outcome of a script hook. A failing hook only stops RustScan if the hooks are fatal,
+/// true tells the caller to exit.
+fn report_hook(
+    script_hooks: &ScriptHooks,
+    outcome: anyhow::Result<String>,
+    greppable: bool,
+    accessible: bool,
+) -> bool {
+    match outcome {
+        Ok(output) if output.is_empty() => false,
+        Ok(output) => {
+            detail!(output, greppable, accessible);
+            false
+        }
+        Err(e) => {
+            warning!(format!("Script hook failed: {e}"), greppable, accessible);
+            script_hooks.fatal
+        }
+    }
 }
 
 /// Prints the opening title of RustScan
@@ -234,9 +945,10 @@ The Modern Day Port Scanner."#;
 }
 
 /// Goes through all possible IP inputs (files or via argparsing)
-/// Parses the string(s) into IPs
-fn parse_addresses(input: &Opts) -> Vec<IpAddr> {
+/// Parses the string(s) into IPs, along with the hostnames they were resolved from
+fn parse_addresses(input: &Opts) -> (Vec<IpAddr>, HashMap<IpAddr, String>) {
     let mut ips: Vec<IpAddr> = Vec::new();
+    let mut original_targets: HashMap<IpAddr, String> = HashMap::new();
     let mut unresolved_addresses: Vec<&str> = Vec::new();
     let backup_resolver =
         Resolver::new(ResolverConfig::cloudflare_tls(), ResolverOpts::default()).unwrap();
@@ -244,6 +956,7 @@ fn parse_addresses(input: &Opts) -> Vec<IpAddr> {
     for address in &input.addresses {
         let parsed_ips = parse_address(address, &backup_resolver);
         if !parsed_ips.is_empty() {
+            record_original_target(address, &parsed_ips, &mut original_targets);
             ips.extend(parsed_ips);
         } else {
             unresolved_addresses.push(address);
@@ -264,7 +977,7 @@ fn parse_addresses(input: &Opts) -> Vec<IpAddr> {
             continue;
         }
 
-        if let Ok(x) = read_ips_from_file(file_path, &backup_resolver) {
+        if let Ok(x) = read_ips_from_file(file_path, &backup_resolver, &mut original_targets) {
             ips.extend(x);
         } else {
             warning!(
@@ -275,7 +988,24 @@ fn parse_addresses(input: &Opts) -> Vec<IpAddr> {
         }
     }
 
-    ips
+    (ips, original_targets)
+}
+
+/// Remembers the hostname the ips were resolved from for the {{target}} script placeholder,
+/// the first one when several resolve to the same ip. The ips and CIDRs given as is are left out.
+fn record_original_target(
+    address: &str,
+    ips: &[IpAddr],
+    original_targets: &mut HashMap<IpAddr, String>,
+) {
+    if IpCidr::from_str(address).is_ok() {
+        return;
+    }
+    for ip in ips {
+        original_targets
+            .entry(*ip)
+            .or_insert_with(|| address.to_string());
+    }
 }
 
 /// Given a string, parse it as an host, IP address, or CIDR.
@@ -314,6 +1044,7 @@ fn resolve_ips_from_host(source: &str, backup_resolver: &Resolver) -> Vec<IpAddr
 fn read_ips_from_file(
     ips: &std::path::Path,
     backup_resolver: &Resolver,
+    original_targets: &mut HashMap<IpAddr, String>,
 ) -> Result<Vec<std::net::IpAddr>, std::io::Error> {
     let file = File::open(ips)?;
     let reader = BufReader::new(file);
@@ -322,7 +1053,9 @@ fn read_ips_from_file(
 
     for address_line in reader.lines() {
         if let Ok(address) = address_line {
-            ips.extend(parse_address(&address, backup_resolver));
+            let parsed_ips = parse_address(&address, backup_resolver);
+            record_original_target(&address, &parsed_ips, original_targets);
+            ips.extend(parsed_ips);
         } else {
             debug!("Line in file is not valid");
         }
@@ -395,8 +1128,12 @@ fn infer_batch_size(opts: &Opts, ulimit: RawRlim) -> u16 {
 
 #[cfg(test)]
 mod tests {
-    use crate::{adjust_ulimit_size, infer_batch_size, parse_addresses, print_opening, Opts};
-    use std::net::Ipv4Addr;
+    use crate::{
+        adjust_ulimit_size, infer_batch_size, parse_addresses, print_opening,
+        record_original_target, Opts,
+    };
+    use std::collections::HashMap;
+    use std::net::{IpAddr, Ipv4Addr};
 
     #[test]
     fn batch_size_lowered() {
@@ -454,11 +1191,27 @@ mod tests {
         assert!(batch_size == opts.batch_size);
     }
 
+    #[test]
+    fn original_targets_are_hostnames() {
+        let ip: IpAddr = "127.0.0.1".parse().unwrap();
+        let mut original_targets = HashMap::new();
+        record_original_target("127.0.0.1", &[ip], &mut original_targets);
+        record_original_target("127.0.0.0/30", &[ip], &mut original_targets);
+        assert!(original_targets.is_empty());
+
+        record_original_target("localhost", &[ip], &mut original_targets);
+        record_original_target("other.localhost", &[ip], &mut original_targets);
+        assert_eq!(
+            original_targets.get(&ip).map(String::as_str),
+            Some("localhost")
+        );
+    }
+
     #[test]
     fn parse_correct_addresses() {
         let mut opts = Opts::default();
         opts.addresses = vec!["127.0.0.1".to_owned(), "192.168.0.0/30".to_owned()];
-        let ips = parse_addresses(&opts);
+        let (ips, _) = parse_addresses(&opts);
 
         assert_eq!(
             ips,
@@ -476,7 +1229,7 @@ mod tests {
     fn parse_correct_host_addresses() {
         let mut opts = Opts::default();
         opts.addresses = vec!["google.com".to_owned()];
-        let ips = parse_addresses(&opts);
+        let (ips, _) = parse_addresses(&opts);
 
         assert_eq!(ips.len(), 1);
     }
@@ -485,7 +1238,7 @@ mod tests {
     fn parse_correct_and_incorrect_addresses() {
         let mut opts = Opts::default();
         opts.addresses = vec!["127.0.0.1".to_owned(), "im_wrong".to_owned()];
-        let ips = parse_addresses(&opts);
+        let (ips, _) = parse_addresses(&opts);
 
         assert_eq!(ips, [Ipv4Addr::new(127, 0, 0, 1),]);
     }
@@ -494,7 +1247,7 @@ mod tests {
     fn parse_incorrect_addresses() {
         let mut opts = Opts::default();
         opts.addresses = vec!["im_wrong".to_owned(), "300.10.1.1".to_owned()];
-        let ips = parse_addresses(&opts);
+        let (ips, _) = parse_addresses(&opts);
 
         assert_eq!(ips.is_empty(), true);
     }
@@ -503,7 +1256,7 @@ mod tests {
         // Host file contains IP, Hosts, incorrect IPs, incorrect hosts
         let mut opts = Opts::default();
         opts.addresses = vec!["fixtures/hosts.txt".to_owned()];
-        let ips = parse_addresses(&opts);
+        let (ips, _) = parse_addresses(&opts);
         assert_eq!(ips.len(), 3);
     }
 
@@ -512,7 +1265,7 @@ mod tests {
         // Host file contains IP, Hosts, incorrect IPs, incorrect hosts
         let mut opts = Opts::default();
         opts.addresses = vec!["fixtures/empty_hosts.txt".to_owned()];
-        let ips = parse_addresses(&opts);
+        let (ips, _) = parse_addresses(&opts);
         assert_eq!(ips.len(), 0);
     }
 
@@ -521,7 +1274,7 @@ mod tests {
         // Host file contains IP, Hosts, incorrect IPs, incorrect hosts
         let mut opts = Opts::default();
         opts.addresses = vec!["fixtures/naughty_string.txt".to_owned()];
-        let ips = parse_addresses(&opts);
+        let (ips, _) = parse_addresses(&opts);
         assert_eq!(ips.len(), 0);
     }
 }
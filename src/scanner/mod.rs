@@ -8,6 +8,7 @@ use async_std::net::TcpStream;
 use async_std::prelude::*;
 use colored::Colorize;
 use futures::stream::FuturesUnordered;
+use serde_derive::{Deserialize, Serialize};
 use std::{
     collections::HashSet,
     net::{IpAddr, Shutdown, SocketAddr},
@@ -15,6 +16,26 @@ use std::{
     time::Duration,
 };
 
+/// What the scan found out about a port. A refused connection makes it closed,
+/// any other failure, like a timeout, filtered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PortState {
+    Open,
+    Closed,
+    Filtered,
+}
+
+impl PortState {
+    fn of_error(error: &io::Error) -> Self {
+        if error.kind() == io::ErrorKind::ConnectionRefused {
+            PortState::Closed
+        } else {
+            PortState::Filtered
+        }
+    }
+}
+
 /// The class for the scanner
 /// IP is data type IpAddr and is the IP address
 /// start & end is where the port scan starts and ends
@@ -54,13 +75,32 @@ impl Scanner {
         }
     }
 
+    /// How many ip-port pairs a run scans.
+    pub fn sockets_count(&self) -> usize {
+        self.ips.len() * self.port_strategy.order().len()
+    }
+
     /// Runs scan_range with chunk sizes
     /// If you want to run RustScan normally, this is the entry point used
     /// Returns all open ports as Vec<u16>
     pub async fn run(&self) -> Vec<SocketAddr> {
+        self.scan(false)
+            .await
+            .into_iter()
+            .map(|(socket, _)| socket)
+            .collect()
+    }
+
+    /// Like run, but returns every scanned socket with its state, the closed and filtered ones too.
+    pub async fn run_with_states(&self) -> Vec<(SocketAddr, PortState)> {
+        self.scan(true).await
+    }
+
+    // The open sockets, and with keep_all the ones that are not open too.
+    async fn scan(&self, keep_all: bool) -> Vec<(SocketAddr, PortState)> {
         let ports: Vec<u16> = self.port_strategy.order();
         let mut socket_iterator: SocketIterator = SocketIterator::new(&self.ips, &ports);
-        let mut open_sockets: Vec<SocketAddr> = Vec::new();
+        let mut scanned_sockets: Vec<(SocketAddr, PortState)> = Vec::new();
         let mut ftrs = FuturesUnordered::new();
         let mut errors: HashSet<String> = HashSet::with_capacity(self.ips.len() * 1000);
 
@@ -84,8 +124,11 @@ impl Scanner {
             }
 
             match result {
-                Ok(socket) => open_sockets.push(socket),
-                Err(e) => {
+                Ok(socket) => scanned_sockets.push((socket, PortState::Open)),
+                Err((socket, e)) => {
+                    if keep_all {
+                        scanned_sockets.push((socket, PortState::of_error(&e)));
+                    }
                     let error_string = e.to_string();
                     if errors.len() < self.ips.len() * 1000 {
                         errors.insert(error_string);
@@ -94,15 +137,15 @@ impl Scanner {
             }
         }
         debug!("Typical socket connection errors {:?}", errors);
-        debug!("Open Sockets found: {:?}", &open_sockets);
-        open_sockets
+        debug!("Scanned Sockets found: {:?}", &scanned_sockets);
+        scanned_sockets
     }
 
     /// Given a socket, scan it self.tries times.
     /// Turns the address into a SocketAddr
     /// Deals with the <result> type
     /// If it experiences error ErrorKind::Other then too many files are open and it Panics!
-    /// Else any other error, it returns the error in Result as a string
+    /// Else any other error, it returns the error in Result as a string, with the socket and the kind of the last try
     /// If no errors occur, it returns the port number in Result to signify the port is open.
     /// This function mainly deals with the logic of Results handling.
     /// # Example
@@ -110,7 +153,7 @@ impl Scanner {
     ///     self.scan_socket(socket)
     ///
     /// Note: `self` must contain `self.ip`.
-    async fn scan_socket(&self, socket: SocketAddr) -> io::Result<SocketAddr> {
+    async fn scan_socket(&self, socket: SocketAddr) -> Result<SocketAddr, (SocketAddr, io::Error)> {
         let tries = self.tries.get();
 
         for nr_try in 1..=tries {
@@ -144,7 +187,7 @@ impl Scanner {
                     if nr_try == tries {
                         error_string.push(' ');
                         error_string.push_str(&socket.ip().to_string());
-                        return Err(io::Error::new(io::ErrorKind::Other, error_string));
+                        return Err((socket, io::Error::new(e.kind(), error_string)));
                     }
                 }
             };
@@ -287,4 +330,20 @@ mod tests {
         block_on(scanner.run());
         assert_eq!(1, 1);
     }
+    #[test]
+    fn port_state_of_error() {
+        let error = |kind| io::Error::new(kind, "127.0.0.1");
+        assert_eq!(
+            PortState::of_error(&error(io::ErrorKind::ConnectionRefused)),
+            PortState::Closed
+        );
+        assert_eq!(
+            PortState::of_error(&error(io::ErrorKind::TimedOut)),
+            PortState::Filtered
+        );
+        assert_eq!(
+            PortState::of_error(&error(io::ErrorKind::Other)),
+            PortState::Filtered
+        );
+    }
 }
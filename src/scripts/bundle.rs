@@ -0,0 +1,139 @@
+//! Scripts and their config shipped together as a single zip archive, used with --scripts-bundle.
+
+use anyhow::{Context, Result};
+use std::fs::{self, File};
+use std::io::{self, Read, Seek};
+use std::path::{Path, PathBuf};
+use tempfile::TempDir;
+use zip::ZipArchive;
+
+// Name of the script config inside a bundle, every other file is a script.
+static BUNDLE_CONFIG: &str = "rustscan_scripts.toml";
+
+/// A scripts bundle unpacked into a temporary directory laid out like the user's home dir,
+/// a .rustscan_scripts.toml config next to a .rustscan_scripts folder with the scripts.
+/// The scripts need a real path to run, so the directory lives as long as the bundle.
+#[derive(Debug)]
+pub struct ScriptBundle {
+    dir: TempDir,
+}
+
+impl ScriptBundle {
+    /// Unpacks the zip archive at path.
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = File::open(path)
+            .with_context(|| format!("Failed to open scripts bundle {}", path.display()))?;
+        Self::from_reader(file)
+            .with_context(|| format!("Failed to read scripts bundle {}", path.display()))
+    }
+
+    /// Unpacks a zip archive. Files in folders of the archive end up flattened, by their file name.
+    pub fn from_reader(reader: impl Read + Seek) -> Result<Self> {
+        let mut archive = ZipArchive::new(reader)?;
+        let dir = tempfile::Builder::new()
+            .prefix("rustscan-bundle")
+            .tempdir()?;
+        let scripts_dir = dir.path().join(".rustscan_scripts");
+        fs::create_dir(&scripts_dir)?;
+
+        for index in 0..archive.len() {
+            let mut entry = archive.by_index(index)?;
+            if entry.is_dir() {
+                continue;
+            }
+            // enclosed_name rejects the names escaping the archive, like ../x.
+            let name = entry
+                .enclosed_name()
+                .and_then(|name| name.file_name().map(PathBuf::from));
+            if let Some(name) = name {
+                let target = if name == Path::new(BUNDLE_CONFIG) {
+                    dir.path().join(".rustscan_scripts.toml")
+                } else {
+                    scripts_dir.join(name)
+                };
+                io::copy(&mut entry, &mut File::create(&target)?)?;
+                #[cfg(unix)]
+                if let Some(mode) = entry.unix_mode() {
+                    use std::os::unix::fs::PermissionsExt;
+                    fs::set_permissions(&target, fs::Permissions::from_mode(mode))?;
+                }
+                debug!(
+                    "Unpacked bundle entry {} to {}",
+                    entry.name(),
+                    target.display()
+                );
+            } else {
+                debug!("Skipping bundle entry {}", entry.name());
+            }
+        }
+        Ok(Self { dir })
+    }
+
+    /// The directory taking the place of the home dir when looking for the config and the scripts.
+    pub fn base_dir(&self) -> &Path {
+        self.dir.path()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ScriptBundle;
+    use crate::scripts::{config_script_paths, parse_scripts, ScriptConfig};
+    use std::io::{Cursor, Write};
+    use zip::write::SimpleFileOptions;
+    use zip::ZipWriter;
+
+    fn zip_of(files: &[(&str, &str)]) -> Cursor<Vec<u8>> {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, content) in files {
+            writer
+                .start_file(*name, SimpleFileOptions::default())
+                .unwrap();
+            writer.write_all(content.as_bytes()).unwrap();
+        }
+        let mut archive = writer.finish().unwrap();
+        archive.set_position(0);
+        archive
+    }
+
+    #[test]
+    fn read_scripts_from_bundle() {
+        let archive = zip_of(&[
+            ("rustscan_scripts.toml", "tags = [\"web\"]\n"),
+            (
+                "scripts/web.sh",
+                "#!/bin/bash\n# tags = [\"web\"]\n# call_format = \"curl {{ip}}\"\n",
+            ),
+            (
+                "ssh.sh",
+                "#!/bin/bash\n# tags = [\"ssh\"]\n# call_format = \"ssh {{ip}}\"\n",
+            ),
+        ]);
+        let bundle = ScriptBundle::from_reader(archive).unwrap();
+
+        let config =
+            ScriptConfig::read_config_from(&bundle.base_dir().join(".rustscan_scripts.toml"))
+                .unwrap();
+        assert_eq!(config.tags, Some(vec!["web".to_string()]));
+
+        let parsed = parse_scripts(
+            config_script_paths(&bundle.base_dir().join(".rustscan_scripts"), &config).unwrap(),
+        );
+        assert_eq!(parsed.len(), 2);
+        let web = parsed.iter().find(|script| {
+            script
+                .path
+                .as_ref()
+                .is_some_and(|path| path.ends_with(".rustscan_scripts/web.sh"))
+        });
+        assert_eq!(
+            web.and_then(|script| script.call_format.clone()),
+            Some("curl {{ip}}".into())
+        );
+    }
+
+    #[test]
+    fn invalid_bundle_fails() {
+        assert!(ScriptBundle::from_reader(Cursor::new(b"not a zip".to_vec())).is_err());
+    }
+}
@@ -0,0 +1,70 @@
+//! Why a script file did not parse, or parsed with something to look at, for linters and editors.
+
+use std::fmt;
+
+/// How bad a ParseDiagnostic is, an error means the script is not usable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// A problem found while parsing a script file, see ScriptFile::parse_with_diagnostics.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseDiagnostic {
+    pub severity: Severity,
+    pub message: String,
+    /// The line of the file the problem is on, starting at 1, approximate and None when it is not known.
+    pub line: Option<usize>,
+}
+
+impl ParseDiagnostic {
+    pub fn error(message: String, line: Option<usize>) -> Self {
+        ParseDiagnostic {
+            severity: Severity::Error,
+            message,
+            line,
+        }
+    }
+
+    pub fn warning(message: String, line: Option<usize>) -> Self {
+        ParseDiagnostic {
+            severity: Severity::Warning,
+            message,
+            line,
+        }
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+}
+
+impl fmt::Display for ParseDiagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        match self.line {
+            Some(line) => write!(f, "{severity} at line {line}: {}", self.message),
+            None => write!(f, "{severity}: {}", self.message),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ParseDiagnostic;
+
+    #[test]
+    fn display_with_and_without_line() {
+        assert_eq!(
+            ParseDiagnostic::error("bad tags".to_string(), Some(3)).to_string(),
+            "error at line 3: bad tags"
+        );
+        let warning = ParseDiagnostic::warning("no call_format".to_string(), None);
+        assert_eq!(warning.to_string(), "warning: no call_format");
+        assert!(!warning.is_error());
+    }
+}
@@ -0,0 +1,1088 @@
+//! Runs a batch of scripts against a host on a bounded pool of worker threads.
+
+use super::{
+    interrupted, load_scripts, CommandRunner, PlaceholderTemplate, PortSet, ResultWriter,
+    RunContext, RunTargets, Script, ScriptConfig, ScriptFile, ScriptResult, ScriptsLocation,
+    SubprocessRunner, TemplateEngine,
+};
+use anyhow::{anyhow, Result};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver};
+use std::sync::{Arc, Condvar, Mutex, PoisonError, RwLock};
+use std::thread;
+use std::vec;
+use trust_dns_resolver::Resolver;
+
+/// Gets told about the lifecycle of every script the ScriptEngine runs, for example to drive a progress bar.
+/// The methods are called from the worker threads, with more than one worker possibly at the same time,
+/// hence the Send + Sync bound. Every method does nothing by default.
+pub trait ScriptObserver: fmt::Debug + Send + Sync {
+    /// Right before the script starts against the ip.
+    fn on_script_start(&self, _script: &ScriptFile, _ip: IpAddr) {}
+
+    /// After every script, whether it succeeded or failed.
+    fn on_script_done(&self, _result: &ScriptResult) {}
+
+    /// After a failed script, before on_script_done.
+    fn on_script_error(&self, _script: &ScriptFile, _err: &anyhow::Error) {}
+
+    /// Instead of on_script_start, when the script is not run against the ip, as its depends_on did not succeed
+    /// or its os_match or service_match do not match.
+    fn on_script_skipped(&self, _script: &ScriptFile, _ip: IpAddr, _reason: &str) {}
+}
+
+/// The ScriptObserver ignoring every event, used by default.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoopObserver;
+
+impl ScriptObserver for NoopObserver {}
+
+/// Looks up the hostname of an ip for the {{hostname}} placeholder.
+pub trait HostResolver: fmt::Debug + Send + Sync {
+    /// The hostname the ip resolves back to, None if it does not.
+    fn reverse(&self, ip: IpAddr) -> Option<String>;
+}
+
+/// Reverse DNS through the resolvers of the system configuration.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemResolver;
+
+impl HostResolver for SystemResolver {
+    fn reverse(&self, ip: IpAddr) -> Option<String> {
+        let resolver = Resolver::from_system_conf().ok()?;
+        let lookup = resolver.reverse_lookup(ip).ok()?;
+        let name = lookup.iter().next()?.to_utf8();
+        Some(name.trim_end_matches('.').to_string())
+    }
+}
+
+/// The hostnames looked up during a run, so every ip is only resolved once, whatever the number of scripts.
+/// The lock is held during a lookup, a worker asking for the same ip waits for the first one instead of resolving it again.
+#[derive(Debug)]
+pub struct HostCache {
+    resolver: Arc<dyn HostResolver>,
+    hostnames: Mutex<HashMap<IpAddr, Option<String>>>,
+}
+
+impl HostCache {
+    pub fn new(resolver: Arc<dyn HostResolver>) -> Self {
+        Self {
+            resolver,
+            hostnames: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The hostname of the ip, resolved on the first call.
+    pub fn hostname(&self, ip: IpAddr) -> Option<String> {
+        match self.hostnames.lock() {
+            Ok(mut hostnames) => hostnames
+                .entry(ip)
+                .or_insert_with(|| self.resolver.reverse(ip))
+                .clone(),
+            Err(_) => self.resolver.reverse(ip),
+        }
+    }
+}
+
+// Whether the scripts of a host that finished so far succeeded, by name, for the depends_on of the others.
+#[derive(Debug, Default)]
+struct Completions {
+    succeeded: Mutex<HashMap<String, bool>>,
+    finished: Condvar,
+}
+
+impl Completions {
+    fn finish(&self, name: String, succeeded: bool) {
+        if let Ok(mut completions) = self.succeeded.lock() {
+            completions.insert(name, succeeded);
+        }
+        self.finished.notify_all();
+    }
+
+    // Blocks until the script finished, the scripts are ordered so it was taken off the queue already.
+    fn wait_for(&self, name: &str) -> bool {
+        let Ok(mut completions) = self.succeeded.lock() else {
+            return false;
+        };
+        loop {
+            if let Some(succeeded) = completions.get(name) {
+                return *succeeded;
+            }
+            completions = match self.finished.wait(completions) {
+                Ok(completions) => completions,
+                Err(_) => return false,
+            };
+        }
+    }
+}
+
+// The outcome of every run materialized in the phase, shared by the streams of hosts_stream, so the dependents of
+// a script left out as a duplicate get the outcome of the run it repeats. None while that run did not finish.
+#[derive(Debug, Default)]
+struct RunOutcomes {
+    outcomes: Mutex<HashMap<RunKey, Option<bool>>>,
+    finished: Condvar,
+}
+
+impl RunOutcomes {
+    fn finish(&self, key: &RunKey, succeeded: bool) {
+        if let Ok(mut outcomes) = self.outcomes.lock() {
+            outcomes.insert(key.clone(), Some(succeeded));
+        }
+        self.finished.notify_all();
+    }
+
+    // Blocks until the run finished, every run materialized is finished by its stream, even one that stopped early.
+    fn wait_for(&self, key: &RunKey) -> bool {
+        let Ok(mut outcomes) = self.outcomes.lock() else {
+            return false;
+        };
+        loop {
+            match outcomes.get(key) {
+                Some(Some(succeeded)) => return *succeeded,
+                Some(None) => {}
+                None => return false,
+            }
+            outcomes = match self.finished.wait(outcomes) {
+                Ok(outcomes) => outcomes,
+                Err(_) => return false,
+            };
+        }
+    }
+}
+
+// The summed cost of the scripts running, shared by every stream of an engine and its clones.
+#[derive(Debug)]
+struct Budget {
+    limit: u32,
+    spent: Mutex<u32>,
+    freed: Condvar,
+}
+
+impl Budget {
+    // Blocks until the cost fits into what is left, a script costing more than the whole budget
+    // waits until nothing else runs. The cost is given back when the Spent is dropped.
+    fn spend(budget: &Arc<Budget>, cost: u32) -> Spent {
+        let cost = cost.min(budget.limit);
+        let mut spent = budget.spent.lock().unwrap_or_else(PoisonError::into_inner);
+        while *spent + cost > budget.limit {
+            spent = budget
+                .freed
+                .wait(spent)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+        *spent += cost;
+        Spent {
+            budget: Arc::clone(budget),
+            cost,
+        }
+    }
+}
+
+// The cost of a running script, taken out of its Budget.
+struct Spent {
+    budget: Arc<Budget>,
+    cost: u32,
+}
+
+impl Drop for Spent {
+    fn drop(&mut self) {
+        let mut spent = self
+            .budget
+            .spent
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        *spent -= self.cost;
+        self.budget.freed.notify_all();
+    }
+}
+
+// What makes two runs of a script run the same commands: the script file, the host, the ports it gets
+// and its call formats.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct RunKey {
+    path: Option<PathBuf>,
+    ip: IpAddr,
+    ports: Vec<u16>,
+    trigger_port: Option<String>,
+    call_formats: Vec<String>,
+}
+
+impl RunKey {
+    fn of(script: &Script) -> Self {
+        let mut ports = script.open_ports.clone();
+        ports.sort_unstable();
+        Self {
+            path: script
+                .path
+                .as_ref()
+                .map(|path| fs::canonicalize(path).unwrap_or_else(|_| path.clone())),
+            ip: script.ip,
+            ports,
+            trigger_port: script.trigger_port.clone(),
+            call_formats: script.final_call_formats().unwrap_or_default(),
+        }
+    }
+}
+
+/// The scripts to run against a host, with its scanned ports and the target it was scanned for,
+/// None when the ip was given as is.
+#[derive(Debug, Clone)]
+pub struct HostJob {
+    pub ip: IpAddr,
+    pub ports: PortSet,
+    pub scripts: Vec<ScriptFile>,
+    pub original_target: Option<String>,
+}
+
+/// Where ScriptEngine::reload finds the scripts. Without a config the config file of the location is read
+/// again on every reload.
+#[derive(Debug, Clone)]
+pub struct ScriptSource {
+    pub location: ScriptsLocation,
+    pub config: Option<ScriptConfig>,
+}
+
+/// Runs every ScriptFile against a host and hands out the ScriptResults as they complete.
+/// The clones of an engine share its scripts, a reload of one is seen by all of them.
+#[derive(Debug, Clone)]
+pub struct ScriptEngine {
+    scripts: Arc<RwLock<Vec<ScriptFile>>>,
+    source: Option<ScriptSource>,
+    workers: usize,
+    context: RunContext,
+    runner: Arc<dyn CommandRunner>,
+    template_engine: Arc<dyn TemplateEngine>,
+    observer: Arc<dyn ScriptObserver>,
+    hosts: Arc<HostCache>,
+    targets: Option<Arc<RunTargets>>,
+    // The target of the host as given on the commandline, set per host by hosts_stream.
+    original_target: Option<String>,
+    output: Option<Arc<ResultWriter>>,
+    budget: Option<Arc<Budget>>,
+}
+
+impl ScriptEngine {
+    /// Builds the engine with a single worker, running the scripts one after the other.
+    pub fn new(scripts: Vec<ScriptFile>) -> Self {
+        Self {
+            scripts: Arc::new(RwLock::new(scripts)),
+            source: None,
+            workers: 1,
+            context: RunContext::default(),
+            runner: Arc::new(SubprocessRunner::default()),
+            template_engine: Arc::new(PlaceholderTemplate),
+            observer: Arc::new(NoopObserver),
+            hosts: Arc::new(HostCache::new(Arc::new(SystemResolver))),
+            targets: None,
+            original_target: None,
+            output: None,
+            budget: None,
+        }
+    }
+
+    /// Sets how many scripts may run at the same time, 0 is treated as 1.
+    pub fn workers(mut self, workers: usize) -> Self {
+        self.workers = workers.max(1);
+        self
+    }
+
+    /// Sets the run every Script of the engine belongs to.
+    pub fn context(mut self, context: RunContext) -> Self {
+        self.context = context;
+        self
+    }
+
+    /// Sets what spawns the commands of every Script.
+    pub fn runner(mut self, runner: Arc<dyn CommandRunner>) -> Self {
+        self.runner = runner;
+        self
+    }
+
+    /// Admits the scripts until the sum of the costs of the running ones reaches the budget, over every stream
+    /// of the engine, a heavy script takes more of it than a cheap one. The workers of a stream are set to the
+    /// budget, so scripts of the default cost 1 run budget at a time. 0 is treated as 1.
+    pub fn budget(mut self, budget: u32) -> Self {
+        let budget = budget.max(1);
+        self.workers = budget as usize;
+        self.budget = Some(Arc::new(Budget {
+            limit: budget,
+            spent: Mutex::new(0),
+            freed: Condvar::new(),
+        }));
+        self
+    }
+
+    /// Sets what fills the placeholders of the call formats of every Script.
+    #[allow(dead_code)]
+    pub fn template_engine(mut self, template_engine: Arc<dyn TemplateEngine>) -> Self {
+        self.template_engine = template_engine;
+        self
+    }
+
+    /// Sets who gets told about the scripts starting and finishing.
+    pub fn observer(mut self, observer: Arc<dyn ScriptObserver>) -> Self {
+        self.observer = observer;
+        self
+    }
+
+    /// Sets where the results are rendered to as they complete, after the observer was told about them.
+    /// Without one they are only handed out.
+    pub fn output(mut self, output: Arc<ResultWriter>) -> Self {
+        self.output = Some(output);
+        self
+    }
+
+    /// Sets how the hostnames are looked up, with a new cache for them.
+    #[allow(dead_code)]
+    pub fn resolver(mut self, resolver: Arc<dyn HostResolver>) -> Self {
+        self.hosts = Arc::new(HostCache::new(resolver));
+        self
+    }
+
+    /// Sets the hosts of the whole run, for the run scoped scripts.
+    pub fn targets(mut self, targets: RunTargets) -> Self {
+        self.targets = Some(Arc::new(targets));
+        self
+    }
+
+    /// Sets where reload finds the scripts.
+    #[allow(dead_code)]
+    pub fn source(mut self, source: ScriptSource) -> Self {
+        self.source = Some(source);
+        self
+    }
+
+    /// The scripts the next stream runs.
+    pub fn scripts(&self) -> Vec<ScriptFile> {
+        self.scripts
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    /// Discovers, parses and filters the scripts of the source again, for long lived processes whose
+    /// scripts folder changes between runs. It can be called from any thread while streams are running:
+    /// the new scripts are swapped in at once, the streams started before keep running the scripts they started with.
+    /// On an error the scripts are left as they were. Returns how many scripts were loaded.
+    #[allow(dead_code)]
+    pub fn reload(&self) -> Result<usize> {
+        let source = self
+            .source
+            .as_ref()
+            .ok_or_else(|| anyhow!("The script engine has no source to reload the scripts from"))?;
+        let scripts = load_scripts(&source.location, source.config.as_ref())?;
+        let loaded = scripts.len();
+        *self.scripts.write().unwrap_or_else(PoisonError::into_inner) = scripts;
+        debug!("Reloaded {} script(s)", loaded);
+        Ok(loaded)
+    }
+
+    /// Starts running the scripts against the ip and the open ports in the background.
+    /// The results arrive in completion order, which is the submission order with a single worker.
+    /// The receiver is exhausted once every script finished.
+    pub fn results_stream(&self, ip: IpAddr, open_ports: &[u16]) -> Receiver<ScriptResult> {
+        self.ports_stream(ip, PortSet::open(open_ports.to_vec()))
+    }
+
+    /// Like results_stream, with the scanned ports of every state for the scripts with port_states.
+    /// A script resolving to the same file, ports and call formats as one before it runs only once.
+    pub fn ports_stream(&self, ip: IpAddr, ports: PortSet) -> Receiver<ScriptResult> {
+        self.runs_stream(ip, ports, &Arc::default())
+    }
+
+    // Leaves out the scripts whose run was already materialized in the phase, they would repeat its commands.
+    // The others are returned with their run, the left out ones by name with the run they repeat.
+    fn unique_runs(
+        &self,
+        scripts: Vec<ScriptFile>,
+        ip: IpAddr,
+        ports: &PortSet,
+        runs: &RunOutcomes,
+    ) -> (Vec<(ScriptFile, RunKey)>, HashMap<String, RunKey>) {
+        let mut outcomes = runs.outcomes.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut unique = Vec::new();
+        let mut duplicates = HashMap::new();
+        for script_f in scripts {
+            let key = RunKey::of(&Script::build_with_ports(
+                script_f.clone(),
+                ip,
+                ports.clone(),
+            ));
+            if outcomes.contains_key(&key) {
+                debug!("Skipping duplicate run of {} on {}", script_f.name(), ip);
+                self.observer.on_script_skipped(
+                    &script_f,
+                    ip,
+                    "it already runs with the same ports and commands",
+                );
+                duplicates.insert(script_f.name(), key);
+            } else {
+                outcomes.insert(key.clone(), None);
+                unique.push((script_f, key));
+            }
+        }
+        (unique, duplicates)
+    }
+
+    fn runs_stream(
+        &self,
+        ip: IpAddr,
+        ports: PortSet,
+        runs: &Arc<RunOutcomes>,
+    ) -> Receiver<ScriptResult> {
+        let (sender, receiver) = mpsc::channel();
+        // A snapshot, a reload while the stream runs does not change its scripts.
+        let (scripts, duplicates) = self.unique_runs(self.scripts(), ip, &ports, runs);
+        let workers = self.workers.min(scripts.len());
+        let names: Arc<Vec<String>> = Arc::new(
+            scripts
+                .iter()
+                .map(|(script_f, _)| script_f.name())
+                .collect(),
+        );
+        let duplicates = Arc::new(duplicates);
+        let queue = Arc::new(Mutex::new(scripts.into_iter()));
+        let ports = Arc::new(ports);
+        let completions = Arc::new(Completions::default());
+
+        for _ in 0..workers {
+            let sender = sender.clone();
+            let queue = Arc::clone(&queue);
+            let ports = Arc::clone(&ports);
+            let names = Arc::clone(&names);
+            let duplicates = Arc::clone(&duplicates);
+            let runs = Arc::clone(runs);
+            let completions = Arc::clone(&completions);
+            let context = self.context.clone();
+            let runner = Arc::clone(&self.runner);
+            let template_engine = Arc::clone(&self.template_engine);
+            let observer = Arc::clone(&self.observer);
+            let hosts = Arc::clone(&self.hosts);
+            let targets = self.targets.clone();
+            let original_target = self.original_target.clone();
+            let output = self.output.clone();
+            let budget = self.budget.clone();
+            thread::spawn(move || {
+                while let Some((script_f, key)) = next_script(&queue, &context) {
+                    if let Some(dependency) = &script_f.depends_on {
+                        // A dependency not run against this host counts as not succeeded,
+                        // a duplicate one has the outcome of the run it repeats.
+                        let succeeded = match duplicates.get(dependency) {
+                            Some(run) => runs.wait_for(run),
+                            None => names.contains(dependency) && completions.wait_for(dependency),
+                        };
+                        if !succeeded {
+                            observer.on_script_skipped(
+                                &script_f,
+                                ip,
+                                &format!("its dependency {dependency} did not succeed"),
+                            );
+                            completions.finish(script_f.name(), false);
+                            runs.finish(&key, false);
+                            continue;
+                        }
+                    }
+                    if let Some(reason) = detection_skip_reason(&script_f, &context, ip) {
+                        observer.on_script_skipped(&script_f, ip, reason);
+                        completions.finish(script_f.name(), false);
+                        runs.finish(&key, false);
+                        continue;
+                    }
+                    // Held until the script finished, the next ones wait for its cost to be given back.
+                    let _spent = budget
+                        .as_ref()
+                        .map(|budget| Budget::spend(budget, script_f.cost()));
+                    observer.on_script_start(&script_f, ip);
+                    // Only looked up for the scripts asking for it, reverse DNS can be slow.
+                    let hostname = if uses_hostname(&script_f) {
+                        hosts.hostname(ip)
+                    } else {
+                        None
+                    };
+                    let result =
+                        Script::build_with_ports(script_f.clone(), ip, PortSet::clone(&ports))
+                            .context(context.clone())
+                            .runner(Arc::clone(&runner))
+                            .template_engine(Arc::clone(&template_engine))
+                            .hostname(hostname)
+                            .original_target(original_target.clone())
+                            .targets(targets.clone())
+                            .execute();
+                    if let Some(error) = &result.error {
+                        observer.on_script_error(&script_f, &anyhow!(error.clone()));
+                    }
+                    observer.on_script_done(&result);
+                    if let Some(output) = &output {
+                        if let Err(e) = output.write_result(&result) {
+                            warn!("Failed to write script result: {}", e);
+                        }
+                    }
+                    completions.finish(script_f.name(), result.error.is_none());
+                    runs.finish(&key, result.error.is_none());
+                    if sender.send(result).is_err() {
+                        // Nobody is listening anymore.
+                        break;
+                    }
+                }
+                // The runs left when the stream stopped early never start, their duplicates count as not succeeded.
+                if let Ok(mut queue) = queue.lock() {
+                    for (_, key) in queue.by_ref() {
+                        runs.finish(&key, false);
+                    }
+                }
+            });
+        }
+        receiver
+    }
+
+    /// Runs the scripts of every host, host_workers hosts at the same time, 0 is treated as 1.
+    /// Every host gets its own pool of workers, so at most host_workers times workers scripts run at once.
+    /// The results of all the hosts arrive in completion order, the scripts of the engine itself are not run.
+    /// Jobs of the same ip share the duplicate check, a script runs once per ip, ports and call formats.
+    pub fn hosts_stream(&self, jobs: Vec<HostJob>, host_workers: usize) -> Receiver<ScriptResult> {
+        let (sender, receiver) = mpsc::channel();
+        let host_workers = host_workers.max(1).min(jobs.len());
+        let queue = Arc::new(Mutex::new(jobs.into_iter()));
+        let runs = Arc::new(RunOutcomes::default());
+
+        for _ in 0..host_workers {
+            let sender = sender.clone();
+            let queue = Arc::clone(&queue);
+            let runs = Arc::clone(&runs);
+            let engine = self.clone();
+            thread::spawn(move || {
+                while let Some(job) = next_job(&queue, &engine.context) {
+                    let host_engine = ScriptEngine {
+                        scripts: Arc::new(RwLock::new(job.scripts)),
+                        original_target: job.original_target,
+                        ..engine.clone()
+                    };
+                    for result in host_engine.runs_stream(job.ip, job.ports, &runs) {
+                        if sender.send(result).is_err() {
+                            return;
+                        }
+                    }
+                }
+            });
+        }
+        receiver
+    }
+}
+
+// Like next_script, for the hosts.
+fn next_job(queue: &Mutex<vec::IntoIter<HostJob>>, context: &RunContext) -> Option<HostJob> {
+    if interrupted() || context.past_deadline() {
+        return None;
+    }
+    queue.lock().ok().and_then(|mut queue| queue.next())
+}
+
+// Why the script is not run against the ip for its os_match or service_match, None to run it.
+fn detection_skip_reason(
+    script_f: &ScriptFile,
+    context: &RunContext,
+    ip: IpAddr,
+) -> Option<&'static str> {
+    match script_f.matches_detection(context.detection.as_ref(), ip) {
+        Some(false) => Some("the detected os or services do not match"),
+        None if context.skip_undetected => Some("nothing was detected to match its os or services"),
+        Some(true) | None => None,
+    }
+}
+
+fn uses_hostname(script_f: &ScriptFile) -> bool {
+    script_f
+        .call_format
+        .as_ref()
+        .is_some_and(|call_format| call_format.to_string().contains("{{hostname}}"))
+}
+
+// Takes the next script off the queue, the lock is released before the script runs
+// so the other workers can pick up the following ones. After a Ctrl-C or the deadline the queue is left as is.
+fn next_script<T>(queue: &Mutex<vec::IntoIter<T>>, context: &RunContext) -> Option<T> {
+    if interrupted() || context.past_deadline() {
+        return None;
+    }
+    queue.lock().ok().and_then(|mut queue| queue.next())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{HostJob, HostResolver, ScriptEngine, ScriptObserver, ScriptSource};
+    use crate::scripts::{
+        CommandRunner, PortSet, RunContext, ScriptFile, ScriptResult, ScriptsLocation,
+        SubprocessRunner,
+    };
+    use std::net::IpAddr;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    fn sleep_script(name: &str, seconds: &str) -> ScriptFile {
+        let mut script_f =
+            ScriptFile::new("fixtures/.rustscan_scripts/test_script.txt".into()).unwrap();
+        script_f.path = Some(format!("{name}.sh").into());
+        script_f.call_format = Some(format!("bash -c 'sleep {seconds}; echo {name}'").into());
+        script_f
+    }
+
+    #[test]
+    fn stream_in_submission_order_with_one_worker() {
+        let engine =
+            ScriptEngine::new(vec![sleep_script("slow", "0.2"), sleep_script("fast", "0")]);
+        let names: Vec<String> = engine
+            .results_stream("127.0.0.1".parse().unwrap(), &[80])
+            .iter()
+            .map(|result| result.script_name)
+            .collect();
+        assert_eq!(names, ["slow", "fast"]);
+    }
+
+    #[test]
+    fn stream_in_completion_order_with_workers() {
+        let engine =
+            ScriptEngine::new(vec![sleep_script("slow", "0.5"), sleep_script("fast", "0")])
+                .workers(2);
+        let start = Instant::now();
+        let results: Vec<ScriptResult> = engine
+            .results_stream("127.0.0.1".parse().unwrap(), &[80])
+            .iter()
+            .collect();
+
+        assert_eq!(results[0].script_name, "fast");
+        assert_eq!(results[0].output, "fast\n");
+        assert_eq!(results[1].script_name, "slow");
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn deadline_keeps_the_completed_results() {
+        let deadline = Instant::now() + Duration::from_millis(500);
+        let engine = ScriptEngine::new(vec![
+            sleep_script("fast", "0"),
+            sleep_script("slow", "10"),
+            sleep_script("late", "0"),
+        ])
+        .context(RunContext::default().deadline(deadline))
+        .runner(Arc::new(SubprocessRunner {
+            deadline: Some(deadline),
+            ..SubprocessRunner::default()
+        }));
+        let start = Instant::now();
+        let results: Vec<ScriptResult> = engine
+            .results_stream("127.0.0.1".parse().unwrap(), &[80])
+            .iter()
+            .collect();
+
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].output, "fast\n");
+        assert_eq!(results[0].error, None);
+        assert_eq!(results[1].script_name, "slow");
+        assert_eq!(results[1].error_kind.as_deref(), Some("deadline"));
+    }
+
+    #[derive(Debug, Default)]
+    struct RecordingObserver {
+        events: Mutex<Vec<String>>,
+    }
+
+    impl ScriptObserver for RecordingObserver {
+        fn on_script_start(&self, script: &ScriptFile, ip: IpAddr) {
+            let name = script.path.as_ref().unwrap().display();
+            self.events
+                .lock()
+                .unwrap()
+                .push(format!("start {name} {ip}"));
+        }
+
+        fn on_script_done(&self, result: &ScriptResult) {
+            let name = &result.script_name;
+            self.events.lock().unwrap().push(format!("done {name}"));
+        }
+
+        fn on_script_error(&self, script: &ScriptFile, err: &anyhow::Error) {
+            let name = script.path.as_ref().unwrap().display();
+            self.events
+                .lock()
+                .unwrap()
+                .push(format!("error {name} {err}"));
+        }
+
+        fn on_script_skipped(&self, script: &ScriptFile, _ip: IpAddr, reason: &str) {
+            let name = script.name();
+            self.events
+                .lock()
+                .unwrap()
+                .push(format!("skip {name} {reason}"));
+        }
+    }
+
+    #[test]
+    fn observer_sees_the_lifecycle() {
+        let mut failing = sleep_script("failing", "0");
+        failing.call_format = Some("false".into());
+        let observer = Arc::new(RecordingObserver::default());
+        let engine =
+            ScriptEngine::new(vec![sleep_script("ok", "0"), failing]).observer(observer.clone());
+        assert_eq!(
+            engine
+                .results_stream("127.0.0.1".parse().unwrap(), &[80])
+                .iter()
+                .count(),
+            2
+        );
+
+        assert_eq!(
+            *observer.events.lock().unwrap(),
+            vec![
+                "start ok.sh 127.0.0.1",
+                "done ok",
+                "start failing.sh 127.0.0.1",
+                "error failing.sh Exit code = 1",
+                "done failing"
+            ]
+        );
+    }
+
+    #[test]
+    fn dependents_wait_for_their_dependency() {
+        let mut failing = sleep_script("failing", "0");
+        failing.call_format = Some("false".into());
+        let mut after_slow = sleep_script("after_slow", "0");
+        after_slow.depends_on = Some("slow".to_string());
+        let mut after_failing = sleep_script("after_failing", "0");
+        after_failing.depends_on = Some("failing".to_string());
+        let mut after_skipped = sleep_script("after_skipped", "0");
+        after_skipped.depends_on = Some("after_failing".to_string());
+        let mut after_missing = sleep_script("after_missing", "0");
+        after_missing.depends_on = Some("missing".to_string());
+
+        let observer = Arc::new(RecordingObserver::default());
+        let engine = ScriptEngine::new(vec![
+            sleep_script("slow", "0.3"),
+            failing,
+            after_slow,
+            after_failing,
+            after_skipped,
+            after_missing,
+        ])
+        .workers(4)
+        .observer(observer.clone());
+        let names: Vec<String> = engine
+            .results_stream("127.0.0.1".parse().unwrap(), &[80])
+            .iter()
+            .map(|result| result.script_name)
+            .collect();
+        // after_slow waited for slow, even though a worker was free right away.
+        assert_eq!(names, ["failing", "slow", "after_slow"]);
+
+        let events = observer.events.lock().unwrap();
+        for skipped in &[
+            "skip after_failing its dependency failing did not succeed",
+            "skip after_skipped its dependency after_failing did not succeed",
+            "skip after_missing its dependency missing did not succeed",
+        ] {
+            assert!(events.iter().any(|event| event == skipped), "{}", skipped);
+        }
+    }
+
+    #[derive(Debug, Default)]
+    struct CountingResolver {
+        lookups: AtomicUsize,
+    }
+
+    impl HostResolver for CountingResolver {
+        fn reverse(&self, _ip: IpAddr) -> Option<String> {
+            self.lookups.fetch_add(1, Ordering::SeqCst);
+            Some("host.example".to_string())
+        }
+    }
+
+    #[test]
+    fn hostname_resolved_once_per_ip() {
+        let mut scripts: Vec<ScriptFile> = (0..3)
+            .map(|index| {
+                let mut script_f = sleep_script(&format!("script{index}"), "0");
+                script_f.call_format = Some("echo {{hostname}}".into());
+                script_f
+            })
+            .collect();
+        scripts.push(sleep_script("no_hostname", "0"));
+        let resolver = Arc::new(CountingResolver::default());
+        let engine = ScriptEngine::new(scripts)
+            .workers(2)
+            .resolver(resolver.clone());
+
+        let outputs: Vec<String> = engine
+            .results_stream("127.0.0.1".parse().unwrap(), &[80])
+            .iter()
+            .map(|result| result.output)
+            .collect();
+        assert_eq!(
+            outputs
+                .iter()
+                .filter(|output| *output == "host.example\n")
+                .count(),
+            3
+        );
+        assert_eq!(resolver.lookups.load(Ordering::SeqCst), 1);
+
+        engine
+            .results_stream("::1".parse().unwrap(), &[80])
+            .iter()
+            .count();
+        assert_eq!(resolver.lookups.load(Ordering::SeqCst), 2);
+    }
+
+    #[derive(Debug, Default)]
+    struct InFlightRunner {
+        running: AtomicUsize,
+        most: AtomicUsize,
+    }
+
+    impl CommandRunner for InFlightRunner {
+        fn run(&self, _arguments: &[String]) -> anyhow::Result<(i64, String, String)> {
+            let running = self.running.fetch_add(1, Ordering::SeqCst) + 1;
+            self.most.fetch_max(running, Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(100));
+            self.running.fetch_sub(1, Ordering::SeqCst);
+            Ok((0, String::new(), String::new()))
+        }
+    }
+
+    #[test]
+    fn hosts_stream_bounds_the_commands() {
+        let jobs: Vec<HostJob> = (1..=3)
+            .map(|host| HostJob {
+                ip: format!("10.0.0.{host}").parse().unwrap(),
+                ports: PortSet::open(vec![80]),
+                scripts: (0..3)
+                    .map(|index| sleep_script(&format!("script{index}"), "0"))
+                    .collect(),
+                original_target: None,
+            })
+            .collect();
+        let runner = Arc::new(InFlightRunner::default());
+        let engine = ScriptEngine::new(Vec::new())
+            .workers(2)
+            .runner(runner.clone());
+
+        let results: Vec<ScriptResult> = engine.hosts_stream(jobs, 2).iter().collect();
+        assert_eq!(results.len(), 9);
+        assert!(results.iter().all(|result| result.error.is_none()));
+        let most = runner.most.load(Ordering::SeqCst);
+        assert!(most > 2 && most <= 4, "{} commands ran at once", most);
+    }
+
+    // Tracks the summed cost of the running commands, the cost is the argument of echo.
+    #[derive(Debug, Default)]
+    struct CostRunner {
+        running: Mutex<u32>,
+        most: AtomicUsize,
+    }
+
+    impl CommandRunner for CostRunner {
+        fn run(&self, arguments: &[String]) -> anyhow::Result<(i64, String, String)> {
+            let cost: u32 = arguments[1].parse().unwrap();
+            let running = {
+                let mut running = self.running.lock().unwrap();
+                *running += cost;
+                *running
+            };
+            self.most.fetch_max(running as usize, Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(50));
+            *self.running.lock().unwrap() -= cost;
+            Ok((0, String::new(), String::new()))
+        }
+    }
+
+    #[test]
+    fn budget_bounds_the_running_costs() {
+        let costed = |costs: &[u32]| -> Vec<ScriptFile> {
+            costs
+                .iter()
+                .enumerate()
+                .map(|(index, cost)| {
+                    let mut script_f = sleep_script(&format!("script{index}"), "0");
+                    script_f.call_format = Some(format!("echo {cost}").into());
+                    script_f.cost = Some(*cost);
+                    script_f
+                })
+                .collect()
+        };
+        let costs = [3, 1, 2, 1, 1, 3, 2, 1];
+        let runner = Arc::new(CostRunner::default());
+        let engine = ScriptEngine::new(costed(&costs))
+            .budget(4)
+            .runner(runner.clone());
+        let jobs: Vec<HostJob> = (1..=2)
+            .map(|host| HostJob {
+                ip: format!("10.0.0.{host}").parse().unwrap(),
+                ports: PortSet::open(vec![80]),
+                scripts: engine.scripts(),
+                original_target: None,
+            })
+            .collect();
+
+        let results: Vec<ScriptResult> = engine.hosts_stream(jobs, 2).iter().collect();
+        assert_eq!(results.len(), 2 * costs.len());
+        assert!(results.iter().all(|result| result.error.is_none()));
+        let most = runner.most.load(Ordering::SeqCst);
+        assert!(most > 1 && most <= 4, "costs of {} ran at once", most);
+
+        // A script costing more than the whole budget runs alone instead of never.
+        let runner = Arc::new(CostRunner::default());
+        let engine = ScriptEngine::new(costed(&[1, 9, 1]))
+            .budget(4)
+            .runner(runner.clone());
+        assert_eq!(
+            engine
+                .results_stream("127.0.0.1".parse().unwrap(), &[80])
+                .iter()
+                .count(),
+            3
+        );
+        assert_eq!(runner.most.load(Ordering::SeqCst), 9);
+    }
+
+    #[test]
+    fn identical_runs_run_once() {
+        // Both filters leave port 80, so the two selections build the same command.
+        let mut web = sleep_script("web", "0");
+        web.ports_filter = Some("80-90".to_string());
+        let mut http = web.clone();
+        http.ports_filter = Some("80,8080".to_string());
+        let mut other_ports = web.clone();
+        other_ports.ports_filter = Some("443".to_string());
+        let observer = Arc::new(RecordingObserver::default());
+        let engine =
+            ScriptEngine::new(vec![web.clone(), http, other_ports]).observer(observer.clone());
+        let results: Vec<ScriptResult> = engine
+            .results_stream("127.0.0.1".parse().unwrap(), &[80, 443])
+            .iter()
+            .collect();
+        assert_eq!(
+            results
+                .iter()
+                .map(|result| result.ports.clone())
+                .collect::<Vec<Vec<u16>>>(),
+            vec![vec![80], vec![443]]
+        );
+        assert!(observer
+            .events
+            .lock()
+            .unwrap()
+            .contains(&"skip web it already runs with the same ports and commands".to_string()));
+
+        // Jobs of the same ip share the check, another ip runs it again.
+        let job = |ip: &str| HostJob {
+            ip: ip.parse().unwrap(),
+            ports: PortSet::open(vec![80]),
+            scripts: vec![web.clone()],
+            original_target: None,
+        };
+        let engine = ScriptEngine::new(Vec::new());
+        let jobs = vec![job("10.0.0.1"), job("10.0.0.1"), job("10.0.0.2")];
+        assert_eq!(engine.hosts_stream(jobs, 1).iter().count(), 2);
+    }
+
+    #[test]
+    fn dependents_of_a_duplicate_get_its_outcome() {
+        let mut failing = sleep_script("failing", "0");
+        failing.call_format = Some("false".into());
+        let dependent = |dependency: &str| {
+            let mut script_f = sleep_script(&format!("after_{dependency}"), "0");
+            script_f.depends_on = Some(dependency.to_string());
+            script_f
+        };
+        let job = |scripts: Vec<ScriptFile>| HostJob {
+            ip: "10.0.0.1".parse().unwrap(),
+            ports: PortSet::open(vec![80]),
+            scripts,
+            original_target: None,
+        };
+        let observer = Arc::new(RecordingObserver::default());
+        let engine = ScriptEngine::new(Vec::new()).observer(observer.clone());
+        let jobs = vec![
+            job(vec![sleep_script("web", "0.2"), failing.clone()]),
+            job(vec![sleep_script("web", "0.2"), dependent("web")]),
+            job(vec![failing, dependent("failing")]),
+        ];
+        let mut names: Vec<String> = engine
+            .hosts_stream(jobs, 3)
+            .iter()
+            .map(|result| result.script_name)
+            .collect();
+        names.sort();
+        // The second web is left out, its dependent waited for the first one instead of being skipped.
+        assert_eq!(names, ["after_web", "failing", "web"]);
+        assert!(observer
+            .events
+            .lock()
+            .unwrap()
+            .contains(&"skip after_failing its dependency failing did not succeed".to_string()));
+    }
+
+    #[test]
+    fn stream_without_scripts_ends() {
+        let engine = ScriptEngine::new(Vec::new()).workers(4);
+        assert_eq!(
+            engine
+                .results_stream("127.0.0.1".parse().unwrap(), &[80])
+                .iter()
+                .count(),
+            0
+        );
+    }
+
+    #[test]
+    fn reload_picks_up_the_changed_scripts() {
+        let dir = tempfile::tempdir().unwrap();
+        let location = ScriptsLocation::in_dir(dir.path());
+        std::fs::create_dir(&location.scripts_dir).unwrap();
+        std::fs::write(&location.config, "tags = [\"web\"]\n").unwrap();
+        let write_script = |name: &str, tag: &str| {
+            std::fs::write(
+                location.scripts_dir.join(format!("{name}.sh")),
+                format!("#!/bin/bash\n# tags = [\"{tag}\"]\n# call_format = \"echo {name}\"\n"),
+            )
+            .unwrap();
+        };
+        write_script("first", "web");
+        write_script("other", "ssh");
+
+        let engine = ScriptEngine::new(Vec::new()).source(ScriptSource {
+            location: location.clone(),
+            config: None,
+        });
+        let names = |engine: &ScriptEngine| {
+            let mut names: Vec<String> = engine.scripts().iter().map(ScriptFile::name).collect();
+            names.sort();
+            names
+        };
+        assert_eq!(engine.reload().unwrap(), 1);
+        assert_eq!(names(&engine), ["first"]);
+
+        let stream = engine.results_stream("127.0.0.1".parse().unwrap(), &[80]);
+        write_script("second", "web");
+        std::fs::remove_file(location.scripts_dir.join("first.sh")).unwrap();
+        assert_eq!(engine.reload().unwrap(), 1);
+        // The clones share the scripts, while the stream started before keeps its own.
+        assert_eq!(names(&engine.clone()), ["second"]);
+        let outputs: Vec<String> = stream.iter().map(|result| result.output).collect();
+        assert_eq!(outputs, ["first\n"]);
+
+        // A failed reload keeps the scripts.
+        std::fs::write(&location.config, "tags = ").unwrap();
+        assert!(engine.reload().is_err());
+        assert_eq!(names(&engine), ["second"]);
+        assert!(ScriptEngine::new(Vec::new()).reload().is_err());
+    }
+}
@@ -0,0 +1,90 @@
+//! The failure categories of the scripts module, for embedders matching on them.
+
+use std::error::Error;
+use std::fmt;
+use std::path::PathBuf;
+
+/// Why a script, its config or its discovery failed.
+/// The functions of the module return anyhow errors, which carry a ScriptError when the failure falls
+/// into one of these categories, ScriptError::of gets it back out.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ScriptError {
+    /// A script header, the config file or a call_format could not be parsed.
+    Parse(String),
+    /// The scripts folder does not exist.
+    NotFound(PathBuf),
+    /// The scripts folder path exists, but is a file.
+    NotADirectory(PathBuf),
+    /// The command ran, but exited with a non zero exit code.
+    NonZeroExit(i64),
+    /// The program of the command could not be started, it is not installed or not on the PATH.
+    BinaryMissing(String),
+    /// Ctrl-C was hit before or while the command ran.
+    Interrupted,
+    /// The --scripts-deadline passed before or while the command ran.
+    DeadlineExceeded,
+}
+
+impl ScriptError {
+    /// The ScriptError carried by the error, None if it is not one of the categories.
+    pub fn of(error: &anyhow::Error) -> Option<&ScriptError> {
+        error.downcast_ref::<ScriptError>()
+    }
+
+    /// A short name of the category, for the machine readable outputs.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ScriptError::Parse(_) => "parse",
+            ScriptError::NotFound(_) => "not_found",
+            ScriptError::NotADirectory(_) => "not_a_directory",
+            ScriptError::NonZeroExit(_) => "non_zero_exit",
+            ScriptError::BinaryMissing(_) => "binary_missing",
+            ScriptError::Interrupted => "interrupted",
+            ScriptError::DeadlineExceeded => "deadline",
+        }
+    }
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScriptError::Parse(message) => write!(f, "{message}"),
+            ScriptError::NotFound(path) => {
+                write!(
+                    f,
+                    "Can't find scripts folder {}, the path does not exist",
+                    path.display()
+                )
+            }
+            ScriptError::NotADirectory(path) => write!(
+                f,
+                "Scripts folder {} exists but is a file, expected a directory",
+                path.display()
+            ),
+            ScriptError::NonZeroExit(exit_code) => write!(f, "Exit code = {exit_code}"),
+            ScriptError::BinaryMissing(program) => {
+                write!(f, "Failed to run {program}, it is not found")
+            }
+            ScriptError::Interrupted => write!(f, "Interrupted"),
+            ScriptError::DeadlineExceeded => write!(f, "Stopped by the scripts deadline"),
+        }
+    }
+}
+
+impl Error for ScriptError {}
+
+#[cfg(test)]
+mod tests {
+    use super::ScriptError;
+    use anyhow::Context;
+
+    #[test]
+    fn error_survives_context() {
+        let error = Err::<(), _>(ScriptError::NonZeroExit(2))
+            .context("Exit code = 2 (after 2 attempts)")
+            .unwrap_err();
+        assert_eq!(error.to_string(), "Exit code = 2 (after 2 attempts)");
+        assert_eq!(ScriptError::of(&error), Some(&ScriptError::NonZeroExit(2)));
+        assert_eq!(ScriptError::of(&anyhow::anyhow!("other")), None);
+    }
+}
@@ -0,0 +1,110 @@
+//! The hosts the scripts run against for --scripts-hosts, the other scanned hosts are not scripted.
+
+use anyhow::{anyhow, Context, Result};
+use cidr_utils::cidr::IpCidr;
+use std::fs;
+use std::net::IpAddr;
+use std::path::Path;
+
+/// A set of CIDRs and single ips, a single ip is a CIDR of one address.
+#[derive(Debug, Clone)]
+pub struct HostFilter {
+    cidrs: Vec<IpCidr>,
+}
+
+impl HostFilter {
+    /// Reads the filter from a file with an ip or CIDR on every line, when spec is the path of one,
+    /// or from a comma separated list like 10.0.0.0/24,192.168.1.5 otherwise.
+    /// Blank lines and the lines starting with a # are skipped.
+    pub fn read(spec: &str) -> Result<HostFilter> {
+        let path = Path::new(spec);
+        if path.is_file() {
+            let content = fs::read_to_string(path)
+                .with_context(|| format!("Failed to read the scripts hosts {}", path.display()))?;
+            let entries = content
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with('#'));
+            return Self::parse(entries)
+                .with_context(|| format!("Invalid scripts hosts file {}", path.display()));
+        }
+        Self::parse(
+            spec.split(',')
+                .map(str::trim)
+                .filter(|entry| !entry.is_empty()),
+        )
+    }
+
+    fn parse<'a>(entries: impl Iterator<Item = &'a str>) -> Result<HostFilter> {
+        let cidrs = entries
+            .map(|entry| {
+                IpCidr::from_str(entry).map_err(|_| {
+                    anyhow!(
+                        "{} is neither an ip nor a CIDR, nor an existing file",
+                        entry
+                    )
+                })
+            })
+            .collect::<Result<Vec<IpCidr>>>()?;
+        if cidrs.is_empty() {
+            return Err(anyhow!("The scripts hosts are empty"));
+        }
+        Ok(HostFilter { cidrs })
+    }
+
+    /// Whether the scripts run against the ip.
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        self.cidrs.iter().any(|cidr| cidr.contains(ip))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::HostFilter;
+    use std::fs;
+    use std::net::IpAddr;
+
+    fn scripted(filter: &HostFilter, ips: &[&str]) -> Vec<String> {
+        ips.iter()
+            .filter(|ip| filter.contains(ip.parse::<IpAddr>().unwrap()))
+            .map(ToString::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn cidr_filter() {
+        let ips = ["10.0.0.1", "10.0.0.200", "10.0.1.1", "192.168.1.5", "::1"];
+        let filter = HostFilter::read("10.0.0.0/24, 192.168.1.5").unwrap();
+        assert_eq!(
+            scripted(&filter, &ips),
+            vec!["10.0.0.1", "10.0.0.200", "192.168.1.5"]
+        );
+        assert_eq!(
+            HostFilter::read("10.0.0.0/33").unwrap_err().to_string(),
+            "10.0.0.0/33 is neither an ip nor a CIDR, nor an existing file"
+        );
+        assert!(HostFilter::read(" , ").is_err());
+    }
+
+    #[test]
+    fn file_filter() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("hosts.txt");
+        fs::write(
+            &path,
+            "# allowlist\n10.0.0.1\n\n  fe80::/64\n172.16.0.0/16\n",
+        )
+        .unwrap();
+        let filter = HostFilter::read(path.to_str().unwrap()).unwrap();
+        assert_eq!(
+            scripted(
+                &filter,
+                &["10.0.0.1", "10.0.0.2", "fe80::1", "::1", "172.16.9.9"]
+            ),
+            vec!["10.0.0.1", "fe80::1", "172.16.9.9"]
+        );
+
+        fs::write(&path, "10.0.0.1\nnot-an-ip\n").unwrap();
+        assert!(HostFilter::read(path.to_str().unwrap()).is_err());
+    }
+}
@@ -0,0 +1,152 @@
+//! The example scripts and the starter config written by --scripts-init.
+
+use super::{scripts_location, ScriptsLocation};
+use anyhow::{anyhow, Context, Result};
+use std::fs;
+use std::path::PathBuf;
+
+static STARTER_CONFIG: &str = r#"# RustScan script config, written by --scripts-init.
+
+# Tags to filter on scripts. Only scripts containing all these tags will run.
+tags = ["example"]
+
+# Only this developer(s) scripts to run. A script has to list at least one of them.
+# developer = ["example"]
+
+# Also run the built-in default script, if it passes the filters above.
+# include_default = true
+
+# Values of your own, available in every call_format as {{vars.wordlist}} or {{wordlist}}.
+# [vars]
+# wordlist = "/usr/share/wordlists/dirb/common.txt"
+"#;
+
+static NMAP_SERVICES: &str = r#"#!intentional_blank_line
+#tags = ["example", "nmap"]
+#developer = [ "example", "https://example.org" ]
+#description = "Runs an nmap service detection on the open ports"
+#ports_separator = ","
+#call_format = "nmap -sV -p {{port}} {{ip}}"
+
+# The script header ends at the first blank line.
+# This file only holds a header, the system installed nmap runs with the open ports joined by ",".
+"#;
+
+static HTTP_TITLE: &str = r#"#!/bin/bash
+#tags = ["example", "http"]
+#developer = [ "example", "https://example.org" ]
+#description = "Prints the title of the web page on port 80"
+#trigger_port = "80"
+#call_format = "bash {{script}} {{ip}} {{port}}"
+
+# The script header ends at the first blank line.
+# With trigger_port the script only runs when port 80 is open, once for it.
+
+curl -s --max-time 5 "http://$1:$2/" | grep -io '<title>[^<]*' | head -n 1 | cut -d '>' -f 2
+"#;
+
+static BANNER: &str = r#"#!/usr/bin/python3
+#tags = ["example", "banner"]
+#developer = [ "example", "https://example.org" ]
+#description = "Prints the first bytes every open port sends"
+#ports_separator = ","
+#call_format = "python3 {{script}} {{ip}} {{port}}"
+
+# The script header ends at the first blank line.
+# The open ports are joined by "," into a single argument.
+
+import socket
+import sys
+
+ip, ports = sys.argv[1], sys.argv[2]
+for port in ports.split(","):
+    try:
+        with socket.create_connection((ip, int(port)), timeout=3) as connection:
+            banner = connection.recv(256).decode(errors="replace").strip()
+    except OSError as e:
+        banner = "no banner ({})".format(e)
+    print("{}: {}".format(port, banner))
+"#;
+
+// The example scripts, by their file name in the scripts folder.
+static EXAMPLE_SCRIPTS: &[(&str, &str)] = &[
+    ("nmap_services.txt", NMAP_SERVICES),
+    ("http_title.sh", HTTP_TITLE),
+    ("banner.py", BANNER),
+];
+
+/// Writes the starter config and the example scripts where --scripts custom looks for them, see init_scripts_in.
+#[cfg(not(tarpaulin_include))]
+pub fn init_example_scripts(force: bool) -> Result<Vec<PathBuf>> {
+    init_scripts_in(&scripts_location(None)?, force)
+}
+
+/// Creates the scripts folder of the location and writes the starter config and the example scripts into it.
+/// Existing files are only overwritten with force, otherwise nothing is written. Returns the written files.
+pub fn init_scripts_in(location: &ScriptsLocation, force: bool) -> Result<Vec<PathBuf>> {
+    let files: Vec<(PathBuf, &str)> = std::iter::once((location.config.clone(), STARTER_CONFIG))
+        .chain(
+            EXAMPLE_SCRIPTS
+                .iter()
+                .map(|(name, content)| (location.scripts_dir.join(name), *content)),
+        )
+        .collect();
+
+    let existing: Vec<String> = files
+        .iter()
+        .filter(|(path, _)| path.exists())
+        .map(|(path, _)| path.display().to_string())
+        .collect();
+    if !existing.is_empty() && !force {
+        return Err(anyhow!(
+            "Not overwriting {}, use --force to replace them",
+            existing.join(", ")
+        ));
+    }
+
+    fs::create_dir_all(&location.scripts_dir).with_context(|| {
+        format!(
+            "Failed to create scripts folder {}",
+            location.scripts_dir.display()
+        )
+    })?;
+    for (path, content) in &files {
+        fs::write(path, content).with_context(|| format!("Failed to write {}", path.display()))?;
+    }
+    Ok(files.into_iter().map(|(path, _)| path).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{init_scripts_in, EXAMPLE_SCRIPTS};
+    use crate::scripts::{load_scripts, ScriptsLocation};
+    use std::fs;
+
+    #[test]
+    fn init_writes_the_examples() {
+        let home = tempfile::tempdir().unwrap();
+        let location = ScriptsLocation::in_dir(home.path());
+        let written = init_scripts_in(&location, false).unwrap();
+        assert_eq!(written.len(), EXAMPLE_SCRIPTS.len() + 1);
+        assert!(location.config.is_file());
+        for (name, _) in EXAMPLE_SCRIPTS {
+            assert!(location.scripts_dir.join(name).is_file());
+        }
+
+        // Every example parses and is selected by the starter config.
+        let scripts = load_scripts(&location, None).unwrap();
+        assert_eq!(scripts.len(), EXAMPLE_SCRIPTS.len());
+
+        fs::write(&location.config, "tags = []").unwrap();
+        assert!(init_scripts_in(&location, false)
+            .unwrap_err()
+            .to_string()
+            .contains("--force"));
+        assert_eq!(fs::read_to_string(&location.config).unwrap(), "tags = []");
+
+        init_scripts_in(&location, true).unwrap();
+        assert!(fs::read_to_string(&location.config)
+            .unwrap()
+            .contains(r#"tags = ["example"]"#));
+    }
+}
@@ -0,0 +1,206 @@
+//! Ctrl-C handling for the scripts phase, the running commands are stopped instead of being left orphaned.
+
+use anyhow::{anyhow, Result};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use subprocess::{ExitStatus, Popen};
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+// Every running script command, removed again once it exited.
+static CHILDREN: Mutex<Vec<(u64, Arc<Mutex<Popen>>)>> = Mutex::new(Vec::new());
+
+// How long a wait holds on to a child, so a Ctrl-C can get in between to kill it.
+const WAIT_SLICE: Duration = Duration::from_millis(50);
+
+/// Installs the Ctrl-C handler, SIGINT on unix and the console control event on Windows.
+/// From then on a Ctrl-C kills the running script commands and no new ones are started.
+#[cfg(not(tarpaulin_include))]
+pub fn install_interrupt_handler() -> Result<()> {
+    ctrlc::set_handler(interrupt).map_err(|e| anyhow!("Failed to install Ctrl-C handler: {}", e))
+}
+
+/// Whether Ctrl-C was hit during the scripts phase.
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+fn interrupt() {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+    if let Ok(children) = CHILDREN.lock() {
+        for (_, child) in children.iter() {
+            kill(child);
+        }
+    }
+}
+
+// A SIGTERM on unix, the process can clean up or ignore it. Windows has no such signal, it is terminated right away.
+fn terminate(child: &Mutex<Popen>) {
+    if let Ok(mut child) = child.lock() {
+        if let Err(e) = child.terminate() {
+            debug!("Failed to terminate script command: {}", e);
+        }
+    }
+}
+
+fn kill(child: &Mutex<Popen>) {
+    if let Ok(mut child) = child.lock() {
+        if let Err(e) = child.kill() {
+            debug!("Failed to kill script command: {}", e);
+        }
+    }
+}
+
+/// A running command in the registry, it is taken out again when dropped.
+pub(super) struct TrackedChild {
+    id: u64,
+    process: Arc<Mutex<Popen>>,
+}
+
+impl TrackedChild {
+    /// Registers the process, it is killed right away when the interrupt came in while it was spawning.
+    pub(super) fn track(process: Popen) -> Self {
+        let tracked = Self {
+            id: NEXT_ID.fetch_add(1, Ordering::SeqCst),
+            process: Arc::new(Mutex::new(process)),
+        };
+        if let Ok(mut children) = CHILDREN.lock() {
+            children.push((tracked.id, Arc::clone(&tracked.process)));
+        }
+        if interrupted() {
+            tracked.kill();
+        }
+        tracked
+    }
+
+    pub(super) fn kill(&self) {
+        kill(&self.process);
+    }
+
+    /// Terminates the process once the deadline passed, unless it exited and the Watchdog was dropped before.
+    /// A process still running grace later, because it ignores the signal, is killed.
+    pub(super) fn kill_at(&self, deadline: Instant, grace: Duration) -> Watchdog {
+        let (done, finished) = mpsc::channel::<()>();
+        let expired = Arc::new(AtomicBool::new(false));
+        let process = Arc::clone(&self.process);
+        let fired = Arc::clone(&expired);
+        thread::spawn(move || {
+            let wait = deadline.saturating_duration_since(Instant::now());
+            if let Err(RecvTimeoutError::Timeout) = finished.recv_timeout(wait) {
+                fired.store(true, Ordering::SeqCst);
+                terminate(&process);
+                if let Err(RecvTimeoutError::Timeout) = finished.recv_timeout(grace) {
+                    kill(&process);
+                }
+            }
+        });
+        Watchdog {
+            _done: done,
+            expired,
+        }
+    }
+
+    /// Waits for the process to exit, in slices so the interrupt handler can lock it in between.
+    pub(super) fn wait(&self) -> Result<ExitStatus> {
+        loop {
+            let mut process = self
+                .process
+                .lock()
+                .map_err(|_| anyhow!("Script command lock poisoned"))?;
+            if let Some(status) = process.wait_timeout(WAIT_SLICE)? {
+                return Ok(status);
+            }
+        }
+    }
+}
+
+/// Guards a process against its deadline, dropping it stops the guard.
+pub(super) struct Watchdog {
+    _done: Sender<()>,
+    expired: Arc<AtomicBool>,
+}
+
+impl Watchdog {
+    /// Whether the deadline passed and the process was killed for it.
+    pub(super) fn expired(&self) -> bool {
+        self.expired.load(Ordering::SeqCst)
+    }
+}
+
+impl Drop for TrackedChild {
+    fn drop(&mut self) {
+        if let Ok(mut children) = CHILDREN.lock() {
+            children.retain(|(id, _)| *id != self.id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{TrackedChild, CHILDREN};
+    use std::time::{Duration, Instant};
+    use subprocess::{Exec, ExitStatus};
+
+    #[test]
+    fn tracked_child_leaves_the_registry() {
+        let child = TrackedChild::track(Exec::cmd("true").popen().unwrap());
+        let id = child.id;
+        assert!(CHILDREN
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|(tracked, _)| *tracked == id));
+        assert_eq!(child.wait().unwrap(), ExitStatus::Exited(0));
+
+        drop(child);
+        assert!(!CHILDREN
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|(tracked, _)| *tracked == id));
+    }
+
+    #[test]
+    fn watchdog_kills_at_the_deadline() {
+        let child = TrackedChild::track(Exec::cmd("sleep").arg("10").popen().unwrap());
+        let watchdog = child.kill_at(
+            Instant::now() + Duration::from_millis(100),
+            Duration::from_secs(10),
+        );
+        assert!(matches!(child.wait().unwrap(), ExitStatus::Signaled(_)));
+        assert!(watchdog.expired());
+
+        let child = TrackedChild::track(Exec::cmd("true").popen().unwrap());
+        let watchdog = child.kill_at(Instant::now() + Duration::from_secs(10), Duration::ZERO);
+        assert_eq!(child.wait().unwrap(), ExitStatus::Exited(0));
+        assert!(!watchdog.expired());
+    }
+
+    #[test]
+    fn watchdog_kills_an_ignored_terminate() {
+        let child = TrackedChild::track(
+            Exec::cmd("sh")
+                .args(&["-c", "trap '' TERM; exec sleep 10"])
+                .popen()
+                .unwrap(),
+        );
+        let start = Instant::now();
+        let watchdog = child.kill_at(
+            Instant::now() + Duration::from_millis(100),
+            Duration::from_millis(300),
+        );
+        assert_eq!(child.wait().unwrap(), ExitStatus::Signaled(9));
+        assert!(start.elapsed() >= Duration::from_millis(400));
+        assert!(watchdog.expired());
+    }
+
+    #[test]
+    fn killed_child_stops() {
+        let child = TrackedChild::track(Exec::cmd("sleep").arg("10").popen().unwrap());
+        child.kill();
+        assert!(matches!(child.wait().unwrap(), ExitStatus::Signaled(_)));
+    }
+}
@@ -8,6 +8,8 @@
 //!      default
 //!          This is the default behavior, like as it was from the beginning of RustScan.
 //!          The user do not have to chose anything for this. This is the only script embedded in RustScan running as default.
+//!          It runs nmap -vvv -p {{port}} {{ip}}, --scripts-default-verbosity sets how many -v flags it gets instead,
+//!          0 for none, and --scripts-default-args adds arguments before the -p, like --scripts-default-args "-sV -Pn".
 //!
 //!      none
 //!          The user have to use the --scripts none commandline argument or scripts = "none" in the config file.
@@ -15,13 +17,65 @@
 //!
 //!      custom
 //!          The user have to use the --scripts custom commandline argument or scripts = "custom" in the config file.
-//!          Rustscan will look for the script configuration file in the user's home dir: home_dir/.rustscan_scripts.toml
-//!          The config file have 3 optional fields, tag, developer and port. Just the tag field will be used forther in the process.
-//!          RustScan will also look for available scripts in the user's home dir: home_dir/.rustscan_scripts
-//!          and will try to read all the files, and parse them into a vector of ScriptFiles.
+//!          Rustscan will look for the script configuration file in the XDG config dir first: config_dir/rustscan/scripts.toml
+//!          ($XDG_CONFIG_HOME or ~/.config on Linux), falling back to the user's home dir: home_dir/.rustscan_scripts.toml
+//!          The config file have 3 optional fields, tag, developer and port. The tag and developer fields will be used further in the process.
+//!          RustScan will also look for available scripts in config_dir/rustscan/scripts first, if that folder exists,
+//!          falling back to the user's home dir: home_dir/.rustscan_scripts. The config and the folder are looked up on their own,
+//!          a legacy folder can be used with an XDG config file.
+//!          RustScan reads every file of that folder and parses them into a vector of ScriptFiles.
+//!          Only the files ending in .sh, .py, .pl or .txt and the executables without an extension are read, folders are skipped.
+//!          An extensions = ["rb", ""] list in the config file replaces these, "" stands for the extensionless executables.
+//!          With a scripts = ["a.sh", "b.py"] list in the config file exactly those files are parsed, in that order,
+//!          instead of every file of the scripts dir. Relative paths are resolved against the scripts folder.
+//!          --scripts-config path reads the config from that file instead, it can be repeated, like for a shared base config
+//!          and a personal overlay. The files are merged in order: the lists tags, ports, developer, scripts and extensions
+//!          get the entries of the later files appended, vars, aliases and profiles are merged by name, the other fields of a later file replace
+//!          the earlier ones. Unlike the looked up config the given files have to exist.
+//!          init_scripts returns a ScriptsOrigin with the config files it read, the scripts folder it used and the tags
+//!          it selected with, the Debug option logs them, to tell where the config that took effect came from.
 //!          Filtering on tags means the tags found in the rustscan_scripts.toml file will also have to be present in the Scriptfile,
 //!          otherwise the script will not be selected.
 //!          All of the rustscan_script.toml tags have to be present at minimum in a Scriptfile to get selected, but can be also more.
+//!          Without a config file, or without tags in it, there is no tag filter, every parsed script will run.
+//!          A missing scripts folder or a config that can't be read stops RustScan, with --scripts-optional it is
+//!          a warning instead and the scan results are reported without running any script.
+//!          When no script gets selected a warning tells why: the scripts folder is empty, none of its files have the
+//!          extensions, the scripts list of the config is empty, none of the headers parse,
+//!          the scripts do not have the tags, the selected ones are experimental, or the config has no tags but the other
+//!          filters dropped every script.
+//!          With --scripts-require-match that is an error stopping RustScan instead, so a misspelled tag fails a CI run.
+//!          The --scripts-tags commandline argument overrides the config file tags, an empty value selects every parsed script.
+//!          An [aliases] table in the config file names tag sets, web = ["http", "tls"], and --scripts-alias web filters
+//!          on those tags instead of the config file ones, together with the --scripts-tags ones if given too.
+//!          An unknown alias is an error listing the defined ones.
+//!          [profiles.name] tables in the config file hold other tags, developer and ports, --scripts-profile name
+//!          selects one and the fields it sets replace the top-level ones. Without the flag a [profiles.default] applies,
+//!          if there is one. An unknown profile is an error listing the defined ones.
+//!          With min_tag_matches = 2 in the config file a script only has to have 2 of the config tags, instead of all of them.
+//!          A tag starting with a !, like tags = ["web", "!intrusive"], excludes every script carrying it, even if the
+//!          script has the other tags. It works with --scripts-tags too, and only negated tags select all the other scripts.
+//!          Tags are trimmed and empty ones dropped, with case_insensitive_tags = true in the config file their case is ignored too.
+//!          If developers are present in the config file, a Scriptfile also has to list at least one of them in its developer field.
+//!          The tag and developer filters are combined, a Scriptfile has to pass both to get selected.
+//!          The --scripts-exclude commandline argument leaves out the selected scripts by their file name without extension.
+//!          --scripts-validate checks the config and every script without running anything: the headers have to parse,
+//!          the call formats may only use known placeholders, and the program they start has to be on the PATH.
+//!          It prints a line for each file and exits with 1 if any problem is found, for pre-commit hooks and CI.
+//!          --scripts-lint reports the selected scripts sharing an identical tag set, and exits.
+//!          --scripts-check-deps lists the programs the selected scripts start, the first word of their call formats,
+//!          and exits non zero when one of them is not found on the PATH.
+//!          --scripts-init writes a starter config and a few example scripts where the custom scripts are looked for,
+//!          existing files are only replaced with --force.
+//!          The selected scripts run ordered by their priority header, lower first, 0 without one.
+//!          Scripts with the same priority run in file name order, or in the order of the scripts list.
+//!          With include_default = true in the config file the default script is added to the parsed scripts,
+//!          it only runs if it passes the same filters.
+//!
+//!          With --scripts-bundle path.zip the config and the scripts come from a zip archive instead of the home dir.
+//!          A rustscan_scripts.toml in the archive is the config, every other file is a script, files in folders
+//!          are taken by their file name. The archive is unpacked into a temporary dir for the run, so {{script}}
+//!          is a path inside that dir.
 //!
 //! Config file example:
 //! fixtures/test_rustscan_scripts.toml
@@ -31,31 +85,369 @@
 //! fixtures/test_script.pl
 //! fixtures/test_script.sh
 //! fixtures/test_script.txt
+//! fixtures/test_script_block.py
+//!
+//! An optional description header, description = "...", is shown by --scripts-list and included in the JSON results.
+//!
+//! Script headers are the # lines right after the first line of the file.
+//! Binary files, with a NUL byte near their start, are skipped without being parsed.
+//! To use another comment syntax, the headers can be fenced between a RUSTSCAN-START and a RUSTSCAN-END line,
+//! everything in between is read as is. A TOML front matter between two +++ lines, the first of them
+//! within the first 10 lines of the file, is read as is too, see fixtures/test_script_front_matter.lua.
 //!
 //! call_format in script files can be of 2 variants.
 //! One is where all of the possible tags {{script}} {{ip}} {{port}} are there.
 //!     The {{script}} part will be replaced with the scriptfile full path gathered while parsing available scripts.
 //!     The {{ip}} part will be replaced with the ip we got from the scan.
+//!     The {{ip_bracketed}} part is the same ip, but IPv6 addresses are put between brackets, for urls like http://{{ip_bracketed}}:{{port}}
+//!     The {{hostname}} part will be replaced with the reverse DNS name of the ip, or the ip itself if it has none.
+//!     It is looked up once per ip, and only when a selected script uses it.
+//!     The {{target}} part will be replaced with the target the ip was scanned for, exactly as it was given,
+//!     like example.com, for SNI or virtual hosts. It is the ip itself when that was given, or was part of a CIDR.
 //!     The {{port}} part will be reaplced with the ports separated with the ports_separator found in the script file
+//!     The {{ports_compact}} part will be replaced with the open ports sorted, the consecutive ones collapsed into ranges,
+//!     like 80,443,8000-8100, a nmap style port spec much shorter than the full list.
+//!     The {{ports_file}} part will be replaced with the path of a temporary file listing the ports one per line,
+//!     for tools reading their ports from a file. The file is removed once the script finished.
+//!     The {{scan_id}} and {{timestamp}} parts will be replaced with a UUID and the RFC3339 start time of the RustScan run,
+//!     the same for every script of a run, for grouping the results later.
+//!     With --scripts-seed the UUID comes from the seed, the runs with the same seed get the same one.
+//!     The {{scan_fingerprint}} part will be replaced with a 16 hex digit hash of the scan options, the ports or range,
+//!     batch size, timeout, tries, scan order and top ports. The same options give the same fingerprint in every run,
+//!     so the findings can be tied to the scan profile that produced them. It is empty when the RunContext has none.
+//!     The {{results_file}} part will be replaced with the path of the file the scan results were saved to, set with
+//!     RunContext::results_file, for tools taking their targets from a file like nmap -iL. It is library only, RustScan
+//!     itself writes no results file, so it is always empty from the command line and --scripts-validate reports it.
+//!     The {{port1}} to {{port9}} parts will be replaced with the Nth lowest open port, each on its own,
+//!     for tools taking a fixed number of ports as separate arguments. They are empty when fewer ports are open,
+//!     and the open ports past the highest one used are left out. There is no {{port10}} or higher.
+//!     A [vars] table in the config file, for example wordlist = "/usr/share/wordlists/common.txt",
+//!     adds a {{vars.wordlist}} and a {{wordlist}} placeholder to every call format.
+//!     The built-in placeholders take precedence when a var has the same name.
+//!     The {{ports_flag:--port}} part will be replaced with the flag repeated for every open port, --port 80 --port 443,
+//!     for tools taking one port per flag. A flag ending in =, like {{ports_flag:--port=}}, gives --port=80 --port=443.
+//!     Without open ports it's replaced with nothing.
+//!     A {{?PORT:TEXT}} part, like {{?443:--https}}, will be replaced with TEXT when PORT is one of the open ports
+//!     of the script, and with nothing otherwise. PORT is a single port number, TEXT is taken as written,
+//!     spaces split it into several arguments like in the rest of the call format, and it can't contain braces.
+//!     Only the presence of one port can be tested, there is no else, no negation and no nesting.
+//!     A modifier after a | transforms the value of a placeholder, {{ip|base64}} is the ip in base64 with padding,
+//!     {{port|hex}} the hex digits of every byte of the port text, 80 gives 3830, and {{vars.user|upper}} and
+//!     {{vars.user|lower}} change the case. The modifiers are base64, hex, upper and lower, one per placeholder.
+//!     The {{services}} part will be replaced with the port:service pairs of the open ports with a known service,
+//!     separated with a ",", for example 80:http,443:https, and {{banner}} with the banner of the port,
+//!     or of the lowest open port with one when {{port}} has several ports. They are library only, they come from
+//!     the PortMetadata an embedder gives to Script::port_metadata, the port scan does not collect any, so they are
+//!     always empty from the command line and --scripts-validate reports them.
+//!     The {{scan_start}} part will be replaced with the RFC3339 start time of the port scan, {{scan_elapsed}} with the seconds
+//!     from then until the scripts started, like 12.345, {{ports_scanned}} with the number of ip-port pairs scanned and
+//!     {{ports_open}} with the number of them found open. They are the same for every script of the run.
+//!     A literal {{ or }} is written as {{{{ or }}}}, for example {{{{name}}}} ends up as {{name}} in the command.
+//!     With a template_delimiters list of << and >> in the config file the placeholders are written <<ip>> instead,
+//!     in the call formats, the output filters and the hooks, and {{ and }} are literal, for commands taking
+//!     Jinja or Go templates as arguments.
+//!     Scripts without a ports_separator use the --scripts-ports-separator commandline argument, or "," without it.
+//!     The separator is inserted verbatim, it can also be a space or an empty string.
+//!     A [separators] table sets the separator of each kind of list on its own, for example
+//!     separators = { ports = ",", ips = " ", sockets = ";" }. ports joins {{port}}, {{all_ports}}, {{closed_ports}}
+//!     and {{filtered_ports}} and takes precedence over the ports_separator, ips joins {{all_ips}} and sockets
+//!     {{all_sockets}}. The missing ones fall back to the ports_separator for the ports and to "," for the others.
+//!     The filled format is split like a shell would do it, so a space separated list ends up as one argument per port.
+//!
+//! The placeholders are filled by a TemplateEngine, PlaceholderTemplate replacing the {{name}} ones by default.
+//! Script::template_engine and ScriptEngine::template_engine plug in another one, built with the jinja feature
+//! JinjaTemplate renders the call formats with the loops, conditionals and filters of minijinja.
+//! --scripts-template jinja selects it from the command line, without the feature it is an error.
+//!
+//! The ports given to a script can be narrowed down with ports_filter, a list of ports and ranges like "22,80-90",
+//! and max_ports, which keeps only the lowest numbered ports. A filter not matching any open port leaves no ports.
+//!
+//! The --scripts-top-ports commandline argument caps the ports of every script to the lowest N open ports,
+//! before the max_ports and ports_filter of the script are applied. required_ports are still checked against all open ports.
+//!
+//! With required_ports, for example required_ports = [139, 445], a script only runs against an ip if all of them are open.
+//! Unlike the port field, none of them replaces {{port}}.
+//!
+//! With min_open_ports and max_open_ports, like min_open_ports = 10, a script only runs against an ip with that many
+//! open ports at least or at most, for the expensive scripts only worth it on the hosts with many open services.
+//! The bounds are inclusive, a min_open_ports over the max_open_ports is rejected while parsing.
+//!
+//! The ports list of the config file, ports and ranges like 80 and 8000-8100, scopes the whole scripting phase: every
+//! selected script only gets the open ports among them, before its own ports_filter is applied, and is not run against
+//! an ip without any open port among them. A script with a port field is only run when one of its ports is among them.
+//! Without ports in the config every open port is given, like before.
+//!
+//! With exclude_ports, for example exclude_ports = [22], those ports are taken out of the open ports given to the script.
+//! A script whose exclude_ports cover every open port of an ip is not run against it.
+//!
+//! With port_states, a list of the port states open, closed and filtered, the script gets the scanned ports
+//! in those states instead of the open ones, a refused connection makes a port closed and a timeout filtered.
+//! Without it a script only gets the open ports. The port scan only keeps the other states when a selected script asks for them,
+//! and the scripts still only run against ips with at least one open port. The {{closed_ports}} and
+//! {{filtered_ports}} parts are replaced with those ports separated with the ports_separator, whatever port_states is.
+//!
+//! The port field, for example port = "80", replaces {{port}} with that port, a list like port = "80,443"
+//! with the ports joined by the ports_separator. With a range, like port = "8000-8100" or port = "80,8000-8100",
+//! the call formats instead run once for every open port inside it, {{port}} being that port.
+//! The {{index}} part counts these runs from 0, in the order of the ports, it is 0 for the first port, 1 for the next.
+//! The call formats of one port all get the same index, without a range it's always 0. Every host counts from 0 again.
 //!
 //! And when there is only {{ip}} and {{port}} is in the format, ony those will be replaced with the arguments from the scan.
 //! This makes it easy to run a system installed command like nmap, and give any kind of arguments to it.
 //!
-//! If the format is different, the script will be silently discarded and will not run. With the Debug option it's possible to see where it goes wrong.
+//! call_format can also be an array of formats, they run in sequence and their outputs are concatenated.
+//! By default the first failing command stops the script, with fail_fast = false the rest still runs.
+//!
+//! When there is no call_format but an interpreter is defined, for example interpreter = "python3",
+//! the script file itself will run as: python3 {{script}} {{ip}} {{port}}
+//! Without either of them the interpreter is taken from the #! first line of the script, #!/usr/bin/env python3 gives python3.
+//!
+//! With scope = "run" in the script file a script runs once for the whole scan, after the scripts of every host,
+//! instead of once per host. On top of the usual placeholders, where {{ip}} is 0.0.0.0 and {{port}} every open port of any host,
+//! it gets {{all_ips}}, the ips with open ports separated with a ",", {{all_ports}}, every open port of any host
+//! separated with the ports_separator, and {{all_sockets}}, every open ip:port pair separated with a "," ([ip]:port for IPv6).
+//! The [separators] table changes each of them.
+//!
+//! --scripts-ip-version 4 or 6 only runs the host scripts against the ips of that version, for tools supporting
+//! only one of them, the default both runs them against every ip.
+//!
+//! With depends_on, for example depends_on = "version_detect", a script only runs against an ip after the script
+//! of that file name, without extension, succeeded on it. It is skipped when that one failed or was skipped.
+//! The dependencies run first, a dependency that is not selected or a dependency cycle fail the selection.
+//!
+//! With requires_root = true a script needs root, or an elevated administrator on Windows, like raw socket nmap scans.
+//! When RustScan is not elevated it is skipped with a warning, --scripts-run-unprivileged runs it anyway, also with a warning.
+//!
+//! A script with experimental = true or an experimental tag is still in development, it is left out after the tag matching
+//! unless --scripts-experimental is given, so it can sit in the scripts folder without running in the normal runs.
+//!
+//! With os_match or service_match, regexes ignoring the case like os_match = "windows" or service_match = "^smb$",
+//! a script only runs against the hosts whose detected OS, or one of their detected services, match.
+//! They are checked against the detection data of the RunContext, the hosts without detected data run the script anyway,
+//! unless --scripts-skip-undetected is given. Invalid patterns are rejected while parsing.
+//! The detection data comes from --scripts-detection path, a TOML file with a table per quoted ip holding
+//! an optional os string, like os = "Windows", and an optional services list of names like smb or http.
+//! The port scan does not detect any.
+//!
+//! --scripts-rate N starts at most N script commands per second over the whole run, evenly spaced, on top of
+//! the concurrency limits, which only bound how many run at the same time. There is no limit by default.
+//!
+//! The scripts of a host run on a ScriptEngine, --scripts-concurrency sets how many of them run at the same time (default 1).
+//! With more than one worker the results are reported in completion order.
+//! --scripts-host-concurrency sets how many hosts have their scripts running at the same time (default 1),
+//! so at most its value times --scripts-concurrency commands are running at once.
+//! --scripts-per-host-concurrency is another name of --scripts-concurrency.
+//! --scripts-hosts takes a comma separated list of ips and CIDRs, or a file with one of them on every line, and only
+//! the scanned hosts inside it are scripted, the {{all_ips}} of the run scoped scripts only has those too.
+//! The other hosts are still scanned and reported.
+//! --scripts-budget N weighs the scripts by their cost header instead, cost = 5 for a full nmap run and 1 without one:
+//! scripts are started until the costs of the running ones add up to N, over all the hosts. It replaces
+//! --scripts-concurrency, with only cost 1 scripts N of them run at the same time. A script costing more than N runs alone.
+//! A script is run only once per host for the same script file, ports and call formats, even when several
+//! selections, like two ports_filter of the same file, would run the same commands. The duplicates are skipped.
+//!
+//! With --scripts-output-dir every script output is written into its own file, gzipped with --scripts-output-compress.
+//! With --scripts-combined-output path they are all appended to a single file instead, each after a
+//! ==> script on ip port port <== line. A failed script has its error there.
+//! With --scripts-sqlite path every result is inserted into the script_results table of a SQLite database,
+//! created on the first run, with its scan_id, the time it was inserted at, ip, port, script, stdout, exit_code
+//! and error. The results of later runs are added to the same table, for querying the findings across runs.
+//!
+//! A failing script prints a warning, but RustScan still exits with 0. With --scripts-ignore-errors the failures are only logged,
+//! with --scripts-strict any failing script makes RustScan exit with 1.
+//!
+//! With --scripts-manifest path a JSON summary of the scripting phase is written once all the scripts completed:
+//! the RustScan version, the scan_id and timestamp, the scripts directory and tags, the selected scripts,
+//! every run with its ip, ports, output and error, and the number of passed, failed and skipped runs.
+//! With --scripts-output json the same ScriptReport is printed to stdout instead of the outputs of the scripts.
+//! It has a format_version, and can be read back with serde, ScriptReport implements Deserialize too.
+//! With --scripts-junit path a JUnit XML report is written for the CI systems: a testsuite per script with the
+//! summed up time of its runs, and a testcase per run against a host, failed with the error when the script
+//! exited non zero, timed out or could not start, and its stderr. The outputs and stderrs are in the system-out and
+//! system-err of the testcases.
+//! Every run in the JSON results has a success field, a successful script printing nothing has success true
+//! and an empty output. The text output and the combined output show (no output) for it instead of a blank line.
+//!
+//! A status line is printed to stderr as every script completes, colored unless --no-color or NO_COLOR is set.
+//! Once all of them completed a summary line follows: how many scripts were discovered, parsed and selected,
+//! and how many runs there were, succeeded, failed and were skipped for missing required ports.
+//!
+//! Commands are spawned directly, pipes, globs and other shell features in a call_format do not work.
+//! With shell = true in the script file, or --scripts-shell for the scripts without a shell field, the filled
+//! call_format runs through sh -c (cmd /C on Windows) instead. The ip and ports are not quoted for the shell,
+//! so only use it with scripts you trust.
+//!
+//! The stdout of a command is captured up to --script-max-output bytes (4 MiB by default). A command writing more
+//! is stopped, failing with the exit code of the killed command, and its output is cut off at the limit with a note
+//! that it was capped. Its stderr is captured up to that limit too, into the stderr of the result.
+//! A failed command keeps its output and stderr in the result.
+//! With --scripts-stream the stdout of the commands is also printed line by line as it comes, for slow tools
+//! like a full nmap scan, the captured output staying the same. Their stderr is printed line by line as it comes then,
+//! and once they exited otherwise.
+//!
+//! The results are rendered to a ResultWriter as they complete, stdout on the commandline. Embedders give the
+//! ScriptEngine one around any writer, like an in-memory buffer or a socket, with ScriptEngine::output.
+//!
+//! The commands are spawned through a CommandRunner, SubprocessRunner by default. Tests can put a runner
+//! recording the commands in its place.
+//!
+//! A ScriptObserver given to the ScriptEngine is told about every script starting, failing and finishing.
+//!
+//! Long lived embedders give the ScriptEngine a ScriptSource and call ScriptEngine::reload after changing the
+//! scripts folder, the scripts are found, parsed and filtered again without a restart.
+//!
+//! Hitting Ctrl-C during the scripts phase kills the running script commands and starts no new ones.
+//! The results that already came in are still printed and written, then RustScan exits with 130.
+//!
+//! --scripts-deadline N caps the whole scripts phase at N seconds, for bounded CI runs. When it passes the running
+//! commands are terminated, SIGTERM on unix, and killed if they still run --scripts-kill-grace seconds later
+//! (default 2). On Windows they are terminated right away. Their results get a deadline error, no new script
+//! is started, and the results completed until then are reported with a warning that the run was cut short.
+//!
+//! A command passes when it exits with 0, success_exit_codes = [0, 1] lets the tools exiting with 1 on findings,
+//! like grep without a match, pass too. Every other exit code fails the script. The exit_code of the results is the
+//! passing non zero one then. An empty list is rejected while parsing.
+//! The command of a result is the argv that ran, after templating and splitting, to rerun it by hand. With several
+//! call formats it is the first failing one, or the last one when they all passed.
+//! Its binary_path is the absolute path of the program of the call format found on the PATH, empty when there is none,
+//! the one a shell = true command line starts and not the shell. A remote script has the program as written, it is on
+//! the remote host. With a version_arg, like version_arg = "--version", the binary_version is the first line the program
+//! prints for it, to tell which build produced a result later. It is run once per binary in a run, outside of
+//! --scripts-rate, and empty without a version_arg or for a remote script.
+//!
+//! A failed script, non-zero exit code or failing to spawn, is retried retries times (default 0) with an exponential backoff.
+//! The --script-retries commandline argument sets this for the scripts without their own retries field.
+//!
+//! With an extract regex the output of a script is replaced with the first capture group of the pattern,
+//! the full output is kept when it does not match. Patterns without a capture group are rejected while parsing.
+//!
+//! A [[findings]] table with a name and a pattern, for example name = "version" and pattern = 'version: ([\d.]+)',
+//! collects every match of the pattern in the output under that name, into the findings of the JSON results.
+//! A match gives its first capture group, or the whole match for a pattern without one. There can be several
+//! of them, they have to come after the other headers, as TOML puts every key after a table header into the table.
+//! Findings are taken from the full output, before an extract pattern replaces it. Invalid patterns are rejected while parsing.
+//!
+//! An output_filter call format, like output_filter = "jq -c .", or --scripts-output-filter for the scripts without one,
+//! gets the output of the script on its stdin, and its stdout replaces the output before the extract pattern and the
+//! findings are applied and anything is printed or stored. It has the placeholders of the call formats.
+//! A failing filter is logged and the raw output kept.
+//!
+//! A stdin header, like stdin = "{{ip}}", is written to the stdin of every command of the script, with the placeholders
+//! of the call formats filled, for tools reading their targets from it like nmap -iL -. The ports and ips don't have
+//! to fit on the command line then. The commands with a stdin are never streamed, without it they inherit the stdin
+//! of RustScan.
+//!
+//! A [remote] table with a host, and optionally a user and a port, runs the commands of the script on that
+//! machine over ssh instead of locally. The placeholders are filled in locally, so {{script}} is the local path,
+//! and the filled command is quoted for the remote shell: ssh -o BatchMode=yes -p port user@host -- 'command'.
+//! ssh never prompts, the key has to be set up for it. The output filter still runs locally.
+//!
+//! The open ports are sorted ascending and deduplicated before the call formats are filled, so the commands
+//! and the saved outputs are the same whatever order the scan found the ports in. sort_ports = false in the
+//! header of a script, or --scripts-scan-port-order for the scripts without that header, keeps the scan order.
+//!
+//! With capture_output = false the output of the commands is never read, their stdout and stderr go nowhere
+//! and only the exit code tells whether the script passed, for health checks with chatty commands.
+//! Their output is empty in the results.
+//!
+//! With sensitive = true the output of a script, like credentials or tokens, is never printed: the terminal,
+//! the JSON results, the combined output and the manifest only show its length, its findings and the arguments of its command are redacted too,
+//! and it is not streamed. Only its --scripts-output-dir file gets the full output, readable by the owner only on unix.
+//!
+//! --scripts-dump prints the ScriptFile every discovered script was parsed into, as TOML or in the --scripts-output format,
+//! to compare the headers as written with what RustScan read from them. Scripts failing to parse show the reason.
+//!
+//! The errors of a failing script or config carry a ScriptError with their category, a parse error, a missing
+//! scripts folder or a file in its place, a non zero exit code, a program that is not installed, a Ctrl-C or the deadline. ScriptError::of gets it out
+//! of the anyhow error, and its kind is in the error_kind of the JSON results.
+//!
+//! The config file can also have a pre_hook and a post_hook call format, running once before and after all the scripts.
+//! They are not tied to an ip, only {{ips}} is replaced, with all the scanned ips separated with a ",".
+//! A failing hook is a warning, with hooks_fatal = true it stops RustScan.
+//!
+//! A selected script with neither a call_format nor an interpreter is skipped with a warning.
+//!
+//! Headers over 200 lines or 16 KiB, or nesting arrays and tables more than 32 levels deep, are rejected
+//! before parsing, so a crafted script file can't exhaust the memory or the stack of RustScan.
+//!
+//! If the headers do not parse, the script will be discarded and will not run. With the Debug option it's possible
+//! to see where it goes wrong, --scripts-validate and --scripts-dump show it too. A call format with a placeholder
+//! that is not known, like a misspelled {{ipp}}, fails the script with an error naming the script and the format,
+//! instead of running it with the placeholder left empty.
+//! ScriptFile::parse_with_diagnostics gives the reasons to tools like linters, each an error or a warning
+//! with the line of the file it is on when that is known.
 
 #![allow(clippy::module_name_repetitions)]
 
-use crate::input::ScriptsRequired;
-use anyhow::{anyhow, Result};
+mod bundle;
+pub use bundle::ScriptBundle;
+
+mod diagnostic;
+#[allow(unused_imports)]
+pub use diagnostic::{ParseDiagnostic, Severity};
+
+mod engine;
+#[allow(unused_imports)]
+pub use engine::{
+    HostCache, HostResolver, NoopObserver, ScriptObserver, ScriptSource, SystemResolver,
+};
+pub use engine::{HostJob, ScriptEngine};
+
+mod error;
+pub use error::ScriptError;
+
+mod host_filter;
+pub use host_filter::HostFilter;
+
+mod init;
+pub use init::init_example_scripts;
+
+mod interrupt;
+pub use interrupt::{install_interrupt_handler, interrupted};
+use interrupt::{TrackedChild, Watchdog};
+
+mod privilege;
+use privilege::running_elevated;
+
+mod template;
+#[cfg(feature = "jinja")]
+#[allow(unused_imports)]
+pub use template::JinjaTemplate;
+use template::{add_modified_values, MODIFIERS};
+pub use template::{select_template_engine, PlaceholderTemplate, TemplateEngine};
+
+mod validate;
+use validate::find_program;
+pub use validate::{missing_binaries, script_binaries, validate_scripts};
+
+mod output;
+pub use output::{
+    format_script_dump, format_script_list, format_stats, format_status, format_timings,
+    scripts_exit_code, write_junit, write_manifest, write_result_to_dir, CombinedOutput,
+    ResultWriter, ScriptReport, ScriptStats, ScriptStatus, SqliteOutput,
+};
+
+use crate::input::{Opts, PortRange, ScriptsRequired};
+use crate::scanner::PortState;
+use anyhow::{anyhow, Context, Result};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use regex::Regex;
 use serde_derive::{Deserialize, Serialize};
-use std::collections::HashSet;
-use std::convert::TryInto;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::{self, Write as _};
 use std::fs::{self, File};
 use std::io::{self, prelude::*};
 use std::net::IpAddr;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 use std::string::ToString;
-use subprocess::{Exec, ExitStatus};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+use subprocess::{Exec, ExitStatus, NullFile, Popen, PopenError, Redirection};
+use tempfile::NamedTempFile;
 use text_placeholder::Template;
 
 static DEFAULT: &str = r#"tags = ["core_approved", "RustScan", "default"]
@@ -64,61 +456,592 @@ ports_separator = ","
 call_format = "nmap -vvv -p {{port}} {{ip}}"
 "#;
 
-#[cfg(not(tarpaulin_include))]
-pub fn init_scripts(scripts: ScriptsRequired) -> Result<Vec<ScriptFile>> {
-    let mut scripts_to_run: Vec<ScriptFile> = Vec::new();
+// Delay before the first retry of a failed script, doubled on every further retry.
+const RETRY_BACKOFF: Duration = Duration::from_millis(100);
+
+// Extensions of the files taken from the scripts folder, "" for executables without an extension.
+static DEFAULT_EXTENSIONS: &[&str] = &["sh", "py", "pl", "txt", ""];
+
+// Markers of a fenced script header, for languages that do not use # comments.
+static HEADER_START: &str = "RUSTSCAN-START";
+static HEADER_END: &str = "RUSTSCAN-END";
+// Delimiter line of a TOML front matter header, only looked for in the first FRONT_MATTER_LINES lines.
+static FRONT_MATTER: &str = "+++";
+const FRONT_MATTER_LINES: usize = 10;
+// Limits of the headers read from a script file, larger or deeper ones are rejected before TOML parsing.
+// Only the first MAX_SCAN_BYTES of a file are read, so a single endless line can't blow up the memory.
+const MAX_SCAN_BYTES: u64 = 1024 * 1024;
+const MAX_HEADER_LINES: usize = 200;
+const MAX_HEADER_BYTES: usize = 16 * 1024;
+const MAX_HEADER_NESTING: usize = 32;
 
-    match scripts {
-        ScriptsRequired::None => Ok(scripts_to_run),
+#[cfg(not(tarpaulin_include))]
+pub fn init_scripts(
+    opts: &Opts,
+    bundle: Option<&ScriptBundle>,
+    config: Option<ScriptConfig>,
+) -> Result<(Vec<ScriptFile>, ScriptStats, ScriptsOrigin)> {
+    match opts.scripts {
+        ScriptsRequired::None => Ok((Vec::new(), ScriptStats::default(), ScriptsOrigin::default())),
         ScriptsRequired::Default => {
-            let default_script =
-                toml::from_str::<ScriptFile>(&DEFAULT).expect("Failed to parse Script file.");
-            scripts_to_run.push(default_script);
-            Ok(scripts_to_run)
-        }
-        ScriptsRequired::Custom => {
-            let scripts_dir_base = match dirs::home_dir() {
-                Some(dir) => dir,
-                None => return Err(anyhow!("Could not infer scripts path.")),
+            let stats = ScriptStats {
+                selected: 1,
+                ..ScriptStats::default()
             };
-            let script_paths = match find_scripts(scripts_dir_base) {
-                Ok(script_paths) => script_paths,
-                Err(e) => return Err(anyhow!(e)),
+            let origin = ScriptsOrigin {
+                tags: default_script().tags,
+                ..ScriptsOrigin::default()
             };
-            debug!("Scripts paths \n{:?}", script_paths);
+            Ok((vec![default_script_for(opts)], stats, origin))
+        }
+        // With --scripts-optional the scripts are a best effort, the scan results are reported without them.
+        ScriptsRequired::Custom => match init_custom_scripts(opts, bundle, config) {
+            Err(e) if opts.scripts_optional => {
+                crate::warning!(
+                    format!("Initiating scripts failed, continuing without them\n{e:#}"),
+                    opts.greppable,
+                    opts.accessible
+                );
+                Ok((Vec::new(), ScriptStats::default(), ScriptsOrigin::default()))
+            }
+            initialized => initialized,
+        },
+    }
+}
+
+fn init_custom_scripts(
+    opts: &Opts,
+    bundle: Option<&ScriptBundle>,
+    config: Option<ScriptConfig>,
+) -> Result<(Vec<ScriptFile>, ScriptStats, ScriptsOrigin)> {
+    let mut stats = ScriptStats::default();
+    let location = scripts_location(bundle)?;
+    let mut origin = ScriptsOrigin {
+        config_files: if config.is_some() {
+            Vec::new()
+        } else {
+            config_files(&location, &opts.scripts_config)
+        },
+        scripts_dir: Some(location.scripts_dir.clone()),
+        tags: None,
+    };
+    debug!("{}", origin);
+    // A config built in code is used as is, otherwise it's read from the config file of the location.
+    let mut script_config = match config {
+        Some(script_config) => script_config,
+        None => match read_script_config(&location, &opts.scripts_config) {
+            Ok(script_config) => script_config,
+            Err(e) => return Err(anyhow!(e)),
+        },
+    };
+    debug!("Script config \n{:?}", script_config);
+
+    let scripts_dir = location.scripts_dir;
+    let mut parsed_scripts = discover_scripts(&scripts_dir, &script_config, &mut stats)?;
+
+    script_config = script_config.with_profile(opts.scripts_profile.as_deref())?;
+    // Tags given on the commandline take precedence over the ScriptConfig tags.
+    if let Some(tags) = &opts.scripts_tags {
+        script_config.tags = Some(tags.clone());
+    }
+    if let Some(alias) = &opts.scripts_alias {
+        let alias_tags = script_config.alias_tags(alias)?;
+        script_config.tags = match &opts.scripts_tags {
+            Some(tags) => union(Some(tags.clone()), Some(alias_tags)),
+            None => Some(alias_tags),
+        };
+    }
+    origin.tags.clone_from(&script_config.tags);
+
+    // The default script goes through the same filters as the custom ones.
+    if script_config.include_default.unwrap_or(false) {
+        parsed_scripts.push(default_script_for(opts));
+    }
+
+    let mut scripts_to_run = filter_scripts(&parsed_scripts, &script_config);
+    if let Some(excluded) = &opts.scripts_exclude {
+        scripts_to_run = exclude_scripts(scripts_to_run, excluded);
+    }
+    // The experimental scripts are gated after the tag matching, selecting their tag is not enough.
+    if !opts.scripts_experimental {
+        let filtered = scripts_to_run.len();
+        scripts_to_run.retain(|script| {
+            let experimental = script.is_experimental();
+            if experimental {
+                debug!(
+                    "Skipping experimental script {}, --scripts-experimental runs it",
+                    script.name()
+                );
+            }
+            !experimental
+        });
+        stats.experimental = filtered - scripts_to_run.len();
+    }
+    scripts_to_run.retain(|script| {
+        let runnable = script.is_runnable();
+        if !runnable {
+            crate::warning!(
+                format!(
+                    "Skipping script {}, it has no call_format",
+                    script
+                        .path
+                        .as_deref()
+                        .unwrap_or_else(|| Path::new(""))
+                        .display()
+                ),
+                opts.greppable,
+                opts.accessible
+            );
+        }
+        runnable
+    });
+    check_privileges(&mut scripts_to_run, running_elevated(), opts);
+    // Lower priorities run first, the sort is stable so ties keep the order they were found in.
+    scripts_to_run.sort_by_key(ScriptFile::priority);
+    let scripts_to_run = order_by_dependencies(scripts_to_run)?;
+    debug!("\nScript(s) to run {:?}", scripts_to_run);
+    stats.selected = scripts_to_run.len();
+    if let Some(message) = empty_selection_message(&stats, &script_config, &scripts_dir) {
+        if opts.scripts_require_match {
+            return Err(anyhow!(
+                "{}, --scripts-require-match needs at least one",
+                message
+            ));
+        }
+        crate::warning!(message, opts.greppable, opts.accessible);
+    }
+    Ok((scripts_to_run, stats, origin))
+}
+
+// The parsed scripts of the files the config selects, without duplicates and with the template_delimiters applied.
+fn discover_scripts(
+    scripts_dir: &Path,
+    script_config: &ScriptConfig,
+    stats: &mut ScriptStats,
+) -> Result<Vec<ScriptFile>> {
+    let script_paths = config_script_paths(scripts_dir, script_config)?;
+    debug!("Scripts paths \n{:?}", script_paths);
+    stats.discovered = script_paths.len();
+
+    let parsed_scripts = parse_scripts(script_paths);
+    stats.parsed = parsed_scripts.len();
+    let mut parsed_scripts = dedup_scripts(parsed_scripts);
+    if let Some((open, close)) = script_config.delimiters() {
+        for script_f in &mut parsed_scripts {
+            script_f.use_delimiters(open, close);
+        }
+    }
+    debug!("Scripts parsed \n{:?}", parsed_scripts);
+    Ok(parsed_scripts)
+}
+
+/// Discovers, parses and filters the scripts of the location like init_scripts does, without the commandline
+/// options, for ScriptEngine::reload. Without a config the config file of the location is read.
+pub fn load_scripts(
+    location: &ScriptsLocation,
+    config: Option<&ScriptConfig>,
+) -> Result<Vec<ScriptFile>> {
+    let script_config = match config {
+        Some(script_config) => script_config.clone(),
+        None => ScriptConfig::read_config_from(&location.config)?,
+    };
+    let parsed_scripts = discover_scripts(
+        &location.scripts_dir,
+        &script_config,
+        &mut ScriptStats::default(),
+    )?;
+    let mut scripts = filter_scripts(&parsed_scripts, &script_config);
+    scripts.retain(ScriptFile::is_runnable);
+    scripts.sort_by_key(ScriptFile::priority);
+    order_by_dependencies(scripts)
+}
+
+// The os_match and service_match patterns ignore the case, Windows matches windows.
+fn detection_regex(pattern: &str) -> Result<Regex, regex::Error> {
+    regex::RegexBuilder::new(pattern)
+        .case_insensitive(true)
+        .build()
+}
 
-            let parsed_scripts = parse_scripts(script_paths);
-            debug!("Scripts parsed \n{:?}", parsed_scripts);
+/// Skips the scripts with requires_root = true when not elevated, or only warns about them with
+/// --scripts-run-unprivileged, their commands are likely to fail then.
+fn check_privileges(scripts: &mut Vec<ScriptFile>, elevated: bool, opts: &Opts) {
+    if elevated {
+        return;
+    }
+    scripts.retain(|script| {
+        if !script.requires_root() {
+            return true;
+        }
+        let message = if opts.scripts_run_unprivileged {
+            format!(
+                "Script {} requires root, RustScan is not elevated so it may fail",
+                script.name()
+            )
+        } else {
+            format!(
+                "Skipping script {}, it requires root and RustScan is not elevated, \
+                 --scripts-run-unprivileged runs it anyway",
+                script.name()
+            )
+        };
+        crate::warning!(message, opts.greppable, opts.accessible);
+        opts.scripts_run_unprivileged
+    });
+}
 
-            let script_config = match ScriptConfig::read_config() {
-                Ok(script_config) => script_config,
-                Err(e) => return Err(anyhow!(e)),
+/// Moves every script after the one it depends_on, keeping the order otherwise.
+/// Fails for a dependency that is not among the scripts and for a dependency cycle.
+fn order_by_dependencies(scripts: Vec<ScriptFile>) -> Result<Vec<ScriptFile>> {
+    let names: Vec<String> = scripts.iter().map(ScriptFile::name).collect();
+    let mut placed = vec![false; scripts.len()];
+    let mut order: Vec<usize> = Vec::with_capacity(scripts.len());
+    for start in 0..scripts.len() {
+        // The script and its chain of dependencies not placed yet, each script has at most one.
+        let mut chain: Vec<usize> = Vec::new();
+        let mut current = Some(start);
+        while let Some(index) = current.filter(|index| !placed[*index]) {
+            if chain.contains(&index) {
+                let cycle: Vec<&str> = chain.iter().map(|index| names[*index].as_str()).collect();
+                return Err(anyhow!(
+                    "Script dependency cycle: {} -> {}",
+                    cycle.join(" -> "),
+                    names[index]
+                ));
+            }
+            chain.push(index);
+            current = match &scripts[index].depends_on {
+                Some(dependency) => Some(
+                    names
+                        .iter()
+                        .position(|name| name == dependency)
+                        .ok_or_else(|| {
+                            anyhow!(
+                                "Script {} depends on {}, which is not selected",
+                                names[index],
+                                dependency
+                            )
+                        })?,
+                ),
+                None => None,
             };
-            debug!("Script config \n{:?}", script_config);
-
-            // Only Scripts that contain all the tags found in ScriptConfig will be selected.
-            if script_config.tags.is_some() {
-                let config_hashset: HashSet<String> =
-                    script_config.tags.unwrap().into_iter().collect();
-                for script in &parsed_scripts {
-                    if script.tags.is_some() {
-                        let script_hashset: HashSet<String> =
-                            script.tags.clone().unwrap().into_iter().collect();
-                        if config_hashset.is_subset(&script_hashset) {
-                            scripts_to_run.push(script.to_owned());
-                        } else {
-                            debug!(
-                                "\nScript tags does not match config tags {:?} {}",
-                                &script_hashset,
-                                script.path.clone().unwrap().display()
-                            );
-                        }
-                    }
+        }
+        for index in chain.into_iter().rev() {
+            placed[index] = true;
+            order.push(index);
+        }
+    }
+    let mut scripts: Vec<Option<ScriptFile>> = scripts.into_iter().map(Some).collect();
+    Ok(order
+        .into_iter()
+        .filter_map(|index| scripts[index].take())
+        .collect())
+}
+
+/// Why no custom script got selected, None when some are.
+fn empty_selection_message(
+    stats: &ScriptStats,
+    script_config: &ScriptConfig,
+    scripts_dir: &Path,
+) -> Option<String> {
+    if stats.selected > 0 {
+        return None;
+    }
+    Some(if stats.discovered == 0 {
+        no_scripts_message(script_config, scripts_dir)
+    } else if stats.parsed == 0 {
+        format!(
+            "No scripts will run, none of the {} script(s) found have headers that parse",
+            stats.discovered
+        )
+    } else if stats.experimental > 0 {
+        format!(
+            "No scripts will run, the {} selected script(s) are experimental and need --scripts-experimental",
+            stats.experimental
+        )
+    } else {
+        match script_config.tags.as_deref() {
+            None => format!(
+                "No scripts will run, the config has no tags so there is no tag filter, but none of the {} parsed script(s) passed the developer and exclude filters or can run",
+                stats.parsed
+            ),
+            Some([]) => format!(
+                "No scripts will run, the tags are empty so there is no tag filter, but none of the {} parsed script(s) passed the developer and exclude filters or can run",
+                stats.parsed
+            ),
+            Some(tags) => format!(
+                "No scripts will run, none of the {} parsed script(s) have all the tags {:?}",
+                stats.parsed, tags
+            ),
+        }
+    })
+}
+
+// Why no script was found: the scripts folder is empty, which looks like a run with nothing matched otherwise,
+// its files do not have the extensions, or the scripts list of the config is empty.
+fn no_scripts_message(script_config: &ScriptConfig, scripts_dir: &Path) -> String {
+    let entries = fs::read_dir(scripts_dir).map(Iterator::count);
+    if script_config.scripts.is_some() {
+        "No scripts will run, the scripts list of the config is empty".to_string()
+    } else if let Ok(0) = entries {
+        format!(
+            "No scripts will run, the scripts folder {} is empty, the scripts have to be put there",
+            scripts_dir.display()
+        )
+    } else if let Ok(entries) = entries {
+        format!(
+            "No scripts will run, none of the {} file(s) in {} have one of the extensions {:?}",
+            entries,
+            scripts_dir.display(),
+            script_config
+                .extensions
+                .clone()
+                .unwrap_or_else(default_extensions)
+        )
+    } else {
+        format!(
+            "No scripts will run, there are no scripts in {}",
+            scripts_dir.display()
+        )
+    }
+}
+
+/// Where the script config file and the scripts folder are.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScriptsLocation {
+    pub config: PathBuf,
+    pub scripts_dir: PathBuf,
+}
+
+impl ScriptsLocation {
+    /// The .rustscan_scripts.toml and the .rustscan_scripts folder of a dir, the home dir or an unpacked bundle.
+    pub fn in_dir(dir: &Path) -> Self {
+        Self {
+            config: dir.join(".rustscan_scripts.toml"),
+            scripts_dir: dir.join(".rustscan_scripts"),
+        }
+    }
+
+    /// The rustscan/scripts.toml and the rustscan/scripts folder of the config dir, each of them when it exists,
+    /// the ones of the home dir otherwise.
+    fn layered(config_dir: Option<&Path>, home_dir: Option<&Path>) -> Result<Self> {
+        let xdg = config_dir.map(|dir| Self {
+            config: dir.join("rustscan").join("scripts.toml"),
+            scripts_dir: dir.join("rustscan").join("scripts"),
+        });
+        let legacy = home_dir.map(Self::in_dir);
+        let (xdg, legacy) = match (xdg, legacy) {
+            (Some(xdg), Some(legacy)) => (xdg, legacy),
+            (Some(only), None) | (None, Some(only)) => return Ok(only),
+            (None, None) => return Err(anyhow!("Could not infer scripts path.")),
+        };
+        Ok(Self {
+            config: if xdg.config.is_file() {
+                xdg.config
+            } else {
+                legacy.config
+            },
+            scripts_dir: if xdg.scripts_dir.is_dir() {
+                xdg.scripts_dir
+            } else {
+                legacy.scripts_dir
+            },
+        })
+    }
+}
+
+/// Where the custom scripts of init_scripts were read from, to tell which config took effect.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ScriptsOrigin {
+    /// The config files read, merged in this order. Empty when there is none, the config was built in code
+    /// or no custom scripts are run.
+    pub config_files: Vec<PathBuf>,
+    /// The scripts folder, None when no custom scripts are run.
+    pub scripts_dir: Option<PathBuf>,
+    /// The tags the scripts were selected with, after the profile, --scripts-tags and --scripts-alias.
+    /// None without a tag filter, as recorded in the --scripts-manifest.
+    pub tags: Option<Vec<String>>,
+}
+
+impl fmt::Display for ScriptsOrigin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let config_files: Vec<String> = self
+            .config_files
+            .iter()
+            .map(|path| path.display().to_string())
+            .collect();
+        write!(
+            f,
+            "Script config {}, scripts folder {}",
+            if config_files.is_empty() {
+                "none".to_string()
+            } else {
+                config_files.join(", ")
+            },
+            self.scripts_dir
+                .as_deref()
+                .map_or_else(|| "none".to_string(), |dir| dir.display().to_string())
+        )
+    }
+}
+
+/// The config files read_script_config reads, the config file of the location only when it exists.
+fn config_files(location: &ScriptsLocation, configs: &[PathBuf]) -> Vec<PathBuf> {
+    if configs.is_empty() {
+        Some(location.config.clone())
+            .filter(|config| config.is_file())
+            .into_iter()
+            .collect()
+    } else {
+        configs.to_vec()
+    }
+}
+
+/// The merged --scripts-config files when there are any, the config file of the location otherwise.
+fn read_script_config(location: &ScriptsLocation, configs: &[PathBuf]) -> Result<ScriptConfig> {
+    if configs.is_empty() {
+        ScriptConfig::read_config_from(&location.config)
+    } else {
+        ScriptConfig::read_configs(configs)
+    }
+}
+
+/// Where the config and the scripts folder are looked for, in the unpacked bundle if there is one,
+/// otherwise in the XDG config dir, falling back to the home dir for each of them.
+fn scripts_location(bundle: Option<&ScriptBundle>) -> Result<ScriptsLocation> {
+    match bundle {
+        Some(bundle) => Ok(ScriptsLocation::in_dir(bundle.base_dir())),
+        None => {
+            ScriptsLocation::layered(dirs::config_dir().as_deref(), dirs::home_dir().as_deref())
+        }
+    }
+}
+
+/// The built-in nmap script, running when --scripts default is used.
+pub fn default_script() -> ScriptFile {
+    toml::from_str::<ScriptFile>(DEFAULT).expect("Failed to parse Script file.")
+}
+
+/// The built-in nmap script with the --scripts-default-verbosity and --scripts-default-args of the opts,
+/// the same as default_script without either of them.
+pub fn default_script_for(opts: &Opts) -> ScriptFile {
+    let mut script_f = default_script();
+    if opts.scripts_default_verbosity.is_some() || opts.scripts_default_args.is_some() {
+        script_f.call_format = Some(CallFormat::Single(default_call_format(
+            opts.scripts_default_verbosity,
+            opts.scripts_default_args.as_deref(),
+        )));
+    }
+    script_f
+}
+
+// nmap with a -v for every verbosity level, 3 without one, and the args before the ports and the ip.
+fn default_call_format(verbosity: Option<u8>, args: Option<&str>) -> String {
+    let mut call_format = "nmap".to_string();
+    let verbosity = usize::from(verbosity.unwrap_or(3));
+    if verbosity > 0 {
+        call_format.push_str(" -");
+        call_format.push_str(&"v".repeat(verbosity));
+    }
+    if let Some(args) = args.map(str::trim).filter(|args| !args.is_empty()) {
+        call_format.push(' ');
+        call_format.push_str(args);
+    }
+    call_format.push_str(" -p {{port}} {{ip}}");
+    call_format
+}
+
+/// Only Scripts that contain all the tags found in ScriptConfig will be selected.
+/// No tags or an empty tag list means there is no tag filter, so every parsed script is selected.
+/// When developers are configured too, the script also needs at least one of them in its developer list.
+pub fn filter_scripts(
+    parsed_scripts: &[ScriptFile],
+    script_config: &ScriptConfig,
+) -> Vec<ScriptFile> {
+    let mut scripts_to_run: Vec<ScriptFile> = Vec::new();
+
+    let case_insensitive = script_config.case_insensitive_tags.unwrap_or(false);
+    let tag_key = |tag: &String| {
+        if case_insensitive {
+            tag.to_lowercase()
+        } else {
+            tag.clone()
+        }
+    };
+
+    let config_tags = script_config.tags.as_deref().unwrap_or_default();
+    // A tag starting with ! excludes the scripts carrying it, whatever the other tags.
+    let (negated, config_hashset): (HashSet<String>, HashSet<String>) = config_tags
+        .iter()
+        .map(|tag| match tag.trim().strip_prefix('!') {
+            Some(negated) => (true, negated.trim().to_string()),
+            None => (false, tag.trim().to_string()),
+        })
+        .filter(|(_, tag)| !tag.is_empty())
+        .map(|(negated, tag)| (negated, tag_key(&tag)))
+        .fold(
+            (HashSet::new(), HashSet::new()),
+            |(mut negated_tags, mut tags), (negated, tag)| {
+                if negated {
+                    negated_tags.insert(tag);
+                } else {
+                    tags.insert(tag);
+                }
+                (negated_tags, tags)
+            },
+        );
+    for script in parsed_scripts {
+        let script_hashset: HashSet<String> = script.tags.iter().flatten().map(tag_key).collect();
+        if let Some(tag) = negated.intersection(&script_hashset).next() {
+            debug!(
+                "\nScript excluded by the negated tag !{} {}",
+                tag,
+                script.path.clone().unwrap_or_default().display()
+            );
+            continue;
+        }
+        if !config_hashset.is_empty() {
+            // With min_tag_matches a script only has to share that many of the config tags.
+            let matches = match script_config.min_tag_matches {
+                Some(min_tag_matches) => {
+                    config_hashset.intersection(&script_hashset).count() >= min_tag_matches
                 }
+                None => config_hashset.is_subset(&script_hashset),
+            };
+            if !matches {
+                debug!(
+                    "\nScript tags does not match config tags {:?} {}",
+                    &script_hashset,
+                    script.path.clone().unwrap_or_default().display()
+                );
+                continue;
             }
-            debug!("\nScript(s) to run {:?}", scripts_to_run);
-            Ok(scripts_to_run)
         }
+        if !matches_developer(script, script_config) {
+            debug!(
+                "\nScript developer does not match config developer {:?} {}",
+                &script.developer,
+                script.path.clone().unwrap_or_default().display()
+            );
+            continue;
+        }
+        scripts_to_run.push(ScriptFile {
+            port_scope: script_config.port_scope(),
+            ..script.to_owned()
+        });
+    }
+    scripts_to_run
+}
+
+/// A script matches when the config has no developers, or shares at least one with the script.
+fn matches_developer(script: &ScriptFile, script_config: &ScriptConfig) -> bool {
+    match &script_config.developer {
+        Some(config_developers) if !config_developers.is_empty() => script
+            .developer
+            .iter()
+            .flatten()
+            .any(|developer| config_developers.contains(developer)),
+        _ => true,
     }
 }
 
@@ -133,7 +1056,46 @@ pub fn parse_scripts(scripts: Vec<PathBuf>) -> Vec<ScriptFile> {
     parsed_scripts
 }
 
+/// A discovered script file with its parsed headers, None if the headers failed to parse.
+#[derive(Debug, Clone)]
+pub struct ScriptListing {
+    pub path: PathBuf,
+    pub script: Option<ScriptFile>,
+
+    // Why the headers failed to parse.
+    pub error: Option<String>,
+}
+
+/// Finds and parses the custom scripts of the user without filtering or running them.
+#[cfg(not(tarpaulin_include))]
+pub fn list_scripts(configs: &[PathBuf]) -> Result<Vec<ScriptListing>> {
+    let location = scripts_location(None)?;
+    let script_config = read_script_config(&location, configs).unwrap_or_default();
+    let extensions = script_config.extensions.unwrap_or_else(default_extensions);
+    let script_paths = scripts_in_dir(location.scripts_dir, &extensions)?;
+    Ok(parse_script_listings(script_paths))
+}
+
+pub fn parse_script_listings(scripts: Vec<PathBuf>) -> Vec<ScriptListing> {
+    scripts
+        .into_iter()
+        .map(|path| match ScriptFile::parse(path.clone()) {
+            Ok(script) => ScriptListing {
+                path,
+                script: Some(script),
+                error: None,
+            },
+            Err(e) => ScriptListing {
+                path,
+                script: None,
+                error: Some(format!("{e:#}")),
+            },
+        })
+        .collect()
+}
+
 #[derive(Clone, Debug)]
+#[allow(clippy::struct_excessive_bools)]
 pub struct Script {
     // Path to the script itself.
     path: Option<PathBuf>,
@@ -141,296 +1103,6498 @@ pub struct Script {
     // Ip got from scanner.
     ip: IpAddr,
 
-    // Ports found with portscan.
+    // Ports found with portscan, in the port_states of the ScriptFile, the open ones by default.
     open_ports: Vec<u16>,
 
+    // Scanned ports that are not open, for {{closed_ports}} and {{filtered_ports}}.
+    closed_ports: Vec<u16>,
+    filtered_ports: Vec<u16>,
+
     // Port found in ScriptFile, if defined only this will run with the ip.
     trigger_port: Option<String>,
 
     // Character to join ports in case we want to use a string format of them, for example nmap -p.
     ports_separator: Option<String>,
 
+    // Joins the ips of {{all_ips}} and the ip:port pairs of {{all_sockets}}, "," when None.
+    ips_separator: Option<String>,
+    sockets_separator: Option<String>,
+
     // Tags found in ScriptFile.
     tags: Option<Vec<String>>,
 
-    // The format how we want the script to run.
-    call_format: Option<String>,
+    // The formats how we want the script to run, executed in sequence.
+    call_formats: Vec<String>,
+
+    // Stop at the first failing call_format instead of running the rest.
+    fail_fast: bool,
+
+    // Interpreter to run the script file itself with, used when there is no call_format.
+    interpreter: Option<String>,
+    description: Option<String>,
+    hostname: Option<String>,
+    original_target: Option<String>,
+    targets: Option<Arc<RunTargets>>,
+    port_metadata: BTreeMap<u16, PortMetadata>,
+
+    // How many times a failed run is retried.
+    retries: u8,
+
+    // Run the filled call format through the system shell instead of spawning it directly.
+    shell: bool,
+
+    // Pattern whose first capture group replaces the output, when it matches.
+    extract: Option<Regex>,
+
+    // The named patterns collecting the findings from the output.
+    findings: Vec<(String, Regex)>,
+
+    // The output is redacted everywhere but the --scripts-output-dir files, and never streamed.
+    sensitive: bool,
+
+    // Call format of a command getting the output on its stdin, its stdout replaces the output.
+    output_filter: Option<String>,
+
+    // Written to the stdin of every command, with the placeholders of the call formats filled.
+    stdin: Option<String>,
+
+    // Argument making the program of the commands print its version, like --version, for the results.
+    version_arg: Option<String>,
+
+    // Machine the commands run on over ssh, locally without one.
+    remote: Option<Remote>,
+
+    // With false only the exit code of the commands is kept, their output is thrown away.
+    capture_output: bool,
+
+    // Sort and deduplicate the open ports before filling the call formats, false keeps the scan order.
+    sort_ports: bool,
+
+    // The exit codes a command passes with, only 0 by default.
+    success_exit_codes: Vec<i64>,
+
+    // Identifies the RustScan run, shared by every script of it.
+    context: RunContext,
+
+    // Spawns the commands of the Script.
+    runner: Arc<dyn CommandRunner>,
+
+    // Fills the placeholders of the call formats.
+    template_engine: Arc<dyn TemplateEngine>,
 }
 
-#[derive(Serialize)]
-struct ExecPartsScript {
-    script: String,
-    ip: String,
-    port: String,
+// What the commands of a Script ran and printed, the output and stderr are kept when one of them failed too.
+#[derive(Debug, Default)]
+struct Ran {
+    // The command deciding the outcome, the first failing one or the last one.
+    command: Command,
+    output: String,
+    stderr: String,
 }
 
-#[derive(Serialize)]
-struct ExecParts {
-    ip: String,
-    port: String,
+// A command of a Script, its argv and what is written to its stdin.
+#[derive(Debug, Default)]
+struct Command {
+    arguments: Vec<String>,
+    stdin: Option<String>,
+    // The program the call format starts, before the argv is wrapped into the shell or ssh.
+    program: Option<String>,
 }
 
-impl Script {
-    pub fn build(
-        path: Option<PathBuf>,
-        ip: IpAddr,
-        open_ports: Vec<u16>,
-        trigger_port: Option<String>,
-        ports_separator: Option<String>,
-        tags: Option<Vec<String>>,
-        call_format: Option<String>,
-    ) -> Self {
-        Self {
-            path,
-            ip,
-            open_ports,
-            trigger_port,
-            ports_separator,
-            tags,
-            call_format,
-        }
+/// Runs a command given as an already split argv, the first argument is the program.
+/// Returns the exit code, the captured stdout and the captured stderr, empty for the runners not capturing it.
+pub trait CommandRunner: fmt::Debug + Send + Sync {
+    fn run(&self, arguments: &[String]) -> Result<(i64, String, String)>;
+
+    /// Like run, but the output is never shown as it comes, for the sensitive scripts.
+    fn run_unstreamed(&self, arguments: &[String]) -> Result<(i64, String, String)> {
+        self.run(arguments)
     }
 
-    // Some variables get changed before read, and compiler throws warning on warn(unused_assignments)
-    #[allow(unused_assignments)]
-    pub fn run(self) -> Result<String> {
-        debug!("run self {:?}", &self);
+    /// Like run, but the output is thrown away instead of captured, for the capture_output = false scripts.
+    /// Only the exit code is returned.
+    fn run_discarding(&self, arguments: &[String]) -> Result<i64> {
+        self.run_unstreamed(arguments)
+            .map(|(exit_code, _, _)| exit_code)
+    }
 
-        let separator = self.ports_separator.unwrap_or_else(|| ",".into());
+    /// Like run_unstreamed, with the input written to the stdin of the command, for the scripts with a stdin.
+    /// Runners that can not feed a stdin run the command without it.
+    fn run_with_stdin(&self, arguments: &[String], _stdin: &str) -> Result<(i64, String, String)> {
+        self.run_unstreamed(arguments)
+    }
 
-        let mut ports_str = self
-            .open_ports
-            .iter()
-            .map(ToString::to_string)
-            .collect::<Vec<String>>()
-            .join(&separator);
-        if let Some(port) = self.trigger_port {
-            ports_str = port;
-        }
+    /// Like run_unstreamed, outside of any rate limit, for the quick probes like the version_arg of a script.
+    fn run_unthrottled(&self, arguments: &[String]) -> Result<(i64, String, String)> {
+        self.run_unstreamed(arguments)
+    }
+}
 
-        let mut final_call_format = String::new();
-        if let Some(call_format) = self.call_format {
-            final_call_format = call_format;
-        } else {
-            return Err(anyhow!("Failed to parse execution format."));
-        }
-        let default_template: Template = Template::new(&final_call_format);
-        let mut to_run = String::new();
+// Default limit of the captured stdout of a single command.
+pub const DEFAULT_MAX_OUTPUT: usize = 4 * 1024 * 1024;
 
-        if final_call_format.contains("{{script}}") {
-            let exec_parts_script: ExecPartsScript = ExecPartsScript {
-                script: self.path.unwrap().to_str().unwrap().to_string(),
-                ip: self.ip.to_string(),
-                port: ports_str,
-            };
-            to_run = default_template.fill_with_struct(&exec_parts_script)?;
-        } else {
-            let exec_parts: ExecParts = ExecParts {
-                ip: self.ip.to_string(),
-                port: ports_str,
-            };
-            to_run = default_template.fill_with_struct(&exec_parts)?;
-        }
-        debug!("\nScript format to run {}", to_run);
+// Default time a command gets to exit after the terminate signal of the deadline, before it is killed.
+pub const DEFAULT_KILL_GRACE: Duration = Duration::from_secs(2);
 
-        let arguments = shell_words::split(&to_run).expect("Failed to parse script arguments");
+/// The CommandRunner spawning a real process, used by default.
+#[derive(Debug, Clone, Copy)]
+pub struct SubprocessRunner {
+    // A command writing more bytes than this to stdout is stopped, and its output cut off. Its stderr is cut off
+    // there too.
+    pub max_output: usize,
 
-        execute_script(arguments)
-    }
+    // Also print the stdout of the commands line by line as it comes, instead of only capturing it.
+    pub stream: bool,
+
+    // The commands still running then are terminated, and none are started after it.
+    pub deadline: Option<Instant>,
+
+    // How long a command terminated at the deadline gets to exit, before it is killed.
+    pub kill_grace: Duration,
 }
 
-#[cfg(not(tarpaulin_include))]
-fn execute_script(mut arguments: Vec<String>) -> Result<String> {
-    debug!("\nScript arguments vec: {:?}", &arguments);
-    let process = Exec::cmd(&arguments.remove(0)).args(&arguments);
-    match process.capture() {
-        Ok(c) => {
-            let es = match c.exit_status {
-                ExitStatus::Exited(c) => c.try_into().unwrap(),
-                ExitStatus::Signaled(c) => c.into(),
-                ExitStatus::Other(c) => c,
-                ExitStatus::Undetermined => -1,
-            };
-            if es != 0 {
-                return Err(anyhow!("Exit code = {}", es));
-            }
-            Ok(c.stdout_str())
-        }
-        Err(error) => {
-            debug!("Command error {}", error.to_string());
-            Err(anyhow!(error.to_string()))
+impl Default for SubprocessRunner {
+    fn default() -> Self {
+        Self {
+            max_output: DEFAULT_MAX_OUTPUT,
+            stream: false,
+            deadline: None,
+            kill_grace: DEFAULT_KILL_GRACE,
         }
     }
 }
 
-pub fn find_scripts(mut path: PathBuf) -> Result<Vec<PathBuf>> {
-    path.push(".rustscan_scripts");
-    if path.is_dir() {
-        debug!("Scripts folder found {}", &path.display());
-        let mut files_vec: Vec<PathBuf> = Vec::new();
-        for entry in fs::read_dir(path)? {
-            let entry = entry?;
-            files_vec.push(entry.path());
-        }
-        Ok(files_vec)
-    } else {
-        Err(anyhow!("Can't find scripts folder {}", path.display()))
+impl CommandRunner for SubprocessRunner {
+    fn run(&self, arguments: &[String]) -> Result<(i64, String, String)> {
+        execute_script(
+            arguments,
+            None,
+            self.max_output,
+            self.stream,
+            self.deadline,
+            self.kill_grace,
+        )
     }
-}
 
-#[derive(Debug, Clone, Deserialize)]
-pub struct ScriptFile {
-    pub path: Option<PathBuf>,
-    pub tags: Option<Vec<String>>,
-    pub developer: Option<Vec<String>>,
-    pub port: Option<String>,
-    pub ports_separator: Option<String>,
-    pub call_format: Option<String>,
+    fn run_unstreamed(&self, arguments: &[String]) -> Result<(i64, String, String)> {
+        execute_script(
+            arguments,
+            None,
+            self.max_output,
+            false,
+            self.deadline,
+            self.kill_grace,
+        )
+    }
+
+    fn run_discarding(&self, arguments: &[String]) -> Result<i64> {
+        execute_discarding(arguments, self.deadline, self.kill_grace)
+    }
+
+    fn run_with_stdin(&self, arguments: &[String], stdin: &str) -> Result<(i64, String, String)> {
+        execute_script(
+            arguments,
+            Some(stdin),
+            self.max_output,
+            false,
+            self.deadline,
+            self.kill_grace,
+        )
+    }
+}
+
+/// A CommandRunner starting at most rate commands per second through another runner, whatever the concurrency.
+/// It's a token bucket holding a single token, so the launches are spaced evenly instead of coming in bursts.
+#[derive(Debug)]
+pub struct RateLimitedRunner {
+    runner: Arc<dyn CommandRunner>,
+    interval: Duration,
+
+    // When the next command may start.
+    next_launch: Mutex<Instant>,
+}
+
+impl RateLimitedRunner {
+    /// Limits the runner to rate launches per second, 0 is treated as 1.
+    pub fn new(runner: Arc<dyn CommandRunner>, rate: u32) -> Self {
+        Self {
+            runner,
+            interval: Duration::from_secs(1) / rate.max(1),
+            next_launch: Mutex::new(Instant::now()),
+        }
+    }
+}
+
+impl RateLimitedRunner {
+    // Waits for the next launch slot. It is taken under the lock, the waiting for it happens without.
+    fn wait_for_launch(&self) {
+        let launch = match self.next_launch.lock() {
+            Ok(mut next_launch) => {
+                let launch = (*next_launch).max(Instant::now());
+                *next_launch = launch + self.interval;
+                launch
+            }
+            Err(_) => Instant::now(),
+        };
+        let wait = launch.saturating_duration_since(Instant::now());
+        if !wait.is_zero() {
+            thread::sleep(wait);
+        }
+    }
+}
+
+impl CommandRunner for RateLimitedRunner {
+    fn run(&self, arguments: &[String]) -> Result<(i64, String, String)> {
+        self.wait_for_launch();
+        self.runner.run(arguments)
+    }
+
+    fn run_unstreamed(&self, arguments: &[String]) -> Result<(i64, String, String)> {
+        self.wait_for_launch();
+        self.runner.run_unstreamed(arguments)
+    }
+
+    fn run_discarding(&self, arguments: &[String]) -> Result<i64> {
+        self.wait_for_launch();
+        self.runner.run_discarding(arguments)
+    }
+
+    fn run_with_stdin(&self, arguments: &[String], stdin: &str) -> Result<(i64, String, String)> {
+        self.wait_for_launch();
+        self.runner.run_with_stdin(arguments, stdin)
+    }
+
+    fn run_unthrottled(&self, arguments: &[String]) -> Result<(i64, String, String)> {
+        self.runner.run_unthrottled(arguments)
+    }
+}
+
+/// Values shared by every script of one RustScan run, for correlating their outputs.
+#[derive(Debug, Clone, Default)]
+pub struct RunContext {
+    // Random UUID generated once per run.
+    pub scan_id: String,
+
+    // Start of the run in RFC3339.
+    pub timestamp: String,
+
+    // Hash of the scan options, the same for the same scan profile, see Opts::scan_fingerprint.
+    pub scan_fingerprint: String,
+
+    // The file the scan results were saved to, for {{results_file}}, None when none was written.
+    pub results_file: Option<PathBuf>,
+
+    // The [vars] of the script config, placeholders of every call format.
+    pub vars: BTreeMap<String, String>,
+
+    // The port scan the scripts got their ports from, None before a scan.
+    pub scan: Option<ScanContext>,
+
+    // The OS and services detected on the hosts, for os_match and service_match, None when nothing detects them.
+    pub detection: Option<Detection>,
+
+    // Skip the scripts with an os_match or service_match when nothing was detected for it, instead of running them.
+    pub skip_undetected: bool,
+
+    // End of the scripting phase, no script is started after it.
+    pub deadline: Option<Instant>,
+
+    // The version_arg output of every binary probed in the run, by binary path and argument.
+    versions: Arc<Mutex<HashMap<(String, String), String>>>,
+}
+
+// A UUID version 4 made of the random bits, with the version and variant bits set.
+fn uuid_v4(random: u128) -> String {
+    let id = (random & !(0xf000 << 64) & !(0xc000 << 48)) | (0x4000 << 64) | (0x8000 << 48);
+    let id = format!("{id:032x}");
+    format!(
+        "{}-{}-{}-{}-{}",
+        &id[..8],
+        &id[8..12],
+        &id[12..16],
+        &id[16..20],
+        &id[20..]
+    )
+}
+
+impl RunContext {
+    pub fn new() -> Self {
+        Self {
+            scan_id: uuid_v4(rand::random::<u128>()),
+            timestamp: humantime::format_rfc3339_seconds(SystemTime::now()).to_string(),
+            scan_fingerprint: String::new(),
+            results_file: None,
+            vars: BTreeMap::new(),
+            scan: None,
+            detection: None,
+            skip_undetected: false,
+            deadline: None,
+            versions: Arc::default(),
+        }
+    }
+
+    /// Draws the scan_id from the seed instead, runs with the same seed get the same {{scan_id}}.
+    /// Every random choice of the scripting is to come from this seed, so a run can be reproduced.
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.scan_id = uuid_v4(StdRng::seed_from_u64(seed).gen::<u128>());
+        self
+    }
+
+    pub fn scan_fingerprint(mut self, scan_fingerprint: String) -> Self {
+        self.scan_fingerprint = scan_fingerprint;
+        self
+    }
+
+    /// Sets the file an embedder saved the scan results to, for {{results_file}}. The CLI writes none.
+    #[allow(dead_code)]
+    pub fn results_file(mut self, results_file: PathBuf) -> Self {
+        self.results_file = Some(results_file);
+        self
+    }
+
+    pub fn vars(mut self, vars: BTreeMap<String, String>) -> Self {
+        self.vars = vars;
+        self
+    }
+
+    pub fn scan(mut self, scan: ScanContext) -> Self {
+        self.scan = Some(scan);
+        self
+    }
+
+    pub fn detection(mut self, detection: Detection) -> Self {
+        self.detection = Some(detection);
+        self
+    }
+
+    pub fn skip_undetected(mut self, skip_undetected: bool) -> Self {
+        self.skip_undetected = skip_undetected;
+        self
+    }
+
+    pub fn deadline(mut self, deadline: Instant) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Whether the deadline of the scripting phase passed.
+    pub fn past_deadline(&self) -> bool {
+        self.deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+    }
+}
+
+/// The OS and the services detected on the hosts, by the scan or an earlier script.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Detection {
+    os: HashMap<IpAddr, String>,
+    services: HashMap<IpAddr, Vec<String>>,
+}
+
+// A table of a --scripts-detection file, what is known about one host.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DetectedHost {
+    os: Option<String>,
+    #[serde(default)]
+    services: Vec<String>,
+}
+
+impl Detection {
+    /// Reads the detection file of --scripts-detection, a TOML table per quoted ip
+    /// with an optional os string and an optional services list.
+    pub fn read_from(path: &Path) -> Result<Detection> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read the detection file {}", path.display()))?;
+        Self::parse(&content).with_context(|| format!("Invalid detection file {}", path.display()))
+    }
+
+    fn parse(content: &str) -> Result<Detection> {
+        let hosts: BTreeMap<String, DetectedHost> = toml::from_str(content)?;
+        let mut detection = Detection::default();
+        for (ip, host) in hosts {
+            let ip = IpAddr::from_str(&ip).map_err(|_| anyhow!("{} is not an ip", ip))?;
+            if let Some(os) = host.os {
+                detection = detection.os(ip, &os);
+            }
+            for service in host.services {
+                detection = detection.service(ip, &service);
+            }
+        }
+        Ok(detection)
+    }
+
+    /// Sets the OS detected on the ip, like Windows or Linux.
+    pub fn os(mut self, ip: IpAddr, os: &str) -> Self {
+        self.os.insert(ip, os.to_string());
+        self
+    }
+
+    /// Adds a service detected on the ip, like http or smb.
+    pub fn service(mut self, ip: IpAddr, service: &str) -> Self {
+        self.services
+            .entry(ip)
+            .or_default()
+            .push(service.to_string());
+        self
+    }
+}
+
+/// What the port scan of the run did, for the {{scan_start}}, {{scan_elapsed}}, {{ports_scanned}}
+/// and {{ports_open}} placeholders. The same for every script of the run.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScanContext {
+    // Start of the port scan in RFC3339.
+    pub started: String,
+
+    // Time from the start of the port scan until the scripts started.
+    pub elapsed: Duration,
+
+    // Every ip-port pair the scan tried.
+    pub ports_scanned: usize,
+
+    // The ip-port pairs found open.
+    pub ports_open: usize,
+}
+
+impl ScanContext {
+    /// The context of a scan started at started, with the elapsed time up to now.
+    pub fn new(started: SystemTime, ports_scanned: usize, ports_open: usize) -> Self {
+        Self {
+            started: humantime::format_rfc3339_seconds(started).to_string(),
+            elapsed: started.elapsed().unwrap_or_default(),
+            ports_scanned,
+            ports_open,
+        }
+    }
+}
+
+// Stand-ins for the escaped braces while the template is filled, from the Unicode private use area.
+const ESCAPED_OPEN: &str = "\u{e000}";
+const ESCAPED_CLOSE: &str = "\u{e001}";
+
+/// Replaces the {{{{ and }}}} escapes of a call format, so they are not taken for placeholders.
+fn protect_escaped_braces(call_format: &str) -> String {
+    call_format
+        .replace("{{{{", ESCAPED_OPEN)
+        .replace("}}}}", ESCAPED_CLOSE)
+}
+
+/// Turns the protected escapes into literal {{ and }}.
+fn restore_escaped_braces(filled: &str) -> String {
+    filled
+        .replace(ESCAPED_OPEN, "{{")
+        .replace(ESCAPED_CLOSE, "}}")
+}
+
+/// Replaces every {{?PORT:TEXT}} with TEXT when PORT is open, with nothing otherwise.
+/// An invalid port is left as is, so it's reported as an unknown placeholder.
+fn expand_port_conditionals(call_format: &str, open_ports: &[u16]) -> String {
+    let conditional =
+        Regex::new(r"\{\{\?(\d+):([^{}]*)\}\}").expect("Invalid port conditional pattern");
+    conditional
+        .replace_all(call_format, |captures: &regex::Captures| {
+            match captures[1].parse::<u16>() {
+                Ok(port) if open_ports.contains(&port) => captures[2].to_string(),
+                Ok(_) => String::new(),
+                Err(_) => captures[0].to_string(),
+            }
+        })
+        .into_owned()
+}
+
+/// Replaces every {{ports_flag:FLAG}} with FLAG and a port for each open port, --port 80 --port 443.
+/// A FLAG ending in = is joined to the port, --port=80 --port=443. It's empty without open ports.
+/// text_placeholder has no loops, so this is done before the rest of the placeholders are filled.
+fn expand_ports_flags(call_format: &str, open_ports: &[u16]) -> String {
+    let ports_flag =
+        Regex::new(r"\{\{ports_flag:([^{}]*)\}\}").expect("Invalid ports_flag pattern");
+    ports_flag
+        .replace_all(call_format, |captures: &regex::Captures| {
+            let flag = &captures[1];
+            let separator = if flag.ends_with('=') { "" } else { " " };
+            open_ports
+                .iter()
+                .map(|port| format!("{flag}{separator}{port}"))
+                .collect::<Vec<String>>()
+                .join(" ")
+        })
+        .into_owned()
+}
+
+/// Keeps the open ports inside the ports filter, then the lowest max_ports of them.
+/// Filters of parsed ScriptFiles are validated in ScriptFile::new, an invalid one is not applied.
+fn select_ports(
+    mut open_ports: Vec<u16>,
+    filter: Option<&str>,
+    max_ports: Option<usize>,
+) -> Vec<u16> {
+    if let Some(filter) = filter {
+        match parse_ports_filter(filter) {
+            Ok(ranges) => open_ports.retain(|port| {
+                ranges
+                    .iter()
+                    .any(|range| (range.start..=range.end).contains(port))
+            }),
+            Err(e) => debug!("{}", e),
+        }
+    }
+    if let Some(max_ports) = max_ports {
+        open_ports.sort_unstable();
+        open_ports.truncate(max_ports);
+    }
+    open_ports
+}
+
+/// The limit lowest numbered ports, a limit of 0 keeps all of them.
+pub fn lowest_ports(ports: &[u16], limit: usize) -> Vec<u16> {
+    let mut ports = ports.to_vec();
+    if limit > 0 {
+        ports.sort_unstable();
+        ports.truncate(limit);
+    }
+    ports
+}
+
+/// Parses a comma separated list of ports and start-end ranges, for example 22,80-90,443.
+fn parse_ports_filter(filter: &str) -> Result<Vec<PortRange>> {
+    filter
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let (start, end) = entry.split_once('-').unwrap_or((entry, entry));
+            Ok(PortRange {
+                start: start.trim().parse()?,
+                end: end.trim().parse()?,
+            })
+        })
+        .collect::<Result<Vec<PortRange>, std::num::ParseIntError>>()
+        .map_err(|e| anyhow!("Invalid ports_filter {}: {}", filter, e))
+}
+
+/// The user vars as {{vars.name}} and {{name}}, overlaid with the built-in placeholders,
+/// which win on a name collision.
+fn placeholder_values(
+    exec_parts: &ExecParts,
+    vars: &BTreeMap<String, String>,
+) -> Result<serde_json::Map<String, serde_json::Value>> {
+    let mut values = serde_json::Map::new();
+    for (name, value) in vars {
+        values.insert(format!("vars.{name}"), value.clone().into());
+        values.insert(name.clone(), value.clone().into());
+    }
+    if let serde_json::Value::Object(builtins) = serde_json::to_value(exec_parts)? {
+        values.extend(builtins);
+    }
+    Ok(values)
+}
+
+/// Parses the port field of a script, a port N, a list N,M or a range N-M, which can be combined.
+fn parse_trigger_port(port: &str) -> Result<Vec<PortRange>> {
+    let invalid = || {
+        anyhow!(
+            "Invalid port {}, expected a port N, a list N,M or a range N-M",
+            port
+        )
+    };
+    let ranges = parse_ports_filter(port).map_err(|_| invalid())?;
+    if ranges.is_empty() || ranges.iter().any(|range| range.start > range.end) {
+        return Err(invalid());
+    }
+    Ok(ranges)
+}
+
+// The values the call_format placeholders get replaced with.
+#[derive(Serialize)]
+struct ExecParts {
+    script: Option<String>,
+    ip: String,
+    ip_bracketed: String,
+    hostname: String,
+    target: String,
+    port: String,
+    ports_compact: String,
+    ports_file: String,
+    index: String,
+    scan_id: String,
+    scan_fingerprint: String,
+    results_file: String,
+    timestamp: String,
+    port1: String,
+    port2: String,
+    port3: String,
+    port4: String,
+    port5: String,
+    port6: String,
+    port7: String,
+    port8: String,
+    port9: String,
+    all_ips: String,
+    all_ports: String,
+    all_sockets: String,
+    services: String,
+    banner: String,
+    closed_ports: String,
+    filtered_ports: String,
+    scan_start: String,
+    scan_elapsed: String,
+    ports_scanned: String,
+    ports_open: String,
 }
 
-impl ScriptFile {
-    fn new(script: PathBuf) -> Option<ScriptFile> {
-        let real_path = script.clone();
-        let mut lines_buf = String::new();
-        if let Ok(file) = File::open(script) {
-            for line in io::BufReader::new(file).lines().skip(1) {
-                if let Ok(mut line) = line {
-                    if line.starts_with('#') {
-                        line.retain(|c| c != '#');
-                        line = line.trim().to_string();
-                        line.push('\n');
-                        lines_buf.push_str(&line);
-                    } else {
-                        break;
-                    }
-                }
-            }
-        } else {
-            debug!("Failed to read file: {}", &real_path.display());
-            return None;
+impl Script {
+    /// Builds the Script with the headers from the ScriptFile and the ip-ports from the scan.
+    /// The open ports are narrowed down by the exclude_ports, port_scope, ports_filter and max_ports of the ScriptFile.
+    pub fn build(script_f: ScriptFile, ip: IpAddr, open_ports: Vec<u16>) -> Self {
+        Self::build_with_ports(script_f, ip, PortSet::open(open_ports))
+    }
+
+    /// Like build, with the scanned ports of every state, the port_states of the ScriptFile pick
+    /// which of them the script gets.
+    pub fn build_with_ports(script_f: ScriptFile, ip: IpAddr, ports: PortSet) -> Self {
+        let mut open_ports = ports.in_states(&script_f.port_states());
+        open_ports.retain(|port| {
+            !script_f
+                .exclude_ports
+                .iter()
+                .flatten()
+                .any(|excluded| excluded == port)
+        });
+        let open_ports = select_ports(open_ports, script_f.port_scope.as_deref(), None);
+        let open_ports = select_ports(
+            open_ports,
+            script_f.ports_filter.as_deref(),
+            script_f.max_ports,
+        );
+        Self {
+            path: script_f.path,
+            ip,
+            open_ports,
+            closed_ports: ports.closed,
+            filtered_ports: ports.filtered,
+            trigger_port: script_f.port,
+            ports_separator: script_f
+                .separators
+                .as_ref()
+                .and_then(|separators| separators.ports.clone())
+                .or(script_f.ports_separator),
+            ips_separator: script_f
+                .separators
+                .as_ref()
+                .and_then(|separators| separators.ips.clone()),
+            sockets_separator: script_f
+                .separators
+                .and_then(|separators| separators.sockets),
+            tags: script_f.tags,
+            call_formats: script_f
+                .call_format
+                .map(CallFormat::into_vec)
+                .unwrap_or_default(),
+            fail_fast: script_f.fail_fast.unwrap_or(true),
+            interpreter: script_f.interpreter,
+            description: script_f.description,
+            hostname: None,
+            original_target: None,
+            targets: None,
+            port_metadata: BTreeMap::new(),
+            retries: script_f.retries.unwrap_or(0),
+            shell: script_f.shell.unwrap_or(false),
+            sensitive: script_f.sensitive.unwrap_or(false),
+            output_filter: script_f.output_filter,
+            stdin: script_f.stdin,
+            version_arg: script_f.version_arg,
+            remote: script_f.remote,
+            capture_output: script_f.capture_output.unwrap_or(true),
+            sort_ports: script_f.sort_ports.unwrap_or(true),
+            success_exit_codes: script_f.success_exit_codes.unwrap_or_else(|| vec![0]),
+            // Patterns of parsed ScriptFiles are validated in ScriptFile::new.
+            extract: script_f
+                .extract
+                .and_then(|pattern| Regex::new(&pattern).ok()),
+            findings: script_f
+                .findings
+                .iter()
+                .flatten()
+                .filter_map(|finding| {
+                    Some((finding.name.clone(), Regex::new(&finding.pattern).ok()?))
+                })
+                .collect(),
+            context: RunContext::default(),
+            runner: Arc::new(SubprocessRunner::default()),
+            template_engine: Arc::new(PlaceholderTemplate),
+        }
+    }
+
+    /// Sets what spawns the commands, for example to record them instead of running them.
+    #[allow(dead_code)]
+    pub fn runner(mut self, runner: Arc<dyn CommandRunner>) -> Self {
+        self.runner = runner;
+        self
+    }
+
+    /// Sets what fills the placeholders of the call formats, for a richer template syntax than {{name}}.
+    pub fn template_engine(mut self, template_engine: Arc<dyn TemplateEngine>) -> Self {
+        self.template_engine = template_engine;
+        self
+    }
+
+    /// Sets the hosts of the run for the {{all_ips}}, {{all_ports}} and {{all_sockets}} placeholders.
+    pub fn targets(mut self, targets: Option<Arc<RunTargets>>) -> Self {
+        self.targets = targets;
+        self
+    }
+
+    /// Sets what the scan found out about the open ports, for the {{services}} and {{banner}} placeholders.
+    /// Only an embedder calls it, the CLI has no port metadata.
+    #[allow(dead_code)]
+    pub fn port_metadata(mut self, port_metadata: BTreeMap<u16, PortMetadata>) -> Self {
+        self.port_metadata = port_metadata;
+        self
+    }
+
+    /// Sets the hostname for {{hostname}}, the ip is used without one.
+    pub fn hostname(mut self, hostname: Option<String>) -> Self {
+        self.hostname = hostname;
+        self
+    }
+
+    /// Sets the target as given on the commandline for {{target}}, the ip is used without one.
+    pub fn original_target(mut self, original_target: Option<String>) -> Self {
+        self.original_target = original_target;
+        self
+    }
+
+    /// Sets the run the Script belongs to, filling the {{scan_id}} and {{timestamp}} placeholders.
+    pub fn context(mut self, context: RunContext) -> Self {
+        self.context = context;
+        self
+    }
+
+    /// Builds the argument vector the Script would execute, without running anything.
+    /// The first element is the program, the rest are its arguments.
+    /// With multiple call formats this is the first command, see preview_all for every one of them.
+    #[allow(dead_code)]
+    pub fn preview(&self) -> Result<Vec<String>> {
+        self.preview_all()?
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow!("Failed to parse execution format."))
+    }
+
+    /// Builds the argument vectors of every command the Script would execute, in order.
+    /// The {{ports_file}} placeholder is left empty, the file only exists while the Script runs.
+    pub fn preview_all(&self) -> Result<Vec<Vec<String>>> {
+        Ok(self
+            .normalized()
+            .commands(None)?
+            .into_iter()
+            .map(|command| command.arguments)
+            .collect())
+    }
+
+    // The Script with its open ports sorted ascending and without duplicates, unless sort_ports is off,
+    // so the same ports always give the same commands whatever order the scan found them in.
+    fn normalized(&self) -> Cow<'_, Script> {
+        if !self.sort_ports {
+            return Cow::Borrowed(self);
+        }
+        let mut open_ports = self.open_ports.clone();
+        open_ports.sort_unstable();
+        open_ports.dedup();
+        if open_ports == self.open_ports {
+            return Cow::Borrowed(self);
+        }
+        Cow::Owned(Script {
+            open_ports,
+            ..self.clone()
+        })
+    }
+
+    /// Path of the script file, None for the default script.
+    #[allow(dead_code)]
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// Ip the Script runs against.
+    #[allow(dead_code)]
+    pub fn ip(&self) -> IpAddr {
+        self.ip
+    }
+
+    /// Open ports found by the scan.
+    #[allow(dead_code)]
+    pub fn open_ports(&self) -> &[u16] {
+        &self.open_ports
+    }
+
+    /// The unfilled call formats, in the order they run.
+    #[allow(dead_code)]
+    pub fn call_format(&self) -> &[String] {
+        &self.call_formats
+    }
+
+    /// Tags found in the ScriptFile.
+    #[allow(dead_code)]
+    pub fn tags(&self) -> &[String] {
+        self.tags.as_deref().unwrap_or_default()
+    }
+
+    fn commands(&self, ports_file: Option<&Path>) -> Result<Vec<Command>> {
+        let separator = self.ports_separator.clone().unwrap_or_else(|| ",".into());
+
+        let ports_strs = match &self.trigger_port {
+            // A range runs the call formats once for every open port inside it.
+            Some(port) if port.contains('-') => {
+                select_ports(self.open_ports.clone(), Some(port), None)
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect()
+            }
+            // A list of ports is joined with the separator, like the scanned ports are.
+            Some(port) => vec![port
+                .split(',')
+                .map(str::trim)
+                .filter(|port| !port.is_empty())
+                .collect::<Vec<&str>>()
+                .join(&separator)],
+            None => vec![self
+                .open_ports
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<String>>()
+                .join(&separator)],
+        };
+
+        let call_formats = self.final_call_formats()?;
+        ports_strs
+            .iter()
+            .enumerate()
+            .flat_map(|(index, ports_str)| {
+                call_formats.iter().map(move |call_format| {
+                    let (arguments, program) =
+                        self.fill_command(call_format, ports_str, index, ports_file)?;
+                    Ok(Command {
+                        arguments,
+                        program,
+                        stdin: self
+                            .stdin
+                            .as_deref()
+                            .map(|stdin| self.render_format(stdin, ports_str, index, ports_file))
+                            .transpose()?,
+                    })
+                })
+            })
+            .collect()
+    }
+
+    // The banner of the port when {{port}} is a single one, otherwise of the lowest open port with a banner.
+    fn banner(&self, ports_str: &str) -> Option<String> {
+        let banner_of = |port: &u16| self.port_metadata.get(port)?.banner.clone();
+        if let Ok(port) = ports_str.parse::<u16>() {
+            return banner_of(&port);
+        }
+        let mut sorted_ports = self.open_ports.clone();
+        sorted_ports.sort_unstable();
+        sorted_ports.iter().find_map(banner_of)
+    }
+
+    // The ports sorted and separated with the ports_separator.
+    fn join_ports(&self, ports: &[u16]) -> String {
+        let mut sorted_ports = ports.to_vec();
+        sorted_ports.sort_unstable();
+        sorted_ports
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<String>>()
+            .join(self.ports_separator.as_deref().unwrap_or(","))
+    }
+
+    // The port:service pairs of the open ports with a known service, separated with a ",".
+    fn services(&self) -> String {
+        self.port_metadata
+            .iter()
+            .filter(|(port, _)| self.open_ports.contains(port))
+            .filter_map(|(port, metadata)| Some(format!("{port}:{}", metadata.service.as_ref()?)))
+            .collect::<Vec<String>>()
+            .join(",")
+    }
+
+    // The path of the scan results file, empty with a debug note when the call format asks for a missing one.
+    fn results_file(&self, call_format: &str) -> String {
+        if let Some(path) = &self.context.results_file {
+            return path.to_string_lossy().into_owned();
+        }
+        if call_format.contains("{{results_file}}") {
+            debug!(
+                "No scan results file was written, {{{{results_file}}}} is left empty in {}",
+                call_format
+            );
+        }
+        String::new()
+    }
+
+    fn final_call_formats(&self) -> Result<Vec<String>> {
+        // Without a call_format the script file itself is run with the interpreter.
+        match (self.call_formats.is_empty(), &self.interpreter) {
+            (false, _) => Ok(self.call_formats.clone()),
+            (true, Some(interpreter)) => Ok(vec![format!(
+                "{interpreter} {{{{script}}}} {{{{ip}}}} {{{{port}}}}"
+            )]),
+            (true, None) => {
+                Err(ScriptError::Parse("Failed to parse execution format.".to_string()).into())
+            }
+        }
+    }
+
+    fn fill_format(
+        &self,
+        final_call_format: &str,
+        ports_str: &str,
+        index: usize,
+        ports_file: Option<&Path>,
+    ) -> Result<Vec<String>> {
+        Ok(self
+            .fill_command(final_call_format, ports_str, index, ports_file)?
+            .0)
+    }
+
+    // The argv of fill_format, and the program the call format starts before the shell or ssh wrapping.
+    // In shell mode that is the first word of the command line.
+    fn fill_command(
+        &self,
+        final_call_format: &str,
+        ports_str: &str,
+        index: usize,
+        ports_file: Option<&Path>,
+    ) -> Result<(Vec<String>, Option<String>)> {
+        let to_run = self.render_format(final_call_format, ports_str, index, ports_file)?;
+        debug!("\nScript format to run {}", to_run);
+
+        let program = if self.shell {
+            shell_words::split(&to_run)
+                .ok()
+                .and_then(|words| words.into_iter().next())
+        } else {
+            None
+        };
+        let arguments = self.split_command(to_run)?;
+        let program = program.or_else(|| arguments.first().cloned());
+        Ok(match &self.remote {
+            Some(remote) => (remote.command(&arguments), program),
+            None => (arguments, program),
+        })
+    }
+
+    // The format with its placeholders filled, a call format before it is split into arguments or the stdin.
+    // The index counts the runs of the call formats for the ports of a trigger range.
+    fn render_format(
+        &self,
+        final_call_format: &str,
+        ports_str: &str,
+        index: usize,
+        ports_file: Option<&Path>,
+    ) -> Result<String> {
+        // Escaped braces are kept out of the template, and put back as literal braces after filling it.
+        let written = final_call_format;
+        let final_call_format = protect_escaped_braces(final_call_format);
+        let final_call_format = expand_ports_flags(&final_call_format, &self.open_ports);
+        let final_call_format = expand_port_conditionals(&final_call_format, &self.open_ports);
+
+        let script = self
+            .path
+            .as_ref()
+            .map(|path| path.to_string_lossy().into_owned());
+        if script.is_none() && final_call_format.contains("{{script}}") {
+            return Err(anyhow!("Script path is required by the {{script}} format."));
+        }
+        let mut sorted_ports = self.open_ports.clone();
+        sorted_ports.sort_unstable();
+        let nth_port = |n: usize| {
+            sorted_ports
+                .get(n - 1)
+                .map(ToString::to_string)
+                .unwrap_or_default()
+        };
+        let targets = self.targets.as_deref();
+        let scan = self.context.scan.as_ref();
+        let exec_parts: ExecParts = ExecParts {
+            script,
+            ip: self.ip.to_string(),
+            ip_bracketed: match self.ip {
+                IpAddr::V4(ip) => ip.to_string(),
+                IpAddr::V6(ip) => format!("[{ip}]"),
+            },
+            hostname: self.hostname.clone().unwrap_or_else(|| self.ip.to_string()),
+            target: self
+                .original_target
+                .clone()
+                .unwrap_or_else(|| self.ip.to_string()),
+            port: ports_str.to_string(),
+            ports_compact: compact_ports(&self.open_ports),
+            ports_file: ports_file
+                .map(|path| path.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            index: index.to_string(),
+            scan_id: self.context.scan_id.clone(),
+            scan_fingerprint: self.context.scan_fingerprint.clone(),
+            results_file: self.results_file(&final_call_format),
+            timestamp: self.context.timestamp.clone(),
+            port1: nth_port(1),
+            port2: nth_port(2),
+            port3: nth_port(3),
+            port4: nth_port(4),
+            port5: nth_port(5),
+            port6: nth_port(6),
+            port7: nth_port(7),
+            port8: nth_port(8),
+            port9: nth_port(9),
+            all_ips: targets
+                .map(|targets| targets.all_ips(self.ips_separator.as_deref().unwrap_or(",")))
+                .unwrap_or_default(),
+            all_ports: targets
+                .map(|targets| {
+                    targets
+                        .all_ports()
+                        .iter()
+                        .map(ToString::to_string)
+                        .collect::<Vec<String>>()
+                        .join(self.ports_separator.as_deref().unwrap_or(","))
+                })
+                .unwrap_or_default(),
+            all_sockets: targets
+                .map(|targets| {
+                    targets.all_sockets(self.sockets_separator.as_deref().unwrap_or(","))
+                })
+                .unwrap_or_default(),
+            services: self.services(),
+            banner: self.banner(ports_str).unwrap_or_default(),
+            closed_ports: self.join_ports(&self.closed_ports),
+            filtered_ports: self.join_ports(&self.filtered_ports),
+            scan_start: scan.map(|scan| scan.started.clone()).unwrap_or_default(),
+            scan_elapsed: scan
+                .map(|scan| format!("{:.3}", scan.elapsed.as_secs_f64()))
+                .unwrap_or_default(),
+            ports_scanned: scan
+                .map(|scan| scan.ports_scanned.to_string())
+                .unwrap_or_default(),
+            ports_open: scan
+                .map(|scan| scan.ports_open.to_string())
+                .unwrap_or_default(),
+        };
+        let mut values = placeholder_values(&exec_parts, &self.context.vars)?;
+        if self.template_engine.checks_placeholders() {
+            add_modified_values(&final_call_format, &mut values)?;
+            self.check_placeholders(written, &final_call_format, &values)?;
+        }
+        Ok(restore_escaped_braces(
+            &self.template_engine.render(&final_call_format, &values)?,
+        ))
+    }
+
+    // A placeholder without a value would be left empty, the script running with a wrong command instead.
+    fn check_placeholders(
+        &self,
+        written: &str,
+        call_format: &str,
+        values: &serde_json::Map<String, serde_json::Value>,
+    ) -> Result<()> {
+        let placeholder = Regex::new(r"\{\{([^{}]*)\}\}").expect("Invalid placeholder pattern");
+        let unknown: Vec<&str> = placeholder
+            .captures_iter(call_format)
+            .filter_map(|captures| captures.get(1))
+            .map(|name| name.as_str())
+            .filter(|name| !values.contains_key(*name))
+            .collect();
+        if unknown.is_empty() {
+            return Ok(());
+        }
+        Err(ScriptError::Parse(format!(
+            "Unknown placeholder(s) {} in the call format {written} of {}",
+            unknown
+                .iter()
+                .map(|name| format!("{{{{{name}}}}}"))
+                .collect::<Vec<String>>()
+                .join(", "),
+            self.path.as_deref().map_or_else(
+                || "the default script".to_string(),
+                |path| path.display().to_string()
+            )
+        ))
+        .into())
+    }
+
+    // The argv of the filled call format, the system shell's one with shell = true.
+    fn split_command(&self, to_run: String) -> Result<Vec<String>> {
+        if self.shell {
+            if to_run.trim().is_empty() {
+                return Err(anyhow!("Script format to run is empty."));
+            }
+            return Ok(shell_command(to_run));
+        }
+
+        match shell_words::split(&to_run) {
+            Ok(arguments) if !arguments.is_empty() => Ok(arguments),
+            Ok(_) => Err(anyhow!("Script format to run is empty.")),
+            Err(e) => {
+                Err(ScriptError::Parse(format!("Failed to parse script arguments: {e}")).into())
+            }
+        }
+    }
+
+    /// Writes the open ports one per line into a temporary file, if a call_format asks for {{ports_file}}.
+    /// The file is removed when the returned handle is dropped.
+    fn write_ports_file(&self) -> Result<Option<NamedTempFile>> {
+        if !self
+            .final_call_formats()?
+            .iter()
+            .any(|f| protect_escaped_braces(f).contains("{{ports_file}}"))
+        {
+            return Ok(None);
+        }
+
+        let mut ports_file = NamedTempFile::new()?;
+        for port in &self.open_ports {
+            writeln!(ports_file, "{port}")?;
+        }
+        ports_file.flush()?;
+        debug!("Ports file written to {}", ports_file.path().display());
+        Ok(Some(ports_file))
+    }
+
+    /// Runs every command of the Script in sequence and concatenates their outputs.
+    /// With fail_fast the first failing command stops the run, otherwise the rest still runs
+    /// and the failures are reported together at the end.
+    #[allow(dead_code)]
+    pub fn run(self) -> Result<String> {
+        let mut ran = Ran::default();
+        self.run_commands(&mut ran)?;
+        Ok(self.extract_output(ran.output))
+    }
+
+    // The last non zero exit code of a passing command, 0 when they all exited with 0. The concatenated outputs
+    // of the commands, before the extract pattern is applied, and their stderrs are put into ran.
+    fn run_commands(&self, ran: &mut Ran) -> Result<i64> {
+        debug!("run self {:?}", &self);
+
+        // Kept alive until every command ran, dropping it removes the file even on error.
+        let script = self.normalized();
+        let ports_file = script.write_ports_file()?;
+        let commands = script.commands(ports_file.as_ref().map(NamedTempFile::path))?;
+
+        let mut output = String::new();
+        let mut passed_with = 0;
+        let mut errors: Vec<String> = Vec::new();
+        for next in commands {
+            let (outcome, command_output, stderr) =
+                self.execute_with_retries(&next.arguments, next.stdin.as_deref());
+            if errors.is_empty() {
+                ran.command = next;
+            }
+            output.push_str(&command_output);
+            ran.stderr.push_str(&stderr);
+            match outcome {
+                Ok(exit_code) => {
+                    if exit_code != 0 {
+                        passed_with = exit_code;
+                    }
+                }
+                Err(e) if self.fail_fast => {
+                    ran.output = output;
+                    return Err(e);
+                }
+                Err(e) => errors.push(e.to_string()),
+            }
+        }
+
+        if errors.is_empty() {
+            ran.output = self.filter_output(output);
+            Ok(passed_with)
+        } else {
+            ran.output = output;
+            Err(anyhow!(errors.join("\n")))
+        }
+    }
+
+    // Pipes the output through the output_filter, a failing filter is logged and the output kept as is.
+    fn filter_output(&self, output: String) -> String {
+        let Some(filter) = &self.output_filter else {
+            return output;
+        };
+        let filtered = self
+            .fill_format(filter, &self.join_ports(&self.open_ports), 0, None)
+            .and_then(|arguments| run_filter(&arguments, &output));
+        match filtered {
+            Ok(filtered) => filtered,
+            Err(e) => {
+                warn!(
+                    "Output filter {} failed, keeping the raw output: {}",
+                    filter, e
+                );
+                output
+            }
+        }
+    }
+
+    // Replaces the output with the first capture group of the extract pattern, if it matches.
+    fn extract_output(&self, output: String) -> String {
+        if let Some(extract) = &self.extract {
+            if let Some(capture) = extract
+                .captures(&output)
+                .and_then(|captures| captures.get(1))
+            {
+                return capture.as_str().to_string();
+            }
+            debug!(
+                "Extract pattern {} did not match the script output",
+                extract
+            );
+        }
+        output
+    }
+
+    // Every match of the findings patterns in the output by name, the first capture group or the whole match.
+    fn collect_findings(&self, output: &str) -> BTreeMap<String, Vec<String>> {
+        let mut findings: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        for (name, pattern) in &self.findings {
+            let matches = pattern.captures_iter(output).filter_map(|captures| {
+                captures
+                    .get(1)
+                    .or_else(|| captures.get(0))
+                    .map(|found| found.as_str().to_string())
+            });
+            findings.entry(name.clone()).or_default().extend(matches);
+        }
+        findings
+    }
+
+    /// Runs the Script like run does, and collects the outcome with its wall-clock duration into a ScriptResult.
+    pub fn execute(self) -> ScriptResult {
+        let script_name = script_name(self.path.as_deref());
+        let port = self
+            .trigger_port
+            .clone()
+            .unwrap_or_else(|| "all".to_string());
+        let ip = self.ip;
+        let ports = self.normalized().open_ports.clone();
+        let description = self.description.clone();
+        let sensitive = self.sensitive;
+
+        let start = Instant::now();
+        let mut ran = Ran::default();
+        let outcome = self.run_commands(&mut ran);
+        let duration = start.elapsed();
+        let Ran {
+            command,
+            output,
+            stderr,
+        } = ran;
+        let (binary_path, binary_version) = self.binary_info(command.program.as_deref());
+        let command = command.arguments;
+
+        let (output, findings, error, error_kind, exit_code) = match outcome {
+            Ok(exit_code) => (
+                self.extract_output(output.clone()),
+                self.collect_findings(&output),
+                None,
+                None,
+                Some(exit_code),
+            ),
+            // The output of a failed run is kept as the commands printed it.
+            Err(e) => (
+                output,
+                BTreeMap::new(),
+                Some(e.to_string()),
+                ScriptError::of(&e).map(|error| error.kind().to_string()),
+                match ScriptError::of(&e) {
+                    Some(ScriptError::NonZeroExit(exit_code)) => Some(*exit_code),
+                    _ => None,
+                },
+            ),
+        };
+        ScriptResult {
+            script_name,
+            description,
+            ip,
+            port,
+            ports,
+            output,
+            stderr,
+            findings,
+            success: error.is_none(),
+            error,
+            error_kind,
+            exit_code,
+            command,
+            binary_path,
+            binary_version,
+            duration,
+            sensitive,
+        }
+    }
+
+    // The absolute path of the program the call format starts, and its version when the script has a version_arg.
+    // Either is empty when it is not known. The program of a remote script is on the remote host, it is kept as
+    // written and not probed.
+    fn binary_info(&self, program: Option<&str>) -> (String, String) {
+        if self.remote.is_some() {
+            return (program.unwrap_or_default().to_string(), String::new());
+        }
+        let Some(path) = program.and_then(find_program) else {
+            return (String::new(), String::new());
+        };
+        let path = fs::canonicalize(&path)
+            .unwrap_or(path)
+            .display()
+            .to_string();
+        let version = match &self.version_arg {
+            Some(version_arg) => self.binary_version(&path, version_arg),
+            None => String::new(),
+        };
+        (path, version)
+    }
+
+    // The first line the binary prints for the version_arg, cached per binary and argument for the run.
+    // The probe runs without the lock and the rate limit, two scripts starting together may both probe.
+    fn binary_version(&self, binary: &str, version_arg: &str) -> String {
+        let key = (binary.to_string(), version_arg.to_string());
+        if let Some(version) = self
+            .context
+            .versions
+            .lock()
+            .ok()
+            .and_then(|versions| versions.get(&key).cloned())
+        {
+            return version;
+        }
+        let version = self
+            .runner
+            .run_unthrottled(&[binary.to_string(), version_arg.to_string()])
+            .map(|(_, output, _)| {
+                output
+                    .lines()
+                    .map(str::trim)
+                    .find(|line| !line.is_empty())
+                    .unwrap_or_default()
+                    .to_string()
+            })
+            .unwrap_or_default();
+        match self.context.versions.lock() {
+            Ok(mut versions) => versions.entry(key).or_insert(version).clone(),
+            Err(_) => version,
+        }
+    }
+
+    /// Runs a single command, retrying a failed run up to self.retries times with an exponential backoff.
+    /// What the last attempt printed is returned with its outcome, see execute_timed.
+    fn execute_with_retries(
+        &self,
+        arguments: &[String],
+        stdin: Option<&str>,
+    ) -> (Result<i64>, String, String) {
+        let mut backoff = RETRY_BACKOFF;
+        let mut attempts: u32 = 1;
+        loop {
+            match self.execute_timed(arguments, stdin) {
+                (Err(e), _, _)
+                    if attempts <= u32::from(self.retries)
+                        && !interrupted()
+                        && !self.context.past_deadline() =>
+                {
+                    debug!(
+                        "Script attempt {} failed: {}, retrying in {:?}",
+                        attempts, e, backoff
+                    );
+                    thread::sleep(backoff);
+                    backoff *= 2;
+                    attempts += 1;
+                }
+                (Err(e), stdout, stderr) if attempts > 1 => {
+                    let message = format!("{e} (after {attempts} attempts)");
+                    return (Err(e.context(message)), stdout, stderr);
+                }
+                outcome => return outcome,
+            }
+        }
+    }
+
+    /// Runs a single command once and logs how long it took.
+    /// It passes with one of the success_exit_codes, which are returned with its output and stderr.
+    /// A failing exit code still has them, they are empty when it could not run.
+    /// A command with a stdin is never streamed, and its output is thrown away after it without capture.
+    fn execute_timed(
+        &self,
+        arguments: &[String],
+        stdin: Option<&str>,
+    ) -> (Result<i64>, String, String) {
+        let script = self
+            .path
+            .as_ref()
+            .map_or_else(|| "default".to_string(), |path| path.display().to_string());
+
+        let start = Instant::now();
+        let outcome = if let Some(stdin) = stdin {
+            let outcome = self.runner.run_with_stdin(arguments, stdin);
+            if self.capture_output {
+                outcome
+            } else {
+                outcome.map(|(exit_code, _, _)| (exit_code, String::new(), String::new()))
+            }
+        } else if !self.capture_output {
+            self.runner
+                .run_discarding(arguments)
+                .map(|exit_code| (exit_code, String::new(), String::new()))
+        } else if self.sensitive {
+            self.runner.run_unstreamed(arguments)
+        } else {
+            self.runner.run(arguments)
+        };
+        let elapsed = start.elapsed().as_millis();
+
+        match outcome {
+            Ok((exit_code, stdout, stderr)) => {
+                info!(
+                    "Script {} against {} took {}ms (exit {})",
+                    script, self.ip, elapsed, exit_code
+                );
+                if self.success_exit_codes.contains(&exit_code) {
+                    (Ok(exit_code), stdout, stderr)
+                } else {
+                    (
+                        Err(ScriptError::NonZeroExit(exit_code).into()),
+                        stdout,
+                        stderr,
+                    )
+                }
+            }
+            Err(e) => {
+                info!(
+                    "Script {} against {} took {}ms (failed to run)",
+                    script, self.ip, elapsed
+                );
+                (Err(e), String::new(), String::new())
+            }
+        }
+    }
+}
+
+/// The argv running the command line through the system shell.
+#[cfg(not(windows))]
+fn shell_command(command_line: String) -> Vec<String> {
+    vec!["sh".to_string(), "-c".to_string(), command_line]
+}
+
+/// The argv running the command line through the system shell.
+#[cfg(windows)]
+fn shell_command(command_line: String) -> Vec<String> {
+    vec!["cmd".to_string(), "/C".to_string(), command_line]
+}
+
+// Starts the command with its stdout piped and its stderr redirected if given, or with its stdout and
+// stderr going nowhere without capture. With piped_stdin its stdin is a pipe too, it inherits the one of RustScan
+// otherwise. A missing program is a BinaryMissing error.
+fn spawn(
+    arguments: &[String],
+    capture: bool,
+    stderr: Option<Redirection>,
+    piped_stdin: bool,
+) -> Result<Popen> {
+    debug!("\nScript arguments vec: {:?}", &arguments);
+    let (program, args) = arguments
+        .split_first()
+        .ok_or_else(|| anyhow!("Script format to run is empty."))?;
+    if interrupted() {
+        return Err(ScriptError::Interrupted.into());
+    }
+    let exec = Exec::cmd(program).args(args);
+    let exec = if capture {
+        let exec = exec.stdout(Redirection::Pipe);
+        match stderr {
+            Some(stderr) => exec.stderr(stderr),
+            None => exec,
+        }
+    } else {
+        exec.stdout(NullFile).stderr(NullFile)
+    };
+    let exec = if piped_stdin {
+        exec.stdin(Redirection::Pipe)
+    } else {
+        exec
+    };
+    exec.popen().map_err(|error| {
+        debug!("Command error {}", error);
+        match error {
+            PopenError::IoError(e) if e.kind() == io::ErrorKind::NotFound => {
+                ScriptError::BinaryMissing(program.clone()).into()
+            }
+            error => anyhow!(error.to_string()),
+        }
+    })
+}
+
+fn exit_code(status: ExitStatus) -> i64 {
+    match status {
+        ExitStatus::Exited(c) => c.into(),
+        ExitStatus::Signaled(c) => c.into(),
+        ExitStatus::Other(c) => c.into(),
+        ExitStatus::Undetermined => -1,
+    }
+}
+
+// Runs the command with its stdout and stderr going nowhere, only the exit code is kept.
+fn execute_discarding(
+    arguments: &[String],
+    deadline: Option<Instant>,
+    kill_grace: Duration,
+) -> Result<i64> {
+    check_deadline(deadline)?;
+    let process = TrackedChild::track(spawn(arguments, false, None, false)?);
+    let watchdog = deadline.map(|deadline| process.kill_at(deadline, kill_grace));
+    let status = process.wait()?;
+    if watchdog.as_ref().is_some_and(Watchdog::expired) {
+        return Err(ScriptError::DeadlineExceeded.into());
+    }
+    Ok(exit_code(status))
+}
+
+fn check_deadline(deadline: Option<Instant>) -> Result<()> {
+    if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+        return Err(ScriptError::DeadlineExceeded.into());
+    }
+    Ok(())
+}
+
+/// Runs the command, returning its exit code, captured stdout and stderr.
+/// The arguments are the already split argv, the first one is the program, nothing is parsed again.
+/// The stderr is captured too and goes to the stderr of RustScan, see StderrCapture.
+#[cfg(not(tarpaulin_include))]
+fn execute_script(
+    arguments: &[String],
+    stdin: Option<&str>,
+    max_output: usize,
+    stream: bool,
+    deadline: Option<Instant>,
+    kill_grace: Duration,
+) -> Result<(i64, String, String)> {
+    check_deadline(deadline)?;
+    let stderr_file = if stream {
+        None
+    } else {
+        Some(tempfile::tempfile()?)
+    };
+    let stderr_redirection = match &stderr_file {
+        Some(file) => Redirection::File(file.try_clone()?),
+        None => Redirection::Pipe,
+    };
+    let mut process = spawn(arguments, true, Some(stderr_redirection), stdin.is_some())?;
+
+    // A streamed stdout is read here, otherwise the communicator reads it, writing the stdin meanwhile.
+    let pipe = if stream { process.stdout.take() } else { None };
+    let stderr =
+        match stderr_file {
+            Some(file) => StderrCapture::File(file),
+            None => StderrCapture::Streamed(process.stderr.take().map(|pipe| {
+                thread::spawn(move || stream_lines(pipe, &mut io::stderr(), max_output))
+            })),
+        };
+    let communicator = process.communicate_start(stdin.map(|stdin| stdin.as_bytes().to_vec()));
+    // Tracked from here on, so a Ctrl-C kills it; it only has to be reaped after reading its output.
+    let process = TrackedChild::track(process);
+    let watchdog = deadline.map(|deadline| process.kill_at(deadline, kill_grace));
+
+    // One byte over the limit tells a capped output apart from one of exactly max_output bytes.
+    let mut stdout = if let Some(pipe) = pipe {
+        stream_lines(pipe, &mut io::stdout(), max_output)?
+    } else {
+        let mut communicator = communicator.limit_size(max_output.saturating_add(1));
+        // The pipe stays open while a killed shell's children still run, the read gives up at the deadline too.
+        if let Some(deadline) = deadline {
+            communicator =
+                communicator.limit_time(deadline.saturating_duration_since(Instant::now()));
+        }
+        match communicator.read() {
+            Ok((stdout, _)) => stdout.unwrap_or_default(),
+            // The watchdog terminates it at the deadline too, and kills it once the grace passed.
+            Err(error) if error.error.kind() == io::ErrorKind::TimedOut => {
+                process.wait()?;
+                return Err(ScriptError::DeadlineExceeded.into());
+            }
+            Err(error) => return Err(anyhow!(error.error.to_string())),
+        }
+    };
+
+    if stdout.len() > max_output {
+        debug!("Script output exceeded {} bytes, stopping it", max_output);
+        process.kill();
+        // The exit code is the one of the killed command, it did not pass, unless it exited before.
+        let es = exit_code(process.wait()?);
+        stdout.truncate(max_output);
+        let mut output = String::from_utf8_lossy(&stdout).into_owned();
+        let _ = write!(output, "\n[output capped at {max_output} bytes]\n");
+        return Ok((es, output, stderr.collect(max_output)));
+    }
+
+    let es = exit_code(process.wait()?);
+    if watchdog.as_ref().is_some_and(Watchdog::expired) {
+        return Err(ScriptError::DeadlineExceeded.into());
+    }
+    Ok((
+        es,
+        String::from_utf8_lossy(&stdout).into_owned(),
+        stderr.collect(max_output),
+    ))
+}
+
+// Where the stderr of a captured command goes. Into a temporary file, copied to the stderr of RustScan once
+// the command exited, or with --scripts-stream into a pipe another thread copies there line by line as it comes.
+// A file can't fill up and block the command, like an unread pipe would.
+enum StderrCapture {
+    File(fs::File),
+    Streamed(Option<thread::JoinHandle<io::Result<Vec<u8>>>>),
+}
+
+impl StderrCapture {
+    // The first max_output bytes of the stderr.
+    fn collect(self, max_output: usize) -> String {
+        let mut stderr = Vec::new();
+        match self {
+            StderrCapture::File(mut file) => {
+                let read = file
+                    .seek(io::SeekFrom::Start(0))
+                    .and_then(|_| file.take(max_output as u64).read_to_end(&mut stderr));
+                if let Err(e) = read {
+                    debug!("Failed to read the stderr of the script: {}", e);
+                }
+                let _ = io::stderr().write_all(&stderr);
+            }
+            StderrCapture::Streamed(reader) => {
+                if let Some(Ok(Ok(streamed))) = reader.map(thread::JoinHandle::join) {
+                    stderr = streamed;
+                }
+                stderr.truncate(max_output);
+            }
+        }
+        String::from_utf8_lossy(&stderr).into_owned()
+    }
+}
+
+// The ports sorted, and the runs of consecutive ones collapsed into ranges, like 80,443,8000-8100.
+fn compact_ports(ports: &[u16]) -> String {
+    let mut sorted_ports = ports.to_vec();
+    sorted_ports.sort_unstable();
+    sorted_ports.dedup();
+    let mut ranges: Vec<(u16, u16)> = Vec::new();
+    for port in sorted_ports {
+        match ranges.last_mut() {
+            Some((_, end)) if u32::from(*end) + 1 == u32::from(port) => *end = port,
+            _ => ranges.push((port, port)),
+        }
+    }
+    ranges
+        .iter()
+        .map(|(start, end)| {
+            if start == end {
+                start.to_string()
+            } else {
+                format!("{start}-{end}")
+            }
+        })
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+// Runs the output filter with the input on its stdin, returning its stdout.
+fn run_filter(arguments: &[String], input: &str) -> Result<String> {
+    let (program, args) = arguments
+        .split_first()
+        .ok_or_else(|| anyhow!("Output filter to run is empty."))?;
+    let capture = Exec::cmd(program)
+        .args(args)
+        .stdin(input)
+        .stdout(Redirection::Pipe)
+        .capture()?;
+    if !capture.exit_status.success() {
+        return Err(anyhow!("it exited with {:?}", capture.exit_status));
+    }
+    Ok(capture.stdout_str())
+}
+
+// Copies the output to the sink line by line as it comes, flushing each line, and returns it.
+// Reading stops one byte over max_output, the sink gets at most max_output bytes.
+fn stream_lines(
+    output: impl Read,
+    sink: &mut impl Write,
+    max_output: usize,
+) -> io::Result<Vec<u8>> {
+    let limit = max_output.saturating_add(1);
+    let mut reader = io::BufReader::new(output.take(limit as u64));
+    let mut captured = Vec::new();
+    let mut line = Vec::new();
+    while reader.read_until(b'\n', &mut line)? > 0 {
+        let shown = line.len().min(max_output.saturating_sub(captured.len()));
+        sink.write_all(&line[..shown])?;
+        sink.flush()?;
+        captured.append(&mut line);
+    }
+    Ok(captured)
+}
+
+/// The outcome of a Script run against an ip, ready to be printed or saved.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScriptResult {
+    // Name of the script, the file name without extension or "default" for the embedded script.
+    pub script_name: String,
+
+    // The description header of the script, if it has one.
+    pub description: Option<String>,
+
+    // Ip the script ran against.
+    pub ip: IpAddr,
+
+    // Port label, the trigger_port if one is defined, otherwise "all".
+    pub port: String,
+
+    // The open ports the script was given.
+    pub ports: Vec<u16>,
+
+    // Captured stdout of the script, also of a failed one.
+    pub output: String,
+
+    // Captured stderr of the script.
+    #[serde(default)]
+    pub stderr: String,
+
+    // The matches of the findings patterns in the output, by the name of the pattern.
+    #[serde(default)]
+    pub findings: BTreeMap<String, Vec<String>>,
+
+    // Why the script failed, None if it succeeded.
+    pub error: Option<String>,
+
+    // Whether the script succeeded, an empty output of a successful script means it printed nothing.
+    #[serde(default)]
+    pub success: bool,
+
+    // The ScriptError kind of the failure, when it falls into one of the categories.
+    pub error_kind: Option<String>,
+
+    // Exit code of the command that decided the outcome, 0 on success, None when it could not be run or was stopped.
+    #[serde(default)]
+    pub exit_code: Option<i64>,
+
+    // The argv of the command that decided the outcome, after templating and splitting, empty when none ran.
+    #[serde(default)]
+    pub command: Vec<String>,
+
+    // The absolute path of the program of the command, empty when it is not found.
+    #[serde(default)]
+    pub binary_path: String,
+
+    // The first line the program printed for the version_arg of the script, empty without one.
+    #[serde(default)]
+    pub binary_version: String,
+
+    // Wall-clock duration of the whole run, retries included.
+    #[serde(
+        rename = "duration_ms",
+        serialize_with = "serialize_millis",
+        deserialize_with = "deserialize_millis"
+    )]
+    pub duration: Duration,
+
+    // The output and findings are redacted in everything but the --scripts-output-dir files.
+    #[serde(default)]
+    pub sensitive: bool,
+}
+
+impl ScriptResult {
+    /// The result with the output, the findings and the command arguments of a sensitive script replaced by
+    /// their length, for the terminal and the reports. The results of the other scripts are returned as is.
+    pub fn redacted(&self) -> ScriptResult {
+        let redact = |text: &str| format!("[sensitive, {} bytes redacted]", text.len());
+        let mut result = self.clone();
+        if result.sensitive {
+            result.output = redact(&self.output);
+            if !self.stderr.is_empty() {
+                result.stderr = redact(&self.stderr);
+            }
+            for value in result.findings.values_mut().flatten() {
+                *value = redact(value);
+            }
+            for argument in result.command.iter_mut().skip(1) {
+                *argument = redact(argument);
+            }
+        }
+        result
+    }
+}
+
+fn serialize_millis<S: serde::Serializer>(
+    duration: &Duration,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    serializer.serialize_u128(duration.as_millis())
+}
+
+fn deserialize_millis<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<Duration, D::Error> {
+    let millis: u64 = serde::Deserialize::deserialize(deserializer)?;
+    Ok(Duration::from_millis(millis))
+}
+
+/// Removes the scripts whose file name without extension matches one of the excluded names, ignoring case.
+pub fn exclude_scripts(scripts: Vec<ScriptFile>, excluded: &[String]) -> Vec<ScriptFile> {
+    scripts
+        .into_iter()
+        .filter(|script| {
+            let stem = script
+                .path
+                .as_ref()
+                .and_then(|path| path.file_stem())
+                .map(|stem| stem.to_string_lossy().to_lowercase());
+            let is_excluded =
+                stem.is_some_and(|stem| excluded.iter().any(|name| name.to_lowercase() == stem));
+            if is_excluded {
+                debug!("Excluding script {:?}", script.path);
+            }
+            !is_excluded
+        })
+        .collect()
+}
+
+/// Groups the scripts sharing the exact same tag set, only the groups of more than one script are returned.
+/// Such scripts all fire on the same selection, which is often a copy-paste mistake.
+pub fn duplicate_tag_sets(scripts: &[ScriptFile]) -> Vec<Vec<PathBuf>> {
+    let mut groups: Vec<(Vec<String>, Vec<PathBuf>)> = Vec::new();
+    for script in scripts {
+        let mut tags: Vec<String> = script.tags.iter().flatten().cloned().collect();
+        tags.sort();
+        tags.dedup();
+        let path = script.path.clone().unwrap_or_else(|| "default".into());
+        match groups
+            .iter_mut()
+            .find(|(group_tags, _)| *group_tags == tags)
+        {
+            Some((_, paths)) => paths.push(path),
+            None => groups.push((tags, vec![path])),
+        }
+    }
+    groups
+        .into_iter()
+        .filter(|(_, paths)| paths.len() > 1)
+        .map(|(_, paths)| paths)
+        .collect()
+}
+
+/// Removes the scripts found more than once, for example through a symlink.
+/// Scripts are compared on their canonicalized path, or the raw path when it can't be canonicalized.
+pub fn dedup_scripts(scripts: Vec<ScriptFile>) -> Vec<ScriptFile> {
+    let mut seen: HashSet<PathBuf> = HashSet::new();
+    scripts
+        .into_iter()
+        .filter(|script| match &script.path {
+            Some(path) => {
+                let key = fs::canonicalize(path).unwrap_or_else(|_| path.clone());
+                let is_new = seen.insert(key);
+                if !is_new {
+                    debug!("Skipping duplicate script {}", path.display());
+                }
+                is_new
+            }
+            None => true,
+        })
+        .collect()
+}
+
+/// The script files to parse. An explicit scripts list in the ScriptConfig is used as is and in its order,
+/// relative paths are resolved against the scripts directory. Without it the scripts directory is read.
+pub fn config_script_paths(
+    scripts_dir: &Path,
+    script_config: &ScriptConfig,
+) -> Result<Vec<PathBuf>> {
+    if let Some(scripts) = &script_config.scripts {
+        return Ok(scripts
+            .iter()
+            .map(|script| scripts_dir.join(script))
+            .collect());
+    }
+    let extensions = script_config
+        .extensions
+        .clone()
+        .unwrap_or_else(default_extensions);
+    scripts_in_dir(scripts_dir.to_path_buf(), &extensions)
+}
+
+fn default_extensions() -> Vec<String> {
+    DEFAULT_EXTENSIONS.iter().map(ToString::to_string).collect()
+}
+
+/// The files of the .rustscan_scripts folder of the dir with one of the DEFAULT_EXTENSIONS.
+#[allow(dead_code)]
+pub fn find_scripts(path: PathBuf) -> Result<Vec<PathBuf>> {
+    find_scripts_with_extensions(path, &default_extensions())
+}
+
+/// The files of the .rustscan_scripts folder of the dir with one of the extensions, see scripts_in_dir.
+#[allow(dead_code)]
+pub fn find_scripts_with_extensions(
+    mut path: PathBuf,
+    extensions: &[String],
+) -> Result<Vec<PathBuf>> {
+    path.push(".rustscan_scripts");
+    scripts_in_dir(path, extensions)
+}
+
+/// The files of the scripts folder with one of the extensions, with or without the leading ".".
+/// An empty extension stands for the executable files without an extension. Folders are skipped.
+fn scripts_in_dir(path: PathBuf, extensions: &[String]) -> Result<Vec<PathBuf>> {
+    if path.is_dir() {
+        debug!("Scripts folder found {}", &path.display());
+        let extensions: Vec<&str> = extensions
+            .iter()
+            .map(|extension| extension.trim().trim_start_matches('.'))
+            .collect();
+        let mut files_vec: Vec<PathBuf> = Vec::new();
+        for entry in fs::read_dir(path)? {
+            let entry = entry?.path();
+            if is_script_file(&entry, &extensions) {
+                files_vec.push(entry);
+            } else {
+                debug!("Skipping {}, it is not a script file", entry.display());
+            }
+        }
+        // The order of read_dir depends on the platform, sorted the scripts always come in the same order.
+        files_vec.sort();
+        Ok(files_vec)
+    } else if path.exists() {
+        Err(ScriptError::NotADirectory(path).into())
+    } else if is_broken_symlink(&path) {
+        let message = format!("Scripts folder {} is a broken symlink", path.display());
+        Err(anyhow::Error::from(ScriptError::NotFound(path)).context(message))
+    } else {
+        Err(ScriptError::NotFound(path).into())
+    }
+}
+
+// The link itself is there, but not what it points to.
+fn is_broken_symlink(path: &Path) -> bool {
+    !path.exists() && path.symlink_metadata().is_ok()
+}
+
+// A directory or a broken symlink where the config file is expected, the read errors of those do not tell.
+fn check_config_path(path: &Path) -> Result<()> {
+    if path.is_dir() {
+        Err(anyhow!(
+            "Script config {} is a directory, expected a file",
+            path.display()
+        ))
+    } else if is_broken_symlink(path) {
+        Err(anyhow!(
+            "Script config {} is a broken symlink",
+            path.display()
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+fn is_script_file(path: &Path, extensions: &[&str]) -> bool {
+    if !path.is_file() {
+        return false;
+    }
+    match path.extension() {
+        Some(extension) => extensions
+            .iter()
+            .any(|allowed| !allowed.is_empty() && extension.eq_ignore_ascii_case(allowed)),
+        None => extensions.contains(&"") && is_executable(path),
+    }
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    fs::metadata(path).is_ok_and(|metadata| metadata.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_executable(_path: &Path) -> bool {
+    true
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ScriptFile {
+    pub path: Option<PathBuf>,
+    pub tags: Option<Vec<String>>,
+    pub developer: Option<Vec<String>>,
+    pub description: Option<String>,
+    pub port: Option<String>,
+    pub ports_separator: Option<String>,
+    pub call_format: Option<CallFormat>,
+    pub fail_fast: Option<bool>,
+    pub interpreter: Option<String>,
+    pub retries: Option<u8>,
+    pub extract: Option<String>,
+    pub max_ports: Option<usize>,
+    pub ports_filter: Option<String>,
+    pub required_ports: Option<Vec<u16>>,
+    pub min_open_ports: Option<usize>,
+    pub max_open_ports: Option<usize>,
+    pub shell: Option<bool>,
+    pub scope: Option<ScriptScope>,
+    pub priority: Option<i32>,
+    pub exclude_ports: Option<Vec<u16>>,
+    pub port_states: Option<Vec<PortState>>,
+    pub findings: Option<Vec<Finding>>,
+    pub depends_on: Option<String>,
+    pub requires_root: Option<bool>,
+    pub experimental: Option<bool>,
+    pub version_arg: Option<String>,
+    pub os_match: Option<String>,
+    pub service_match: Option<String>,
+    pub sensitive: Option<bool>,
+    pub output_filter: Option<String>,
+    pub stdin: Option<String>,
+    pub remote: Option<Remote>,
+    pub capture_output: Option<bool>,
+    pub sort_ports: Option<bool>,
+    pub separators: Option<Separators>,
+    pub success_exit_codes: Option<Vec<i64>>,
+    pub cost: Option<u32>,
+
+    // The ports of the ScriptConfig that selected the script, not a header.
+    #[serde(skip)]
+    pub port_scope: Option<String>,
+}
+
+/// The [separators] header, what joins each kind of list placeholder. Every one missing falls back to a ",",
+/// ports to the ports_separator first.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Separators {
+    /// {{port}}, {{all_ports}}, {{closed_ports}} and {{filtered_ports}}.
+    pub ports: Option<String>,
+    /// {{all_ips}}.
+    pub ips: Option<String>,
+    /// {{all_sockets}}.
+    pub sockets: Option<String>,
+}
+
+/// The [remote] header, the machine the commands of the script run on over ssh.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Remote {
+    pub host: String,
+    pub user: Option<String>,
+    pub port: Option<u16>,
+}
+
+impl Remote {
+    /// The ssh argv running the arguments on the remote host, joined and quoted for its shell.
+    /// BatchMode makes ssh fail instead of prompting for a password.
+    pub fn command(&self, arguments: &[String]) -> Vec<String> {
+        let mut command = vec![
+            "ssh".to_string(),
+            "-o".to_string(),
+            "BatchMode=yes".to_string(),
+        ];
+        if let Some(port) = self.port {
+            command.extend(["-p".to_string(), port.to_string()]);
+        }
+        command.push(match &self.user {
+            Some(user) => format!("{user}@{}", self.host),
+            None => self.host.clone(),
+        });
+        command.push("--".to_string());
+        command.push(shell_words::join(arguments));
+        command
+    }
+
+    // A host or user starting with a - would be taken for an ssh option.
+    fn validate(&self) -> Result<()> {
+        let valid = |name: &str| !name.is_empty() && !name.starts_with('-') && !name.contains('@');
+        if !valid(&self.host) || !self.user.as_deref().is_none_or(valid) {
+            return Err(anyhow!(
+                "Invalid remote {}, the host and user can't be empty, start with - or contain @",
+                self.host
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// A named pattern of the [[findings]] header, collecting its matches in the output of the script.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct Finding {
+    pub name: String,
+    pub pattern: String,
+}
+
+/// Whether a script runs against every host, or once for the whole run.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ScriptScope {
+    #[default]
+    Host,
+    Run,
+}
+
+/// What the scan found out about an open port, given to a Script with Script::port_metadata.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PortMetadata {
+    // Name of the service guessed for the port, like http.
+    pub service: Option<String>,
+
+    // The first bytes the service sent.
+    pub banner: Option<String>,
+}
+
+/// The scanned ports of a host by their state.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PortSet {
+    pub open: Vec<u16>,
+    pub closed: Vec<u16>,
+    pub filtered: Vec<u16>,
+}
+
+impl PortSet {
+    /// Only open ports, what the port scan reports unless the other states are asked for.
+    pub fn open(open: Vec<u16>) -> Self {
+        Self {
+            open,
+            ..Self::default()
+        }
+    }
+
+    /// The ports in any of the states, the open ones first.
+    fn in_states(&self, states: &[PortState]) -> Vec<u16> {
+        [
+            (PortState::Open, &self.open),
+            (PortState::Closed, &self.closed),
+            (PortState::Filtered, &self.filtered),
+        ]
+        .iter()
+        .filter(|(state, _)| states.contains(state))
+        .flat_map(|(_, ports)| ports.iter().copied())
+        .collect()
+    }
+}
+
+// The file name of the script without extension, "default" without a path.
+fn script_name(path: Option<&Path>) -> String {
+    path.and_then(Path::file_stem).map_or_else(
+        || "default".to_string(),
+        |stem| stem.to_string_lossy().into_owned(),
+    )
+}
+
+/// Every scanned ip with open ports and those ports, for the {{all_*}} placeholders of the run scoped scripts.
+#[derive(Debug, Clone, Default)]
+pub struct RunTargets {
+    pub hosts: Vec<(IpAddr, Vec<u16>)>,
+}
+
+impl RunTargets {
+    /// The ips with open ports in the order of ips, without the ones outside of --scripts-hosts.
+    pub fn new(
+        ips: &[IpAddr],
+        ports_per_ip: &HashMap<IpAddr, Vec<u16>>,
+        script_hosts: Option<&HostFilter>,
+    ) -> RunTargets {
+        RunTargets {
+            hosts: ips
+                .iter()
+                .filter(|ip| script_hosts.is_none_or(|script_hosts| script_hosts.contains(**ip)))
+                .filter_map(|ip| ports_per_ip.get(ip).map(|ports| (*ip, ports.clone())))
+                .collect(),
+        }
+    }
+
+    /// The ips joined with the separator.
+    fn all_ips(&self, separator: &str) -> String {
+        self.hosts
+            .iter()
+            .map(|(ip, _)| ip.to_string())
+            .collect::<Vec<String>>()
+            .join(separator)
+    }
+
+    /// The open ports of any host, sorted and without duplicates.
+    pub fn all_ports(&self) -> Vec<u16> {
+        let mut ports: Vec<u16> = self
+            .hosts
+            .iter()
+            .flat_map(|(_, ports)| ports.iter().copied())
+            .collect();
+        ports.sort_unstable();
+        ports.dedup();
+        ports
+    }
+
+    /// Every open ip:port pair joined with the separator, IPv6 addresses between brackets.
+    fn all_sockets(&self, separator: &str) -> String {
+        self.hosts
+            .iter()
+            .flat_map(|(ip, ports)| {
+                ports.iter().map(move |port| match ip {
+                    IpAddr::V4(ip) => format!("{ip}:{port}"),
+                    IpAddr::V6(ip) => format!("[{ip}]:{port}"),
+                })
+            })
+            .collect::<Vec<String>>()
+            .join(separator)
+    }
+}
+
+/// A single call_format string, or an array of them to run more commands per script.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum CallFormat {
+    Single(String),
+    Multiple(Vec<String>),
+}
+
+impl CallFormat {
+    pub fn into_vec(self) -> Vec<String> {
+        match self {
+            CallFormat::Single(call_format) => vec![call_format],
+            CallFormat::Multiple(call_formats) => call_formats,
+        }
+    }
+
+    fn commands_mut(&mut self) -> &mut [String] {
+        match self {
+            CallFormat::Single(call_format) => std::slice::from_mut(call_format),
+            CallFormat::Multiple(call_formats) => call_formats.as_mut_slice(),
+        }
+    }
+
+    /// Appends the arguments to the end of every command.
+    pub fn push_args(&mut self, args: &str) {
+        for call_format in self.commands_mut() {
+            call_format.push(' ');
+            call_format.push_str(args);
+        }
+    }
+
+    /// Rewrites the placeholders of every command from the open and close delimiters into {{ and }}.
+    pub fn use_delimiters(&mut self, open: &str, close: &str) {
+        for call_format in self.commands_mut() {
+            *call_format = with_default_delimiters(call_format, open, close);
+        }
+    }
+}
+
+/// Rewrites a call format with other placeholder delimiters, like <<ip>>, into the {{ip}} the templates use.
+/// The {{ and }} already in it are escaped, so they stay as written.
+pub fn with_default_delimiters(call_format: &str, open: &str, close: &str) -> String {
+    if (open, close) == ("{{", "}}") {
+        return call_format.to_string();
+    }
+    let escaped = call_format.replace("{{", "{{{{").replace("}}", "}}}}");
+    let placeholder = Regex::new(&format!(
+        "{}(.*?){}",
+        regex::escape(open),
+        regex::escape(close)
+    ))
+    .expect("Invalid delimiters pattern");
+    placeholder.replace_all(&escaped, "{{$1}}").into_owned()
+}
+
+impl From<&str> for CallFormat {
+    fn from(call_format: &str) -> Self {
+        CallFormat::Single(call_format.to_string())
+    }
+}
+
+impl From<String> for CallFormat {
+    fn from(call_format: String) -> Self {
+        CallFormat::Single(call_format)
+    }
+}
+
+impl fmt::Display for CallFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CallFormat::Single(call_format) => write!(f, "{call_format}"),
+            CallFormat::Multiple(call_formats) => write!(f, "{}", call_formats.join("; ")),
+        }
+    }
+}
+
+impl ScriptFile {
+    fn new(script: PathBuf) -> Option<ScriptFile> {
+        let real_path = script.clone();
+        let (parsed, diagnostics) = Self::parse_with_diagnostics(script);
+        for diagnostic in &diagnostics {
+            if parsed.is_some() {
+                debug!("{}: {}", &real_path.display(), diagnostic);
+            } else {
+                debug!("Skipping {}: {}", &real_path.display(), diagnostic);
+            }
+        }
+        parsed
+    }
+
+    /// Parses the script file like new, with the reasons it did not parse or parsed with a warning.
+    /// The line of a header that does not parse is the line of the file it is on.
+    pub fn parse_with_diagnostics(script: PathBuf) -> (Option<ScriptFile>, Vec<ParseDiagnostic>) {
+        let mut diagnostics = Vec::new();
+        match Self::parse_noting(script, &mut diagnostics) {
+            Ok(parsed) => {
+                if !parsed.is_runnable() {
+                    diagnostics.push(ParseDiagnostic::warning(
+                        "It has neither a call_format nor an interpreter, it can not run"
+                            .to_string(),
+                        None,
+                    ));
+                }
+                (Some(parsed), diagnostics)
+            }
+            Err(e) => {
+                // The header errors are noted with their line already.
+                if !diagnostics.iter().any(ParseDiagnostic::is_error) {
+                    diagnostics.push(ParseDiagnostic::error(format!("{e:#}"), None));
+                }
+                (None, diagnostics)
+            }
+        }
+    }
+
+    /// Reads and parses the headers of the script file, the error says why they are not usable.
+    fn parse(script: PathBuf) -> Result<ScriptFile> {
+        Self::parse_noting(script, &mut Vec::new())
+    }
+
+    fn parse_noting(script: PathBuf, diagnostics: &mut Vec<ParseDiagnostic>) -> Result<ScriptFile> {
+        let real_path = script.clone();
+        let (shebang, (lines_buf, line_numbers)) = if let Ok(file) = File::open(script) {
+            let mut reader = io::BufReader::new(file.take(MAX_SCAN_BYTES));
+            if is_binary(&mut reader) {
+                return Err(anyhow!("Binary file, it is skipped"));
+            }
+            let mut lines = reader.lines().map_while(io::Result::ok).peekable();
+            let shebang = lines.peek().and_then(|line| shebang_interpreter(line));
+            (shebang, read_headers(lines, diagnostics)?)
+        } else {
+            return Err(anyhow!("Failed to read file: {}", &real_path.display()));
+        };
+        debug!("ScriptFile {} lines\n{}", &real_path.display(), &lines_buf);
+        if header_nesting(&lines_buf) > MAX_HEADER_NESTING {
+            return Err(ScriptError::Parse(format!(
+                "Script headers nest arrays or tables deeper than {MAX_HEADER_NESTING} levels"
+            ))
+            .into());
+        }
+
+        let mut parsed = match toml::from_str::<ScriptFile>(&lines_buf) {
+            Ok(parsed) => parsed,
+            Err(e) => {
+                let message = format!("Failed to parse ScriptFile headers: {e}");
+                let line = e
+                    .line_col()
+                    .and_then(|(line, _)| line_numbers.get(line).copied());
+                diagnostics.push(ParseDiagnostic::error(message.clone(), line));
+                return Err(ScriptError::Parse(message).into());
+            }
+        };
+        debug!("Parsed ScriptFile{} \n{:?}", &real_path.display(), &parsed);
+        if let Some(filter) = &parsed.ports_filter {
+            parse_ports_filter(filter)?;
+        }
+        if let Some(port) = &parsed.port {
+            parse_trigger_port(port)?;
+        }
+        if let Some(pattern) = &parsed.extract {
+            match Regex::new(pattern) {
+                Ok(extract) if extract.captures_len() > 1 => {}
+                Ok(_) => return Err(anyhow!("Extract pattern {} has no capture group", pattern)),
+                Err(e) => return Err(anyhow!("Invalid extract pattern {}: {}", pattern, e)),
+            }
+        }
+        for pattern in [&parsed.os_match, &parsed.service_match]
+            .iter()
+            .copied()
+            .flatten()
+        {
+            if let Err(e) = detection_regex(pattern) {
+                return Err(anyhow!("Invalid detection pattern {}: {}", pattern, e));
+            }
+        }
+        if let Some(remote) = &parsed.remote {
+            remote.validate()?;
+        }
+        if parsed
+            .success_exit_codes
+            .as_ref()
+            .is_some_and(Vec::is_empty)
+        {
+            return Err(ScriptError::Parse(
+                "success_exit_codes is empty, no command could pass".to_string(),
+            )
+            .into());
+        }
+        if let (Some(min), Some(max)) = (parsed.min_open_ports, parsed.max_open_ports) {
+            if min > max {
+                return Err(ScriptError::Parse(format!(
+                    "min_open_ports {min} is over max_open_ports {max}, no host could fit"
+                ))
+                .into());
+            }
+        }
+        for finding in parsed.findings.iter().flatten() {
+            if let Err(e) = Regex::new(&finding.pattern) {
+                return Err(anyhow!(
+                    "Invalid findings pattern {}: {}",
+                    finding.pattern,
+                    e
+                ));
+            }
+        }
+        if parsed.call_format.is_none() && parsed.interpreter.is_none() {
+            parsed.interpreter = shebang;
+        }
+        parsed.tags = parsed.tags.map(normalize_tags);
+        parsed.path = Some(real_path);
+        Ok(parsed)
+    }
+
+    /// Rewrites the call formats, the output_filter and the stdin from the template_delimiters of the config into {{ and }}.
+    pub fn use_delimiters(&mut self, open: &str, close: &str) {
+        if let Some(call_format) = &mut self.call_format {
+            call_format.use_delimiters(open, close);
+        }
+        if let Some(filter) = &mut self.output_filter {
+            *filter = with_default_delimiters(filter, open, close);
+        }
+        if let Some(stdin) = &mut self.stdin {
+            *stdin = with_default_delimiters(stdin, open, close);
+        }
+    }
+
+    /// The file name without extension, "default" for the embedded script, what depends_on refers to.
+    pub fn name(&self) -> String {
+        script_name(self.path.as_deref())
+    }
+
+    /// Whether the os_match and service_match of the script match what was detected on the ip,
+    /// true without them. None when a pattern could not be checked, nothing was detected for it.
+    pub fn matches_detection(&self, detection: Option<&Detection>, ip: IpAddr) -> Option<bool> {
+        let matches = |pattern: &str, values: &[&String]| -> Option<bool> {
+            if values.is_empty() {
+                return None;
+            }
+            let regex = detection_regex(pattern).ok()?;
+            Some(values.iter().any(|value| regex.is_match(value)))
+        };
+        let os: Vec<&String> = detection
+            .and_then(|detection| detection.os.get(&ip))
+            .into_iter()
+            .collect();
+        let services: Vec<&String> = detection
+            .and_then(|detection| detection.services.get(&ip))
+            .into_iter()
+            .flatten()
+            .collect();
+        let checks = [
+            self.os_match
+                .as_deref()
+                .map(|pattern| matches(pattern, &os)),
+            self.service_match
+                .as_deref()
+                .map(|pattern| matches(pattern, &services)),
+        ];
+        let checks: Vec<Option<bool>> = checks.iter().flatten().copied().collect();
+        if checks.contains(&Some(false)) {
+            Some(false)
+        } else if checks.contains(&None) {
+            None
+        } else {
+            Some(true)
+        }
+    }
+
+    pub fn requires_root(&self) -> bool {
+        self.requires_root.unwrap_or(false)
+    }
+
+    /// Whether the script is still in development, with experimental = true or an experimental tag.
+    /// It only runs with --scripts-experimental.
+    pub fn is_experimental(&self) -> bool {
+        self.experimental.unwrap_or(false)
+            || self
+                .tags
+                .iter()
+                .flatten()
+                .any(|tag| tag.eq_ignore_ascii_case("experimental"))
+    }
+
+    /// The required_ports that are not among the open ports, the script is only run when this is empty.
+    pub fn missing_required_ports(&self, open_ports: &[u16]) -> Vec<u16> {
+        self.required_ports
+            .iter()
+            .flatten()
+            .filter(|port| !open_ports.contains(port))
+            .copied()
+            .collect()
+    }
+
+    /// Whether the number of open ports of the host is below min_open_ports or above max_open_ports,
+    /// the script is not run then. Without them every host fits.
+    pub fn outside_open_ports_range(&self, open_ports: &[u16]) -> bool {
+        let count = open_ports.iter().collect::<HashSet<&u16>>().len();
+        self.min_open_ports.is_some_and(|min| count < min)
+            || self.max_open_ports.is_some_and(|max| count > max)
+    }
+
+    /// Whether the exclude_ports leave none of the open ports, the script is not run then.
+    pub fn excludes_all_ports(&self, open_ports: &[u16]) -> bool {
+        match &self.exclude_ports {
+            Some(excluded) if !excluded.is_empty() => {
+                !open_ports.is_empty() && open_ports.iter().all(|port| excluded.contains(port))
+            }
+            _ => false,
+        }
+    }
+
+    /// Whether the open ports are outside the config ports, the script is not run then.
+    /// A script with a port field is outside when none of its ports are among the config ports.
+    pub fn out_of_scope(&self, open_ports: &[u16]) -> bool {
+        let Some(scope) = self.port_scope.as_deref() else {
+            return false;
+        };
+        match self.port.as_deref().map(parse_trigger_port) {
+            Some(Ok(trigger_ports)) => {
+                let Ok(scope) = parse_ports_filter(scope) else {
+                    return false;
+                };
+                !trigger_ports.iter().any(|trigger| {
+                    scope
+                        .iter()
+                        .any(|range| trigger.start <= range.end && range.start <= trigger.end)
+                })
+            }
+            _ => {
+                !open_ports.is_empty()
+                    && select_ports(open_ports.to_vec(), Some(scope), None).is_empty()
+            }
+        }
+    }
+
+    /// The states of the ports the script gets, only open without port_states.
+    pub fn port_states(&self) -> Vec<PortState> {
+        self.port_states
+            .clone()
+            .unwrap_or_else(|| vec![PortState::Open])
+    }
+
+    /// Whether the script asks for closed or filtered ports, the port scan has to keep them then.
+    pub fn wants_unopened_ports(&self) -> bool {
+        self.port_states()
+            .iter()
+            .any(|state| *state != PortState::Open)
+    }
+
+    pub fn scope(&self) -> ScriptScope {
+        self.scope.unwrap_or_default()
+    }
+
+    /// Where the script is in the order they run in, lower runs first, 0 without a priority.
+    pub fn priority(&self) -> i32 {
+        self.priority.unwrap_or(0)
+    }
+
+    /// How much of the --scripts-budget the script takes while it runs, 1 without a cost.
+    pub fn cost(&self) -> u32 {
+        self.cost.unwrap_or(1)
+    }
+
+    /// A ScriptFile can only run with a call_format, or an interpreter to run the file itself with.
+    pub fn is_runnable(&self) -> bool {
+        self.call_format.is_some() || self.interpreter.is_some()
+    }
+}
+
+/// Whether the start of the file has a NUL byte, which text files never have.
+fn is_binary(reader: &mut impl BufRead) -> bool {
+    // The first fill of the buffer is the prefix checked, nothing is consumed.
+    reader.fill_buf().is_ok_and(|prefix| prefix.contains(&0))
+}
+
+/// The interpreter of a #! first line, #!/usr/bin/env python3 gives python3 and #!/bin/bash -e gives /bin/bash -e.
+fn shebang_interpreter(line: &str) -> Option<String> {
+    let mut parts = line.strip_prefix("#!")?.split_whitespace();
+    let program = parts.next()?;
+    let rest: Vec<&str> = parts.collect();
+    if Path::new(program)
+        .file_name()
+        .is_some_and(|name| name == "env")
+    {
+        // env -S splits the rest of the line into separate arguments, which the call format does anyway.
+        let args: Vec<&str> = rest.into_iter().filter(|arg| *arg != "-S").collect();
+        if args.is_empty() {
+            return None;
+        }
+        return Some(args.join(" "));
+    }
+    let mut interpreter = vec![program];
+    interpreter.extend(rest);
+    Some(interpreter.join(" "))
+}
+
+/// Trims the whitespace around the tags, dropping the ones left empty.
+fn normalize_tags(tags: Vec<String>) -> Vec<String> {
+    tags.into_iter()
+        .filter_map(|tag| {
+            let trimmed = tag.trim();
+            if trimmed.is_empty() {
+                debug!("Dropping empty tag {:?}", tag);
+                None
+            } else {
+                Some(trimmed.to_string())
+            }
+        })
+        .collect()
+}
+
+/// Collects the header lines of a script file.
+/// A fenced region between RUSTSCAN-START and RUSTSCAN-END marker lines is read as is, whatever comment
+/// syntax surrounds it. So is a TOML front matter between two +++ lines, starting in the first lines of the file.
+/// Without either of them the leading # lines after the first line are used, with the #s removed.
+/// Headers over MAX_HEADER_LINES lines or MAX_HEADER_BYTES bytes are an error. Returns the headers with the file line
+/// of each of their lines, a fence that is never closed is noted in the diagnostics.
+fn read_headers(
+    lines: impl Iterator<Item = String>,
+    diagnostics: &mut Vec<ParseDiagnostic>,
+) -> Result<(String, Vec<usize>)> {
+    let mut lines_buf = String::new();
+    let mut fenced_buf = String::new();
+    let mut line_numbers = Vec::new();
+    let mut fenced_line_numbers = Vec::new();
+    let mut header_lines = 0;
+    let mut leading_comments = true;
+    let mut in_fence = false;
+    let mut in_front_matter = false;
+    let mut opened_at = 0;
+
+    for (index, mut line) in lines.enumerate() {
+        if in_front_matter {
+            if line.trim() == FRONT_MATTER {
+                return Ok((fenced_buf, fenced_line_numbers));
+            }
+            header_lines += 1;
+            fenced_buf.push_str(&line);
+            fenced_buf.push('\n');
+            fenced_line_numbers.push(index + 1);
+        } else if in_fence {
+            if line.contains(HEADER_END) {
+                return Ok((fenced_buf, fenced_line_numbers));
+            }
+            header_lines += 1;
+            fenced_buf.push_str(line.trim());
+            fenced_buf.push('\n');
+            fenced_line_numbers.push(index + 1);
+        } else if line.contains(HEADER_START) {
+            in_fence = true;
+            opened_at = index + 1;
+        } else if index < FRONT_MATTER_LINES && line.trim() == FRONT_MATTER {
+            in_front_matter = true;
+            opened_at = index + 1;
+        } else if leading_comments && index > 0 {
+            if line.starts_with('#') {
+                header_lines += 1;
+                line.retain(|c| c != '#');
+                lines_buf.push_str(line.trim());
+                lines_buf.push('\n');
+                line_numbers.push(index + 1);
+            } else {
+                leading_comments = false;
+            }
+        }
+        if header_lines > MAX_HEADER_LINES
+            || lines_buf.len().max(fenced_buf.len()) > MAX_HEADER_BYTES
+        {
+            return Err(ScriptError::Parse(format!(
+                "Script headers exceed {MAX_HEADER_LINES} lines or {MAX_HEADER_BYTES} bytes"
+            ))
+            .into());
+        }
+    }
+
+    let unclosed = if in_fence {
+        Some(format!(
+            "Script header fence is never closed with {HEADER_END}"
+        ))
+    } else if in_front_matter {
+        Some(format!(
+            "Script front matter is never closed with {FRONT_MATTER}"
+        ))
+    } else {
+        None
+    };
+    if let Some(message) = unclosed {
+        debug!("{}", message);
+        diagnostics.push(ParseDiagnostic::warning(message, Some(opened_at)));
+    }
+    Ok((lines_buf, line_numbers))
+}
+
+/// The deepest nesting of arrays and inline tables in the TOML headers, brackets in strings and comments
+/// are not counted. The TOML parser recurses for each level, so crafted headers could overflow the stack.
+fn header_nesting(headers: &str) -> usize {
+    let mut depth: usize = 0;
+    let mut deepest = 0;
+    let mut quote = None;
+    let mut escaped = false;
+    let mut in_comment = false;
+    for c in headers.chars() {
+        if in_comment {
+            in_comment = c != '\n';
+        } else if let Some(open) = quote {
+            if escaped {
+                escaped = false;
+            } else if c == '\\' && open == '"' {
+                escaped = true;
+            } else if c == open {
+                quote = None;
+            }
+        } else {
+            match c {
+                '"' | '\'' => quote = Some(c),
+                '#' => in_comment = true,
+                '[' | '{' => {
+                    depth += 1;
+                    deepest = deepest.max(depth);
+                }
+                ']' | '}' => depth = depth.saturating_sub(1),
+                _ => {}
+            }
+        }
+    }
+    deepest
+}
+
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct ScriptConfig {
+    pub tags: Option<Vec<String>>,
+    pub ports: Option<Vec<String>>,
+    pub developer: Option<Vec<String>>,
+    pub include_default: Option<bool>,
+    pub pre_hook: Option<String>,
+    pub post_hook: Option<String>,
+    pub hooks_fatal: Option<bool>,
+    pub case_insensitive_tags: Option<bool>,
+    pub scripts: Option<Vec<PathBuf>>,
+    pub extensions: Option<Vec<String>>,
+    pub vars: Option<BTreeMap<String, String>>,
+    pub min_tag_matches: Option<usize>,
+    pub aliases: Option<BTreeMap<String, Vec<String>>>,
+    pub template_delimiters: Option<Vec<String>>,
+    pub profiles: Option<BTreeMap<String, ScriptProfile>>,
+}
+
+/// A [profiles.name] table of the script config, the fields it sets replace the top-level ones when it is selected.
+#[derive(Debug, Deserialize, Clone, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ScriptProfile {
+    pub tags: Option<Vec<String>>,
+    pub developer: Option<Vec<String>>,
+    pub ports: Option<Vec<String>>,
+}
+
+// The ports joined into a ports filter, None without any.
+fn port_scope(ports: Option<&[String]>) -> Option<String> {
+    let ports: Vec<&str> = ports
+        .into_iter()
+        .flatten()
+        .map(|port| port.trim())
+        .filter(|port| !port.is_empty())
+        .collect();
+    Some(ports.join(",")).filter(|_| !ports.is_empty())
+}
+
+// The entries of base with the ones of overlay added, replacing the ones with the same name.
+fn extend<V>(
+    base: Option<BTreeMap<String, V>>,
+    overlay: Option<BTreeMap<String, V>>,
+) -> Option<BTreeMap<String, V>> {
+    match (base, overlay) {
+        (Some(mut base), Some(overlay)) => {
+            base.extend(overlay);
+            Some(base)
+        }
+        (base, overlay) => overlay.or(base),
+    }
+}
+
+// The entries of base followed by the ones of overlay it does not have yet.
+fn union<T: PartialEq>(base: Option<Vec<T>>, overlay: Option<Vec<T>>) -> Option<Vec<T>> {
+    match (base, overlay) {
+        (Some(mut base), Some(overlay)) => {
+            for entry in overlay {
+                if !base.contains(&entry) {
+                    base.push(entry);
+                }
+            }
+            Some(base)
+        }
+        (base, overlay) => base.or(overlay),
+    }
+}
+
+/// Commands running once before and once after all the scripts, not tied to any ip.
+/// Their call formats only know the {{ips}} placeholder, every scanned ip separated with a ",".
+#[derive(Debug, Clone, Default)]
+pub struct ScriptHooks {
+    pub pre_hook: Option<String>,
+    pub post_hook: Option<String>,
+    // A failing hook stops RustScan instead of only warning.
+    pub fatal: bool,
+}
+
+#[derive(Serialize)]
+struct HookParts {
+    ips: String,
+}
+
+impl ScriptHooks {
+    pub fn from_config(script_config: &ScriptConfig) -> Self {
+        let hook = |hook: &Option<String>| match script_config.delimiters() {
+            Some((open, close)) => hook
+                .as_deref()
+                .map(|hook| with_default_delimiters(hook, open, close)),
+            None => hook.clone(),
+        };
+        Self {
+            pre_hook: hook(&script_config.pre_hook),
+            post_hook: hook(&script_config.post_hook),
+            fatal: script_config.hooks_fatal.unwrap_or(false),
+        }
+    }
+
+    /// Runs the pre_hook, if there is one, returning its output.
+    pub fn run_pre(&self, ips: &[IpAddr]) -> Result<String> {
+        self.pre_hook
+            .as_deref()
+            .map_or_else(|| Ok(String::new()), |hook| run_hook(hook, ips))
+    }
+
+    /// Runs the post_hook, if there is one, returning its output.
+    pub fn run_post(&self, ips: &[IpAddr]) -> Result<String> {
+        self.post_hook
+            .as_deref()
+            .map_or_else(|| Ok(String::new()), |hook| run_hook(hook, ips))
+    }
+}
+
+fn run_hook(call_format: &str, ips: &[IpAddr]) -> Result<String> {
+    let hook_parts = HookParts {
+        ips: ips
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<String>>()
+            .join(","),
+    };
+    let to_run = Template::new(call_format).fill_with_struct(&hook_parts)?;
+    debug!("\nHook format to run {}", to_run);
+
+    let arguments = shell_words::split(&to_run)
+        .map_err(|e| anyhow!("Failed to parse hook arguments: {}", e))?;
+    match execute_script(
+        &arguments,
+        None,
+        DEFAULT_MAX_OUTPUT,
+        false,
+        None,
+        DEFAULT_KILL_GRACE,
+    )? {
+        (0, stdout, _) => Ok(stdout),
+        (exit_code, _, _) => Err(anyhow!("Hook {} exit code = {}", to_run, exit_code)),
+    }
+}
+
+/// Reads the hooks from the script config, only custom scripts have them.
+#[cfg(not(tarpaulin_include))]
+pub fn init_hooks(opts: &Opts, bundle: Option<&ScriptBundle>) -> Result<ScriptHooks> {
+    if opts.scripts != ScriptsRequired::Custom {
+        return Ok(ScriptHooks::default());
+    }
+    let config = read_script_config(&scripts_location(bundle)?, &opts.scripts_config)?;
+    Ok(ScriptHooks::from_config(&config))
+}
+
+/// Reads the [vars] of the script config, there are none unless custom scripts are used.
+#[cfg(not(tarpaulin_include))]
+pub fn init_vars(opts: &Opts, bundle: Option<&ScriptBundle>) -> Result<BTreeMap<String, String>> {
+    if opts.scripts != ScriptsRequired::Custom {
+        return Ok(BTreeMap::new());
+    }
+    let config = read_script_config(&scripts_location(bundle)?, &opts.scripts_config)?;
+    Ok(config.vars.unwrap_or_default())
+}
+
+#[cfg(not(tarpaulin_include))]
+impl ScriptConfig {
+    /// The ports of the config joined into a ports filter, like "80,8000-8100", None without any.
+    pub fn port_scope(&self) -> Option<String> {
+        port_scope(self.ports.as_deref())
+    }
+
+    /// Reads the .rustscan_scripts.toml of the dir, the home dir or an unpacked bundle.
+    #[allow(dead_code)]
+    pub fn read_config_in(dir: &Path) -> Result<ScriptConfig> {
+        ScriptConfig::read_config_from(&dir.join(".rustscan_scripts.toml"))
+    }
+
+    /// Reads the ScriptConfig from path. A missing file is not an error, it means there is no
+    /// tag filter and every parsed script will run. Any other IO error is returned.
+    pub fn read_config_from(path: &Path) -> Result<ScriptConfig> {
+        check_config_path(path)?;
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                debug!(
+                    "No script config found at {}, running all parsed scripts",
+                    path.display()
+                );
+                return Ok(ScriptConfig::default());
+            }
+            Err(e) => return Err(e.into()),
+        };
+        Ok(ScriptConfig::parse(&content, &path.display().to_string())?)
+    }
+
+    /// Reads the config files and merges them in order, see merge. Unlike with read_config_from
+    /// a missing file is an error, the paths were given explicitly.
+    pub fn read_configs(paths: &[PathBuf]) -> Result<ScriptConfig> {
+        let mut merged = ScriptConfig::default();
+        for path in paths {
+            check_config_path(path)?;
+            let content = fs::read_to_string(path)
+                .with_context(|| format!("Failed to read script config {}", path.display()))?;
+            merged = merged.merge(ScriptConfig::parse(&content, &path.display().to_string())?);
+        }
+        Ok(merged)
+    }
+}
+
+/// Builds a ScriptConfig in code, starting from ScriptConfig::default().
+#[allow(dead_code)]
+impl ScriptConfig {
+    pub fn tags(mut self, tags: Vec<String>) -> Self {
+        self.tags = Some(normalize_tags(tags));
+        self
+    }
+
+    pub fn ports(mut self, ports: Vec<String>) -> Self {
+        self.ports = Some(ports);
+        self
+    }
+
+    pub fn developer(mut self, developer: Vec<String>) -> Self {
+        self.developer = Some(developer);
+        self
+    }
+
+    /// Lays the overlay config over this one. The lists, tags, ports, developer, scripts and extensions,
+    /// are the union of both, the overlay's new entries appended. The vars, aliases and profiles are merged by name, and the overlay
+    /// replaces every other field it sets.
+    pub fn merge(self, overlay: ScriptConfig) -> ScriptConfig {
+        ScriptConfig {
+            tags: union(self.tags, overlay.tags),
+            ports: union(self.ports, overlay.ports),
+            developer: union(self.developer, overlay.developer),
+            include_default: overlay.include_default.or(self.include_default),
+            pre_hook: overlay.pre_hook.or(self.pre_hook),
+            post_hook: overlay.post_hook.or(self.post_hook),
+            hooks_fatal: overlay.hooks_fatal.or(self.hooks_fatal),
+            case_insensitive_tags: overlay.case_insensitive_tags.or(self.case_insensitive_tags),
+            scripts: union(self.scripts, overlay.scripts),
+            extensions: union(self.extensions, overlay.extensions),
+            vars: extend(self.vars, overlay.vars),
+            min_tag_matches: overlay.min_tag_matches.or(self.min_tag_matches),
+            aliases: extend(self.aliases, overlay.aliases),
+            template_delimiters: overlay.template_delimiters.or(self.template_delimiters),
+            profiles: extend(self.profiles, overlay.profiles),
+        }
+    }
+
+    /// The open and close template_delimiters of the placeholders, None for the default {{ and }}.
+    /// A list without exactly two non empty delimiters is rejected while parsing.
+    pub fn delimiters(&self) -> Option<(&str, &str)> {
+        match self.template_delimiters.as_deref() {
+            Some([open, close]) if !open.is_empty() && !close.is_empty() => {
+                Some((open.as_str(), close.as_str()))
+            }
+            _ => None,
+        }
+    }
+
+    /// The tags the alias of the [aliases] table stands for, an unknown alias is an error listing the defined ones.
+    pub fn alias_tags(&self, alias: &str) -> Result<Vec<String>> {
+        let aliases = self.aliases.clone().unwrap_or_default();
+        match aliases.get(alias) {
+            Some(tags) => Ok(normalize_tags(tags.clone())),
+            None if aliases.is_empty() => Err(anyhow!(
+                "Unknown scripts alias {}, the script config defines no aliases",
+                alias
+            )),
+            None => Err(anyhow!(
+                "Unknown scripts alias {alias}, the defined ones are: {}",
+                aliases.keys().cloned().collect::<Vec<_>>().join(", ")
+            )),
+        }
+    }
+
+    /// The config with the fields of the named profile, or of the default profile without a name if there is one.
+    /// An unknown profile is an error listing the defined ones.
+    pub fn with_profile(mut self, name: Option<&str>) -> Result<ScriptConfig> {
+        let profiles = self.profiles.clone().unwrap_or_default();
+        let profile = match name {
+            Some(name) => match profiles.get(name) {
+                Some(profile) => profile.clone(),
+                None if profiles.is_empty() => {
+                    return Err(anyhow!(
+                        "Unknown scripts profile {}, the script config defines no profiles",
+                        name
+                    ))
+                }
+                None => {
+                    return Err(anyhow!(
+                        "Unknown scripts profile {name}, the defined ones are: {}",
+                        profiles.keys().cloned().collect::<Vec<_>>().join(", ")
+                    ))
+                }
+            },
+            None => match profiles.get("default") {
+                Some(profile) => profile.clone(),
+                None => return Ok(self),
+            },
+        };
+        if let Some(tags) = profile.tags {
+            self.tags = Some(normalize_tags(tags));
+        }
+        if let Some(developer) = profile.developer {
+            self.developer = Some(developer);
+        }
+        if let Some(ports) = profile.ports {
+            self.ports = Some(ports);
+        }
+        Ok(self)
+    }
+
+    // Parses the toml content, origin names where it came from in the error.
+    fn parse(content: &str, origin: &str) -> Result<ScriptConfig, ScriptError> {
+        let mut config = toml::from_str::<ScriptConfig>(content)
+            .map_err(|e| ScriptError::Parse(format!("Failed to parse {origin}: {e}")))?;
+        config.tags = config.tags.map(normalize_tags);
+        let profile_scopes = config
+            .profiles
+            .iter()
+            .flat_map(BTreeMap::values)
+            .map(|profile| port_scope(profile.ports.as_deref()));
+        for scope in std::iter::once(config.port_scope())
+            .chain(profile_scopes)
+            .flatten()
+        {
+            parse_ports_filter(&scope).map_err(|_| {
+                ScriptError::Parse(format!(
+                    "Invalid ports {scope} in {origin}, expected ports N or ranges N-M"
+                ))
+            })?;
+        }
+        if config.template_delimiters.is_some() && config.delimiters().is_none() {
+            return Err(ScriptError::Parse(format!(
+                "Invalid template_delimiters in {origin}, expected an open and a close delimiter"
+            )));
+        }
+        Ok(config)
+    }
+}
+
+/// Parses a ScriptConfig from the content of a .rustscan_scripts.toml.
+impl FromStr for ScriptConfig {
+    type Err = ScriptError;
+
+    fn from_str(content: &str) -> Result<Self, Self::Err> {
+        ScriptConfig::parse(content, "script config")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        check_privileges, compact_ports, config_script_paths, dedup_scripts, default_call_format,
+        default_extensions, default_script, default_script_for, duplicate_tag_sets,
+        empty_selection_message, exclude_scripts, filter_scripts, find_program, find_scripts,
+        find_scripts_with_extensions, format_stats, header_nesting, init_scripts, lowest_ports,
+        order_by_dependencies, parse_ports_filter, parse_scripts, parse_trigger_port, read_headers,
+        scripts_in_dir, scripts_location, shebang_interpreter, shell_command, stream_lines,
+        with_default_delimiters, CallFormat, CommandRunner, Detection, Finding, HostFilter,
+        ParseDiagnostic, PortMetadata, PortSet, RateLimitedRunner, Remote, RunContext, RunTargets,
+        ScanContext, Script, ScriptBundle, ScriptConfig, ScriptError, ScriptFile, ScriptHooks,
+        ScriptResult, ScriptScope, ScriptStats, ScriptsLocation, Separators, Severity,
+        SubprocessRunner, TemplateEngine,
+    };
+    use crate::input::{Opts, PortRange};
+    use crate::scanner::PortState;
+    use std::collections::{BTreeMap, HashMap};
+    use std::net::IpAddr;
+    use std::path::{Path, PathBuf};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::{Duration, Instant, SystemTime};
+
+    // Function for testing only, it inserts static values into ip and open_ports
+    // Doesn't use impl in case it's implemented in the super module at some point
+    fn into_script(script_f: ScriptFile) -> Script {
+        Script::build(script_f, "127.0.0.1".parse().unwrap(), vec![80, 8080])
+    }
+
+    #[test]
+    fn find_and_parse_scripts() {
+        let scripts = find_scripts("fixtures/".into()).unwrap();
+        let scripts = parse_scripts(scripts);
+        assert_eq!(scripts.len(), 5);
+    }
+
+    #[test]
+    fn xdg_location_comes_first() {
+        let config_dir = tempfile::tempdir().unwrap();
+        let home_dir = tempfile::tempdir().unwrap();
+        let layered =
+            || ScriptsLocation::layered(Some(config_dir.path()), Some(home_dir.path())).unwrap();
+        assert_eq!(layered(), ScriptsLocation::in_dir(home_dir.path()));
+
+        let rustscan_dir = config_dir.path().join("rustscan");
+        std::fs::create_dir(&rustscan_dir).unwrap();
+        std::fs::write(rustscan_dir.join("scripts.toml"), "").unwrap();
+        assert_eq!(layered().config, rustscan_dir.join("scripts.toml"));
+        assert_eq!(
+            layered().scripts_dir,
+            home_dir.path().join(".rustscan_scripts")
+        );
+
+        std::fs::create_dir(rustscan_dir.join("scripts")).unwrap();
+        assert_eq!(layered().scripts_dir, rustscan_dir.join("scripts"));
+
+        assert_eq!(
+            ScriptsLocation::layered(None, Some(home_dir.path())).unwrap(),
+            ScriptsLocation::in_dir(home_dir.path())
+        );
+        assert!(ScriptsLocation::layered(None, None).is_err());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn xdg_config_home_is_honored() {
+        let config_dir = tempfile::tempdir().unwrap();
+        let rustscan_dir = config_dir.path().join("rustscan");
+        std::fs::create_dir(&rustscan_dir).unwrap();
+        std::fs::write(rustscan_dir.join("scripts.toml"), "tags = [\"xdg\"]\n").unwrap();
+
+        let previous = std::env::var_os("XDG_CONFIG_HOME");
+        std::env::set_var("XDG_CONFIG_HOME", config_dir.path());
+        let location = scripts_location(None);
+        match previous {
+            Some(previous) => std::env::set_var("XDG_CONFIG_HOME", previous),
+            None => std::env::remove_var("XDG_CONFIG_HOME"),
+        }
+
+        let config = ScriptConfig::read_config_from(&location.unwrap().config).unwrap();
+        assert_eq!(config.tags, Some(vec!["xdg".to_string()]));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn dedup_symlinked_scripts() {
+        let dir = tempfile::tempdir().unwrap();
+        let scripts_dir = dir.path().join(".rustscan_scripts");
+        std::fs::create_dir(&scripts_dir).unwrap();
+        let target = scripts_dir.join("test_script.sh");
+        std::fs::copy("fixtures/.rustscan_scripts/test_script.sh", &target).unwrap();
+        std::os::unix::fs::symlink(&target, scripts_dir.join("link.sh")).unwrap();
+
+        let scripts = parse_scripts(find_scripts(dir.path().into()).unwrap());
+        assert_eq!(scripts.len(), 2);
+        assert_eq!(dedup_scripts(scripts).len(), 1);
+    }
+
+    #[test]
+    fn binary_file_is_skipped() {
+        assert!(ScriptFile::new("fixtures/test_script_binary.bin".into()).is_none());
+        assert!(ScriptFile::new("fixtures/test_script_shebang.sh".into()).is_some());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn find_scripts_by_extension() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let scripts_dir = dir.path().join(".rustscan_scripts");
+        std::fs::create_dir(&scripts_dir).unwrap();
+        for name in [
+            "a.sh",
+            "b.PY",
+            "c.pl",
+            "d.txt",
+            "README.md",
+            ".gitkeep",
+            ".a.sh.swp",
+            "plain",
+            "tool",
+        ] {
+            std::fs::write(scripts_dir.join(name), "").unwrap();
+        }
+        std::fs::set_permissions(
+            scripts_dir.join("tool"),
+            std::fs::Permissions::from_mode(0o755),
+        )
+        .unwrap();
+        std::fs::create_dir(scripts_dir.join("nested.sh")).unwrap();
+
+        let names = |paths: Vec<std::path::PathBuf>| {
+            let mut names: Vec<String> = paths
+                .iter()
+                .map(|path| path.file_name().unwrap().to_string_lossy().into_owned())
+                .collect();
+            names.sort();
+            names
+        };
+        assert_eq!(
+            names(find_scripts(dir.path().into()).unwrap()),
+            ["a.sh", "b.PY", "c.pl", "d.txt", "tool"]
+        );
+        assert_eq!(
+            names(find_scripts_with_extensions(dir.path().into(), &[".md".to_string()]).unwrap()),
+            ["README.md"]
+        );
+    }
+
+    #[test]
+    fn find_scripts_sorted() {
+        let dir = tempfile::tempdir().unwrap();
+        let scripts_dir = dir.path().join(".rustscan_scripts");
+        std::fs::create_dir(&scripts_dir).unwrap();
+        // Written out of order, read_dir returns them in creation or hash order depending on the file system.
+        for name in ["z.sh", "m.py", "b.sh", "a.txt", "B.pl"] {
+            std::fs::write(scripts_dir.join(name), "").unwrap();
+        }
+        let paths = find_scripts(dir.path().into()).unwrap();
+        let mut sorted = paths.clone();
+        sorted.sort();
+        assert_eq!(paths, sorted);
+        assert_eq!(
+            paths
+                .iter()
+                .map(|path| path.file_name().unwrap().to_str().unwrap())
+                .collect::<Vec<&str>>(),
+            ["B.pl", "a.txt", "b.sh", "m.py", "z.sh"]
+        );
+    }
+
+    #[test]
+    fn exclude_scripts_by_name() {
+        let scripts = parse_scripts(find_scripts("fixtures/".into()).unwrap());
+        let scripts_to_run = exclude_scripts(scripts.clone(), &["Test_Script_Block".to_string()]);
+
+        assert_eq!(scripts_to_run.len(), scripts.len() - 1);
+        assert!(scripts_to_run.iter().all(|script| script
+            .path
+            .as_ref()
+            .and_then(|path| path.file_stem())
+            .is_some_and(|stem| stem != "test_script_block")));
+    }
+
+    #[test]
+    fn filter_scripts_on_normalized_tags() {
+        let dir = tempfile::tempdir().unwrap();
+        let scripts_dir = dir.path().join(".rustscan_scripts");
+        std::fs::create_dir(&scripts_dir).unwrap();
+        std::fs::write(
+            scripts_dir.join("script.sh"),
+            "#!/bin/bash\n#tags = [\" HTTP \", \"  \", \"Fast\"]\n#call_format = \"echo {{ip}}\"\n",
+        )
+        .unwrap();
+        let config_path = dir.path().join(".rustscan_scripts.toml");
+        std::fs::write(&config_path, "tags = [\"http \", \"fast\"]\n").unwrap();
+
+        let scripts = parse_scripts(find_scripts(dir.path().into()).unwrap());
+        assert_eq!(
+            scripts[0].tags,
+            Some(vec!["HTTP".to_string(), "Fast".to_string()])
+        );
+
+        let mut script_config = ScriptConfig::read_config_from(&config_path).unwrap();
+        assert_eq!(
+            script_config.tags,
+            Some(vec!["http".to_string(), "fast".to_string()])
+        );
+        assert!(filter_scripts(&scripts, &script_config).is_empty());
+
+        script_config.case_insensitive_tags = Some(true);
+        assert_eq!(filter_scripts(&scripts, &script_config).len(), 1);
+    }
+
+    #[test]
+    fn init_scripts_counts() {
+        use structopt::StructOpt;
+        let opts = Opts::from_iter(vec!["rustscan", "--scripts", "custom"]);
+        let scripts: Vec<PathBuf> = [
+            "fixtures/.rustscan_scripts/test_script.sh",
+            "fixtures/.rustscan_scripts/test_script.py",
+            "fixtures/.rustscan_scripts/test_script_invalid_headers.txt",
+            "fixtures/test_script_missing_format.sh",
+            "fixtures/test_script_front_matter.lua",
+        ]
+        .iter()
+        .map(|path| std::fs::canonicalize(path).unwrap())
+        .collect();
+        let config = ScriptConfig {
+            scripts: Some(scripts),
+            ..ScriptConfig::default().tags(vec!["example".to_string()])
+        };
+
+        let (selected, mut stats, _) = init_scripts(&opts, None, Some(config)).unwrap();
+        // The invalid headers do not parse, the missing call_format is not runnable.
+        assert_eq!((stats.discovered, stats.parsed, stats.selected), (5, 4, 3));
+
+        let runner = Arc::new(RecordingRunner::default());
+        let mut results: Vec<ScriptResult> = selected[..2]
+            .iter()
+            .map(|script_f| {
+                into_script(script_f.clone())
+                    .runner(runner.clone())
+                    .execute()
+            })
+            .collect();
+        results[0].error = Some("Exit code = 1".to_string());
+        stats.record_results(&results, 1);
+        assert_eq!(
+            (stats.ran, stats.succeeded, stats.failed, stats.skipped),
+            (2, 1, 1, 1)
+        );
+        assert_eq!(
+            format_stats(&stats),
+            "Scripts: 5 discovered, 4 parsed, 3 selected, 2 ran, 1 succeeded, 1 failed, 1 skipped"
+        );
+    }
+
+    #[test]
+    fn init_scripts_orders_by_priority() {
+        use structopt::StructOpt;
+        let opts = Opts::from_iter(vec!["rustscan", "--scripts", "custom"]);
+        let dir = tempfile::tempdir().unwrap();
+        let mut scripts = Vec::new();
+        for (name, priority) in &[("slow", "10"), ("b", ""), ("recon", "-5"), ("a", "")] {
+            let path = dir.path().join(format!("{name}.sh"));
+            let priority = if priority.is_empty() {
+                String::new()
+            } else {
+                format!("#priority = {priority}\n")
+            };
+            std::fs::write(
+                &path,
+                format!("#!/bin/bash\n{priority}#call_format = \"echo {{{{ip}}}}\"\n"),
+            )
+            .unwrap();
+            scripts.push(path);
+        }
+        let config = ScriptConfig {
+            tags: Some(Vec::new()),
+            scripts: Some(scripts),
+            ..ScriptConfig::default()
+        };
+
+        let (selected, _, _) = init_scripts(&opts, None, Some(config)).unwrap();
+        let names: Vec<String> = selected
+            .iter()
+            .map(|script_f| {
+                let stem = script_f.path.as_ref().unwrap().file_stem().unwrap();
+                stem.to_string_lossy().into_owned()
+            })
+            .collect();
+        // b and a keep the order of the scripts list.
+        assert_eq!(names, ["recon", "b", "a", "slow"]);
+    }
+
+    #[test]
+    fn empty_selection_messages() {
+        let dir = Path::new("/home/user/.rustscan_scripts");
+        let config = ScriptConfig::default().tags(vec!["web".to_string()]);
+        let stats = |discovered, parsed, selected| ScriptStats {
+            discovered,
+            parsed,
+            selected,
+            ..ScriptStats::default()
+        };
+        assert_eq!(empty_selection_message(&stats(2, 2, 1), &config, dir), None);
+        assert_eq!(
+            empty_selection_message(&stats(0, 0, 0), &config, dir).unwrap(),
+            "No scripts will run, there are no scripts in /home/user/.rustscan_scripts"
+        );
+        assert!(empty_selection_message(&stats(2, 0, 0), &config, dir)
+            .unwrap()
+            .contains("none of the 2 script(s) found have headers that parse"));
+        assert!(empty_selection_message(&stats(2, 2, 0), &config, dir)
+            .unwrap()
+            .contains("have all the tags [\"web\"]"));
+        assert_eq!(
+            empty_selection_message(&stats(2, 2, 0), &ScriptConfig::default(), dir).unwrap(),
+            "No scripts will run, the config has no tags so there is no tag filter, but none of the 2 parsed script(s) passed the developer and exclude filters or can run"
+        );
+        let no_tags = ScriptConfig::default().tags(Vec::new());
+        assert!(empty_selection_message(&stats(2, 2, 0), &no_tags, dir)
+            .unwrap()
+            .starts_with("No scripts will run, the tags are empty"));
+    }
+
+    #[test]
+    fn empty_scripts_folder_message() {
+        let dir = tempfile::tempdir().unwrap();
+        let scripts_dir = dir.path().join(".rustscan_scripts");
+        std::fs::create_dir(&scripts_dir).unwrap();
+        let config = ScriptConfig::default();
+
+        let scripts = config_script_paths(&scripts_dir, &config).unwrap();
+        let stats = ScriptStats {
+            discovered: scripts.len(),
+            ..ScriptStats::default()
+        };
+        assert_eq!(
+            empty_selection_message(&stats, &config, &scripts_dir).unwrap(),
+            format!(
+                "No scripts will run, the scripts folder {} is empty, the scripts have to be put there",
+                scripts_dir.display()
+            )
+        );
+
+        std::fs::write(scripts_dir.join("notes.md"), "").unwrap();
+        assert!(empty_selection_message(&stats, &config, &scripts_dir)
+            .unwrap()
+            .contains("none of the 1 file(s)"));
+
+        let listed = ScriptConfig {
+            scripts: Some(Vec::new()),
+            ..ScriptConfig::default()
+        };
+        assert!(empty_selection_message(&stats, &listed, &scripts_dir)
+            .unwrap()
+            .contains("the scripts list of the config is empty"));
+    }
+
+    #[test]
+    fn config_from_str_and_builder() {
+        let parsed: ScriptConfig =
+            "tags = [\" web \", \"fast\"]\nports = [\"80\"]\ndeveloper = [\"example\"]"
+                .parse()
+                .unwrap();
+        let built = ScriptConfig::default()
+            .tags(vec!["web".to_string(), " fast".to_string()])
+            .ports(vec!["80".to_string()])
+            .developer(vec!["example".to_string()]);
+        assert_eq!(parsed.tags, built.tags);
+        assert_eq!(parsed.ports, built.ports);
+        assert_eq!(parsed.developer, built.developer);
+
+        let scripts = parse_scripts(find_scripts("fixtures/".into()).unwrap());
+        let built = built.tags(vec!["core_approved".to_string(), "block".to_string()]);
+        assert_eq!(filter_scripts(&scripts, &built).len(), 1);
+
+        assert!(matches!(
+            "tags = [".parse::<ScriptConfig>(),
+            Err(ScriptError::Parse(_))
+        ));
+    }
+
+    #[test]
+    fn explicit_script_list() {
+        let shebang = std::fs::canonicalize("fixtures/test_script_shebang.sh").unwrap();
+        let script_config = ScriptConfig {
+            scripts: Some(vec!["test_script.sh".into(), shebang.clone()]),
+            ..ScriptConfig::default()
+        };
+        let paths =
+            config_script_paths(Path::new("fixtures/.rustscan_scripts"), &script_config).unwrap();
+        assert_eq!(
+            paths,
+            vec![
+                Path::new("fixtures/.rustscan_scripts/test_script.sh").to_path_buf(),
+                shebang
+            ]
+        );
+        assert_eq!(parse_scripts(paths).len(), 2);
+
+        // Without a list every file of the scripts dir is found.
+        let paths = config_script_paths(
+            Path::new("fixtures/.rustscan_scripts"),
+            &ScriptConfig::default(),
+        )
+        .unwrap();
+        assert_eq!(paths.len(), 6);
+    }
+
+    #[test]
+    fn duplicate_tag_sets_of_fixtures() {
+        let mut scripts = parse_scripts(vec![
+            "fixtures/.rustscan_scripts/test_script.sh".into(),
+            "fixtures/.rustscan_scripts/test_script.txt".into(),
+            "fixtures/.rustscan_scripts/test_script_block.py".into(),
+        ]);
+        assert_eq!(
+            duplicate_tag_sets(&scripts),
+            vec![vec![
+                Path::new("fixtures/.rustscan_scripts/test_script.sh").to_path_buf(),
+                Path::new("fixtures/.rustscan_scripts/test_script.txt").to_path_buf()
+            ]]
+        );
+
+        // The order of the tags doesn't matter.
+        scripts[1].tags = Some(vec!["example".to_string(), "core_approved".to_string()]);
+        assert_eq!(duplicate_tag_sets(&scripts).len(), 1);
+        scripts[1].tags = Some(vec!["example".to_string()]);
+        assert!(duplicate_tag_sets(&scripts).is_empty());
+    }
+
+    #[test]
+    fn filter_scripts_on_tags() {
+        let scripts = parse_scripts(find_scripts("fixtures/".into()).unwrap());
+        let mut script_config = ScriptConfig {
+            tags: Some(vec!["core_approved".to_string(), "example".to_string()]),
+            developer: None,
+            ..ScriptConfig::default()
+        };
+        assert_eq!(filter_scripts(&scripts, &script_config).len(), 5);
+
+        script_config.tags = Some(vec!["core_approved".to_string(), "fast".to_string()]);
+        assert!(filter_scripts(&scripts, &script_config).is_empty());
+    }
+
+    #[test]
+    fn filter_scripts_on_min_tag_matches() {
+        let scripts: Vec<ScriptFile> = [
+            "",
+            "tags = [\"web\"]",
+            "tags = [\"web\", \"fast\"]",
+            "tags = [\"web\", \"fast\", \"tls\"]",
+        ]
+        .iter()
+        .map(|headers| toml::from_str(headers).unwrap())
+        .collect();
+        let tag_counts = |script_config: &ScriptConfig| {
+            filter_scripts(&scripts, script_config)
+                .iter()
+                .map(|script| script.tags.iter().flatten().count())
+                .collect::<Vec<usize>>()
+        };
+        let mut script_config = ScriptConfig {
+            tags: Some(vec!["web".into(), "fast".into(), "tls".into()]),
+            min_tag_matches: Some(2),
+            ..ScriptConfig::default()
+        };
+        assert_eq!(tag_counts(&script_config), vec![2, 3]);
+
+        script_config.min_tag_matches = Some(1);
+        assert_eq!(tag_counts(&script_config), vec![1, 2, 3]);
+
+        script_config.min_tag_matches = Some(0);
+        assert_eq!(tag_counts(&script_config), vec![0, 1, 2, 3]);
+
+        // Without it every config tag has to match.
+        script_config.min_tag_matches = None;
+        assert_eq!(tag_counts(&script_config), vec![3]);
+    }
+
+    #[test]
+    fn filter_scripts_on_negated_tags() {
+        let scripts: Vec<ScriptFile> = [
+            "tags = [\"web\"]",
+            "tags = [\"web\", \"intrusive\"]",
+            "tags = [\"ssh\", \"intrusive\"]",
+        ]
+        .iter()
+        .map(|headers| toml::from_str(headers).unwrap())
+        .collect();
+        let selected = |tags: &[&str]| {
+            let script_config = ScriptConfig {
+                tags: Some(tags.iter().map(ToString::to_string).collect()),
+                ..ScriptConfig::default()
+            };
+            filter_scripts(&scripts, &script_config)
+                .iter()
+                .map(|script| script.tags.clone().unwrap_or_default().join(","))
+                .collect::<Vec<String>>()
+        };
+        // The second script has the positive tag, the negated one still excludes it.
+        assert_eq!(selected(&["web", "!intrusive"]), vec!["web"]);
+        assert_eq!(selected(&["! intrusive"]), vec!["web"]);
+        assert_eq!(selected(&["web"]), vec!["web", "web,intrusive"]);
+        assert_eq!(selected(&["!"]).len(), 3);
+    }
+
+    #[test]
+    fn filter_scripts_empty_tags_selects_all() {
+        let scripts = parse_scripts(find_scripts("fixtures/".into()).unwrap());
+        let script_config = ScriptConfig {
+            tags: Some(vec![String::new()]),
+            developer: None,
+            ..ScriptConfig::default()
+        };
+        assert_eq!(
+            filter_scripts(&scripts, &script_config).len(),
+            scripts.len()
+        );
+    }
+
+    #[test]
+    fn filter_scripts_on_developer() {
+        let scripts = parse_scripts(find_scripts("fixtures/".into()).unwrap());
+        let mut script_config = ScriptConfig {
+            tags: Some(vec!["core_approved".to_string()]),
+            developer: Some(vec!["RustScan".to_string(), "example".to_string()]),
+            ..ScriptConfig::default()
+        };
+        assert_eq!(filter_scripts(&scripts, &script_config).len(), 5);
+
+        script_config.developer = Some(vec!["RustScan".to_string()]);
+        assert!(filter_scripts(&scripts, &script_config).is_empty());
+
+        // Both filters have to pass.
+        script_config.tags = Some(vec!["fast".to_string()]);
+        script_config.developer = Some(vec!["example".to_string()]);
+        assert!(filter_scripts(&scripts, &script_config).is_empty());
+    }
+
+    #[test]
+    fn read_config_file() {
+        let script_config =
+            ScriptConfig::read_config_from(Path::new("fixtures/test_rustscan_scripts.toml"))
+                .unwrap();
+        assert_eq!(
+            script_config.tags,
+            Some(vec!["core_approved".to_string(), "example".to_string()])
+        );
+    }
+
+    fn named_script(name: &str, depends_on: Option<&str>) -> ScriptFile {
+        let mut script_f =
+            ScriptFile::new("fixtures/.rustscan_scripts/test_script.txt".into()).unwrap();
+        script_f.path = Some(format!("{name}.sh").into());
+        script_f.depends_on = depends_on.map(ToString::to_string);
+        script_f
+    }
+
+    #[test]
+    fn merge_script_configs() {
+        let base = ScriptConfig::parse(
+            "tags = [\"web\"]\ndeveloper = [\"team\"]\nmin_tag_matches = 1\n[vars]\nwordlist = \"base.txt\"\nuser = \"admin\"\n",
+            "base",
+        )
+        .unwrap();
+        let overlay = ScriptConfig::parse(
+            "tags = [\"web\", \"fast\"]\nmin_tag_matches = 2\n[vars]\nwordlist = \"mine.txt\"\n",
+            "overlay",
+        )
+        .unwrap();
+
+        let merged = base.merge(overlay);
+        assert_eq!(
+            merged.tags,
+            Some(vec!["web".to_string(), "fast".to_string()])
+        );
+        assert_eq!(merged.developer, Some(vec!["team".to_string()]));
+        assert_eq!(merged.min_tag_matches, Some(2));
+        assert_eq!(
+            merged.vars,
+            Some(BTreeMap::from([
+                ("user".to_string(), "admin".to_string()),
+                ("wordlist".to_string(), "mine.txt".to_string()),
+            ]))
+        );
+        assert!(ScriptConfig::read_configs(&["fixtures/missing_config.toml".into()]).is_err());
+    }
+
+    #[test]
+    fn custom_template_delimiters() {
+        assert_eq!(
+            with_default_delimiters("echo <<ip>> {{ .Name }} <<port>>", "<<", ">>"),
+            "echo {{ip}} {{{{ .Name }}}} {{port}}"
+        );
+        assert_eq!(
+            with_default_delimiters("echo {{ip}}", "{{", "}}"),
+            "echo {{ip}}"
+        );
+
+        let config =
+            ScriptConfig::parse("template_delimiters = [\"<<\", \">>\"]\n", "delimiters").unwrap();
+        assert_eq!(config.delimiters(), Some(("<<", ">>")));
+        assert!(ScriptConfig::parse("template_delimiters = [\"<<\"]\n", "delimiters").is_err());
+
+        let mut script_f =
+            ScriptFile::new("fixtures/.rustscan_scripts/test_script.txt".into()).unwrap();
+        script_f.call_format = Some("echo <<ip>> '{{ .Port }}' <<port>>".into());
+        script_f.use_delimiters("<<", ">>");
+        assert_eq!(
+            into_script(script_f.clone()).preview_all().unwrap(),
+            vec![vec!["echo", "127.0.0.1", "{{ .Port }}", "80,8080"]]
+        );
+
+        // The default delimiters are unaffected.
+        let mut script_f =
+            ScriptFile::new("fixtures/.rustscan_scripts/test_script.txt".into()).unwrap();
+        script_f.call_format = Some("echo {{ip}} <<port>>".into());
+        assert_eq!(
+            into_script(script_f).preview_all().unwrap(),
+            vec![vec!["echo", "127.0.0.1", "<<port>>"]]
+        );
+    }
+
+    #[test]
+    fn alias_selects_its_tags() {
+        use structopt::StructOpt;
+        let dir = tempfile::tempdir().unwrap();
+        let mut scripts = Vec::new();
+        for (name, tags) in &[("web", "\"http\", \"tls\""), ("ssh", "\"ssh\"")] {
+            let path = dir.path().join(format!("{name}.sh"));
+            std::fs::write(
+                &path,
+                format!("#!/bin/bash\n#tags = [{tags}]\n#call_format = \"echo {{{{ip}}}}\"\n"),
+            )
+            .unwrap();
+            scripts.push(path);
+        }
+        let config = ScriptConfig::parse(
+            "tags = [\"ssh\"]\n[aliases]\nweb = [\"http\", \" tls \"]\nremote = [\"ssh\"]\n",
+            "aliases",
+        )
+        .unwrap();
+        assert_eq!(
+            config.alias_tags("web").unwrap(),
+            vec!["http".to_string(), "tls".to_string()]
+        );
+        assert_eq!(
+            config.alias_tags("db").unwrap_err().to_string(),
+            "Unknown scripts alias db, the defined ones are: remote, web"
+        );
+        assert_eq!(
+            ScriptConfig::default()
+                .alias_tags("db")
+                .unwrap_err()
+                .to_string(),
+            "Unknown scripts alias db, the script config defines no aliases"
+        );
+
+        let opts = Opts::from_iter(vec![
+            "rustscan",
+            "--scripts",
+            "custom",
+            "--scripts-alias",
+            "web",
+        ]);
+        let config = ScriptConfig {
+            scripts: Some(scripts),
+            ..config
+        };
+        let (selected, _, _) = init_scripts(&opts, None, Some(config.clone())).unwrap();
+        assert_eq!(selected.len(), 1);
+        assert!(selected[0].path.as_ref().unwrap().ends_with("web.sh"));
+
+        let opts = Opts::from_iter(vec![
+            "rustscan",
+            "--scripts",
+            "custom",
+            "--scripts-alias",
+            "db",
+        ]);
+        assert!(init_scripts(&opts, None, Some(config)).is_err());
+    }
+
+    #[test]
+    fn profiles_select_the_tags() {
+        use structopt::StructOpt;
+        let config = ScriptConfig::parse(
+            "tags = [\"core\"]\ndeveloper = [\"example\"]\n\
+             [profiles.default]\ntags = [\"safe\"]\n\
+             [profiles.web]\ntags = [\"http\", \" tls \"]\nports = [\"80-443\"]\n",
+            "profiles",
+        )
+        .unwrap();
+
+        let web = config.clone().with_profile(Some("web")).unwrap();
+        assert_eq!(web.tags, Some(vec!["http".to_string(), "tls".to_string()]));
+        assert_eq!(web.port_scope().as_deref(), Some("80-443"));
+        assert_eq!(web.developer, Some(vec!["example".to_string()]));
+
+        let default = config.clone().with_profile(None).unwrap();
+        assert_eq!(default.tags, Some(vec!["safe".to_string()]));
+        assert_eq!(default.ports, None);
+
+        assert_eq!(
+            config
+                .clone()
+                .with_profile(Some("db"))
+                .unwrap_err()
+                .to_string(),
+            "Unknown scripts profile db, the defined ones are: default, web"
+        );
+        assert_eq!(
+            ScriptConfig::default()
+                .with_profile(Some("db"))
+                .unwrap_err()
+                .to_string(),
+            "Unknown scripts profile db, the script config defines no profiles"
+        );
+        assert_eq!(
+            ScriptConfig::default().with_profile(None).unwrap().tags,
+            None
+        );
+        assert!(ScriptConfig::parse("[profiles.web]\nports = [\"http\"]\n", "profiles").is_err());
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("web.sh");
+        std::fs::write(
+            &path,
+            "#!/bin/bash\n#tags = [\"http\", \"tls\"]\n#developer = [\"example\"]\n#call_format = \"echo {{ip}}\"\n",
+        )
+        .unwrap();
+        let config = ScriptConfig {
+            scripts: Some(vec![path]),
+            ..config
+        };
+        let opts = Opts::from_iter(vec!["rustscan", "--scripts", "custom"]);
+        let (selected, _, _) = init_scripts(&opts, None, Some(config.clone())).unwrap();
+        assert!(selected.is_empty());
+        let opts = Opts::from_iter(vec![
+            "rustscan",
+            "--scripts",
+            "custom",
+            "--scripts-profile",
+            "web",
+        ]);
+        let (selected, _, _) = init_scripts(&opts, None, Some(config)).unwrap();
+        assert_eq!(selected.len(), 1);
+        assert_eq!(selected[0].port_scope.as_deref(), Some("80-443"));
+    }
+
+    #[test]
+    fn origin_reports_the_config_override() {
+        use structopt::StructOpt;
+        let mut archive = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()))
+            .finish()
+            .unwrap();
+        archive.set_position(0);
+        let bundle = ScriptBundle::from_reader(archive).unwrap();
+        let location = ScriptsLocation::in_dir(bundle.base_dir());
+        std::fs::write(&location.config, "tags = []").unwrap();
+
+        let opts = Opts::from_iter(vec!["rustscan", "--scripts", "custom"]);
+        let (_, _, origin) = init_scripts(&opts, Some(&bundle), None).unwrap();
+        assert_eq!(origin.config_files, vec![location.config.clone()]);
+        assert_eq!(origin.scripts_dir.as_ref(), Some(&location.scripts_dir));
+
+        let dir = tempfile::tempdir().unwrap();
+        let override_config = dir.path().join("override.toml");
+        std::fs::write(&override_config, "tags = [\"web\"]").unwrap();
+        let opts = Opts::from_iter(vec![
+            "rustscan",
+            "--scripts",
+            "custom",
+            "--scripts-config",
+            override_config.to_str().unwrap(),
+        ]);
+        let (_, _, origin) = init_scripts(&opts, Some(&bundle), None).unwrap();
+        assert_eq!(origin.config_files, vec![override_config.clone()]);
+        assert_eq!(
+            origin.to_string(),
+            format!(
+                "Script config {}, scripts folder {}",
+                override_config.display(),
+                location.scripts_dir.display()
+            )
+        );
+
+        let (_, _, origin) =
+            init_scripts(&opts, Some(&bundle), Some(ScriptConfig::default())).unwrap();
+        assert!(origin.config_files.is_empty());
+    }
+
+    #[test]
+    fn origin_records_the_effective_tags() {
+        use structopt::StructOpt;
+        let config = || -> ScriptConfig {
+            toml::from_str("tags = [\"core\"]\n[aliases]\nweb = [\"http\", \"tls\"]\n").unwrap()
+        };
+        let opts = |extra: &[&str]| {
+            let mut args = vec!["rustscan", "--scripts", "custom"];
+            args.extend_from_slice(extra);
+            Opts::from_iter(args)
+        };
+        let origin_tags = |extra: &[&str]| {
+            let mut archive = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()))
+                .finish()
+                .unwrap();
+            archive.set_position(0);
+            let bundle = ScriptBundle::from_reader(archive).unwrap();
+            init_scripts(&opts(extra), Some(&bundle), Some(config()))
+                .unwrap()
+                .2
+                .tags
+        };
+
+        assert_eq!(origin_tags(&[]), Some(vec!["core".to_string()]));
+        assert_eq!(
+            origin_tags(&["--scripts-alias", "web"]),
+            Some(vec!["http".to_string(), "tls".to_string()])
+        );
+        assert_eq!(
+            origin_tags(&["--scripts-tags", "smb", "--scripts-alias", "web"]),
+            Some(vec![
+                "smb".to_string(),
+                "http".to_string(),
+                "tls".to_string()
+            ])
+        );
+        assert_eq!(
+            init_scripts(
+                &Opts::from_iter(vec!["rustscan", "--scripts", "default"]),
+                None,
+                None
+            )
+            .unwrap()
+            .2
+            .tags,
+            default_script().tags
+        );
+    }
+
+    #[test]
+    fn optional_scripts_survive_a_missing_folder() {
+        use structopt::StructOpt;
+        let mut archive = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()))
+            .finish()
+            .unwrap();
+        archive.set_position(0);
+        let bundle = ScriptBundle::from_reader(archive).unwrap();
+        std::fs::remove_dir(bundle.base_dir().join(".rustscan_scripts")).unwrap();
+
+        let opts = Opts::from_iter(vec!["rustscan", "--scripts", "custom"]);
+        let error = init_scripts(&opts, Some(&bundle), None).unwrap_err();
+        assert!(matches!(
+            ScriptError::of(&error),
+            Some(ScriptError::NotFound(_))
+        ));
+
+        let opts = Opts::from_iter(vec![
+            "rustscan",
+            "--scripts",
+            "custom",
+            "--scripts-optional",
+        ]);
+        let (selected, stats, _) = init_scripts(&opts, Some(&bundle), None).unwrap();
+        assert!(selected.is_empty());
+        assert_eq!(stats.selected, 0);
+    }
+
+    #[test]
+    fn experimental_scripts_need_the_flag() {
+        use structopt::StructOpt;
+        let config = ScriptConfig {
+            scripts: Some(
+                [
+                    "fixtures/.rustscan_scripts/test_script.sh",
+                    "fixtures/test_script_experimental.sh",
+                ]
+                .iter()
+                .map(|path| std::fs::canonicalize(path).unwrap())
+                .collect(),
+            ),
+            ..ScriptConfig::default().tags(vec!["example".to_string()])
+        };
+        let names = |selected: Vec<ScriptFile>| -> Vec<String> {
+            selected.iter().map(ScriptFile::name).collect()
+        };
+
+        let opts = Opts::from_iter(vec!["rustscan", "--scripts", "custom"]);
+        let (selected, _, _) = init_scripts(&opts, None, Some(config.clone())).unwrap();
+        assert_eq!(names(selected), vec!["test_script"]);
+
+        // Selecting the experimental tag is not enough.
+        let experimental_tag = config.clone().tags(vec!["experimental".to_string()]);
+        let (selected, stats, _) =
+            init_scripts(&opts, None, Some(experimental_tag.clone())).unwrap();
+        assert!(selected.is_empty());
+        assert_eq!(stats.experimental, 1);
+        let require_match = Opts::from_iter(vec![
+            "rustscan",
+            "--scripts",
+            "custom",
+            "--scripts-require-match",
+        ]);
+        assert_eq!(
+            init_scripts(&require_match, None, Some(experimental_tag))
+                .unwrap_err()
+                .to_string(),
+            "No scripts will run, the 1 selected script(s) are experimental and need --scripts-experimental, \
+             --scripts-require-match needs at least one"
+        );
+
+        let opts = Opts::from_iter(vec![
+            "rustscan",
+            "--scripts",
+            "custom",
+            "--scripts-experimental",
+        ]);
+        let (selected, _, _) = init_scripts(&opts, None, Some(config)).unwrap();
+        assert_eq!(
+            names(selected),
+            vec!["test_script", "test_script_experimental"]
+        );
+
+        let mut script_f = named_script("field", None);
+        assert!(!script_f.is_experimental());
+        script_f.experimental = Some(true);
+        assert!(script_f.is_experimental());
+    }
+
+    #[test]
+    fn require_match_fails_an_empty_selection() {
+        use structopt::StructOpt;
+        let config = ScriptConfig {
+            scripts: Some(vec![std::fs::canonicalize(
+                "fixtures/.rustscan_scripts/test_script.sh",
+            )
+            .unwrap()]),
+            ..ScriptConfig::default().tags(vec!["no-such-tag".to_string()])
+        };
+        let opts = Opts::from_iter(vec!["rustscan", "--scripts", "custom"]);
+        let (selected, _, _) = init_scripts(&opts, None, Some(config.clone())).unwrap();
+        assert!(selected.is_empty());
+
+        let opts = Opts::from_iter(vec![
+            "rustscan",
+            "--scripts",
+            "custom",
+            "--scripts-require-match",
+        ]);
+        let error = init_scripts(&opts, None, Some(config.clone())).unwrap_err();
+        assert!(error.to_string().contains("no-such-tag"), "{}", error);
+        assert!(error.to_string().contains("--scripts-require-match"));
+
+        let matching = ScriptConfig {
+            tags: Some(Vec::new()),
+            ..config
+        };
+        let (selected, _, _) = init_scripts(&opts, None, Some(matching)).unwrap();
+        assert_eq!(selected.len(), 1);
+
+        assert!(Opts::from_iter_safe(vec![
+            "rustscan",
+            "--scripts-require-match",
+            "--scripts-optional",
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn match_detected_os_and_services() {
+        let windows: IpAddr = "10.0.0.1".parse().unwrap();
+        let linux: IpAddr = "10.0.0.2".parse().unwrap();
+        let unknown: IpAddr = "10.0.0.3".parse().unwrap();
+        let detection = Detection::default()
+            .os(windows, "Windows Server 2019")
+            .service(windows, "smb")
+            .os(linux, "Linux")
+            .service(linux, "ssh")
+            .service(linux, "http");
+
+        let mut script_f = named_script("smb_enum", None);
+        assert_eq!(script_f.matches_detection(None, windows), Some(true));
+
+        script_f.os_match = Some("windows".to_string());
+        assert_eq!(
+            script_f.matches_detection(Some(&detection), windows),
+            Some(true)
+        );
+        assert_eq!(
+            script_f.matches_detection(Some(&detection), linux),
+            Some(false)
+        );
+        assert_eq!(script_f.matches_detection(Some(&detection), unknown), None);
+        assert_eq!(script_f.matches_detection(None, windows), None);
+
+        script_f.os_match = None;
+        script_f.service_match = Some("^http$".to_string());
+        assert_eq!(
+            script_f.matches_detection(Some(&detection), linux),
+            Some(true)
+        );
+        assert_eq!(
+            script_f.matches_detection(Some(&detection), windows),
+            Some(false)
+        );
+
+        // Any mismatch wins over an unknown value.
+        script_f.os_match = Some("bsd".to_string());
+        let services_only = Detection::default().service(unknown, "http");
+        assert_eq!(
+            script_f.matches_detection(Some(&services_only), unknown),
+            None
+        );
+        assert_eq!(
+            script_f.matches_detection(Some(&detection), linux),
+            Some(false)
+        );
+    }
+
+    #[test]
+    fn read_detection_file() {
+        let windows: IpAddr = "10.0.0.5".parse().unwrap();
+        let linux: IpAddr = "::1".parse().unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("detection.toml");
+        std::fs::write(
+            &path,
+            "[\"10.0.0.5\"]\nos = \"Windows Server 2019\"\nservices = [\"smb\"]\n\n[\"::1\"]\nservices = [\"ssh\", \"http\"]\n",
+        )
+        .unwrap();
+        assert_eq!(
+            Detection::read_from(&path).unwrap(),
+            Detection::default()
+                .os(windows, "Windows Server 2019")
+                .service(windows, "smb")
+                .service(linux, "ssh")
+                .service(linux, "http")
+        );
+
+        std::fs::write(&path, "[\"web01\"]\nos = \"Linux\"\n").unwrap();
+        let e = Detection::read_from(&path).unwrap_err();
+        assert!(format!("{e:#}").contains("web01 is not an ip"));
+        std::fs::write(&path, "[\"10.0.0.5\"]\nsystem = \"Linux\"\n").unwrap();
+        assert!(Detection::read_from(&path).is_err());
+        assert!(Detection::read_from(&dir.path().join("missing.toml")).is_err());
+    }
+
+    #[test]
+    fn root_scripts_need_elevation() {
+        use structopt::StructOpt;
+
+        let scripts = || {
+            let mut root = named_script("raw_scan", None);
+            root.requires_root = Some(true);
+            vec![named_script("plain", None), root]
+        };
+        let names = |scripts: &[ScriptFile]| -> Vec<String> {
+            scripts.iter().map(ScriptFile::name).collect()
+        };
+
+        let opts = Opts::from_iter(vec!["rustscan", "-a", "127.0.0.1"]);
+        let mut skipped = scripts();
+        check_privileges(&mut skipped, false, &opts);
+        assert_eq!(names(&skipped), vec!["plain"]);
+
+        let mut elevated = scripts();
+        check_privileges(&mut elevated, true, &opts);
+        assert_eq!(names(&elevated), vec!["plain", "raw_scan"]);
+
+        let opts = Opts::from_iter(vec![
+            "rustscan",
+            "-a",
+            "127.0.0.1",
+            "--scripts-run-unprivileged",
+        ]);
+        let mut warned = scripts();
+        check_privileges(&mut warned, false, &opts);
+        assert_eq!(names(&warned), vec!["plain", "raw_scan"]);
+    }
+
+    #[test]
+    fn order_scripts_by_dependencies() {
+        let names = |scripts: Vec<ScriptFile>| -> Vec<String> {
+            order_by_dependencies(scripts)
+                .unwrap()
+                .iter()
+                .map(ScriptFile::name)
+                .collect()
+        };
+        assert_eq!(
+            names(vec![
+                named_script("exploit", Some("version")),
+                named_script("other", None),
+                named_script("version", Some("ping")),
+                named_script("ping", None),
+            ]),
+            ["ping", "version", "exploit", "other"]
+        );
+        // Already in order, nothing moves.
+        assert_eq!(
+            names(vec![
+                named_script("version", None),
+                named_script("exploit", Some("version")),
+            ]),
+            ["version", "exploit"]
+        );
+
+        let cycle = order_by_dependencies(vec![
+            named_script("a", Some("b")),
+            named_script("b", Some("c")),
+            named_script("c", Some("a")),
+        ])
+        .unwrap_err();
+        assert_eq!(
+            cycle.to_string(),
+            "Script dependency cycle: a -> b -> c -> a"
+        );
+        assert!(order_by_dependencies(vec![named_script("a", Some("a"))]).is_err());
+
+        let missing = order_by_dependencies(vec![named_script("a", Some("gone"))]).unwrap_err();
+        assert_eq!(
+            missing.to_string(),
+            "Script a depends on gone, which is not selected"
+        );
+    }
+
+    #[derive(Debug, Default)]
+    struct LaunchRecorder {
+        launches: Mutex<Vec<Instant>>,
+    }
+
+    impl CommandRunner for LaunchRecorder {
+        fn run(&self, _arguments: &[String]) -> anyhow::Result<(i64, String, String)> {
+            self.launches.lock().unwrap().push(Instant::now());
+            Ok((0, String::new(), String::new()))
+        }
+    }
+
+    #[test]
+    fn rate_limited_launches_are_spaced() {
+        let start = Instant::now();
+        let recorder = Arc::new(LaunchRecorder::default());
+        let runner = Arc::new(RateLimitedRunner::new(recorder.clone(), 20));
+        let workers: Vec<thread::JoinHandle<()>> = (0..4)
+            .map(|_| {
+                let runner = Arc::clone(&runner);
+                thread::spawn(move || {
+                    for _ in 0..2 {
+                        runner.run(&["true".to_string()]).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for worker in workers {
+            worker.join().unwrap();
+        }
+
+        let mut launches = recorder.launches.lock().unwrap().clone();
+        launches.sort_unstable();
+        assert_eq!(launches.len(), 8);
+        // 20 per second is one every 50ms, the first one goes right away.
+        for (index, launch) in (0_u32..).zip(&launches) {
+            assert!(launch.duration_since(start) >= Duration::from_millis(50) * index);
+        }
+        assert!(launches[7].duration_since(start) < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn default_call_format_verbosity() {
+        use structopt::StructOpt;
+        // The DEFAULT one without a verbosity or args.
+        assert_eq!(
+            default_script().call_format,
+            Some(CallFormat::Single(default_call_format(None, None)))
+        );
+        assert_eq!(
+            default_call_format(Some(0), None),
+            "nmap -p {{port}} {{ip}}"
+        );
+        assert_eq!(
+            default_call_format(Some(1), None),
+            "nmap -v -p {{port}} {{ip}}"
+        );
+        assert_eq!(
+            default_call_format(None, Some("-sV -Pn")),
+            "nmap -vvv -sV -Pn -p {{port}} {{ip}}"
+        );
+        assert_eq!(
+            default_call_format(Some(0), Some(" ")),
+            "nmap -p {{port}} {{ip}}"
+        );
+
+        let opts = Opts::from_iter(vec!["rustscan", "--scripts-default-verbosity", "2"]);
+        assert_eq!(
+            default_script_for(&opts).call_format,
+            Some(CallFormat::Single(
+                "nmap -vv -p {{port}} {{ip}}".to_string()
+            ))
+        );
+        let opts = Opts::from_iter(vec!["rustscan"]);
+        assert_eq!(
+            default_script_for(&opts).call_format,
+            default_script().call_format
+        );
+    }
+
+    #[test]
+    fn filter_default_script() {
+        let script_config = ScriptConfig {
+            tags: Some(vec!["core_approved".to_string()]),
+            include_default: Some(true),
+            ..ScriptConfig::default()
+        };
+        let mut scripts = parse_scripts(find_scripts("fixtures/".into()).unwrap());
+        scripts.push(default_script());
+        let selected = filter_scripts(&scripts, &script_config);
+        assert!(selected.iter().any(|s| s.path.is_none()));
+
+        let script_config = ScriptConfig {
+            tags: Some(vec!["example".to_string()]),
+            ..script_config
+        };
+        let selected = filter_scripts(&scripts, &script_config);
+        assert!(selected.iter().all(|s| s.path.is_some()));
+    }
+
+    #[test]
+    fn read_missing_config_runs_all_scripts() {
+        let script_config =
+            ScriptConfig::read_config_from(Path::new("fixtures/missing_config.toml")).unwrap();
+        let scripts = parse_scripts(find_scripts("fixtures/".into()).unwrap());
+        assert_eq!(
+            filter_scripts(&scripts, &script_config).len(),
+            scripts.len()
+        );
+    }
+
+    #[test]
+    fn config_without_tags_runs_all_scripts() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(".rustscan_scripts.toml");
+        std::fs::write(&path, "ports = [\"80\"]\n").unwrap();
+        let script_config = ScriptConfig::read_config_from(&path).unwrap();
+        assert_eq!(script_config.tags, None);
+
+        let scripts = parse_scripts(find_scripts("fixtures/".into()).unwrap());
+        assert_eq!(
+            filter_scripts(&scripts, &script_config).len(),
+            scripts.len()
+        );
+    }
+
+    #[test]
+    fn read_invalid_config_names_the_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(".rustscan_scripts.toml");
+        std::fs::write(&path, "tags = [\"core_approved\"\ndeveloper = 1\n").unwrap();
+
+        let error = ScriptConfig::read_config_from(&path).unwrap_err();
+        let message = format!("{error:#}");
+        assert!(message.starts_with(&format!("Failed to parse {}", path.display())));
+        assert!(message.contains("line 2"));
+    }
+
+    #[test]
+    fn hooks_wrap_the_scripts() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("hooks.log");
+        let append = |line: &str| format!("bash -c 'echo {line} >> {}'", log.display());
+        let hooks = ScriptHooks::from_config(&ScriptConfig {
+            pre_hook: Some(append("pre {{ips}}")),
+            post_hook: Some(append("post")),
+            ..ScriptConfig::default()
+        });
+        let ips = ["127.0.0.1".parse().unwrap(), "::1".parse().unwrap()];
+
+        let mut script_f =
+            ScriptFile::new("fixtures/.rustscan_scripts/test_script.txt".into()).unwrap();
+        script_f.call_format = Some(CallFormat::from(append("script {{ip}}")));
+
+        hooks.run_pre(&ips).unwrap();
+        into_script(script_f).run().unwrap();
+        hooks.run_post(&ips).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(log).unwrap(),
+            "pre 127.0.0.1,::1\nscript 127.0.0.1\npost\n"
+        );
+    }
+
+    #[test]
+    fn failing_hook_errors() {
+        let hooks = ScriptHooks::from_config(&ScriptConfig {
+            post_hook: Some("false".to_string()),
+            hooks_fatal: Some(true),
+            ..ScriptConfig::default()
+        });
+        assert!(hooks.fatal);
+        assert_eq!(hooks.run_pre(&[]).unwrap(), "");
+        assert!(hooks.run_post(&[]).is_err());
+    }
+
+    #[test]
+    fn read_unreadable_config_fails() {
+        assert!(ScriptConfig::read_config_from(Path::new("fixtures/")).is_err());
+    }
+
+    #[test]
+    fn wrong_kind_of_path_is_named() {
+        let dir = tempfile::tempdir().unwrap();
+        let scripts_dir = dir.path().join(".rustscan_scripts");
+        std::fs::write(&scripts_dir, "").unwrap();
+        assert_eq!(
+            scripts_in_dir(scripts_dir.clone(), &default_extensions())
+                .unwrap_err()
+                .to_string(),
+            format!(
+                "Scripts folder {} exists but is a file, expected a directory",
+                scripts_dir.display()
+            )
+        );
+        let missing = dir.path().join("missing");
+        assert_eq!(
+            scripts_in_dir(missing.clone(), &default_extensions())
+                .unwrap_err()
+                .to_string(),
+            format!(
+                "Can't find scripts folder {}, the path does not exist",
+                missing.display()
+            )
+        );
+
+        let config = dir.path().join(".rustscan_scripts.toml");
+        std::fs::create_dir(&config).unwrap();
+        assert_eq!(
+            ScriptConfig::read_config_from(&config)
+                .unwrap_err()
+                .to_string(),
+            format!(
+                "Script config {} is a directory, expected a file",
+                config.display()
+            )
+        );
+
+        #[cfg(unix)]
+        {
+            let link = dir.path().join("link");
+            std::os::unix::fs::symlink(&missing, &link).unwrap();
+            let error = scripts_in_dir(link.clone(), &default_extensions()).unwrap_err();
+            assert_eq!(
+                error.to_string(),
+                format!("Scripts folder {} is a broken symlink", link.display())
+            );
+            assert_eq!(ScriptError::of(&error), Some(&ScriptError::NotFound(link)));
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn find_invalid_folder() {
+        let _scripts = find_scripts("Cargo.toml".into()).unwrap();
+    }
+
+    #[test]
+    #[should_panic]
+    fn open_script_file_invalid_headers() {
+        ScriptFile::new("fixtures/.rustscan_scripts/test_script_invalid_headers.txt".into())
+            .unwrap();
+    }
+
+    #[test]
+    #[should_panic]
+    fn open_script_file_invalid_call_format() {
+        let mut script_f =
+            ScriptFile::new("fixtures/.rustscan_scripts/test_script.txt".into()).unwrap();
+        script_f.call_format = Some("qwertyuiop".into());
+        let script: Script = into_script(script_f);
+        let _output = script.run().unwrap();
+    }
+
+    #[test]
+    #[should_panic]
+    fn open_script_file_missing_call_format() {
+        let mut script_f =
+            ScriptFile::new("fixtures/.rustscan_scripts/test_script.txt".into()).unwrap();
+        script_f.call_format = None;
+        let script: Script = into_script(script_f);
+        let _output = script.run().unwrap();
+    }
+
+    #[test]
+    #[should_panic]
+    fn open_nonexisting_script_file() {
+        ScriptFile::new("qwertyuiop.txt".into()).unwrap();
+    }
+
+    #[test]
+    fn parse_txt_script() {
+        let script_f =
+            ScriptFile::new("fixtures/.rustscan_scripts/test_script.txt".into()).unwrap();
+        assert_eq!(
+            script_f.tags,
+            Some(vec!["core_approved".to_string(), "example".to_string()])
+        );
+        assert_eq!(
+            script_f.developer,
+            Some(vec![
+                "example".to_string(),
+                "https://example.org".to_string()
+            ])
+        );
+        assert_eq!(script_f.ports_separator, Some(",".to_string()));
+        assert_eq!(
+            script_f.call_format,
+            Some("nmap -vvv -p {{port}} {{ip}}".into())
+        );
+    }
+
+    #[test]
+    fn preview_script_format() {
+        let script_f = ScriptFile::new("fixtures/.rustscan_scripts/test_script.sh".into()).unwrap();
+        let script: Script = into_script(script_f);
+        assert_eq!(
+            script.preview().unwrap(),
+            vec![
+                "bash",
+                "fixtures/.rustscan_scripts/test_script.sh",
+                "127.0.0.1",
+                "80,8080"
+            ]
+        );
+    }
+
+    #[test]
+    fn preview_script_format_without_path() {
+        let mut script_f =
+            ScriptFile::new("fixtures/.rustscan_scripts/test_script.sh".into()).unwrap();
+        script_f.path = None;
+        let script: Script = into_script(script_f);
+        assert_eq!(
+            script.preview().unwrap_err().to_string(),
+            "Script path is required by the {{script}} format."
+        );
+    }
+
+    #[test]
+    fn preview_indexed_ports() {
+        let mut script_f =
+            ScriptFile::new("fixtures/.rustscan_scripts/test_script.txt".into()).unwrap();
+        script_f.call_format = Some("tool {{port1}} {{port2}} {{port3}} {{port9}}".into());
+        let script = Script::build(
+            script_f.clone(),
+            "127.0.0.1".parse().unwrap(),
+            vec![443, 22, 80],
+        );
+        assert_eq!(script.preview().unwrap(), vec!["tool", "22", "80", "443"]);
+
+        // Only the referenced ports are passed on.
+        script_f.call_format = Some("tool -p {{port1}}".into());
+        let script = into_script(script_f);
+        assert_eq!(script.preview().unwrap(), vec!["tool", "-p", "80"]);
+    }
+
+    #[test]
+    fn open_ports_are_sorted_before_templating() {
+        let mut script_f =
+            ScriptFile::new("fixtures/.rustscan_scripts/test_script.txt".into()).unwrap();
+        script_f.call_format = Some("scan -p {{port}} {{port1}} {{ports_flag:-t}}".into());
+        let shuffled = vec![8080, 22, 443, 22, 80, 8080];
+        let preview = |script_f: &ScriptFile| {
+            Script::build(
+                script_f.clone(),
+                "127.0.0.1".parse().unwrap(),
+                shuffled.clone(),
+            )
+            .preview()
+            .unwrap()
+        };
+        assert_eq!(
+            preview(&script_f),
+            vec![
+                "scan",
+                "-p",
+                "22,80,443,8080",
+                "22",
+                "-t",
+                "22",
+                "-t",
+                "80",
+                "-t",
+                "443",
+                "-t",
+                "8080"
+            ]
+        );
+
+        // Without it the scan order and the duplicates are kept.
+        script_f.sort_ports = Some(false);
+        script_f.call_format = Some("scan -p {{port}}".into());
+        assert_eq!(
+            preview(&script_f),
+            vec!["scan", "-p", "8080,22,443,22,80,8080"]
+        );
+    }
+
+    #[test]
+    fn preview_ports_flag() {
+        let script_f =
+            ScriptFile::new("fixtures/.rustscan_scripts/test_script.txt".into()).unwrap();
+        let preview = |call_format: &str, ports: Vec<u16>| {
+            let mut script_f = script_f.clone();
+            script_f.call_format = Some(call_format.into());
+            Script::build(script_f, "127.0.0.1".parse().unwrap(), ports)
+                .preview()
+                .unwrap()
+        };
+        let ncat = "ncat {{ports_flag:--port}} {{ip}}";
+        assert_eq!(preview(ncat, Vec::new()), vec!["ncat", "127.0.0.1"]);
+        assert_eq!(
+            preview(ncat, vec![80]),
+            vec!["ncat", "--port", "80", "127.0.0.1"]
+        );
+        assert_eq!(
+            preview(ncat, vec![80, 443, 8080]),
+            vec![
+                "ncat",
+                "--port",
+                "80",
+                "--port",
+                "443",
+                "--port",
+                "8080",
+                "127.0.0.1"
+            ]
+        );
+
+        assert_eq!(
+            preview("curl {{ports_flag:-p=}}", vec![80, 443]),
+            vec!["curl", "-p=80", "-p=443"]
+        );
+        // An escaped one stays as written.
+        assert_eq!(
+            preview("echo {{{{ports_flag:-p}}}}", vec![80]),
+            vec!["echo", "{{ports_flag:-p}}"]
+        );
+    }
+
+    #[test]
+    fn preview_port_conditionals() {
+        let script_f =
+            ScriptFile::new("fixtures/.rustscan_scripts/test_script.txt".into()).unwrap();
+        let preview = |call_format: &str, ports: Vec<u16>| {
+            let mut script_f = script_f.clone();
+            script_f.call_format = Some(call_format.into());
+            Script::build(script_f, "127.0.0.1".parse().unwrap(), ports).preview()
+        };
+        let scanner = "scan {{?443:--https}} {{?80:--http --follow}} {{ip}}";
+        assert_eq!(
+            preview(scanner, vec![443]).unwrap(),
+            vec!["scan", "--https", "127.0.0.1"]
+        );
+        assert_eq!(
+            preview(scanner, vec![80, 443]).unwrap(),
+            vec!["scan", "--https", "--http", "--follow", "127.0.0.1"]
+        );
+        assert_eq!(
+            preview(scanner, vec![22]).unwrap(),
+            vec!["scan", "127.0.0.1"]
+        );
+        // Escaped braces stay as written, a port out of range is an unknown placeholder.
+        assert_eq!(
+            preview("echo {{{{?443:x}}}}", vec![443]).unwrap(),
+            vec!["echo", "{{?443:x}}"]
+        );
+        assert!(preview("scan {{?70000:x}}", vec![443]).is_err());
+    }
+
+    #[test]
+    fn preview_port_metadata() {
+        let mut script_f =
+            ScriptFile::new("fixtures/.rustscan_scripts/test_script.txt".into()).unwrap();
+        script_f.call_format = Some("probe {{services}} '{{banner}}'".into());
+        let script = Script::build(
+            script_f.clone(),
+            "127.0.0.1".parse().unwrap(),
+            vec![80, 22, 443],
+        );
+        // Without metadata they are empty.
+        assert_eq!(script.preview().unwrap(), vec!["probe", ""]);
+
+        let metadata = BTreeMap::from([
+            (
+                22,
+                PortMetadata {
+                    service: Some("ssh".to_string()),
+                    banner: Some("SSH-2.0-OpenSSH_8.9".to_string()),
+                },
+            ),
+            (
+                443,
+                PortMetadata {
+                    service: Some("https".to_string()),
+                    banner: None,
+                },
+            ),
+        ]);
+        let script = script.port_metadata(metadata.clone());
+        assert_eq!(
+            script.preview().unwrap(),
+            vec!["probe", "22:ssh,443:https", "SSH-2.0-OpenSSH_8.9"]
+        );
+
+        script_f.port = Some("443".to_string());
+        let script = Script::build(script_f, "127.0.0.1".parse().unwrap(), vec![80, 22, 443])
+            .port_metadata(metadata);
+        assert_eq!(
+            script.preview().unwrap(),
+            vec!["probe", "22:ssh,443:https", ""]
+        );
+    }
+
+    #[test]
+    fn preview_user_vars() {
+        let config: ScriptConfig =
+            toml::from_str("[vars]\nwordlist = \"/tmp/words.txt\"\nip = \"shadowed\"\n").unwrap();
+        let mut script_f =
+            ScriptFile::new("fixtures/.rustscan_scripts/test_script.txt".into()).unwrap();
+        script_f.call_format =
+            Some("gobuster -w {{vars.wordlist}} {{wordlist}} {{ip}} {{vars.ip}}".into());
+        let context = RunContext::default().vars(config.vars.unwrap());
+        let script = into_script(script_f).context(context);
+        assert_eq!(
+            script.preview().unwrap(),
+            vec![
+                "gobuster",
+                "-w",
+                "/tmp/words.txt",
+                "/tmp/words.txt",
+                "127.0.0.1",
+                "shadowed"
+            ]
+        );
+    }
+
+    #[test]
+    fn preview_run_scoped_script() {
+        let mut script_f = toml::from_str::<ScriptFile>(
+            "scope = \"run\"\nports_separator = \" \"\ncall_format = \"report {{all_ips}} {{all_ports}} {{all_sockets}}\"",
+        )
+        .unwrap();
+        assert_eq!(script_f.scope(), ScriptScope::Run);
+        let targets = RunTargets {
+            hosts: vec![
+                ("10.0.0.1".parse().unwrap(), vec![443, 22]),
+                ("::1".parse().unwrap(), vec![80, 22]),
+            ],
+        };
+        let script = Script::build(
+            script_f.clone(),
+            "0.0.0.0".parse().unwrap(),
+            targets.all_ports(),
+        )
+        .targets(Some(Arc::new(targets)));
+        assert_eq!(
+            script.preview().unwrap(),
+            vec![
+                "report",
+                "10.0.0.1,::1",
+                "22",
+                "80",
+                "443",
+                "10.0.0.1:443,10.0.0.1:22,[::1]:80,[::1]:22"
+            ]
+        );
+
+        // The hosts outside of --scripts-hosts are left out of the run scoped ones too.
+        let ips: Vec<IpAddr> = ["10.0.0.1", "192.168.1.5", "10.0.0.2"]
+            .iter()
+            .map(|ip| ip.parse().unwrap())
+            .collect();
+        let ports_per_ip: HashMap<IpAddr, Vec<u16>> =
+            ips.iter().map(|ip| (*ip, vec![22])).collect();
+        let script_hosts = HostFilter::read("10.0.0.0/24").unwrap();
+        let targets = RunTargets::new(&ips, &ports_per_ip, Some(&script_hosts));
+        let script = Script::build(
+            script_f.clone(),
+            "0.0.0.0".parse().unwrap(),
+            targets.all_ports(),
+        )
+        .targets(Some(Arc::new(targets)));
+        assert_eq!(
+            script.preview().unwrap(),
+            vec![
+                "report",
+                "10.0.0.1,10.0.0.2",
+                "22",
+                "10.0.0.1:22,10.0.0.2:22"
+            ]
+        );
+        assert_eq!(
+            RunTargets::new(&ips, &ports_per_ip, None).all_ips(","),
+            "10.0.0.1,192.168.1.5,10.0.0.2"
+        );
+
+        // Every kind of list gets its own separator.
+        script_f.separators = Some(Separators {
+            ports: Some(",".to_string()),
+            ips: Some(" ".to_string()),
+            sockets: Some(";".to_string()),
+        });
+        let targets = RunTargets {
+            hosts: vec![
+                ("10.0.0.1".parse().unwrap(), vec![443]),
+                ("10.0.0.2".parse().unwrap(), vec![22, 80]),
+            ],
+        };
+        let script = Script::build(
+            script_f.clone(),
+            "0.0.0.0".parse().unwrap(),
+            targets.all_ports(),
+        )
+        .targets(Some(Arc::new(targets)));
+        assert_eq!(
+            script.preview().unwrap(),
+            vec![
+                "report",
+                "10.0.0.1",
+                "10.0.0.2",
+                "22,80,443",
+                "10.0.0.1:443;10.0.0.2:22;10.0.0.2:80"
+            ]
+        );
+        let parsed: ScriptFile =
+            toml::from_str("separators = { ips = \" \" }\nports_separator = \";\"").unwrap();
+        let script = Script::build(parsed, "0.0.0.0".parse().unwrap(), vec![22, 80]);
+        assert_eq!(script.ports_separator.as_deref(), Some(";"));
+        assert_eq!(script.ips_separator.as_deref(), Some(" "));
+        assert!(toml::from_str::<ScriptFile>("separators = { findings = \" \" }").is_err());
+
+        // A host script has no targets to fill them with.
+        script_f.scope = None;
+        assert_eq!(script_f.scope(), ScriptScope::Host);
+        assert_eq!(into_script(script_f).preview().unwrap(), vec!["report"]);
+    }
+
+    #[test]
+    fn preview_format_without_script() {
+        let script_f =
+            ScriptFile::new("fixtures/.rustscan_scripts/test_script.txt".into()).unwrap();
+        let script: Script = into_script(script_f);
+        assert_eq!(
+            script.preview().unwrap(),
+            vec!["nmap", "-vvv", "-p", "80,8080", "127.0.0.1"]
+        );
+    }
+
+    #[test]
+    fn ports_separator_precedence() {
+        let mut script_f =
+            ScriptFile::new("fixtures/.rustscan_scripts/test_script.txt".into()).unwrap();
+        script_f.call_format = Some("nmap -p {{port}}".into());
+        // What main does with --scripts-ports-separator.
+        let with_cli_default = |mut script_f: ScriptFile, cli: Option<&str>| {
+            if script_f.ports_separator.is_none() {
+                script_f.ports_separator = cli.map(ToString::to_string);
+            }
+            into_script(script_f).preview().unwrap()
+        };
+
+        // The script file wins over the commandline.
+        script_f.ports_separator = Some(";".to_string());
+        assert_eq!(
+            with_cli_default(script_f.clone(), Some(":")),
+            vec!["nmap", "-p", "80;8080"]
+        );
+
+        // The commandline is used by the scripts without one.
+        script_f.ports_separator = None;
+        assert_eq!(
+            with_cli_default(script_f.clone(), Some(":")),
+            vec!["nmap", "-p", "80:8080"]
+        );
+
+        // Without both the ports are separated with a ",".
+        assert_eq!(
+            with_cli_default(script_f, None),
+            vec!["nmap", "-p", "80,8080"]
+        );
+    }
+
+    #[test]
+    fn preview_ports_separators() {
+        let mut script_f =
+            ScriptFile::new("fixtures/.rustscan_scripts/test_script.txt".into()).unwrap();
+        script_f.call_format = Some("nmap -p{{port}} {{ip}}".into());
+
+        script_f.ports_separator = Some(",".to_string());
+        let script: Script = into_script(script_f.clone());
+        assert_eq!(
+            script.preview().unwrap(),
+            vec!["nmap", "-p80,8080", "127.0.0.1"]
+        );
+
+        script_f.ports_separator = Some(" -p".to_string());
+        let script: Script = into_script(script_f.clone());
+        assert_eq!(
+            script.preview().unwrap(),
+            vec!["nmap", "-p80", "-p8080", "127.0.0.1"]
+        );
+
+        script_f.ports_separator = Some(String::new());
+        let script: Script = into_script(script_f);
+        assert_eq!(
+            script.preview().unwrap(),
+            vec!["nmap", "-p808080", "127.0.0.1"]
+        );
+    }
+
+    #[test]
+    fn preview_keeps_quoted_whitespace() {
+        let mut script_f =
+            ScriptFile::new("fixtures/.rustscan_scripts/test_script.txt".into()).unwrap();
+        script_f.call_format = Some("nmap --script-args 'a  b' -p {{port}} {{ip}}".into());
+        let script: Script = into_script(script_f);
+        assert_eq!(
+            script.preview().unwrap(),
+            vec![
+                "nmap",
+                "--script-args",
+                "a  b",
+                "-p",
+                "80,8080",
+                "127.0.0.1"
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_space_ports_separator() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("space_separator.sh");
+        std::fs::write(
+            &path,
+            "#!/bin/bash\n#tags = [\"example\"]\n#ports_separator = \" \"\n#call_format = \"bash {{script}} {{port}}\"\n",
+        )
+        .unwrap();
+        let script_f = ScriptFile::new(path).unwrap();
+        assert_eq!(script_f.ports_separator, Some(" ".to_string()));
+
+        let script: Script = into_script(script_f);
+        assert_eq!(&script.preview().unwrap()[2..], ["80", "8080"]);
+    }
+
+    #[test]
+    fn preview_interpreter_without_call_format() {
+        let mut script_f =
+            ScriptFile::new("fixtures/.rustscan_scripts/test_script.py".into()).unwrap();
+        script_f.call_format = None;
+        script_f.interpreter = Some("python3".to_string());
+        let script: Script = into_script(script_f);
+        assert_eq!(
+            script.preview().unwrap(),
+            vec![
+                "python3",
+                "fixtures/.rustscan_scripts/test_script.py",
+                "127.0.0.1",
+                "80,8080"
+            ]
+        );
+    }
+
+    #[test]
+    fn run_script_with_interpreter() {
+        let mut script_f =
+            ScriptFile::new("fixtures/.rustscan_scripts/test_script.sh".into()).unwrap();
+        script_f.call_format = None;
+        script_f.interpreter = Some("bash".to_string());
+        let script: Script = into_script(script_f);
+        let output = script.run().unwrap();
+        assert_eq!(output.trim(), "127.0.0.1 80,8080");
+    }
+
+    #[test]
+    fn parse_block_header_script() {
+        let script_f =
+            ScriptFile::new("fixtures/.rustscan_scripts/test_script_block.py".into()).unwrap();
+        assert_eq!(
+            script_f.tags,
+            Some(vec![
+                "core_approved".to_string(),
+                "example".to_string(),
+                "block".to_string()
+            ])
+        );
+        assert_eq!(
+            script_f.call_format,
+            Some("python3 {{script}} {{ip}} {{port}}".into())
+        );
+    }
+
+    #[test]
+    fn parse_front_matter_script() {
+        let script_f = ScriptFile::new("fixtures/test_script_front_matter.lua".into()).unwrap();
+        assert_eq!(
+            script_f.tags,
+            Some(vec![
+                "core_approved".to_string(),
+                "example".to_string(),
+                "front_matter".to_string()
+            ])
+        );
+        assert_eq!(
+            script_f.call_format,
+            Some("lua {{script}} {{ip}} {{port}}".into())
+        );
+    }
+
+    #[test]
+    fn read_headers_fenced_and_leading() {
+        let fenced = vec![
+            "/*",
+            "# RUSTSCAN-START",
+            "tags = [\"a\"]",
+            "RUSTSCAN-END",
+            "*/",
+        ];
+        assert_eq!(
+            read_headers(fenced.into_iter().map(String::from), &mut Vec::new())
+                .unwrap()
+                .0,
+            "tags = [\"a\"]\n"
+        );
+
+        let front_matter = vec![
+            "#!/usr/bin/env lua",
+            "--[[",
+            "+++",
+            "tags = [\"a\"]",
+            " +++",
+            "]]",
+        ];
+        assert_eq!(
+            read_headers(front_matter.into_iter().map(String::from), &mut Vec::new())
+                .unwrap()
+                .0,
+            "tags = [\"a\"]\n"
+        );
+
+        // Too far down the file it's no front matter.
+        let late: Vec<String> = std::iter::repeat_n(String::new(), 10)
+            .chain(vec![
+                "+++".to_string(),
+                "tags = [\"a\"]".to_string(),
+                "+++".to_string(),
+            ])
+            .collect();
+        assert_eq!(
+            read_headers(late.into_iter(), &mut Vec::new()).unwrap().0,
+            ""
+        );
+
+        let leading = vec!["#!/bin/bash", "#tags = [\"a\"]", "", "#not = \"header\""];
+        assert_eq!(
+            read_headers(leading.into_iter().map(String::from), &mut Vec::new())
+                .unwrap()
+                .0,
+            "tags = [\"a\"]\n"
+        );
+    }
+
+    #[test]
+    fn diagnostics_of_a_malformed_header() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("malformed.sh");
+        std::fs::write(
+            &path,
+            "#!/bin/bash\n#tags = [\"a\"]\n#call_format = \"bash {{script}}\"\n#ports_separator = ,\n",
+        )
+        .unwrap();
+        let (script, diagnostics) = ScriptFile::parse_with_diagnostics(path);
+        assert!(script.is_none());
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].severity, Severity::Error);
+        assert_eq!(diagnostics[0].line, Some(4));
+        assert!(diagnostics[0]
+            .message
+            .starts_with("Failed to parse ScriptFile headers"));
+
+        let unclosed = dir.path().join("unclosed.lua");
+        std::fs::write(
+            &unclosed,
+            "--[[\n# RUSTSCAN-START\ncall_format = \"lua {{script}}\"\n",
+        )
+        .unwrap();
+        let (script, diagnostics) = ScriptFile::parse_with_diagnostics(unclosed);
+        // Without its end the fence holds no headers, so there is no call_format either.
+        assert!(script.is_some());
+        assert_eq!(
+            diagnostics,
+            vec![
+                ParseDiagnostic::warning(
+                    "Script header fence is never closed with RUSTSCAN-END".to_string(),
+                    Some(2)
+                ),
+                ParseDiagnostic::warning(
+                    "It has neither a call_format nor an interpreter, it can not run".to_string(),
+                    None
+                )
+            ]
+        );
+
+        let (script, diagnostics) =
+            ScriptFile::parse_with_diagnostics(dir.path().join("missing.sh"));
+        assert!(script.is_none());
+        assert!(diagnostics[0].is_error() && diagnostics[0].line.is_none());
+    }
+
+    #[test]
+    fn oversized_or_deep_headers_are_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let oversized = dir.path().join("oversized.sh");
+        let content = "# tags = [\"a\"]\n".repeat(10_000);
+        std::fs::write(&oversized, format!("#!/bin/bash\n{content}")).unwrap();
+        let error = ScriptFile::parse(oversized.clone()).unwrap_err();
+        assert!(matches!(
+            ScriptError::of(&error),
+            Some(ScriptError::Parse(_))
+        ));
+        assert!(ScriptFile::new(oversized).is_none());
+
+        let deep = dir.path().join("deep.sh");
+        let nested = format!("{}{}", "[".repeat(1000), "]".repeat(1000));
+        std::fs::write(&deep, format!("#!/bin/bash\n# tags = {nested}\n")).unwrap();
+        assert!(ScriptFile::new(deep).is_none());
+
+        assert_eq!(header_nesting("tags = [[\"a\"], { b = \"[[[\" }] # [[["), 2);
+    }
+
+    #[test]
+    fn run_script_retries_until_success() {
+        let dir = tempfile::tempdir().unwrap();
+        let marker = dir.path().join("marker");
+        let mut script_f =
+            ScriptFile::new("fixtures/.rustscan_scripts/test_script.txt".into()).unwrap();
+        script_f.call_format = Some(
+            format!(
+                "bash -c 'if [ -f {0} ]; then echo {{{{ip}}}}; else touch {0}; exit 1; fi'",
+                marker.display()
+            )
+            .into(),
+        );
+
+        let script: Script = into_script(script_f.clone());
+        assert!(script.run().is_err());
+
+        std::fs::remove_file(&marker).unwrap();
+        script_f.retries = Some(1);
+        let script: Script = into_script(script_f);
+        assert_eq!(script.run().unwrap().trim(), "127.0.0.1");
+    }
+
+    #[test]
+    fn run_script_reports_attempts() {
+        let mut script_f =
+            ScriptFile::new("fixtures/.rustscan_scripts/test_script.txt".into()).unwrap();
+        script_f.call_format = Some("bash -c 'exit 3'".into());
+        script_f.retries = Some(2);
+        let script: Script = into_script(script_f);
+        assert_eq!(
+            script.run().unwrap_err().to_string(),
+            "Exit code = 3 (after 3 attempts)"
+        );
+    }
+
+    #[test]
+    fn parse_multiple_call_formats() {
+        let script_f: ScriptFile = toml::from_str(
+            "call_format = [\"echo {{ip}}\", \"echo {{port}}\"]\nports_separator = \" \"",
+        )
+        .unwrap();
+        assert_eq!(
+            script_f.call_format,
+            Some(CallFormat::Multiple(vec![
+                "echo {{ip}}".to_string(),
+                "echo {{port}}".to_string()
+            ]))
+        );
+
+        let script: Script = into_script(script_f);
+        assert_eq!(
+            script.preview_all().unwrap(),
+            vec![vec!["echo", "127.0.0.1"], vec!["echo", "80", "8080"]]
+        );
+        assert_eq!(script.run().unwrap(), "127.0.0.1\n80 8080\n");
+    }
+
+    #[test]
+    fn run_multiple_call_formats_fail_fast() {
+        let mut script_f: ScriptFile = toml::from_str(
+            "call_format = [\"bash -c 'exit 1'\", \"bash -c 'echo ran >&2; exit 2'\"]",
+        )
+        .unwrap();
+        let script: Script = into_script(script_f.clone());
+        assert_eq!(script.run().unwrap_err().to_string(), "Exit code = 1");
+
+        script_f.fail_fast = Some(false);
+        let script: Script = into_script(script_f);
+        assert_eq!(
+            script.run().unwrap_err().to_string(),
+            "Exit code = 1\nExit code = 2"
+        );
+    }
+
+    #[test]
+    fn execute_collects_result() {
+        let script_f = ScriptFile::new("fixtures/.rustscan_scripts/test_script.sh".into()).unwrap();
+        let result = into_script(script_f.clone()).execute();
+        assert_eq!(result.script_name, "test_script");
+        assert_eq!(result.port, "all");
+        assert_eq!(result.output.trim(), "127.0.0.1 80,8080");
+        assert_eq!(result.error, None);
+
+        let mut script_f = script_f;
+        script_f.call_format = Some("bash -c 'sleep 0.1; exit 4'".into());
+        let result = into_script(script_f).execute();
+        assert_eq!(result.error, Some("Exit code = 4".to_string()));
+        assert!(result.duration >= Duration::from_millis(100));
+    }
+
+    #[test]
+    fn execute_captures_the_stderr() {
+        let mut script_f =
+            ScriptFile::new("fixtures/.rustscan_scripts/test_script.sh".into()).unwrap();
+        script_f.call_format = Some("sh -c 'echo partial; echo denied >&2; exit 3'".into());
+        let result = into_script(script_f.clone()).execute();
+        assert_eq!(result.error_kind.as_deref(), Some("non_zero_exit"));
+        assert_eq!(result.exit_code, Some(3));
+        assert_eq!(result.output, "partial\n");
+        assert_eq!(result.stderr, "denied\n");
+
+        script_f.call_format = Some("sh -c 'echo warning >&2'".into());
+        let result = into_script(script_f.clone()).execute();
+        assert!(result.success);
+        assert_eq!(result.output, "");
+        assert_eq!(result.stderr, "warning\n");
+
+        // A streamed stderr is captured and capped too.
+        script_f.call_format = Some("sh -c 'echo out; echo streamed warning >&2'".into());
+        let result = into_script(script_f)
+            .runner(Arc::new(SubprocessRunner {
+                stream: true,
+                max_output: 8,
+                ..SubprocessRunner::default()
+            }))
+            .execute();
+        assert_eq!(result.output, "out\n");
+        assert_eq!(result.stderr, "streamed");
+    }
+
+    #[test]
+    fn execute_classifies_errors() {
+        let mut script_f =
+            ScriptFile::new("fixtures/.rustscan_scripts/test_script.sh".into()).unwrap();
+        script_f.call_format = Some("false".into());
+        let result = into_script(script_f.clone()).execute();
+        assert_eq!(result.error_kind.as_deref(), Some("non_zero_exit"));
+
+        script_f.call_format = Some("no-such-program-rustscan {{ip}}".into());
+        let error = into_script(script_f.clone()).run().unwrap_err();
+        assert_eq!(
+            ScriptError::of(&error),
+            Some(&ScriptError::BinaryMissing(
+                "no-such-program-rustscan".to_string()
+            ))
+        );
+
+        script_f.call_format = Some("echo 'unclosed".into());
+        let result = into_script(script_f).execute();
+        assert_eq!(result.error_kind.as_deref(), Some("parse"));
+
+        let error = find_scripts("Cargo.toml".into()).unwrap_err();
+        assert!(matches!(
+            ScriptError::of(&error),
+            Some(ScriptError::NotFound(_))
+        ));
+    }
+
+    #[test]
+    fn script_without_call_format_is_not_runnable() {
+        let script_f = ScriptFile::new("fixtures/test_script_missing_format.sh".into()).unwrap();
+        assert!(!script_f.is_runnable());
+
+        let script_f =
+            ScriptFile::new("fixtures/.rustscan_scripts/test_script.txt".into()).unwrap();
+        assert!(script_f.is_runnable());
+    }
+
+    #[test]
+    fn run_with_extract() {
+        let mut script_f =
+            ScriptFile::new("fixtures/.rustscan_scripts/test_script.txt".into()).unwrap();
+        script_f.call_format = Some("echo Server: nginx/1.18 on {{ip}}".into());
+        script_f.extract = Some(r"Server: (\S+)".to_string());
+        assert_eq!(into_script(script_f.clone()).run().unwrap(), "nginx/1.18");
+
+        script_f.extract = Some(r"Title: (\S+)".to_string());
+        assert_eq!(
+            into_script(script_f).run().unwrap(),
+            "Server: nginx/1.18 on 127.0.0.1\n"
+        );
+    }
+
+    #[test]
+    fn trigger_port_range_runs_per_port() {
+        let mut script_f =
+            ScriptFile::new("fixtures/.rustscan_scripts/test_script.txt".into()).unwrap();
+        script_f.call_format = Some("curl http://{{ip}}:{{port}}".into());
+        script_f.port = Some("443,8000-8100".to_string());
+        let script = Script::build(
+            script_f,
+            "127.0.0.1".parse().unwrap(),
+            vec![22, 8050, 443, 8000, 9000],
+        );
+        assert_eq!(
+            script.preview_all().unwrap(),
+            vec![
+                vec!["curl", "http://127.0.0.1:443"],
+                vec!["curl", "http://127.0.0.1:8000"],
+                vec!["curl", "http://127.0.0.1:8050"],
+            ]
+        );
+    }
+
+    #[test]
+    fn index_counts_the_per_port_runs() {
+        let mut script_f =
+            ScriptFile::new("fixtures/.rustscan_scripts/test_script.txt".into()).unwrap();
+        script_f.call_format = Some("scan {{ip}}:{{port}} -o out_{{index}}.txt".into());
+        script_f.port = Some("1-1000".to_string());
+        let commands = |ip: &str| {
+            Script::build(
+                script_f.clone(),
+                ip.parse().unwrap(),
+                vec![443, 22, 80, 8080],
+            )
+            .preview_all()
+            .unwrap()
+        };
+        let expected = |ip: &str| {
+            [22, 80, 443]
+                .iter()
+                .enumerate()
+                .map(|(index, port)| {
+                    vec![
+                        "scan".to_string(),
+                        format!("{ip}:{port}"),
+                        "-o".to_string(),
+                        format!("out_{index}.txt"),
+                    ]
+                })
+                .collect::<Vec<Vec<String>>>()
+        };
+        assert_eq!(commands("127.0.0.1"), expected("127.0.0.1"));
+        // Another host counts from 0 again.
+        assert_eq!(commands("10.0.0.1"), expected("10.0.0.1"));
+
+        script_f.port = None;
+        assert_eq!(
+            Script::build(script_f, "127.0.0.1".parse().unwrap(), vec![22, 80])
+                .preview()
+                .unwrap()
+                .last()
+                .unwrap(),
+            "out_0.txt"
+        );
+    }
+
+    #[test]
+    fn trigger_port_uses_the_separator() {
+        let mut script_f =
+            ScriptFile::new("fixtures/.rustscan_scripts/test_script.txt".into()).unwrap();
+        script_f.call_format = Some("nmap -p {{port}} {{ip}}".into());
+        script_f.ports_separator = Some(";".to_string());
+        script_f.port = Some("80".to_string());
+        let script = Script::build(script_f.clone(), "127.0.0.1".parse().unwrap(), vec![22]);
+        assert_eq!(
+            script.preview().unwrap(),
+            vec!["nmap", "-p", "80", "127.0.0.1"]
+        );
+
+        script_f.port = Some("80, 443".to_string());
+        let script = Script::build(script_f, "127.0.0.1".parse().unwrap(), vec![22]);
+        assert_eq!(
+            script.preview().unwrap(),
+            vec!["nmap", "-p", "80;443", "127.0.0.1"]
+        );
+    }
+
+    #[test]
+    fn parse_rejects_invalid_trigger_port() {
+        assert!(parse_trigger_port("80").is_ok());
+        assert!(parse_trigger_port("80,8000-8100").is_ok());
+        let dir = tempfile::tempdir().unwrap();
+        for port in &["8100-8000", "80-http", ","] {
+            assert!(parse_trigger_port(port).is_err());
+            let path = dir.path().join("script.sh");
+            std::fs::write(
+                &path,
+                format!("#!/bin/bash\n#call_format = \"echo {{{{port}}}}\"\n#port = \"{port}\"\n"),
+            )
+            .unwrap();
+            assert!(ScriptFile::new(path).is_none());
+        }
+    }
+
+    #[test]
+    fn execute_with_findings() {
+        let script_f = ScriptFile::new("fixtures/test_script_findings.sh".into()).unwrap();
+        assert_eq!(
+            script_f
+                .findings
+                .as_ref()
+                .map(|findings| findings[0].name.as_str()),
+            Some("version")
+        );
+        let result = into_script(script_f).execute();
+        assert_eq!(result.error, None);
+        assert_eq!(
+            result.findings,
+            BTreeMap::from([
+                (
+                    "version".to_string(),
+                    vec!["1.18".to_string(), "3.0.2".to_string()]
+                ),
+                (
+                    "cve".to_string(),
+                    vec!["CVE-2021-23017".to_string(), "CVE-2022-0778".to_string()]
+                ),
+                ("missing".to_string(), Vec::new()),
+            ])
+        );
+        // The extract pattern only replaces the output, the findings come from all of it.
+        assert_eq!(result.output, "nginx");
+        let json: serde_json::Value =
+            serde_json::from_str(&serde_json::to_string(&result).unwrap()).unwrap();
+        assert_eq!(json["findings"]["version"][1], "3.0.2");
+    }
+
+    #[test]
+    fn parse_rejects_invalid_findings() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("script.sh");
+        std::fs::write(
+            &path,
+            "#!/bin/bash\n#call_format = \"echo {{ip}}\"\n#[[findings]]\n#name = \"version\"\n#pattern = \"(unclosed\"\n",
+        )
+        .unwrap();
+        assert!(ScriptFile::new(path).is_none());
+    }
+
+    #[test]
+    fn parse_rejects_invalid_extract() {
+        let dir = tempfile::tempdir().unwrap();
+        for pattern in &["(unclosed", "no group"] {
+            let path = dir.path().join("script.sh");
+            std::fs::write(
+                &path,
+                format!(
+                    "#!/bin/bash\n#call_format = \"echo {{{{ip}}}}\"\n#extract = \"{pattern}\"\n"
+                ),
+            )
+            .unwrap();
+            assert!(ScriptFile::new(path).is_none());
+        }
+    }
+
+    #[test]
+    fn preview_ip_bracketed() {
+        let mut script_f =
+            ScriptFile::new("fixtures/.rustscan_scripts/test_script.txt".into()).unwrap();
+        script_f.call_format = Some("curl http://{{ip_bracketed}}:{{port}}".into());
+        script_f.port = Some("80".to_string());
+
+        let script = Script::build(script_f.clone(), "127.0.0.1".parse().unwrap(), vec![80]);
+        assert_eq!(
+            script.preview().unwrap(),
+            vec!["curl", "http://127.0.0.1:80"]
+        );
+
+        let script = Script::build(script_f, "::1".parse().unwrap(), vec![80]);
+        assert_eq!(script.preview().unwrap(), vec!["curl", "http://[::1]:80"]);
+    }
+
+    #[test]
+    fn preview_original_target() {
+        let mut script_f =
+            ScriptFile::new("fixtures/.rustscan_scripts/test_script.txt".into()).unwrap();
+        script_f.call_format =
+            Some("curl https://{{target}}:{{port}} --resolve {{target}}:{{port}}:{{ip}}".into());
+        script_f.port = Some("443".to_string());
+        let ip = "93.184.216.34".parse().unwrap();
+
+        let script = Script::build(script_f.clone(), ip, vec![443])
+            .original_target(Some("example.com".to_string()));
+        assert_eq!(
+            script.preview().unwrap(),
+            vec![
+                "curl",
+                "https://example.com:443",
+                "--resolve",
+                "example.com:443:93.184.216.34"
+            ]
+        );
+
+        let script = Script::build(script_f, ip, vec![443]);
+        assert_eq!(
+            script.preview().unwrap(),
+            vec![
+                "curl",
+                "https://93.184.216.34:443",
+                "--resolve",
+                "93.184.216.34:443:93.184.216.34"
+            ]
+        );
+    }
+
+    #[test]
+    fn build_with_exclude_ports() {
+        let mut script_f =
+            ScriptFile::new("fixtures/.rustscan_scripts/test_script.txt".into()).unwrap();
+        script_f.call_format = Some("nmap -p {{port}} {{ip}}".into());
+        script_f.exclude_ports = Some(vec![22, 3389]);
+
+        let script = Script::build(
+            script_f.clone(),
+            "127.0.0.1".parse().unwrap(),
+            vec![22, 80, 443],
+        );
+        assert_eq!(script.open_ports(), [80, 443]);
+        assert_eq!(
+            script.preview().unwrap(),
+            vec!["nmap", "-p", "80,443", "127.0.0.1"]
+        );
+        assert!(!script_f.excludes_all_ports(&[22, 80, 443]));
+
+        let script = Script::build(
+            script_f.clone(),
+            "127.0.0.1".parse().unwrap(),
+            vec![22, 3389],
+        );
+        assert!(script.open_ports().is_empty());
+        assert!(script_f.excludes_all_ports(&[22, 3389]));
+
+        script_f.exclude_ports = Some(Vec::new());
+        assert!(!script_f.excludes_all_ports(&[22]));
+    }
+
+    #[test]
+    fn open_ports_range() {
+        let mut script_f = named_script("expensive", None);
+        assert!(!script_f.outside_open_ports_range(&[]));
+
+        script_f.min_open_ports = Some(3);
+        script_f.max_open_ports = Some(5);
+        assert!(script_f.outside_open_ports_range(&[22, 80]));
+        // Duplicates count once.
+        assert!(script_f.outside_open_ports_range(&[22, 80, 80]));
+        assert!(!script_f.outside_open_ports_range(&[22, 80, 443]));
+        assert!(!script_f.outside_open_ports_range(&[22, 80, 443, 8080, 8443]));
+        assert!(script_f.outside_open_ports_range(&[21, 22, 80, 443, 8080, 8443]));
+
+        script_f.max_open_ports = None;
+        assert!(!script_f.outside_open_ports_range(&[21, 22, 80, 443, 8080, 8443]));
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("inverted.sh");
+        std::fs::write(
+            &path,
+            "#!/bin/bash\n#call_format = \"id\"\n#min_open_ports = 5\n#max_open_ports = 2\n",
+        )
+        .unwrap();
+        assert!(ScriptFile::parse(path).is_err());
+    }
+
+    #[test]
+    fn build_with_port_states() {
+        let mut script_f =
+            ScriptFile::new("fixtures/.rustscan_scripts/test_script.txt".into()).unwrap();
+        script_f.call_format =
+            Some("probe {{port}} closed={{closed_ports}} filtered={{filtered_ports}}".into());
+        let ports = PortSet {
+            open: vec![80],
+            closed: vec![25, 23],
+            filtered: vec![445],
+        };
+
+        // Open only by default.
+        assert!(!script_f.wants_unopened_ports());
+        let script = Script::build_with_ports(
+            script_f.clone(),
+            "127.0.0.1".parse().unwrap(),
+            ports.clone(),
+        );
+        assert_eq!(script.open_ports(), [80]);
+        assert_eq!(
+            script.preview().unwrap(),
+            vec!["probe", "80", "closed=23,25", "filtered=445"]
+        );
+
+        script_f.port_states = Some(vec![PortState::Closed, PortState::Filtered]);
+        assert!(script_f.wants_unopened_ports());
+        let script = Script::build_with_ports(
+            script_f.clone(),
+            "127.0.0.1".parse().unwrap(),
+            ports.clone(),
+        );
+        assert_eq!(script.open_ports(), [25, 23, 445]);
+
+        script_f.port_states = Some(vec![PortState::Filtered]);
+        let script = Script::build_with_ports(script_f, "127.0.0.1".parse().unwrap(), ports);
+        assert_eq!(
+            script.preview().unwrap(),
+            vec!["probe", "445", "closed=23,25", "filtered=445"]
+        );
+
+        let script_f: ScriptFile = toml::from_str("port_states = [\"open\", \"closed\"]").unwrap();
+        assert_eq!(
+            script_f.port_states(),
+            vec![PortState::Open, PortState::Closed]
+        );
+    }
+
+    #[test]
+    fn build_with_max_ports() {
+        let mut script_f =
+            ScriptFile::new("fixtures/.rustscan_scripts/test_script.txt".into()).unwrap();
+        script_f.max_ports = Some(2);
+        let script = Script::build(
+            script_f,
+            "127.0.0.1".parse().unwrap(),
+            vec![8080, 443, 22, 80],
+        );
+        assert_eq!(script.open_ports(), [22, 80]);
+    }
+
+    #[test]
+    fn build_with_ports_filter() {
+        let mut script_f =
+            ScriptFile::new("fixtures/.rustscan_scripts/test_script.txt".into()).unwrap();
+        script_f.ports_filter = Some("22, 80-443".to_string());
+        let script = Script::build(
+            script_f.clone(),
+            "127.0.0.1".parse().unwrap(),
+            vec![8080, 443, 22, 80, 21],
+        );
+        assert_eq!(script.open_ports(), [443, 22, 80]);
+
+        script_f.max_ports = Some(1);
+        let script = Script::build(
+            script_f,
+            "127.0.0.1".parse().unwrap(),
+            vec![8080, 443, 22, 80, 21],
+        );
+        assert_eq!(script.open_ports(), [22]);
+    }
+
+    #[test]
+    fn config_ports_scope_the_scripts() {
+        let config: ScriptConfig = "tags = []\nports = [\"80\", \" 8000-8100 \"]"
+            .parse()
+            .unwrap();
+        assert_eq!(config.port_scope().as_deref(), Some("80,8000-8100"));
+        assert_eq!(ScriptConfig::default().port_scope(), None);
+        assert!("ports = [\"http\"]".parse::<ScriptConfig>().is_err());
+
+        let script_f =
+            ScriptFile::new("fixtures/.rustscan_scripts/test_script.txt".into()).unwrap();
+        let mut script_f = filter_scripts(&[script_f], &config).remove(0);
+        assert_eq!(script_f.port_scope.as_deref(), Some("80,8000-8100"));
+        let script = Script::build(
+            script_f.clone(),
+            "127.0.0.1".parse().unwrap(),
+            vec![22, 80, 443, 8080],
+        );
+        assert_eq!(script.open_ports(), [80, 8080]);
+        assert!(!script_f.out_of_scope(&[22, 80]));
+        assert!(script_f.out_of_scope(&[22, 443]));
+
+        // The ports_filter of the script narrows the scoped ports further.
+        script_f.ports_filter = Some("8000-9000".to_string());
+        let script = Script::build(
+            script_f.clone(),
+            "127.0.0.1".parse().unwrap(),
+            vec![22, 80, 443, 8080],
+        );
+        assert_eq!(script.open_ports(), [8080]);
+
+        script_f.port = Some("443".to_string());
+        assert!(script_f.out_of_scope(&[80, 443]));
+        script_f.port = Some("8050-9000".to_string());
+        assert!(!script_f.out_of_scope(&[80, 443]));
+
+        // Without config ports every open port is in scope.
+        script_f.port_scope = None;
+        assert!(!script_f.out_of_scope(&[22]));
+    }
+
+    #[test]
+    fn lowest_ports_caps_globally() {
+        assert_eq!(lowest_ports(&[8080, 443, 22, 80], 2), vec![22, 80]);
+        assert_eq!(lowest_ports(&[8080, 443], 0), vec![8080, 443]);
+        assert_eq!(lowest_ports(&[8080], 5), vec![8080]);
+
+        // The per-script max_ports applies on top of the global cap.
+        let mut script_f =
+            ScriptFile::new("fixtures/.rustscan_scripts/test_script.txt".into()).unwrap();
+        script_f.max_ports = Some(1);
+        script_f.ports_filter = Some("80-9000".to_string());
+        let script = Script::build(
+            script_f,
+            "127.0.0.1".parse().unwrap(),
+            lowest_ports(&[8080, 443, 22, 80], 2),
+        );
+        assert_eq!(script.open_ports(), [80]);
+    }
+
+    #[test]
+    fn parse_ports_filter_entries() {
+        let ranges = parse_ports_filter("22,80-90").unwrap();
+        assert_eq!(
+            ranges,
+            vec![
+                PortRange { start: 22, end: 22 },
+                PortRange { start: 80, end: 90 }
+            ]
+        );
+        assert!(parse_ports_filter("22,http").is_err());
+        assert!(parse_ports_filter("1-70000").is_err());
+    }
+
+    #[test]
+    fn required_ports_must_all_be_open() {
+        let mut script_f =
+            ScriptFile::new("fixtures/.rustscan_scripts/test_script.txt".into()).unwrap();
+        assert!(script_f.missing_required_ports(&[80]).is_empty());
+
+        script_f.required_ports = Some(vec![139, 445]);
+        assert_eq!(script_f.missing_required_ports(&[80, 139]), vec![445]);
+        assert_eq!(script_f.missing_required_ports(&[]), vec![139, 445]);
+        assert!(script_f.missing_required_ports(&[80, 139, 445]).is_empty());
+    }
+
+    #[test]
+    fn run_keeps_quoted_arguments_intact() {
+        let mut script_f =
+            ScriptFile::new("fixtures/.rustscan_scripts/test_script.txt".into()).unwrap();
+        script_f.call_format = Some(r#"printf '%s\n' "it's \"quoted\"" 'a  b' {{ip}}"#.into());
+        let script = into_script(script_f);
+        assert_eq!(
+            script.preview().unwrap(),
+            vec!["printf", "%s\\n", "it's \"quoted\"", "a  b", "127.0.0.1"]
+        );
+        assert_eq!(script.run().unwrap(), "it's \"quoted\"\na  b\n127.0.0.1\n");
+    }
+
+    #[test]
+    fn preview_scan_context() {
+        let mut script_f =
+            ScriptFile::new("fixtures/.rustscan_scripts/test_script.txt".into()).unwrap();
+        script_f.call_format =
+            Some("report {{scan_start}} {{scan_elapsed}}s {{ports_open}}/{{ports_scanned}}".into());
+        // Without a scan they are empty.
+        assert_eq!(
+            into_script(script_f.clone()).preview().unwrap(),
+            vec!["report", "s", "/"]
+        );
+
+        let scan = ScanContext {
+            started: "2021-01-01T00:00:00Z".to_string(),
+            elapsed: Duration::from_millis(12_345),
+            ports_scanned: 65_535,
+            ports_open: 3,
+        };
+        let command = into_script(script_f)
+            .context(RunContext::default().scan(scan))
+            .preview()
+            .unwrap();
+        assert_eq!(
+            command,
+            vec!["report", "2021-01-01T00:00:00Z", "12.345s", "3/65535"]
+        );
+
+        let started = SystemTime::now() - Duration::from_secs(5);
+        let scan = ScanContext::new(started, 10, 1);
+        assert!(scan.elapsed >= Duration::from_secs(5));
+        assert!(scan.started.ends_with('Z'));
+    }
+
+    #[test]
+    fn preview_run_context() {
+        let mut script_f =
+            ScriptFile::new("fixtures/.rustscan_scripts/test_script.txt".into()).unwrap();
+        script_f.call_format = Some(
+            "collector --run {{scan_id}} --at {{timestamp}} --profile {{scan_fingerprint}}".into(),
+        );
+        let context = RunContext::new().scan_fingerprint("0123456789abcdef".to_string());
+        let command = into_script(script_f.clone())
+            .context(context.clone())
+            .preview()
+            .unwrap();
+        assert_eq!(
+            command,
+            vec![
+                "collector",
+                "--run",
+                &context.scan_id,
+                "--at",
+                &context.timestamp,
+                "--profile",
+                "0123456789abcdef"
+            ]
+        );
+        assert_eq!(context.scan_id.len(), 36);
+        assert_eq!(&context.scan_id[14..15], "4");
+
+        let seeded = RunContext::new().seed(7);
+        assert_eq!(seeded.scan_id, RunContext::new().seed(7).scan_id);
+        assert_ne!(seeded.scan_id, RunContext::new().seed(8).scan_id);
+        assert_eq!(&seeded.scan_id[14..15], "4");
+        assert!(context.timestamp.ends_with('Z'));
+
+        // Without a context the placeholders are left empty.
+        assert_eq!(
+            into_script(script_f).preview().unwrap(),
+            vec!["collector", "--run", "--at", "--profile"]
+        );
+    }
+
+    // Renders every call format into an echo of the format and the ip, recording the values it got.
+    #[derive(Debug, Default)]
+    struct MockTemplate {
+        rendered: Mutex<Vec<String>>,
+    }
+
+    impl TemplateEngine for MockTemplate {
+        fn render(
+            &self,
+            call_format: &str,
+            values: &serde_json::Map<String, serde_json::Value>,
+        ) -> anyhow::Result<String> {
+            self.rendered.lock().unwrap().push(call_format.to_string());
+            Ok(format!(
+                "echo '{call_format}' {}",
+                values["ip"].as_str().unwrap()
+            ))
         }
-        debug!("ScriptFile {} lines\n{}", &real_path.display(), &lines_buf);
 
-        match toml::from_str::<ScriptFile>(&lines_buf) {
-            Ok(mut parsed) => {
-                debug!("Parsed ScriptFile{} \n{:?}", &real_path.display(), &parsed);
-                parsed.path = Some(real_path);
-                // parsed_scripts.push(parsed);
-                Some(parsed)
-            }
-            Err(e) => {
-                debug!("Failed to parse ScriptFile headers {}", e.to_string());
-                None
-            }
+        fn checks_placeholders(&self) -> bool {
+            false
         }
     }
-}
 
-#[derive(Debug, Deserialize, Clone)]
-pub struct ScriptConfig {
-    pub tags: Option<Vec<String>>,
-    pub ports: Option<Vec<String>>,
-    pub developer: Option<Vec<String>>,
-}
+    #[test]
+    fn custom_template_engine_renders() {
+        let mut script_f =
+            ScriptFile::new("fixtures/.rustscan_scripts/test_script.txt".into()).unwrap();
+        script_f.call_format = Some("scan {{ ip | upper }} {{?80:--web}}".into());
+        let engine = Arc::new(MockTemplate::default());
+        let script = into_script(script_f).template_engine(engine.clone());
+        assert_eq!(
+            script.preview().unwrap(),
+            vec!["echo", "scan {{ ip | upper }} --web", "127.0.0.1"]
+        );
+        assert_eq!(
+            script.run().unwrap(),
+            "scan {{ ip | upper }} --web 127.0.0.1\n"
+        );
+        assert_eq!(engine.rendered.lock().unwrap().len(), 2);
+    }
 
-#[cfg(not(tarpaulin_include))]
-impl ScriptConfig {
-    pub fn read_config() -> Result<ScriptConfig> {
-        let mut home_dir = match dirs::home_dir() {
-            Some(dir) => dir,
-            None => return Err(anyhow!("Could not infer ScriptConfig path.")),
-        };
-        home_dir.push(".rustscan_scripts.toml");
+    #[test]
+    fn preview_results_file() {
+        let mut script_f =
+            ScriptFile::new("fixtures/.rustscan_scripts/test_script.txt".into()).unwrap();
+        script_f.call_format = Some("nmap -iL {{results_file}} -p {{port}}".into());
+        let context = RunContext::new().results_file(PathBuf::from("/tmp/scan.gnmap"));
+        assert_eq!(
+            into_script(script_f.clone())
+                .context(context)
+                .preview()
+                .unwrap(),
+            vec!["nmap", "-iL", "/tmp/scan.gnmap", "-p", "80,8080"]
+        );
 
-        let content = fs::read_to_string(home_dir)?;
-        let config = toml::from_str::<ScriptConfig>(&content)?;
-        Ok(config)
+        // Without a results file the placeholder is empty.
+        assert_eq!(
+            into_script(script_f)
+                .context(RunContext::new())
+                .preview()
+                .unwrap(),
+            vec!["nmap", "-iL", "-p", "80,8080"]
+        );
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::{find_scripts, parse_scripts, Script, ScriptFile};
+    #[test]
+    fn shebang_interpreter_lines() {
+        assert_eq!(
+            shebang_interpreter("#!/usr/bin/env python3").as_deref(),
+            Some("python3")
+        );
+        assert_eq!(
+            shebang_interpreter("#!/usr/bin/env -S perl -w").as_deref(),
+            Some("perl -w")
+        );
+        assert_eq!(
+            shebang_interpreter("#!/bin/bash -e").as_deref(),
+            Some("/bin/bash -e")
+        );
+        assert_eq!(shebang_interpreter("#!/usr/bin/env"), None);
+        assert_eq!(shebang_interpreter("#tags = [\"example\"]"), None);
+    }
 
-    // Function for testing only, it inserts static values into ip and open_ports
-    // Doesn't use impl in case it's implemented in the super module at some point
-    fn into_script(script_f: ScriptFile) -> Script {
-        Script::build(
-            script_f.path,
-            "127.0.0.1".parse().unwrap(),
-            vec![80, 8080],
-            script_f.port,
-            script_f.ports_separator,
-            script_f.tags,
-            script_f.call_format,
-        )
+    #[test]
+    fn run_with_shebang_interpreter() {
+        let script_f = ScriptFile::new("fixtures/test_script_shebang.sh".into()).unwrap();
+        assert_eq!(script_f.interpreter.as_deref(), Some("bash"));
+        assert_eq!(
+            into_script(script_f).run().unwrap(),
+            "shebang 127.0.0.1 80,8080\n"
+        );
+
+        // Without a shebang there is nothing to run the script with.
+        let script_f = ScriptFile::new("fixtures/test_script_missing_format.sh".into()).unwrap();
+        assert_eq!(script_f.interpreter, None);
+
+        // An explicit call_format wins over the shebang.
+        let script_f = ScriptFile::new("fixtures/.rustscan_scripts/test_script.py".into()).unwrap();
+        assert_eq!(script_f.interpreter, None);
+    }
+
+    #[derive(Debug, Default)]
+    struct RecordingRunner {
+        calls: Mutex<Vec<Vec<String>>>,
+    }
+
+    impl CommandRunner for RecordingRunner {
+        fn run(&self, arguments: &[String]) -> anyhow::Result<(i64, String, String)> {
+            self.calls.lock().unwrap().push(arguments.to_vec());
+            Ok((0, "canned output\n".to_string(), String::new()))
+        }
     }
 
     #[test]
-    fn find_and_parse_scripts() {
-        let scripts = find_scripts("fixtures/".into()).unwrap();
-        let scripts = parse_scripts(scripts);
-        assert_eq!(scripts.len(), 4);
+    fn run_with_recording_runner() {
+        let script_f = ScriptFile::new("fixtures/.rustscan_scripts/test_script.py".into()).unwrap();
+        let runner = Arc::new(RecordingRunner::default());
+        let output = into_script(script_f).runner(runner.clone()).run().unwrap();
+
+        assert_eq!(output, "canned output\n");
+        assert_eq!(
+            *runner.calls.lock().unwrap(),
+            vec![vec![
+                "python3",
+                "fixtures/.rustscan_scripts/test_script.py",
+                "127.0.0.1",
+                "80,8080"
+            ]]
+        );
     }
 
     #[test]
-    #[should_panic]
-    fn find_invalid_folder() {
-        let _scripts = find_scripts("Cargo.toml".into()).unwrap();
+    fn result_records_the_binary_and_version() {
+        let mut script_f =
+            ScriptFile::new("fixtures/.rustscan_scripts/test_script.txt".into()).unwrap();
+        script_f.call_format = Some("sh -c 'echo {{ip}}'".into());
+        let runner = Arc::new(RecordingRunner::default());
+        let sh = std::fs::canonicalize(find_program("sh").unwrap()).unwrap();
+
+        let result = into_script(script_f.clone())
+            .runner(runner.clone())
+            .execute();
+        assert_eq!(result.binary_path, sh.display().to_string());
+        assert_eq!(result.binary_version, "");
+
+        // The version is probed once per binary in a run.
+        script_f.version_arg = Some("--version".to_string());
+        let context = RunContext::new();
+        for _ in 0..2 {
+            let result = into_script(script_f.clone())
+                .runner(runner.clone())
+                .context(context.clone())
+                .execute();
+            assert_eq!(result.binary_version, "canned output");
+        }
+        let calls = runner.calls.lock().unwrap();
+        assert_eq!(calls.len(), 4);
+        assert_eq!(
+            calls
+                .iter()
+                .filter(|call| call.last().is_some_and(|arg| arg == "--version"))
+                .collect::<Vec<_>>(),
+            vec![&vec![sh.display().to_string(), "--version".to_string()]]
+        );
+        drop(calls);
+
+        // The probe does not wait for a launch of the rate limit.
+        let limited = Arc::new(RateLimitedRunner::new(runner.clone(), 1));
+        let start = Instant::now();
+        let result = into_script(script_f.clone())
+            .runner(limited)
+            .context(RunContext::new())
+            .execute();
+        assert_eq!(result.binary_version, "canned output");
+        assert!(start.elapsed() < Duration::from_millis(500));
+
+        // The program of the call format is recorded, not the shell or ssh running it.
+        let mut in_shell = script_f.clone();
+        in_shell.call_format = Some("cat /dev/null | wc -l".into());
+        in_shell.shell = Some(true);
+        in_shell.version_arg = None;
+        let result = into_script(in_shell).runner(runner.clone()).execute();
+        assert_eq!(result.command[0], shell_command(String::new())[0]);
+        assert_eq!(
+            result.binary_path,
+            std::fs::canonicalize(find_program("cat").unwrap())
+                .unwrap()
+                .display()
+                .to_string()
+        );
+        let mut remote = script_f.clone();
+        remote.call_format = Some("nmap {{ip}}".into());
+        remote.remote = Some(Remote {
+            host: "jump.example".to_string(),
+            user: None,
+            port: None,
+        });
+        let result = into_script(remote).runner(runner.clone()).execute();
+        assert_eq!(result.command[0], "ssh");
+        assert_eq!(
+            (result.binary_path.as_str(), result.binary_version.as_str()),
+            ("nmap", "")
+        );
+
+        script_f.call_format = Some("no-such-program-rustscan".into());
+        let result = into_script(script_f).runner(runner).execute();
+        assert_eq!(
+            (result.binary_path.as_str(), result.binary_version.as_str()),
+            ("", "")
+        );
     }
 
     #[test]
-    #[should_panic]
-    fn open_script_file_invalid_headers() {
-        ScriptFile::new("fixtures/.rustscan_scripts/test_script_invalid_headers.txt".into())
+    fn preview_escaped_braces() {
+        let mut script_f =
+            ScriptFile::new("fixtures/.rustscan_scripts/test_script.txt".into()).unwrap();
+        script_f.call_format =
+            Some("tool --template '{{{{host}}}}:{{port}}' --path {{{{script}}}} {{ip}}".into());
+        script_f.path = None;
+        assert_eq!(
+            into_script(script_f).preview().unwrap(),
+            vec![
+                "tool",
+                "--template",
+                "{{host}}:80,8080",
+                "--path",
+                "{{script}}",
+                "127.0.0.1"
+            ]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn run_direct_and_through_shell() {
+        let mut script_f =
+            ScriptFile::new("fixtures/.rustscan_scripts/test_script.txt".into()).unwrap();
+        script_f.call_format = Some("nmap -p {{port}} {{ip}} | grep open".into());
+
+        let runner = Arc::new(RecordingRunner::default());
+        into_script(script_f.clone())
+            .runner(runner.clone())
+            .run()
             .unwrap();
+        script_f.shell = Some(true);
+        into_script(script_f).runner(runner.clone()).run().unwrap();
+
+        assert_eq!(
+            *runner.calls.lock().unwrap(),
+            vec![
+                vec!["nmap", "-p", "80,8080", "127.0.0.1", "|", "grep", "open"],
+                vec!["sh", "-c", "nmap -p 80,8080 127.0.0.1 | grep open"]
+            ]
+        );
     }
 
+    #[cfg(unix)]
     #[test]
-    #[should_panic]
-    fn open_script_file_invalid_call_format() {
+    fn run_shell_pipeline() {
         let mut script_f =
             ScriptFile::new("fixtures/.rustscan_scripts/test_script.txt".into()).unwrap();
-        script_f.call_format = Some("qwertyuiop".to_string());
-        let script: Script = into_script(script_f);
-        let _output = script.run().unwrap();
+        script_f.call_format = Some("echo {{port}} | tr , ' '".into());
+        script_f.shell = Some(true);
+        assert_eq!(into_script(script_f).run().unwrap(), "80 8080\n");
     }
 
     #[test]
-    #[should_panic]
-    fn open_script_file_missing_call_format() {
+    fn run_caps_the_output() {
         let mut script_f =
             ScriptFile::new("fixtures/.rustscan_scripts/test_script.txt".into()).unwrap();
-        script_f.call_format = None;
-        let script: Script = into_script(script_f);
-        let _output = script.run().unwrap();
+        script_f.call_format = Some("yes {{ip}}".into());
+        let result = into_script(script_f.clone())
+            .runner(Arc::new(SubprocessRunner {
+                max_output: 1000,
+                ..SubprocessRunner::default()
+            }))
+            .execute();
+        // The command was stopped halfway, that is no success.
+        assert!(!result.success);
+        assert_eq!(result.error_kind.as_deref(), Some("non_zero_exit"));
+        assert_ne!(result.exit_code, Some(0));
+        let output = result.output;
+        assert!(output.starts_with("127.0.0.1\n127.0.0.1\n"));
+        assert!(output.ends_with("\n[output capped at 1000 bytes]\n"));
+        assert_eq!(
+            output.len(),
+            1000 + "\n[output capped at 1000 bytes]\n".len()
+        );
+
+        // An output of exactly the limit is not capped.
+        script_f.call_format = Some("printf 1234".into());
+        let output = into_script(script_f)
+            .runner(Arc::new(SubprocessRunner {
+                max_output: 4,
+                ..SubprocessRunner::default()
+            }))
+            .run()
+            .unwrap();
+        assert_eq!(output, "1234");
+    }
+
+    // Remembers when every write came in.
+    #[derive(Default)]
+    struct TimedSink {
+        written: Vec<u8>,
+        writes: Vec<Instant>,
+    }
+
+    impl std::io::Write for TimedSink {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.written.extend_from_slice(buf);
+            self.writes.push(Instant::now());
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
     }
 
     #[test]
-    #[should_panic]
-    fn open_nonexisting_script_file() {
-        ScriptFile::new("qwertyuiop.txt".into()).unwrap();
+    fn stream_lines_as_they_come() {
+        let mut child = std::process::Command::new("sh")
+            .args(["-c", "echo first; sleep 0.3; echo second; printf last"])
+            .stdout(std::process::Stdio::piped())
+            .spawn()
+            .unwrap();
+        let mut sink = TimedSink::default();
+        let captured = stream_lines(child.stdout.take().unwrap(), &mut sink, 1000).unwrap();
+        child.wait().unwrap();
+
+        assert_eq!(captured, b"first\nsecond\nlast");
+        assert_eq!(sink.written, captured);
+        assert_eq!(sink.writes.len(), 3);
+        // The first line was passed on before the command wrote the second one.
+        assert!(sink.writes[1] - sink.writes[0] >= Duration::from_millis(200));
+
+        let mut sink = TimedSink::default();
+        let captured = stream_lines("12\n345\n6".as_bytes(), &mut sink, 4).unwrap();
+        assert_eq!(captured, b"12\n34");
+        assert_eq!(sink.written, b"12\n3");
     }
 
     #[test]
-    fn parse_txt_script() {
-        let script_f =
+    fn run_streamed() {
+        let mut script_f =
             ScriptFile::new("fixtures/.rustscan_scripts/test_script.txt".into()).unwrap();
+        script_f.call_format = Some("printf '{{ip}}\\n{{port}}\\n'".into());
+        let output = into_script(script_f)
+            .runner(Arc::new(SubprocessRunner {
+                stream: true,
+                ..SubprocessRunner::default()
+            }))
+            .run()
+            .unwrap();
+        assert_eq!(output, "127.0.0.1\n80,8080\n");
+    }
+
+    #[test]
+    fn compact_port_ranges() {
+        assert_eq!(compact_ports(&[]), "");
+        assert_eq!(compact_ports(&[443, 22, 80]), "22,80,443");
+        assert_eq!(compact_ports(&[8002, 8000, 8001]), "8000-8002");
         assert_eq!(
-            script_f.tags,
-            Some(vec!["core_approved".to_string(), "example".to_string()])
+            compact_ports(&[80, 443, 8000, 8001, 8002, 8100, 8101, 1, 81, 81]),
+            "1,80-81,443,8000-8002,8100-8101"
         );
+        assert_eq!(compact_ports(&[65534, 65535, 0]), "0,65534-65535");
+
+        let mut script_f =
+            ScriptFile::new("fixtures/.rustscan_scripts/test_script.txt".into()).unwrap();
+        script_f.call_format = Some("nmap -p{{ports_compact}} {{ip}}".into());
+        let script = Script::build(script_f, "127.0.0.1".parse().unwrap(), vec![81, 80, 443]);
         assert_eq!(
-            script_f.developer,
-            Some(vec![
-                "example".to_string(),
-                "https://example.org".to_string()
-            ])
+            script.preview().unwrap(),
+            vec!["nmap", "-p80-81,443", "127.0.0.1"]
         );
-        assert_eq!(script_f.ports_separator, Some(",".to_string()));
+    }
+
+    #[test]
+    fn run_with_output_filter() {
+        let mut script_f =
+            ScriptFile::new("fixtures/.rustscan_scripts/test_script.txt".into()).unwrap();
+        script_f.call_format = Some("echo open {{ip}}".into());
+        script_f.output_filter = Some("tr a-z A-Z".into());
         assert_eq!(
-            script_f.call_format,
-            Some("nmap -vvv -p {{port}} {{ip}}".to_string())
+            into_script(script_f.clone()).run().unwrap(),
+            "OPEN 127.0.0.1\n"
+        );
+
+        script_f.output_filter = Some("sed s/open/{{port}}/".into());
+        assert_eq!(
+            into_script(script_f.clone()).run().unwrap(),
+            "80,8080 127.0.0.1\n"
+        );
+
+        // A failing filter keeps the raw output.
+        script_f.output_filter = Some("false".into());
+        assert_eq!(into_script(script_f).run().unwrap(), "open 127.0.0.1\n");
+    }
+
+    #[test]
+    fn run_with_stdin() {
+        let mut script_f =
+            ScriptFile::new("fixtures/.rustscan_scripts/test_script.txt".into()).unwrap();
+        script_f.call_format = Some("cat".into());
+        script_f.stdin = Some("{{ip}}\n{{port}}\n{{ip|hex}}\n".into());
+        assert_eq!(
+            into_script(script_f.clone()).run().unwrap(),
+            "127.0.0.1\n80,8080\n3132372e302e302e31\n"
+        );
+
+        // Once for every port of a trigger range, each getting its own port.
+        script_f.port = Some("1-9000".into());
+        script_f.stdin = Some("port {{port}}\n".into());
+        assert_eq!(
+            into_script(script_f.clone()).run().unwrap(),
+            "port 80\nport 8080\n"
+        );
+
+        script_f.stdin = Some("{{prot}}".into());
+        assert!(into_script(script_f).run().is_err());
+    }
+
+    #[test]
+    fn unknown_placeholder_is_an_error() {
+        let mut script_f =
+            ScriptFile::new("fixtures/.rustscan_scripts/test_script.txt".into()).unwrap();
+        script_f.call_format = Some("nmap -p {{prot}} {{ipp}} {{{{literal}}}}".into());
+        let result = into_script(script_f).execute();
+        assert_eq!(
+            result.error.as_deref(),
+            Some(
+                "Unknown placeholder(s) {{prot}}, {{ipp}} in the call format \
+                 nmap -p {{prot}} {{ipp}} {{{{literal}}}} of fixtures/.rustscan_scripts/test_script.txt"
+            )
+        );
+        assert_eq!(result.error_kind.as_deref(), Some("parse"));
+    }
+
+    #[test]
+    fn uncaptured_output_keeps_the_exit_code() {
+        let mut script_f =
+            ScriptFile::new("fixtures/.rustscan_scripts/test_script.txt".into()).unwrap();
+        script_f.capture_output = Some(false);
+        script_f.call_format = Some("echo chatty".into());
+        let result = into_script(script_f.clone()).execute();
+        assert_eq!((result.output.as_str(), result.error), ("", None));
+
+        script_f.call_format = Some("sh -c 'echo chatty; exit 3'".into());
+        let result = into_script(script_f).execute();
+        assert_eq!(result.output, "");
+        assert_eq!(result.error_kind.as_deref(), Some("non_zero_exit"));
+        assert!(result.error.unwrap().contains("Exit code = 3"));
+    }
+
+    #[test]
+    fn success_exit_codes_pass() {
+        let mut script_f =
+            ScriptFile::new("fixtures/.rustscan_scripts/test_script.txt".into()).unwrap();
+        script_f.call_format = Some("sh -c 'echo found; exit 1'".into());
+        let result = into_script(script_f.clone()).execute();
+        assert_eq!(result.error_kind.as_deref(), Some("non_zero_exit"));
+
+        script_f.success_exit_codes = Some(vec![0, 1]);
+        let result = into_script(script_f.clone()).execute();
+        assert!(result.success);
+        assert_eq!(result.output, "found\n");
+        assert_eq!(result.exit_code, Some(1));
+
+        script_f.call_format = Some("sh -c 'exit 2'".into());
+        let result = into_script(script_f).execute();
+        assert_eq!(result.exit_code, Some(2));
+        assert!(!result.success);
+
+        let listing = toml::from_str::<ScriptFile>("success_exit_codes = [0, 1]").unwrap();
+        assert_eq!(listing.success_exit_codes, Some(vec![0, 1]));
+    }
+
+    #[test]
+    fn silent_success_is_marked_successful() {
+        let mut script_f =
+            ScriptFile::new("fixtures/.rustscan_scripts/test_script.txt".into()).unwrap();
+        script_f.call_format = Some("true".into());
+        let result = into_script(script_f.clone()).execute();
+        assert!(result.success);
+        assert_eq!(result.output, "");
+        assert_eq!((result.error, result.exit_code), (None, Some(0)));
+
+        script_f.call_format = Some("false".into());
+        let result = into_script(script_f).execute();
+        assert!(!result.success);
+        assert_eq!(result.exit_code, Some(1));
+    }
+
+    #[test]
+    fn result_records_the_command() {
+        let mut script_f =
+            ScriptFile::new("fixtures/.rustscan_scripts/test_script.txt".into()).unwrap();
+        script_f.call_format = Some("echo 'open ports' {{port}} {{ip}}".into());
+        let script = into_script(script_f.clone());
+        let preview = script.preview().unwrap();
+        let result = script.execute();
+        assert_eq!(result.command, preview);
+        assert_eq!(
+            result.command,
+            vec!["echo", "open ports", "80,8080", "127.0.0.1"]
+        );
+
+        // The first failing command is recorded, not the last one.
+        script_f.call_format = Some(CallFormat::Multiple(vec![
+            "false {{ip}}".to_string(),
+            "true".to_string(),
+        ]));
+        script_f.fail_fast = Some(false);
+        let result = into_script(script_f).execute();
+        assert_eq!(result.command, vec!["false", "127.0.0.1"]);
+    }
+
+    #[test]
+    fn remote_wraps_the_command_in_ssh() {
+        let mut script_f =
+            ScriptFile::new("fixtures/.rustscan_scripts/test_script.txt".into()).unwrap();
+        script_f.call_format = Some("echo 'open ports' {{port}} {{ip}}".into());
+        script_f.remote = Some(Remote {
+            host: "jump.example".to_string(),
+            user: Some("scan".to_string()),
+            port: Some(2222),
+        });
+        let runner = Arc::new(RecordingRunner::default());
+        into_script(script_f.clone())
+            .runner(runner.clone())
+            .run()
+            .unwrap();
+        assert_eq!(
+            *runner.calls.lock().unwrap(),
+            vec![vec![
+                "ssh",
+                "-o",
+                "BatchMode=yes",
+                "-p",
+                "2222",
+                "scan@jump.example",
+                "--",
+                "echo 'open ports' 80,8080 127.0.0.1",
+            ]]
+        );
+
+        script_f.remote = None;
+        assert_eq!(
+            into_script(script_f).preview_all().unwrap(),
+            vec![vec!["echo", "open ports", "80,8080", "127.0.0.1"]]
+        );
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("remote.sh");
+        std::fs::write(
+            &path,
+            "#!/bin/bash\n#call_format = \"id\"\n#[remote]\n#host = \"-oProxyCommand=x\"\n",
+        )
+        .unwrap();
+        assert!(ScriptFile::parse(path).is_err());
+    }
+
+    #[test]
+    fn sensitive_output_is_redacted() {
+        let mut script_f =
+            ScriptFile::new("fixtures/.rustscan_scripts/test_script.txt".into()).unwrap();
+        script_f.call_format = Some("echo token=hunter2".into());
+        script_f.findings = Some(vec![Finding {
+            name: "token".to_string(),
+            pattern: "token=(\\S+)".to_string(),
+        }]);
+        script_f.sensitive = Some(true);
+        let result = into_script(script_f).execute();
+        assert!(result.sensitive);
+        assert_eq!(result.output, "token=hunter2\n");
+
+        let redacted = result.redacted();
+        assert_eq!(redacted.output, "[sensitive, 14 bytes redacted]");
+        assert_eq!(
+            redacted.findings["token"],
+            vec!["[sensitive, 7 bytes redacted]"]
+        );
+        assert_eq!(
+            redacted.command,
+            vec!["echo", "[sensitive, 13 bytes redacted]"]
+        );
+        let mut sink = Vec::new();
+        super::output::write_jsonl(&redacted, &mut sink).unwrap();
+        assert!(!String::from_utf8(sink).unwrap().contains("hunter2"));
+    }
+
+    #[test]
+    fn script_accessors() {
+        let script_f =
+            ScriptFile::new("fixtures/.rustscan_scripts/test_script.txt".into()).unwrap();
+        let script = into_script(script_f);
+        assert_eq!(
+            script.path(),
+            Some(Path::new("fixtures/.rustscan_scripts/test_script.txt"))
+        );
+        assert_eq!(script.ip().to_string(), "127.0.0.1");
+        assert_eq!(script.open_ports(), [80, 8080]);
+        assert_eq!(script.call_format(), ["nmap -vvv -p {{port}} {{ip}}"]);
+        assert_eq!(script.tags(), ["core_approved", "example"]);
+    }
+
+    #[test]
+    fn run_with_ports_file() {
+        let mut script_f =
+            ScriptFile::new("fixtures/.rustscan_scripts/test_script.txt".into()).unwrap();
+        script_f.call_format = Some("bash -c 'cat {{ports_file}}; echo {{ports_file}}'".into());
+        let output = into_script(script_f).run().unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+
+        assert_eq!(&lines[..2], ["80", "8080"]);
+        // The temporary file is gone once the script finished.
+        assert!(!Path::new(lines[2]).exists());
+    }
+
+    #[test]
+    fn preview_leaves_ports_file_empty() {
+        let mut script_f =
+            ScriptFile::new("fixtures/.rustscan_scripts/test_script.txt".into()).unwrap();
+        script_f.call_format = Some("nmap -iL '{{ports_file}}' {{ip}}".into());
+        assert_eq!(
+            into_script(script_f).preview().unwrap(),
+            vec!["nmap", "-iL", "", "127.0.0.1"]
         );
     }
 
@@ -0,0 +1,1237 @@
+//! Sinks the ScriptResults can be written to besides stdout.
+
+use super::{CallFormat, RunContext, ScriptFile, ScriptListing, ScriptResult};
+use crate::input::ScriptsOutput;
+use ansi_term::Colour::{Blue, Green, Red, Yellow};
+use anyhow::{anyhow, Result};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde_derive::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::fmt::{self, Write as _};
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+/// Writes the output of a ScriptResult into its own file under dir, named <ip>_<port>_<scriptname>.txt.
+/// The directory is created if it's missing. Existing files are overwritten only if overwrite is set,
+/// otherwise a numeric suffix is added to the file name. With compress the file is gzipped into a .txt.gz.
+pub fn write_result_to_dir(
+    result: &ScriptResult,
+    dir: &Path,
+    overwrite: bool,
+    compress: bool,
+) -> Result<PathBuf> {
+    fs::create_dir_all(dir)?;
+    let extension = if compress { "txt.gz" } else { "txt" };
+
+    let stem = sanitize_file_name(&format!(
+        "{}_{}_{}",
+        result.ip, result.port, result.script_name
+    ));
+    let mut path = dir.join(format!("{stem}.{extension}"));
+    let mut suffix = 1;
+    while !overwrite && path.exists() {
+        path = dir.join(format!("{stem}_{suffix}.{extension}"));
+        suffix += 1;
+    }
+
+    let file = create_output_file(&path, result.sensitive)?;
+    if compress {
+        let mut encoder = GzEncoder::new(file, Compression::default());
+        encoder.write_all(result.output.as_bytes())?;
+        encoder.finish()?;
+    } else {
+        let mut file = file;
+        file.write_all(result.output.as_bytes())?;
+    }
+    debug!("Script output written to {}", path.display());
+    Ok(path)
+}
+
+// The output of a sensitive script is only readable by its owner on unix.
+fn create_output_file(path: &Path, sensitive: bool) -> Result<fs::File> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    if sensitive {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    #[cfg(not(unix))]
+    let _ = sensitive;
+    Ok(options.open(path)?)
+}
+
+/// A single file every ScriptResult is appended to, for --scripts-combined-output.
+/// It can be shared between threads, every result is written in one go under the lock
+/// so the blocks of two scripts never interleave.
+#[derive(Debug)]
+pub struct CombinedOutput {
+    file: Mutex<fs::File>,
+}
+
+impl CombinedOutput {
+    /// Opens the file for appending, it's created if it's missing.
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+        Ok(Self {
+            file: Mutex::new(file),
+        })
+    }
+
+    /// Appends the output of the result, or its error, after a delimiter line naming it.
+    pub fn append(&self, result: &ScriptResult) -> Result<()> {
+        let mut block = format!(
+            "==> {} on {} port {} <==\n",
+            result.script_name, result.ip, result.port
+        );
+        match &result.error {
+            None => block.push_str(shown_output(&result.output)),
+            Some(error) => {
+                let _ = write!(block, "Error {error}");
+            }
+        }
+        if !block.ends_with('\n') {
+            block.push('\n');
+        }
+        let mut file = self
+            .file
+            .lock()
+            .map_err(|_| anyhow!("Combined output lock poisoned"))?;
+        file.write_all(block.as_bytes())?;
+        file.flush()?;
+        Ok(())
+    }
+}
+
+/// A SQLite database every ScriptResult is inserted into, for --scripts-sqlite, the results of every run
+/// go into the same script_results table for querying them later. The engine workers share the single
+/// connection, the inserts are serialized under its lock.
+#[derive(Debug)]
+pub struct SqliteOutput {
+    connection: Mutex<rusqlite::Connection>,
+    scan_id: String,
+}
+
+impl SqliteOutput {
+    /// Opens the database, creating the file and the table on the first run.
+    pub fn open(path: &Path, context: &RunContext) -> Result<Self> {
+        let connection = rusqlite::Connection::open(path)?;
+        connection.execute_batch(
+            "CREATE TABLE IF NOT EXISTS script_results (
+                id INTEGER PRIMARY KEY,
+                scan_id TEXT NOT NULL,
+                timestamp TEXT NOT NULL,
+                ip TEXT NOT NULL,
+                port TEXT NOT NULL,
+                script TEXT NOT NULL,
+                stdout TEXT NOT NULL,
+                exit_code INTEGER,
+                error TEXT
+            );",
+        )?;
+        Ok(Self {
+            connection: Mutex::new(connection),
+            scan_id: context.scan_id.clone(),
+        })
+    }
+
+    /// Inserts the result, with the time it was inserted at in RFC3339.
+    pub fn insert(&self, result: &ScriptResult) -> Result<()> {
+        let connection = self
+            .connection
+            .lock()
+            .map_err(|_| anyhow!("SQLite output lock poisoned"))?;
+        connection.execute(
+            "INSERT INTO script_results (scan_id, timestamp, ip, port, script, stdout, exit_code, error)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            rusqlite::params![
+                self.scan_id,
+                humantime::format_rfc3339_seconds(SystemTime::now()).to_string(),
+                result.ip.to_string(),
+                result.port,
+                result.script_name,
+                result.output,
+                result.exit_code,
+                result.error,
+            ],
+        )?;
+        Ok(())
+    }
+}
+
+/// Where the ScriptResults are rendered to as they complete, stdout for the commandline, any writer for an
+/// embedder, like an in-memory buffer, a socket or a text area. The engine workers share it, hence the Send
+/// bound, every result is written in one go under the lock. The results of sensitive scripts are redacted.
+pub struct ResultWriter {
+    writer: Mutex<Box<dyn Write + Send>>,
+    format: ScriptsOutput,
+    accessible: bool,
+    ignore_errors: bool,
+}
+
+impl fmt::Debug for ResultWriter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ResultWriter")
+            .field("format", &self.format)
+            .field("accessible", &self.accessible)
+            .field("ignore_errors", &self.ignore_errors)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ResultWriter {
+    pub fn new(writer: impl Write + Send + 'static, format: ScriptsOutput) -> Self {
+        Self {
+            writer: Mutex::new(Box::new(writer)),
+            format,
+            accessible: false,
+            ignore_errors: false,
+        }
+    }
+
+    /// The ResultWriter of the commandline, printing to stdout.
+    pub fn stdout(format: ScriptsOutput) -> Self {
+        Self::new(io::stdout(), format)
+    }
+
+    /// Leaves out the [~] and [!] markers of the text format, like --accessible.
+    pub fn accessible(mut self, accessible: bool) -> Self {
+        self.accessible = accessible;
+        self
+    }
+
+    /// Leaves out the errors of the text format, like --scripts-ignore-errors.
+    pub fn ignore_errors(mut self, ignore_errors: bool) -> Self {
+        self.ignore_errors = ignore_errors;
+        self
+    }
+
+    /// Renders a completed result: a JSON line with Jsonl, nothing with Json as the results go into the report,
+    /// otherwise the output, or the error of a failed script.
+    pub fn write_result(&self, result: &ScriptResult) -> Result<()> {
+        let result = result.redacted();
+        let mut writer = self
+            .writer
+            .lock()
+            .map_err(|_| anyhow!("Result writer lock poisoned"))?;
+        match (self.format, &result.error) {
+            (ScriptsOutput::Jsonl, _) => write_jsonl(&result, &mut *writer)?,
+            (ScriptsOutput::Json, _) => {}
+            (ScriptsOutput::Text, None) => {
+                let marker = Blue.bold().paint("[~]").to_string();
+                self.write_line(&mut *writer, &marker, shown_output(&result.output))?;
+            }
+            (ScriptsOutput::Text, Some(error)) if self.ignore_errors => {
+                debug!("Ignored script error {}", error);
+            }
+            (ScriptsOutput::Text, Some(error)) => {
+                let marker = Red.bold().paint("[!]").to_string();
+                self.write_line(&mut *writer, &marker, &format!("Error {error}"))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the report of the run as pretty printed JSON.
+    pub fn write_report(&self, report: &ScriptReport) -> Result<()> {
+        let mut writer = self
+            .writer
+            .lock()
+            .map_err(|_| anyhow!("Result writer lock poisoned"))?;
+        write_report(report, &mut *writer)
+    }
+
+    fn write_line(&self, writer: &mut dyn Write, marker: &str, text: &str) -> Result<()> {
+        if self.accessible {
+            writeln!(writer, "{text}")?;
+        } else {
+            writeln!(writer, "{marker} {text}")?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+// The output of a successful script as printed, a blank one is told apart from a missing line.
+fn shown_output(output: &str) -> &str {
+    if output.trim().is_empty() {
+        "(no output)"
+    } else {
+        output
+    }
+}
+
+/// Writes the ScriptResult as a single line of JSON and flushes the writer,
+/// so a consumer reading the stream sees every result as soon as it completes.
+pub fn write_jsonl(result: &ScriptResult, writer: &mut impl Write) -> Result<()> {
+    serde_json::to_writer(&mut *writer, result)?;
+    writer.write_all(b"\n")?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Replaces every character that could break a file name on some platform,
+/// like the colons of an IPv6 address on Windows.
+fn sanitize_file_name(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '.' || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// How a script ended on a host, for the status lines.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ScriptStatus {
+    Ok,
+    Fail,
+    Skip,
+}
+
+impl ScriptStatus {
+    pub fn of(result: &ScriptResult) -> Self {
+        if result.error.is_none() {
+            ScriptStatus::Ok
+        } else {
+            ScriptStatus::Fail
+        }
+    }
+}
+
+/// Formats a short status line for a script, with a green, red or yellow prefix when color is set,
+/// otherwise a plain [OK], [FAIL] or [SKIP].
+pub fn format_status(status: ScriptStatus, description: &str, color: bool) -> String {
+    let (prefix, paint) = match status {
+        ScriptStatus::Ok => ("[OK]", Green),
+        ScriptStatus::Fail => ("[FAIL]", Red),
+        ScriptStatus::Skip => ("[SKIP]", Yellow),
+    };
+    if color {
+        format!("{} {description}", paint.bold().paint(prefix))
+    } else {
+        format!("{prefix} {description}")
+    }
+}
+
+/// The exit code of RustScan after the scripts ran. Script failures only fail the run in strict mode.
+pub fn scripts_exit_code(results: &[ScriptResult], strict: bool) -> i32 {
+    i32::from(strict && results.iter().any(|result| result.error.is_some()))
+}
+
+// Bumped whenever a field of the ScriptReport is renamed, removed or changes its type.
+pub const REPORT_FORMAT_VERSION: u32 = 1;
+
+/// Counts of the scripting phase, init_scripts fills in the scripts, record_results the runs.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ScriptStats {
+    // Script files found in the scripts folder or listed in the config.
+    pub discovered: usize,
+    // Script files whose headers parsed.
+    pub parsed: usize,
+    // Scripts left after the tag, developer and exclude filters.
+    pub selected: usize,
+    // Scripts the filters selected but left out as experimental, without --scripts-experimental.
+    pub experimental: usize,
+    pub ran: usize,
+    pub succeeded: usize,
+    pub failed: usize,
+    // Runs left out for missing required ports, exclude_ports, a dependency that did not succeed
+    // or a detection that does not match.
+    pub skipped: usize,
+}
+
+impl ScriptStats {
+    pub fn record_results(&mut self, results: &[ScriptResult], skipped: usize) {
+        self.ran = results.len();
+        self.succeeded = results
+            .iter()
+            .filter(|result| result.error.is_none())
+            .count();
+        self.failed = self.ran - self.succeeded;
+        self.skipped = skipped;
+    }
+}
+
+/// The one line summary of the counts.
+pub fn format_stats(stats: &ScriptStats) -> String {
+    format!(
+        "Scripts: {} discovered, {} parsed, {} selected, {} ran, {} succeeded, {} failed, {} skipped",
+        stats.discovered,
+        stats.parsed,
+        stats.selected,
+        stats.ran,
+        stats.succeeded,
+        stats.failed,
+        stats.skipped
+    )
+}
+
+/// The scripting phase of a run, written by --scripts-manifest and printed with --scripts-output json.
+/// It is stable to be read back by other tools, paths and ips are strings and durations are milliseconds.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScriptReport {
+    pub format_version: u32,
+    pub rustscan_version: String,
+    pub scan_id: String,
+    pub timestamp: String,
+    pub scripts_dir: Option<PathBuf>,
+    pub tags: Option<Vec<String>>,
+    // The path of every selected script, "default" for the embedded one.
+    pub selected: Vec<String>,
+    pub runs: Vec<ScriptResult>,
+    pub passed: usize,
+    pub failed: usize,
+    pub skipped: usize,
+}
+
+impl ScriptReport {
+    /// Summarizes the results, skipped counts the scripts left out for missing required ports.
+    pub fn new(
+        context: &RunContext,
+        (scripts_dir, tags): (Option<PathBuf>, Option<Vec<String>>),
+        selected: &[ScriptFile],
+        results: &[ScriptResult],
+        skipped: usize,
+    ) -> Self {
+        let passed = results
+            .iter()
+            .filter(|result| result.error.is_none())
+            .count();
+        Self {
+            format_version: REPORT_FORMAT_VERSION,
+            rustscan_version: env!("CARGO_PKG_VERSION").to_string(),
+            scan_id: context.scan_id.clone(),
+            timestamp: context.timestamp.clone(),
+            scripts_dir,
+            tags,
+            selected: selected
+                .iter()
+                .map(|script_f| {
+                    script_f
+                        .path
+                        .as_ref()
+                        .map_or_else(|| "default".to_string(), |path| path.display().to_string())
+                })
+                .collect(),
+            runs: results.to_vec(),
+            passed,
+            failed: results.len() - passed,
+            skipped,
+        }
+    }
+}
+
+/// Writes the report as pretty printed JSON.
+pub fn write_report(report: &ScriptReport, writer: &mut impl Write) -> Result<()> {
+    serde_json::to_writer_pretty(&mut *writer, report)?;
+    writer.write_all(b"\n")?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Writes the report as pretty printed JSON to path.
+pub fn write_manifest(report: &ScriptReport, path: &Path) -> Result<()> {
+    write_report(report, &mut fs::File::create(path)?)
+}
+
+/// Writes the results as a JUnit XML report to path, for the CI systems showing them as tests, see format_junit.
+pub fn write_junit(results: &[ScriptResult], timestamp: &str, path: &Path) -> Result<()> {
+    fs::write(path, format_junit(results, timestamp))?;
+    Ok(())
+}
+
+/// The results as a JUnit XML report. Every script is a testsuite, with its runs against the hosts as testcases
+/// and their summed up time, the testsuites element has the totals. A failed run, a non zero exit code, a deadline
+/// or a program that could not start, has a failure element with the error as the message, its kind as the type
+/// and the stderr of the run, or the error again without one. The output and stderr of every run are in its
+/// system-out and system-err, redacted for the sensitive scripts.
+pub fn format_junit(results: &[ScriptResult], timestamp: &str) -> String {
+    let mut suites: BTreeMap<&str, Vec<ScriptResult>> = BTreeMap::new();
+    for result in results {
+        suites
+            .entry(&result.script_name)
+            .or_default()
+            .push(result.redacted());
+    }
+    let seconds = |results: &[ScriptResult]| {
+        format!(
+            "{:.3}",
+            results
+                .iter()
+                .map(|result| result.duration.as_secs_f64())
+                .sum::<f64>()
+        )
+    };
+    let failures =
+        |results: &[ScriptResult]| results.iter().filter(|result| !result.success).count();
+
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    let _ = writeln!(
+        xml,
+        "<testsuites name=\"rustscan scripts\" tests=\"{}\" failures=\"{}\" time=\"{}\">",
+        results.len(),
+        failures(results),
+        seconds(results)
+    );
+    for (name, runs) in &suites {
+        let _ = writeln!(
+            xml,
+            "  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" errors=\"0\" time=\"{}\" timestamp=\"{}\">",
+            xml_escape(name),
+            runs.len(),
+            failures(runs),
+            seconds(runs),
+            xml_escape(timestamp)
+        );
+        for run in runs {
+            let _ = write!(
+                xml,
+                "    <testcase classname=\"{}\" name=\"{} port {}\" time=\"{:.3}\">",
+                xml_escape(name),
+                run.ip,
+                xml_escape(&run.port),
+                run.duration.as_secs_f64()
+            );
+            if let Some(error) = &run.error {
+                let _ = write!(
+                    xml,
+                    "\n      <failure message=\"{}\" type=\"{}\">{}</failure>",
+                    xml_escape(error),
+                    xml_escape(run.error_kind.as_deref().unwrap_or("failure")),
+                    xml_escape(if run.stderr.is_empty() {
+                        error
+                    } else {
+                        &run.stderr
+                    })
+                );
+            }
+            if !run.output.is_empty() {
+                let _ = write!(
+                    xml,
+                    "\n      <system-out>{}</system-out>",
+                    xml_escape(&run.output)
+                );
+            }
+            if !run.stderr.is_empty() {
+                let _ = write!(
+                    xml,
+                    "\n      <system-err>{}</system-err>",
+                    xml_escape(&run.stderr)
+                );
+            }
+            let _ = writeln!(xml, "\n    </testcase>");
+        }
+        xml.push_str("  </testsuite>\n");
+    }
+    xml.push_str("</testsuites>\n");
+    xml
+}
+
+// The text with the XML special characters escaped, the control characters XML 1.0 can't hold go, like the
+// escape sequences of colored outputs.
+fn xml_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            '\t' | '\n' | '\r' => escaped.push(c),
+            c if c.is_control() => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Formats the duration of every ScriptResult, slowest first.
+pub fn format_timings(results: &[ScriptResult]) -> String {
+    let mut sorted: Vec<&ScriptResult> = results.iter().collect();
+    sorted.sort_by_key(|result| Reverse(result.duration));
+
+    let mut summary = String::from("Script timings, slowest first:");
+    for result in sorted {
+        let _ = write!(
+            summary,
+            "\n{:>8}ms  {} against {}",
+            result.duration.as_millis(),
+            result.script_name,
+            result.ip
+        );
+    }
+    summary
+}
+
+#[derive(Serialize)]
+struct ScriptListEntry<'a> {
+    path: &'a Path,
+    tags: Option<&'a Vec<String>>,
+    developer: Option<&'a Vec<String>>,
+    description: &'a str,
+    call_format: Option<&'a CallFormat>,
+    parse_error: bool,
+}
+
+/// Formats the discovered scripts as a plain text table, a JSON array or one JSON object per line.
+/// Scripts that failed to parse are marked with a parse error.
+pub fn format_script_list(listings: &[ScriptListing], format: ScriptsOutput) -> Result<String> {
+    if format != ScriptsOutput::Text {
+        let entries: Vec<ScriptListEntry> = listings
+            .iter()
+            .map(|listing| ScriptListEntry {
+                path: &listing.path,
+                tags: listing.script.as_ref().and_then(|s| s.tags.as_ref()),
+                developer: listing.script.as_ref().and_then(|s| s.developer.as_ref()),
+                description: listing
+                    .script
+                    .as_ref()
+                    .and_then(|s| s.description.as_deref())
+                    .unwrap_or_default(),
+                call_format: listing.script.as_ref().and_then(|s| s.call_format.as_ref()),
+                parse_error: listing.script.is_none(),
+            })
+            .collect();
+        if format == ScriptsOutput::Json {
+            return Ok(serde_json::to_string_pretty(&entries)?);
+        }
+        let mut lines = String::new();
+        for entry in &entries {
+            lines.push_str(&serde_json::to_string(entry)?);
+            lines.push('\n');
+        }
+        return Ok(lines);
+    }
+
+    let mut rows: Vec<[String; 5]> = vec![[
+        "PATH".to_string(),
+        "TAGS".to_string(),
+        "DEVELOPER".to_string(),
+        "CALL FORMAT".to_string(),
+        "DESCRIPTION".to_string(),
+    ]];
+    for listing in listings {
+        let path = listing.path.display().to_string();
+        match &listing.script {
+            Some(script) => rows.push([
+                path,
+                script.tags.clone().unwrap_or_default().join(", "),
+                script.developer.clone().unwrap_or_default().join(", "),
+                script
+                    .call_format
+                    .as_ref()
+                    .map(ToString::to_string)
+                    .unwrap_or_default(),
+                script.description.clone().unwrap_or_default(),
+            ]),
+            None => rows.push([
+                path,
+                "[parse error]".to_string(),
+                String::new(),
+                String::new(),
+                String::new(),
+            ]),
+        }
+    }
+
+    let mut widths = [0; 5];
+    for row in &rows {
+        for (width, column) in widths.iter_mut().zip(row.iter()) {
+            *width = (*width).max(column.chars().count());
+        }
+    }
+
+    let mut table = String::new();
+    for row in &rows {
+        let line = row
+            .iter()
+            .zip(widths.iter())
+            .map(|(column, width)| format!("{column:width$}"))
+            .collect::<Vec<String>>()
+            .join("  ");
+        table.push_str(line.trim_end());
+        table.push('\n');
+    }
+    Ok(table)
+}
+
+#[derive(Serialize)]
+struct ScriptDumpEntry<'a> {
+    path: &'a Path,
+    script: Option<&'a ScriptFile>,
+    error: Option<&'a str>,
+}
+
+/// Formats the ScriptFile every discovered script was parsed into, as TOML under a ==> path <== line,
+/// a JSON array or one JSON object per line. Scripts that failed to parse show the error instead.
+pub fn format_script_dump(listings: &[ScriptListing], format: ScriptsOutput) -> Result<String> {
+    let entries: Vec<ScriptDumpEntry> = listings
+        .iter()
+        .map(|listing| ScriptDumpEntry {
+            path: &listing.path,
+            script: listing.script.as_ref(),
+            error: listing.error.as_deref(),
+        })
+        .collect();
+    match format {
+        ScriptsOutput::Json => Ok(serde_json::to_string_pretty(&entries)?),
+        ScriptsOutput::Jsonl => entries.iter().try_fold(String::new(), |mut lines, entry| {
+            lines.push_str(&serde_json::to_string(entry)?);
+            lines.push('\n');
+            Ok(lines)
+        }),
+        ScriptsOutput::Text => entries.iter().try_fold(String::new(), |mut dump, entry| {
+            let _ = writeln!(dump, "==> {} <==", entry.path.display());
+            match (entry.script, entry.error) {
+                (Some(script), _) => dump.push_str(&toml::to_string(script)?),
+                (None, error) => {
+                    let _ = writeln!(dump, "error: {}", error.unwrap_or("failed to parse"));
+                }
+            }
+            dump.push('\n');
+            Ok(dump)
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        format_junit, format_script_dump, format_script_list, format_status, format_timings,
+        sanitize_file_name, scripts_exit_code, write_jsonl, write_manifest, write_report,
+        write_result_to_dir, CombinedOutput, ResultWriter, ScriptReport, ScriptStatus,
+        SqliteOutput, REPORT_FORMAT_VERSION,
+    };
+    use crate::input::ScriptsOutput;
+    use crate::scripts::{default_script, parse_script_listings, RunContext, ScriptResult};
+    use flate2::read::GzDecoder;
+    use std::collections::BTreeMap;
+    use std::fmt::Write as _;
+    use std::fs;
+    use std::io::Read;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::Duration;
+
+    fn script_result(ip: &str) -> ScriptResult {
+        ScriptResult {
+            script_name: "test_script".to_string(),
+            description: None,
+            ip: ip.parse().unwrap(),
+            port: "all".to_string(),
+            ports: vec![80, 443],
+            output: "script output".to_string(),
+            stderr: String::new(),
+            findings: BTreeMap::new(),
+            error: None,
+            success: true,
+            error_kind: None,
+            exit_code: Some(0),
+            command: vec!["echo".to_string()],
+            binary_path: "/bin/echo".to_string(),
+            binary_version: String::new(),
+            duration: Duration::from_millis(10),
+            sensitive: false,
+        }
+    }
+
+    // A writer the test keeps a handle on, the ResultWriter owns the other one.
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl SharedBuffer {
+        fn contents(&self) -> String {
+            String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+        }
+    }
+
+    #[test]
+    fn empty_output_is_reported() {
+        let mut silent = script_result("127.0.0.1");
+        silent.output = String::new();
+        let buffer = SharedBuffer::default();
+        let writer = ResultWriter::new(buffer.clone(), ScriptsOutput::Text).accessible(true);
+        writer.write_result(&silent).unwrap();
+        assert_eq!(buffer.contents(), "(no output)\n");
+
+        let buffer = SharedBuffer::default();
+        let writer = ResultWriter::new(buffer.clone(), ScriptsOutput::Jsonl);
+        writer.write_result(&silent).unwrap();
+        let line: serde_json::Value = serde_json::from_str(buffer.contents().trim_end()).unwrap();
+        assert_eq!(line["output"], "");
+        assert_eq!(line["success"], true);
+        assert!(line["error"].is_null());
+    }
+
+    #[test]
+    fn result_writer_into_buffer() {
+        let mut failed = script_result("127.0.0.2");
+        failed.error = Some("Exit code = 1".to_string());
+        let mut sensitive = script_result("127.0.0.3");
+        sensitive.sensitive = true;
+
+        let buffer = SharedBuffer::default();
+        let writer = ResultWriter::new(buffer.clone(), ScriptsOutput::Text).accessible(true);
+        for result in [script_result("127.0.0.1"), failed.clone(), sensitive] {
+            writer.write_result(&result).unwrap();
+        }
+        assert_eq!(
+            buffer.contents(),
+            "script output\nError Exit code = 1\n[sensitive, 13 bytes redacted]\n"
+        );
+
+        let buffer = SharedBuffer::default();
+        let writer = ResultWriter::new(buffer.clone(), ScriptsOutput::Text).ignore_errors(true);
+        writer.write_result(&failed).unwrap();
+        assert_eq!(buffer.contents(), "");
+
+        let buffer = SharedBuffer::default();
+        let writer = ResultWriter::new(buffer.clone(), ScriptsOutput::Jsonl);
+        writer.write_result(&failed).unwrap();
+        let line: ScriptResult = serde_json::from_str(buffer.contents().trim_end()).unwrap();
+        assert_eq!(line, failed);
+
+        let buffer = SharedBuffer::default();
+        let writer = ResultWriter::new(buffer.clone(), ScriptsOutput::Json);
+        writer.write_result(&failed).unwrap();
+        assert_eq!(buffer.contents(), "");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn sensitive_output_file_is_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut result = script_result("127.0.0.1");
+        result.sensitive = true;
+        let path = write_result_to_dir(&result, dir.path(), false, false).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "script output");
+        assert_eq!(
+            fs::metadata(&path).unwrap().permissions().mode() & 0o777,
+            0o600
+        );
+    }
+
+    #[test]
+    fn write_compressed_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let result = script_result("127.0.0.1");
+        let path = write_result_to_dir(&result, dir.path(), false, true).unwrap();
+        assert_eq!(path, dir.path().join("127.0.0.1_all_test_script.txt.gz"));
+
+        let mut output = String::new();
+        GzDecoder::new(fs::File::open(&path).unwrap())
+            .read_to_string(&mut output)
+            .unwrap();
+        assert_eq!(output, "script output");
+
+        let second = write_result_to_dir(&result, dir.path(), false, true).unwrap();
+        assert_eq!(
+            second,
+            dir.path().join("127.0.0.1_all_test_script_1.txt.gz")
+        );
+    }
+
+    #[test]
+    fn write_jsonl_lines() {
+        let mut failed = script_result("::1");
+        failed.error = Some("Exit code = 1".to_string());
+
+        let mut stream: Vec<u8> = Vec::new();
+        write_jsonl(&script_result("127.0.0.1"), &mut stream).unwrap();
+        write_jsonl(&failed, &mut stream).unwrap();
+
+        let stream = String::from_utf8(stream).unwrap();
+        let lines: Vec<serde_json::Value> = stream
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["ip"], "127.0.0.1");
+        assert_eq!(lines[0]["error"], serde_json::Value::Null);
+        assert_eq!(lines[0]["duration_ms"], 10);
+        assert_eq!(lines[1]["error"], "Exit code = 1");
+    }
+
+    #[test]
+    fn exit_code_of_failed_scripts() {
+        let mut failed = script_result("127.0.0.1");
+        failed.error = Some("Exit code = 1".to_string());
+        let results = vec![script_result("127.0.0.1"), failed];
+
+        assert_eq!(scripts_exit_code(&results, false), 0);
+        assert_eq!(scripts_exit_code(&results, true), 1);
+        assert_eq!(scripts_exit_code(&results[..1], true), 0);
+    }
+
+    #[test]
+    fn status_lines() {
+        let mut result = script_result("127.0.0.1");
+        assert_eq!(ScriptStatus::of(&result), ScriptStatus::Ok);
+        result.error = Some("Exit code = 1".to_string());
+        assert_eq!(ScriptStatus::of(&result), ScriptStatus::Fail);
+
+        assert_eq!(
+            format_status(ScriptStatus::Skip, "test_script on 127.0.0.1", false),
+            "[SKIP] test_script on 127.0.0.1"
+        );
+        let colored = format_status(ScriptStatus::Ok, "test_script on 127.0.0.1", true);
+        assert!(colored.starts_with("\u{1b}["));
+        assert!(colored.ends_with("[OK]\u{1b}[0m test_script on 127.0.0.1"));
+    }
+
+    #[test]
+    fn sanitize_ipv6_file_name() {
+        assert_eq!(sanitize_file_name("::1_80_test"), "__1_80_test");
+    }
+
+    #[test]
+    fn write_creates_dir_and_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let out_dir = dir.path().join("results");
+        let path =
+            write_result_to_dir(&script_result("127.0.0.1"), &out_dir, false, false).unwrap();
+
+        assert_eq!(path, out_dir.join("127.0.0.1_all_test_script.txt"));
+        assert_eq!(fs::read_to_string(path).unwrap(), "script output");
+    }
+
+    #[test]
+    fn write_suffixes_or_overwrites_existing() {
+        let dir = tempfile::tempdir().unwrap();
+        let result = script_result("::1");
+
+        let first = write_result_to_dir(&result, dir.path(), false, false).unwrap();
+        let second = write_result_to_dir(&result, dir.path(), false, false).unwrap();
+        assert_eq!(first, dir.path().join("__1_all_test_script.txt"));
+        assert_eq!(second, dir.path().join("__1_all_test_script_1.txt"));
+
+        let third = write_result_to_dir(&result, dir.path(), true, false).unwrap();
+        assert_eq!(third, first);
+    }
+
+    #[test]
+    fn list_scripts_as_text() {
+        let listings = parse_script_listings(vec![
+            "fixtures/.rustscan_scripts/test_script.txt".into(),
+            "fixtures/.rustscan_scripts/test_script_invalid_headers.txt".into(),
+        ]);
+        let table = format_script_list(&listings, ScriptsOutput::Text).unwrap();
+        let lines: Vec<&str> = table.lines().collect();
+
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("PATH"));
+        assert!(lines[1].contains("core_approved, example"));
+        assert!(lines[1].contains("nmap -vvv -p {{port}} {{ip}}"));
+        assert!(lines[2].contains("[parse error]"));
+    }
+
+    #[test]
+    fn list_scripts_with_description() {
+        let listings = parse_script_listings(vec![
+            "fixtures/.rustscan_scripts/test_script.py".into(),
+            "fixtures/.rustscan_scripts/test_script.txt".into(),
+        ]);
+        let table = format_script_list(&listings, ScriptsOutput::Text).unwrap();
+        let lines: Vec<&str> = table.lines().collect();
+        assert!(lines[0].ends_with("DESCRIPTION"));
+        assert!(lines[1].ends_with("Prints the arguments it was called with"));
+        assert!(lines[2].ends_with("nmap -vvv -p {{port}} {{ip}}"));
+
+        let json = format_script_list(&listings, ScriptsOutput::Json).unwrap();
+        let entries: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(
+            entries[0]["description"],
+            "Prints the arguments it was called with"
+        );
+        assert_eq!(entries[1]["description"], "");
+    }
+
+    #[test]
+    fn dump_parsed_scripts() {
+        let listings = parse_script_listings(vec![
+            "fixtures/.rustscan_scripts/test_script.sh".into(),
+            "fixtures/.rustscan_scripts/test_script_invalid_headers.txt".into(),
+        ]);
+        let dump = format_script_dump(&listings, ScriptsOutput::Text).unwrap();
+        assert!(dump.starts_with("==> fixtures/.rustscan_scripts/test_script.sh <==\n"));
+        assert!(dump.contains("tags = [\"core_approved\", \"example\"]\n"));
+        assert!(dump.contains("ports_separator = \",\"\n"));
+        assert!(dump.contains("call_format = \"bash {{script}} {{ip}} {{port}}\"\n"));
+        assert!(dump.contains("==> fixtures/.rustscan_scripts/test_script_invalid_headers.txt <==\nerror: Failed to parse ScriptFile headers"));
+
+        let json = format_script_dump(&listings, ScriptsOutput::Json).unwrap();
+        let entries: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(entries[0]["script"]["developer"][1], "https://example.org");
+        assert!(entries[0]["script"]["port"].is_null());
+        assert!(entries[0]["error"].is_null());
+        assert!(entries[1]["script"].is_null());
+        assert!(entries[1]["error"]
+            .as_str()
+            .unwrap()
+            .starts_with("Failed to parse ScriptFile headers"));
+    }
+
+    #[test]
+    fn list_scripts_as_json() {
+        let listings = parse_script_listings(vec![
+            "fixtures/.rustscan_scripts/test_script.txt".into(),
+            "fixtures/.rustscan_scripts/test_script_invalid_headers.txt".into(),
+        ]);
+        let json = format_script_list(&listings, ScriptsOutput::Json).unwrap();
+        let entries: serde_json::Value = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(entries[0]["tags"][1], "example");
+        assert_eq!(entries[0]["parse_error"], false);
+        assert_eq!(entries[1]["parse_error"], true);
+        assert!(entries[1]["call_format"].is_null());
+    }
+
+    #[test]
+    fn manifest_summarizes_the_run() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("manifest.json");
+        let context = RunContext {
+            scan_id: "scan".to_string(),
+            timestamp: "2021-01-01T00:00:00Z".to_string(),
+            ..RunContext::default()
+        };
+        let mut failed = script_result("::1");
+        failed.error = Some("Exit code = 1".to_string());
+        let results = vec![script_result("127.0.0.1"), failed];
+        let selected = vec![default_script()];
+        let manifest = ScriptReport::new(
+            &context,
+            (Some("/scripts".into()), Some(vec!["web".to_string()])),
+            &selected,
+            &results,
+            2,
+        );
+        write_manifest(&manifest, &path).unwrap();
+
+        let json: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(json["format_version"], REPORT_FORMAT_VERSION);
+        assert_eq!(json["rustscan_version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(json["scan_id"], "scan");
+        assert_eq!(json["timestamp"], "2021-01-01T00:00:00Z");
+        assert_eq!(json["scripts_dir"], "/scripts");
+        assert_eq!(json["tags"][0], "web");
+        assert_eq!(json["selected"][0], "default");
+        assert_eq!(json["runs"][0]["ports"][1], 443);
+        assert_eq!(json["runs"][1]["ip"], "::1");
+        assert_eq!(json["runs"][1]["error"], "Exit code = 1");
+        assert_eq!(
+            (&json["passed"], &json["failed"], &json["skipped"]),
+            (&1.into(), &1.into(), &2.into())
+        );
+    }
+
+    #[test]
+    fn report_round_trip() {
+        let mut failed = script_result("::1");
+        failed.error = Some("Exit code = 1".to_string());
+        failed.error_kind = Some("non_zero_exit".to_string());
+        failed.description = Some("Prints the arguments".to_string());
+        let results = vec![script_result("127.0.0.1"), failed];
+        let report = ScriptReport::new(
+            &RunContext::new(),
+            (Some("/scripts".into()), None),
+            &[default_script()],
+            &results,
+            0,
+        );
+
+        let mut json = Vec::new();
+        write_report(&report, &mut json).unwrap();
+        let value: serde_json::Value = serde_json::from_slice(&json).unwrap();
+        assert_eq!(value["runs"][1]["ip"], "::1");
+        assert_eq!(value["runs"][0]["duration_ms"], 10);
+
+        let read_back: ScriptReport = serde_json::from_slice(&json).unwrap();
+        assert_eq!(read_back, report);
+    }
+
+    #[test]
+    fn sqlite_output_stores_the_results() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("results.db");
+        let context = RunContext {
+            scan_id: "scan".to_string(),
+            ..RunContext::default()
+        };
+        let sqlite = Arc::new(SqliteOutput::open(&path, &context).unwrap());
+        let mut failed = script_result("10.0.0.2");
+        failed.script_name = "failing".to_string();
+        failed.output = String::new();
+        failed.error = Some("Exit code = 2".to_string());
+        failed.exit_code = Some(2);
+        let writers: Vec<_> = vec![script_result("10.0.0.1"), failed]
+            .into_iter()
+            .map(|result| {
+                let sqlite = Arc::clone(&sqlite);
+                thread::spawn(move || sqlite.insert(&result).unwrap())
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+        drop(sqlite);
+
+        // The table is created once, reopening keeps the rows.
+        SqliteOutput::open(&path, &context).unwrap();
+        let connection = rusqlite::Connection::open(&path).unwrap();
+        let mut statement = connection
+            .prepare(
+                "SELECT scan_id, ip, port, script, stdout, exit_code, error FROM script_results ORDER BY ip",
+            )
+            .unwrap();
+        let rows: Vec<String> = statement
+            .query_map([], |row| {
+                let exit_code: Option<i64> = row.get(5)?;
+                let error: Option<String> = row.get(6)?;
+                Ok(format!(
+                    "{} {} {} {} {:?} {:?} {:?}",
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, String>(2)?,
+                    row.get::<_, String>(3)?,
+                    row.get::<_, String>(4)?,
+                    exit_code,
+                    error
+                ))
+            })
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(
+            rows,
+            vec![
+                "scan 10.0.0.1 all test_script \"script output\" Some(0) None",
+                "scan 10.0.0.2 all failing \"\" Some(2) Some(\"Exit code = 2\")"
+            ]
+        );
+    }
+
+    #[test]
+    fn combined_output_keeps_blocks_intact() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("combined.log");
+        let combined = Arc::new(CombinedOutput::open(&path).unwrap());
+        let lines = |name: &str| {
+            (0..200).fold(String::new(), |mut lines, line| {
+                let _ = writeln!(lines, "{name} line {line}");
+                lines
+            })
+        };
+
+        let writers: Vec<_> = ["first", "second"]
+            .iter()
+            .map(|name| {
+                let combined = Arc::clone(&combined);
+                let mut result = script_result("127.0.0.1");
+                result.script_name = (*name).to_string();
+                result.output = lines(name);
+                thread::spawn(move || {
+                    for _ in 0..20 {
+                        combined.append(&result).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        let content = fs::read_to_string(&path).unwrap();
+        let blocks: Vec<&str> = content.split("==> ").skip(1).collect();
+        assert_eq!(blocks.len(), 40);
+        for block in blocks {
+            let name = block.split(' ').next().unwrap();
+            assert_eq!(
+                block,
+                format!("{name} on 127.0.0.1 port all <==\n{}", lines(name))
+            );
+        }
+    }
+
+    #[test]
+    fn timings_slowest_first() {
+        let mut fast = script_result("127.0.0.1");
+        fast.script_name = "fast".to_string();
+        let mut slow = script_result("127.0.0.1");
+        slow.script_name = "slow".to_string();
+        slow.duration = Duration::from_millis(1500);
+
+        let summary = format_timings(&[fast, slow]);
+        let lines: Vec<&str> = summary.lines().collect();
+        assert_eq!(lines[1].trim(), "1500ms  slow against 127.0.0.1");
+        assert_eq!(lines[2].trim(), "10ms  fast against 127.0.0.1");
+    }
+
+    #[test]
+    fn junit_report() {
+        let passed = script_result("10.0.0.1");
+        let mut failed = script_result("10.0.0.2");
+        failed.port = "22".to_string();
+        failed.success = false;
+        failed.error = Some("exit code 1 <&\"'>".to_string());
+        failed.error_kind = Some("non_zero_exit".to_string());
+        failed.output = "a\u{1b}[31m & b".to_string();
+        failed.stderr = "Permission denied <root>\n".to_string();
+        failed.duration = Duration::from_millis(1500);
+        let mut other = script_result("10.0.0.1");
+        other.script_name = "other".to_string();
+        other.output = String::new();
+
+        let xml = format_junit(&[passed, failed, other], "2026-10-14T10:00:00Z");
+        assert!(xml.starts_with("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<testsuites "));
+        assert!(xml.contains(r#"tests="3" failures="1" time="1.520">"#));
+        assert!(xml.contains(
+            r#"<testsuite name="other" tests="1" failures="0" errors="0" time="0.010" timestamp="2026-10-14T10:00:00Z">"#
+        ));
+        assert!(xml.contains(
+            r#"<testsuite name="test_script" tests="2" failures="1" errors="0" time="1.510""#
+        ));
+        assert!(xml.contains(
+            r#"<testcase classname="test_script" name="10.0.0.2 port 22" time="1.500">"#
+        ));
+        assert!(xml.contains(
+            r#"<failure message="exit code 1 &lt;&amp;&quot;&apos;&gt;" type="non_zero_exit">Permission denied &lt;root&gt;
+</failure>"#
+        ));
+        assert!(xml.contains("<system-err>Permission denied &lt;root&gt;\n</system-err>"));
+        assert_eq!(xml.matches("<system-err>").count(), 1);
+        assert!(xml.contains("<system-out>a[31m &amp; b</system-out>"));
+        assert_eq!(xml.matches("<testcase ").count(), 3);
+        assert_eq!(xml.matches("</testcase>").count(), 3);
+        assert_eq!(xml.matches("<failure ").count(), 1);
+        assert_eq!(xml.matches("<system-out>").count(), 2);
+        assert!(xml.find("name=\"other\"").unwrap() < xml.find("name=\"test_script\"").unwrap());
+        assert!(xml.ends_with("</testsuite>\n</testsuites>\n"));
+    }
+}
@@ -0,0 +1,59 @@
+//! Whether RustScan runs elevated, for the scripts with requires_root = true.
+
+/// Whether the process runs as root, its effective user id is 0.
+#[cfg(unix)]
+pub fn running_elevated() -> bool {
+    // SAFETY: geteuid has no preconditions and can not fail.
+    unsafe { libc::geteuid() == 0 }
+}
+
+/// Whether the process runs with an elevated token, as an administrator past the UAC prompt.
+#[cfg(windows)]
+pub fn running_elevated() -> bool {
+    use windows_sys::Win32::Foundation::{CloseHandle, HANDLE};
+    use windows_sys::Win32::Security::{
+        GetTokenInformation, TokenElevation, TOKEN_ELEVATION, TOKEN_QUERY,
+    };
+    use windows_sys::Win32::System::Threading::{GetCurrentProcess, OpenProcessToken};
+
+    // SAFETY: the token is only used after OpenProcessToken succeeded and closed once,
+    // the buffer is a TOKEN_ELEVATION of the size given.
+    unsafe {
+        let mut token: HANDLE = std::ptr::null_mut();
+        if OpenProcessToken(GetCurrentProcess(), TOKEN_QUERY, &mut token) == 0 {
+            return false;
+        }
+        let mut elevation = TOKEN_ELEVATION { TokenIsElevated: 0 };
+        let mut size = 0;
+        let queried = GetTokenInformation(
+            token,
+            TokenElevation,
+            std::ptr::addr_of_mut!(elevation).cast(),
+            std::mem::size_of::<TOKEN_ELEVATION>() as u32,
+            &mut size,
+        );
+        CloseHandle(token);
+        queried != 0 && elevation.TokenIsElevated != 0
+    }
+}
+
+/// Elsewhere the privileges are unknown, taken as not elevated.
+#[cfg(not(any(unix, windows)))]
+pub fn running_elevated() -> bool {
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::running_elevated;
+
+    #[cfg(unix)]
+    #[test]
+    fn elevated_is_euid_root() {
+        let euid = std::process::Command::new("id").arg("-u").output().unwrap();
+        assert_eq!(
+            running_elevated(),
+            String::from_utf8_lossy(&euid.stdout).trim() == "0"
+        );
+    }
+}
@@ -0,0 +1,224 @@
+//! How the placeholders of a call format are filled in, the {{name}} ones of text_placeholder by default.
+
+use super::ScriptError;
+use crate::input::ScriptsTemplate;
+use anyhow::Result;
+use regex::Regex;
+use serde_json::{Map, Value};
+use std::fmt::{self, Write as _};
+use std::sync::Arc;
+use text_placeholder::Template;
+
+/// Renders a call format with the values of its placeholders, by name, into the command line that is split and run.
+/// The values are the built-in placeholders and the [vars] of the config. The call format comes with the escaped
+/// braces, the {{ports_flag:FLAG}} and the {{?PORT:TEXT}} already taken care of.
+pub trait TemplateEngine: fmt::Debug + Send + Sync {
+    fn render(&self, call_format: &str, values: &Map<String, Value>) -> Result<String>;
+
+    /// Whether every {{name}} of a call format has to have a value, checked before rendering.
+    /// Engines with a syntax of their own, like the filters of {{ ip | upper }}, turn this off.
+    fn checks_placeholders(&self) -> bool {
+        true
+    }
+}
+
+/// The modifiers of a {{name|modifier}} placeholder, see add_modified_values.
+pub static MODIFIERS: &[&str] = &["base64", "hex", "upper", "lower"];
+
+/// Adds the value of every {{name|modifier}} of the call format under the name|modifier key, the value of name
+/// transformed by the modifier: base64 with padding, hex with two lowercase digits per byte, upper or lower case.
+/// The modifiers work on the text of the value, {{port|hex}} of port 80 is 3830. A name without a value is left
+/// to the placeholder checks, an unknown modifier is an error.
+pub fn add_modified_values(call_format: &str, values: &mut Map<String, Value>) -> Result<()> {
+    let placeholder =
+        Regex::new(r"\{\{([^{}|]+)\|([^{}|]+)\}\}").expect("Invalid modifier pattern");
+    for captures in placeholder.captures_iter(call_format) {
+        let (name, modifier) = (&captures[1], &captures[2]);
+        let Some(value) = values.get(name).and_then(Value::as_str) else {
+            continue;
+        };
+        let transformed = match modifier {
+            "base64" => base64(value.as_bytes()),
+            "hex" => hex(value.as_bytes()),
+            "upper" => value.to_uppercase(),
+            "lower" => value.to_lowercase(),
+            _ => {
+                return Err(ScriptError::Parse(format!(
+                "Unknown modifier {modifier} in {{{{{name}|{modifier}}}}}, the modifiers are {}",
+                MODIFIERS.join(", ")
+            ))
+                .into())
+            }
+        };
+        values.insert(format!("{name}|{modifier}"), transformed.into());
+    }
+    Ok(())
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut hex, byte| {
+        let _ = write!(hex, "{byte:02x}");
+        hex
+    })
+}
+
+// The standard base64 alphabet, with = padding.
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let triple = chunk.iter().enumerate().fold(0u32, |triple, (i, byte)| {
+            triple | u32::from(*byte) << (16 - 8 * i)
+        });
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(triple >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+/// The built-in TemplateEngine, replacing every {{name}} with its value and nothing more.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PlaceholderTemplate;
+
+impl TemplateEngine for PlaceholderTemplate {
+    fn render(&self, call_format: &str, values: &Map<String, Value>) -> Result<String> {
+        Ok(Template::new(call_format).fill_with_struct(values)?)
+    }
+}
+
+/// A TemplateEngine with the Jinja syntax of minijinja, for loops, conditionals and filters in the call formats,
+/// like {% for port in port | split(',') %}-p {{ port }} {% endfor %}. The values with a dot in their name, vars.wordlist,
+/// are only reachable by their short name.
+#[cfg(feature = "jinja")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JinjaTemplate;
+
+#[cfg(feature = "jinja")]
+impl TemplateEngine for JinjaTemplate {
+    fn render(&self, call_format: &str, values: &Map<String, Value>) -> Result<String> {
+        Ok(minijinja::Environment::new().render_str(call_format, values)?)
+    }
+
+    fn checks_placeholders(&self) -> bool {
+        false
+    }
+}
+
+/// The TemplateEngine of --scripts-template, jinja is an error when RustScan is built without the jinja feature.
+pub fn select_template_engine(template: ScriptsTemplate) -> Result<Arc<dyn TemplateEngine>> {
+    match template {
+        ScriptsTemplate::Placeholder => Ok(Arc::new(PlaceholderTemplate)),
+        #[cfg(feature = "jinja")]
+        ScriptsTemplate::Jinja => Ok(Arc::new(JinjaTemplate)),
+        #[cfg(not(feature = "jinja"))]
+        ScriptsTemplate::Jinja => Err(anyhow::anyhow!(
+            "The jinja template engine needs RustScan built with the jinja feature"
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        add_modified_values, base64, select_template_engine, PlaceholderTemplate, TemplateEngine,
+    };
+    use crate::input::ScriptsTemplate;
+    use serde_json::{Map, Value};
+
+    fn values() -> Map<String, Value> {
+        let mut values = Map::new();
+        values.insert("ip".to_string(), "10.0.0.1".into());
+        values.insert("port".to_string(), "22,80".into());
+        values
+    }
+
+    #[test]
+    fn placeholder_template_fills_the_names() {
+        assert_eq!(
+            PlaceholderTemplate
+                .render("nmap -p {{port}} {{ip}}", &values())
+                .unwrap(),
+            "nmap -p 22,80 10.0.0.1"
+        );
+        assert!(PlaceholderTemplate.checks_placeholders());
+    }
+
+    #[test]
+    fn modifiers_encode_the_values() {
+        let mut values = values();
+        values.insert("vars.user".to_string(), "Admin".into());
+        add_modified_values(
+            "x {{ip|base64}} {{port|hex}} {{vars.user|upper}} {{vars.user|lower}} {{nothing|hex}}",
+            &mut values,
+        )
+        .unwrap();
+        assert_eq!(values["ip|base64"], "MTAuMC4wLjE=");
+        assert_eq!(values["port|hex"], "32322c3830");
+        assert_eq!(values["vars.user|upper"], "ADMIN");
+        assert_eq!(values["vars.user|lower"], "admin");
+        assert!(!values.contains_key("nothing|hex"));
+        assert_eq!(
+            PlaceholderTemplate
+                .render("{{ip|base64}}:{{port|hex}}", &values)
+                .unwrap(),
+            "MTAuMC4wLjE=:32322c3830"
+        );
+
+        assert!(add_modified_values("{{ip|rot13}}", &mut values)
+            .unwrap_err()
+            .to_string()
+            .contains("Unknown modifier rot13"));
+    }
+
+    #[test]
+    fn base64_padding() {
+        for (plain, encoded) in [
+            ("", ""),
+            ("f", "Zg=="),
+            ("fo", "Zm8="),
+            ("foo", "Zm9v"),
+            ("foob", "Zm9vYg=="),
+        ] {
+            assert_eq!(base64(plain.as_bytes()), encoded);
+        }
+    }
+
+    #[cfg(feature = "jinja")]
+    #[test]
+    fn jinja_template_filters() {
+        let jinja = super::JinjaTemplate;
+        assert_eq!(
+            jinja
+                .render(
+                    "scan {% for port in port | split(',') %}-p {{ port }} {% endfor %}{{ ip | upper }}",
+                    &values()
+                )
+                .unwrap(),
+            "scan -p 22 -p 80 10.0.0.1"
+        );
+        assert!(!jinja.checks_placeholders());
+    }
+
+    #[test]
+    fn select_the_template_engine() {
+        let placeholder = select_template_engine(ScriptsTemplate::Placeholder).unwrap();
+        assert_eq!(
+            placeholder.render("nmap {{ip}}", &values()).unwrap(),
+            "nmap 10.0.0.1"
+        );
+        let jinja = select_template_engine(ScriptsTemplate::Jinja);
+        if cfg!(feature = "jinja") {
+            assert!(!jinja.unwrap().checks_placeholders());
+        } else {
+            assert!(jinja
+                .unwrap_err()
+                .to_string()
+                .contains("built with the jinja feature"));
+        }
+    }
+}
@@ -0,0 +1,402 @@
+//! Static checks of the script config and every script for --scripts-validate, nothing is run.
+
+use super::{
+    config_script_paths, parse_script_listings, protect_escaped_braces, read_script_config,
+    scripts_location, Script, ScriptBundle, ScriptFile, ScriptListing, MODIFIERS,
+};
+use anyhow::Result;
+use regex::Regex;
+use std::collections::{BTreeMap, BTreeSet};
+use std::env;
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::{Path, PathBuf};
+
+// Every placeholder a call_format can use.
+static PLACEHOLDERS: &[&str] = &[
+    "script",
+    "ip",
+    "ip_bracketed",
+    "hostname",
+    "target",
+    "port",
+    "ports_compact",
+    "ports_file",
+    "index",
+    "scan_id",
+    "scan_fingerprint",
+    "results_file",
+    "timestamp",
+    "port1",
+    "port2",
+    "port3",
+    "port4",
+    "port5",
+    "port6",
+    "port7",
+    "port8",
+    "port9",
+    "all_ips",
+    "all_ports",
+    "all_sockets",
+    "services",
+    "banner",
+    "closed_ports",
+    "filtered_ports",
+    "scan_start",
+    "scan_elapsed",
+    "ports_scanned",
+    "ports_open",
+];
+
+// The placeholders only an embedder of the library fills, they are always empty from the command line.
+static LIBRARY_PLACEHOLDERS: &[&str] = &["results_file", "services", "banner"];
+
+/// The problems found in the config or a script file, it passed when there are none.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Validation {
+    pub path: PathBuf,
+    pub problems: Vec<String>,
+}
+
+/// Validates the config and every script it selects the files of, the bundle's ones if there is a bundle.
+/// The configs given with --scripts-config are merged instead of reading the looked up one.
+#[cfg(not(tarpaulin_include))]
+pub fn validate_scripts(
+    bundle: Option<&ScriptBundle>,
+    configs: &[PathBuf],
+) -> Result<Vec<Validation>> {
+    let location = scripts_location(bundle)?;
+    let config = match read_script_config(&location, configs) {
+        Ok(config) => config,
+        Err(e) => {
+            return Ok(vec![Validation {
+                path: configs.first().unwrap_or(&location.config).clone(),
+                problems: vec![format!("{e:#}")],
+            }])
+        }
+    };
+    let script_paths = config_script_paths(&location.scripts_dir, &config)?;
+    let delimiters = config.delimiters();
+    let vars = config.vars.clone().unwrap_or_default();
+    Ok(parse_script_listings(script_paths)
+        .into_iter()
+        .map(|mut listing| {
+            if let (Some((open, close)), Some(script_f)) = (delimiters, &mut listing.script) {
+                script_f.use_delimiters(open, close);
+            }
+            validate_listing(&listing, &vars)
+        })
+        .collect())
+}
+
+/// Checks a parsed script: its headers parse, its call formats only use known placeholders,
+/// or the vars of the config, and the program they start can be found. With a [remote] table only ssh has to be
+/// found, the program is on the remote host.
+pub fn validate_listing(listing: &ScriptListing, vars: &BTreeMap<String, String>) -> Validation {
+    let mut problems = Vec::new();
+    match &listing.script {
+        None => problems.push("the file can not be read or its headers do not parse".to_string()),
+        Some(script_f) if !script_f.is_runnable() => {
+            problems.push("it has neither a call_format nor an interpreter".to_string());
+        }
+        Some(script_f) => {
+            let script = Script::build(
+                script_f.clone(),
+                IpAddr::V4(Ipv4Addr::LOCALHOST),
+                Vec::new(),
+            );
+            let remote = script_f.remote.is_some();
+            if remote && find_program("ssh").is_none() {
+                problems.push("ssh is not found on the PATH, the script runs remotely".to_string());
+            }
+            for call_format in script.final_call_formats().unwrap_or_default() {
+                problems.extend(check_call_format(&call_format, &listing.path, vars, remote));
+            }
+        }
+    }
+    Validation {
+        path: listing.path.clone(),
+        problems,
+    }
+}
+
+fn check_call_format(
+    call_format: &str,
+    script_path: &Path,
+    vars: &BTreeMap<String, String>,
+    remote: bool,
+) -> Vec<String> {
+    let mut problems = Vec::new();
+    // Escaped braces are no placeholders, the messages show the call_format as written.
+    let protected = protect_escaped_braces(call_format);
+
+    let placeholder = Regex::new(r"\{\{([^{}]*)\}\}").expect("Invalid placeholder pattern");
+    for captures in placeholder.captures_iter(&protected) {
+        let name = &captures[1];
+        // A {{name|modifier}} is known when its name and modifier are.
+        let name = match name.split_once('|') {
+            Some((name, modifier)) if MODIFIERS.contains(&modifier) => name,
+            _ => name,
+        };
+        let is_var = vars.contains_key(name.strip_prefix("vars.").unwrap_or(name));
+        let is_ports_flag = name.starts_with("ports_flag:");
+        let is_conditional = name
+            .strip_prefix('?')
+            .and_then(|conditional| conditional.split_once(':'))
+            .is_some_and(|(port, _)| port.parse::<u16>().is_ok());
+        if !PLACEHOLDERS.contains(&name) && !is_var && !is_ports_flag && !is_conditional {
+            problems.push(format!(
+                "unknown placeholder {{{{{name}}}}} in {call_format}"
+            ));
+        } else if LIBRARY_PLACEHOLDERS.contains(&name) {
+            problems.push(format!(
+                "{{{{{name}}}}} is only filled when RustScan is used as a library, it is always empty from the command line in {call_format}"
+            ));
+        }
+    }
+
+    match shell_words::split(&protected) {
+        Ok(arguments) => match arguments.first().map(String::as_str) {
+            None => problems.push("empty call_format".to_string()),
+            Some("{{script}}") => {
+                if !script_path.is_file() {
+                    problems.push(format!("{} does not exist", script_path.display()));
+                }
+            }
+            // A program coming from a placeholder is only known at run time, a remote one is not local.
+            Some(program) if program.contains("{{") || remote => {}
+            Some(program) => {
+                if find_program(program).is_none() {
+                    problems.push(format!("{program} is not found on the PATH"));
+                }
+            }
+        },
+        Err(e) => problems.push(format!("{call_format} does not split into arguments: {e}")),
+    }
+    problems
+}
+
+/// The programs the call formats of the scripts start, without duplicates, for --scripts-check-deps.
+/// The script files run themselves with {{script}} and the programs coming from a placeholder are left out,
+/// a remote script only needs ssh.
+pub fn script_binaries(scripts: &[ScriptFile]) -> BTreeSet<String> {
+    let mut binaries = BTreeSet::new();
+    for script_f in scripts {
+        if script_f.remote.is_some() {
+            binaries.insert("ssh".to_string());
+            continue;
+        }
+        let script = Script::build(
+            script_f.clone(),
+            IpAddr::V4(Ipv4Addr::LOCALHOST),
+            Vec::new(),
+        );
+        for call_format in script.final_call_formats().unwrap_or_default() {
+            let arguments = shell_words::split(&protect_escaped_braces(&call_format));
+            if let Some(program) = arguments
+                .ok()
+                .and_then(|arguments| arguments.into_iter().next())
+            {
+                if !program.contains("{{") {
+                    binaries.insert(program);
+                }
+            }
+        }
+    }
+    binaries
+}
+
+/// The binaries that are not found, see find_program.
+pub fn missing_binaries(binaries: &BTreeSet<String>) -> Vec<String> {
+    binaries
+        .iter()
+        .filter(|binary| find_program(binary).is_none())
+        .cloned()
+        .collect()
+}
+
+/// Where the program is, a path is taken as is, a bare name is searched on the PATH.
+pub fn find_program(program: &str) -> Option<PathBuf> {
+    let path = Path::new(program);
+    if path.components().count() > 1 {
+        return Some(path.to_path_buf()).filter(|path| path.is_file());
+    }
+    let candidates = |dir: PathBuf| {
+        let mut candidates = vec![dir.join(program)];
+        if cfg!(windows) {
+            candidates.push(dir.join(format!("{program}.exe")));
+        }
+        candidates
+    };
+    env::split_paths(&env::var_os("PATH")?)
+        .flat_map(candidates)
+        .find(|candidate| candidate.is_file())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        check_call_format, find_program, missing_binaries, script_binaries, validate_listing,
+    };
+    use crate::scripts::{find_scripts, parse_script_listings, parse_scripts, Remote};
+    use std::collections::{BTreeMap, BTreeSet};
+    use std::path::Path;
+
+    #[test]
+    fn valid_fixture_passes() {
+        let listings =
+            parse_script_listings(vec!["fixtures/.rustscan_scripts/test_script.sh".into()]);
+        assert!(validate_listing(&listings[0], &BTreeMap::new())
+            .problems
+            .is_empty());
+    }
+
+    #[test]
+    fn invalid_headers_fail() {
+        let listings = parse_script_listings(vec![
+            "fixtures/.rustscan_scripts/test_script_invalid_headers.txt".into(),
+        ]);
+        assert_eq!(
+            validate_listing(&listings[0], &BTreeMap::new())
+                .problems
+                .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn library_only_placeholders() {
+        let script = Path::new("fixtures/.rustscan_scripts/test_script.sh");
+        assert_eq!(
+            check_call_format(
+                "{{script}} {{services}} {{banner|hex}}",
+                script,
+                &BTreeMap::new(),
+                false
+            ),
+            vec![
+                "{{services}} is only filled when RustScan is used as a library, it is always empty from the command line in {{script}} {{services}} {{banner|hex}}",
+                "{{banner}} is only filled when RustScan is used as a library, it is always empty from the command line in {{script}} {{services}} {{banner|hex}}"
+            ]
+        );
+        assert_eq!(
+            check_call_format(
+                "{{script}} -iL {{results_file}}",
+                script,
+                &BTreeMap::new(),
+                false
+            ),
+            vec!["{{results_file}} is only filled when RustScan is used as a library, it is always empty from the command line in {{script}} -iL {{results_file}}"]
+        );
+    }
+
+    #[test]
+    fn unknown_placeholder_and_program() {
+        let script = Path::new("fixtures/.rustscan_scripts/test_script.sh");
+        assert_eq!(
+            check_call_format(
+                "bash {{script}} {{ipp}} {{{{ipp}}}}",
+                script,
+                &BTreeMap::new(),
+                false
+            ),
+            vec!["unknown placeholder {{ipp}} in bash {{script}} {{ipp}} {{{{ipp}}}}"]
+        );
+        assert_eq!(
+            check_call_format(
+                "no-such-program-rustscan {{ip}}",
+                script,
+                &BTreeMap::new(),
+                false
+            ),
+            vec!["no-such-program-rustscan is not found on the PATH"]
+        );
+        assert!(check_call_format("{{script}} {{ip}}", script, &BTreeMap::new(), false).is_empty());
+        assert!(check_call_format(
+            "no-such-program-rustscan {{ip}}",
+            script,
+            &BTreeMap::new(),
+            true
+        )
+        .is_empty());
+        assert!(check_call_format(
+            "{{script}} {{ports_flag:--port}}",
+            script,
+            &BTreeMap::new(),
+            false
+        )
+        .is_empty());
+        assert!(check_call_format(
+            "{{script}} {{?443:--https}}",
+            script,
+            &BTreeMap::new(),
+            false
+        )
+        .is_empty());
+        assert_eq!(
+            check_call_format("{{script}} {{?https:-s}}", script, &BTreeMap::new(), false).len(),
+            1
+        );
+
+        assert!(check_call_format(
+            "{{script}} {{ip|base64}} {{port|hex}}",
+            script,
+            &BTreeMap::new(),
+            false
+        )
+        .is_empty());
+        assert_eq!(
+            check_call_format("{{script}} {{ip|rot13}}", script, &BTreeMap::new(), false).len(),
+            1
+        );
+
+        let vars = BTreeMap::from([("wordlist".to_string(), "words.txt".to_string())]);
+        assert!(check_call_format(
+            "{{script}} {{wordlist}} {{vars.wordlist}}",
+            script,
+            &vars,
+            false
+        )
+        .is_empty());
+        assert_eq!(
+            check_call_format("{{script}} {{vars.other}}", script, &vars, false).len(),
+            1
+        );
+    }
+
+    #[test]
+    fn binaries_of_the_fixtures() {
+        let mut scripts = parse_scripts(find_scripts("fixtures".into()).unwrap());
+        let binaries = script_binaries(&scripts);
+        assert_eq!(
+            binaries.iter().map(String::as_str).collect::<Vec<&str>>(),
+            vec!["bash", "nmap", "perl", "python3"]
+        );
+
+        scripts[0].remote = Some(Remote {
+            host: "jump.example".to_string(),
+            user: None,
+            port: None,
+        });
+        scripts[1].call_format = Some("{{script}} {{ip}}".into());
+        scripts.truncate(2);
+        assert_eq!(
+            script_binaries(&scripts),
+            BTreeSet::from(["ssh".to_string()])
+        );
+        assert_eq!(
+            missing_binaries(&BTreeSet::from([
+                "sh".to_string(),
+                "no-such-program-rustscan".to_string()
+            ])),
+            vec!["no-such-program-rustscan"]
+        );
+    }
+
+    #[test]
+    fn find_program_on_path() {
+        assert!(find_program("sh").is_some());
+        assert!(find_program("/bin/sh").is_some());
+        assert!(find_program("./no-such-program").is_none());
+    }
+}